@@ -0,0 +1,13 @@
+//! Processing arbitrary markdown must never panic.
+//!
+//! Run with `cargo +nightly fuzz run add_wavedrom` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(markdown) = std::str::from_utf8(data) {
+        let _ = mdbook_wavedrom::testing::process(markdown);
+    }
+});
@@ -0,0 +1,189 @@
+//! Accessibility checks for `check --a11y`, so books with many figures can track in CI that
+//! every diagram can be followed without seeing it.
+//!
+//! A rendered diagram should have a `caption`, an `alt` text screen readers announce it by, and
+//! `descriptions` of its signals as its long description. Elements of the chapter's own HTML
+//! reacting to the mouse should be reachable with the keyboard as well.
+
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::attrs::Attributes;
+use crate::lint::Warning;
+use crate::wavejson;
+
+/// The names of the checks, for `allow`.
+pub(crate) const NAMES: &[&str] = &[
+    "missing-caption",
+    "missing-alt",
+    "missing-descriptions",
+    "no-keyboard-path",
+];
+
+/// Elements the keyboard reaches without a `tabindex`.
+const FOCUSABLE: &[&str] = &[
+    "a", "button", "input", "select", "textarea", "summary", "details", "iframe",
+];
+
+/// Check the rendered `diagram` of a block with the `attrs`.
+pub(crate) fn check(diagram: &Value, attrs: &Attributes) -> Vec<Warning> {
+    let mut warnings = vec![];
+    if attrs.get("caption").is_none() {
+        warnings.push(Warning {
+            lint: "missing-caption",
+            message: "The diagram has no `caption`".into(),
+        });
+    }
+    if attrs.get("alt").is_none() {
+        warnings.push(Warning {
+            lint: "missing-alt",
+            message: "The diagram has no `alt` text, screen readers can't tell what it shows"
+                .into(),
+        });
+    }
+
+    let descriptions = diagram.get("descriptions").and_then(Value::as_object);
+    let undescribed: Vec<String> = wavejson::lanes(diagram)
+        .into_iter()
+        .filter_map(|lane| lane.get("name").and_then(Value::as_str))
+        .filter(|name| !name.trim().is_empty())
+        .filter(|name| descriptions.is_none_or(|descriptions| !descriptions.contains_key(*name)))
+        .map(|name| format!("`{}`", name))
+        .collect();
+    if descriptions.is_none() && !undescribed.is_empty() {
+        warnings.push(Warning {
+            lint: "missing-descriptions",
+            message: "The diagram has no `descriptions` of its signals".into(),
+        });
+    } else if !undescribed.is_empty() {
+        warnings.push(Warning {
+            lint: "missing-descriptions",
+            message: format!(
+                "The diagram has no `descriptions` of {}",
+                undescribed.join(", ")
+            ),
+        });
+    }
+    warnings
+}
+
+/// The elements of the chapter's `content` that react to the mouse but not to the keyboard, by
+/// where they start. Examples in code are left alone.
+pub(crate) fn keyboard_paths(content: &str) -> Vec<(usize, Warning)> {
+    let code: Vec<Range<usize>> = Parser::new(content)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect();
+    let tag = Regex::new(r"(?is)<([a-z][a-z0-9-]*)\b([^>]*)>").unwrap();
+    let handler = Regex::new(r"(?i)\s(on(?:click|dblclick|mouse\w+))\s*=").unwrap();
+    let keyboard = Regex::new(r"(?i)\s(?:onkey\w+|onfocus\w*)\s*=").unwrap();
+    let tabindex = Regex::new(r"(?i)\stabindex\s*=").unwrap();
+
+    let mut warnings = vec![];
+    for captures in tag.captures_iter(content) {
+        let start = captures.get(0).map_or(0, |m| m.start());
+        if code.iter().any(|range| range.contains(&start)) {
+            continue;
+        }
+        let (name, attributes) = (captures[1].to_ascii_lowercase(), &captures[2]);
+        let mouse = match handler.captures(attributes) {
+            Some(mouse) => mouse[1].to_ascii_lowercase(),
+            None => continue,
+        };
+        let message = if !FOCUSABLE.contains(&name.as_str()) && !tabindex.is_match(attributes) {
+            format!(
+                "The `<{}>` reacts to `{}` but can't be reached with the keyboard, \
+                 give it a `tabindex` and a key handler, or use a `<button>`",
+                name, mouse
+            )
+        } else if !FOCUSABLE.contains(&name.as_str()) && !keyboard.is_match(attributes) {
+            format!(
+                "The `<{}>` reacts to `{}` but not to the keyboard, give it a key handler",
+                name, mouse
+            )
+        } else {
+            continue;
+        };
+        warnings.push((
+            start,
+            Warning {
+                lint: "no-keyboard-path",
+                message,
+            },
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{check, keyboard_paths};
+    use crate::{attrs, wavejson};
+
+    fn messages(diagram: &str, info: &str) -> Vec<String> {
+        let diagram = wavejson::parse(diagram).unwrap();
+        check(&diagram, &attrs::parse(info).1)
+            .into_iter()
+            .map(|warning| format!("{} [{}]", warning.message, warning.lint))
+            .collect()
+    }
+
+    #[test]
+    fn checks_diagrams() {
+        assert_eq!(
+            vec![
+                "The diagram has no `caption` [missing-caption]",
+                "The diagram has no `alt` text, screen readers can't tell what it shows [missing-alt]",
+                "The diagram has no `descriptions` of its signals [missing-descriptions]",
+            ],
+            messages("{signal: [{name: 'clk'}]}", "wavedrom")
+        );
+        assert_eq!(
+            vec!["The diagram has no `descriptions` of `ack` [missing-descriptions]"],
+            messages(
+                "{signal: [{name: 'req'}, ['bus', {name: 'ack'}], {}], \
+                 descriptions: {req: 'Request'}}",
+                "wavedrom caption=\"Handshake\" alt=\"req rises, then ack\""
+            )
+        );
+        assert!(messages("{signal: [{wave: '01'}]}", "wavedrom caption=A alt=B").is_empty());
+    }
+
+    #[test]
+    fn finds_mouse_only_elements() {
+        let content = "<div onclick=\"toggle()\">Show</div>\n\n\
+                       <span tabindex=\"0\" onmouseover=\"peek()\">Peek</span>\n\n\
+                       <button onclick=\"toggle()\">Show</button>\n\n\
+                       <div tabindex=\"0\" onclick=\"toggle()\" onkeydown=\"toggle()\">Show</div>\n\n\
+                       ```html\n<div onclick=\"toggle()\">Example</div>\n```\n";
+        let found: Vec<_> = keyboard_paths(content)
+            .into_iter()
+            .map(|(start, warning)| (start, warning.message))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    0,
+                    "The `<div>` reacts to `onclick` but can't be reached with the keyboard, \
+                     give it a `tabindex` and a key handler, or use a `<button>`"
+                        .to_string()
+                ),
+                (
+                    36,
+                    "The `<span>` reacts to `onmouseover` but not to the keyboard, \
+                     give it a key handler"
+                        .to_string()
+                ),
+            ],
+            found
+        );
+    }
+}
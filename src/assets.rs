@@ -0,0 +1,214 @@
+//! Checking that a book loads the WaveDrom runtime `install` set up for it.
+//!
+//! Without the runtime the page shows the diagrams' WaveJSON as text, not an error anyone
+//! notices in the build. `install` also pins the hashes of the files it copies in
+//! `asset-hashes`, for the build to notice scripts that were changed or replaced since.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The `additional-js` files the diagrams of a page need.
+const RUNTIME_FILES: &[&str] = &[
+    "wavedrom.min.js",
+    "wavedrome-default.js",
+    "wavedrom-runtime.js",
+];
+
+/// The file drawing the diagrams once the page is parsed, and what it calls to do so. Copies
+/// from before it did leave the diagrams undrawn, since chapters no longer draw them.
+const DRAWING_FILE: (&str, &str) = ("wavedrome-default.js", "WaveDrom.ProcessAll()");
+
+/// What's wrong with the `additional-js` entries of the book at `root`, one message per file.
+pub(crate) fn missing_runtime(root: &Path, additional_js: &[PathBuf]) -> Vec<String> {
+    let mut problems = vec![];
+    for file in RUNTIME_FILES {
+        let entries: Vec<_> = additional_js
+            .iter()
+            .filter(|entry| entry.file_name() == Some(file.as_ref()))
+            .collect();
+        if entries.is_empty() {
+            problems.push(format!(
+                "`{}` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`",
+                file
+            ));
+        } else if !entries
+            .iter()
+            .any(|entry| is_remote(entry) || root.join(entry).is_file())
+        {
+            problems.push(format!(
+                "`{}` from `output.html.additional-js` doesn't exist, run `mdbook-wavedrom install`",
+                root.join(entries[0]).display()
+            ));
+        } else if *file == DRAWING_FILE.0 {
+            let outdated = entries.iter().find(|entry| {
+                fs::read_to_string(root.join(entry))
+                    .is_ok_and(|script| !script.contains(DRAWING_FILE.1))
+            });
+            if let Some(entry) = outdated {
+                problems.push(format!(
+                    "`{}` is from an older version and doesn't draw the diagrams, run \
+                     `mdbook-wavedrom install --upgrade`",
+                    root.join(entry).display()
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// The directory `install` copied the files to, relative to the book's root and with a trailing
+/// `/`, e.g. `theme/wavedrom/`. It's where the local copy of the runtime is, and the root if
+/// there isn't one.
+pub fn dir(additional_js: &[PathBuf]) -> String {
+    additional_js
+        .iter()
+        .filter(|entry| !is_remote(entry))
+        .find(|entry| {
+            RUNTIME_FILES
+                .iter()
+                .any(|file| entry.file_name() == Some(file.as_ref()))
+        })
+        .and_then(|entry| entry.parent())
+        .map(|dir| {
+            dir.components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(format!("{}/", name.to_string_lossy())),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The hash `install` pins a file with the `content` by, like `sha256-<base64>` of subresource
+/// integrity.
+pub fn integrity(content: &[u8]) -> String {
+    format!("sha256-{}", base64::encode(Sha256::digest(content)))
+}
+
+/// The files of the book at `root` that don't match the hashes they were pinned with, one
+/// message per file.
+pub(crate) fn changed(root: &Path, hashes: &BTreeMap<String, String>) -> Vec<String> {
+    let mut problems = vec![];
+    for (file, hash) in hashes {
+        let path = root.join(file);
+        match fs::read(&path) {
+            Ok(content) if integrity(&content) == *hash => {}
+            Ok(_) => problems.push(format!(
+                "`{}` doesn't match the hash in `asset-hashes`, it was changed since `install` \
+                 pinned it",
+                path.display()
+            )),
+            Err(e) => problems.push(format!(
+                "`{}` pinned in `asset-hashes` can't be read: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+    problems
+}
+
+fn is_remote(entry: &Path) -> bool {
+    let entry = entry.to_string_lossy();
+    entry.starts_with("http://") || entry.starts_with("https://") || entry.starts_with("//")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{changed, dir, integrity, missing_runtime};
+
+    #[test]
+    fn finds_missing_runtime() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("theme")).unwrap();
+        fs::write(root.path().join("theme/wavedrom.min.js"), "").unwrap();
+        let entries = |entries: &[&str]| entries.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert!(missing_runtime(
+            root.path(),
+            &entries(&[
+                "theme/wavedrom.min.js",
+                "https://cdn.example.com/wavedrome-default.js",
+                "https://cdn.example.com/wavedrom-runtime.js"
+            ])
+        )
+        .is_empty());
+
+        let problems = missing_runtime(root.path(), &entries(&["./wavedrom.min.js"]));
+        assert_eq!(3, problems.len());
+        assert!(problems[0].ends_with("wavedrom.min.js` from `output.html.additional-js` doesn't exist, run `mdbook-wavedrom install`"));
+        assert_eq!(
+            "`wavedrome-default.js` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`",
+            problems[1]
+        );
+        assert_eq!(
+            "`wavedrom-runtime.js` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`",
+            problems[2]
+        );
+
+        // A copy from before it drew the diagrams.
+        fs::write(root.path().join("theme/wavedrome-default.js"), "var skin;").unwrap();
+        fs::write(root.path().join("theme/wavedrom-runtime.js"), "").unwrap();
+        let problems = missing_runtime(
+            root.path(),
+            &entries(&[
+                "theme/wavedrom.min.js",
+                "theme/wavedrome-default.js",
+                "theme/wavedrom-runtime.js",
+            ]),
+        );
+        assert_eq!(1, problems.len());
+        assert!(problems[0].ends_with("wavedrome-default.js` is from an older version and doesn't draw the diagrams, run `mdbook-wavedrom install --upgrade`"));
+    }
+
+    #[test]
+    fn finds_the_directory() {
+        let entries = |entries: &[&str]| entries.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!("", dir(&entries(&["wavedrom.min.js"])));
+        assert_eq!("", dir(&entries(&[])));
+        assert_eq!(
+            "theme/wavedrom/",
+            dir(&entries(&[
+                "other.js",
+                "https://cdn.example.com/wavedrom.min.js",
+                "./theme/wavedrom/wavedrome-default.js"
+            ]))
+        );
+    }
+
+    #[test]
+    fn finds_changed_assets() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("wavedrom.min.js"), "runtime").unwrap();
+        fs::write(root.path().join("wavedrome-default.js"), "skin").unwrap();
+        assert_eq!(
+            "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            integrity(b"")
+        );
+
+        let mut hashes = BTreeMap::new();
+        hashes.insert("wavedrom.min.js".to_string(), integrity(b"runtime"));
+        hashes.insert("wavedrome-default.js".to_string(), integrity(b"skin"));
+        assert!(changed(root.path(), &hashes).is_empty());
+
+        fs::write(root.path().join("wavedrom.min.js"), "replaced").unwrap();
+        hashes.insert("wavedrom-events.js".to_string(), integrity(b"events"));
+        let problems = changed(root.path(), &hashes);
+        assert_eq!(2, problems.len());
+        assert!(
+            problems[0].contains("wavedrom-events.js` pinned in `asset-hashes` can't be read: ")
+        );
+        assert!(problems[1].ends_with(
+            "wavedrom.min.js` doesn't match the hash in `asset-hashes`, it was changed since \
+             `install` pinned it"
+        ));
+    }
+}
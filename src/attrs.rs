@@ -0,0 +1,154 @@
+//! Attributes given in the info string of a code block.
+//!
+//! The first word is the language, everything after it are `key=value` pairs separated by
+//! whitespace or commas, like in mdbook's own `rust,editable`:
+//!
+//! ~~~text
+//! ```wavedrom colors="req:blue,ack:orange"
+//! ~~~
+//!
+//! Values containing whitespace or commas need to be quoted with `"` or `'`.
+//! A key without a value is a flag and reads as `true`.
+
+/// The attributes of a single code block, in the order they were given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    /// The value of `key`. If it was given multiple times, the last one wins.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Give the first attribute, if it's a flag, as the value of `key` instead, for info strings
+    /// naming a value without its key like `wavedrom-include waves/spi.json5`.
+    pub(crate) fn name_first(&mut self, key: &str) {
+        if let Some((first, value)) = self.0.first_mut() {
+            if value == "true" {
+                *value = std::mem::replace(first, key.to_string());
+            }
+        }
+    }
+
+    /// The keys given, in order.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(k, _)| k.as_str())
+    }
+
+    /// The keys and values given, in order.
+    pub(crate) fn into_pairs(self) -> Vec<(String, String)> {
+        self.0
+    }
+}
+
+/// Split an info string into its language and attributes.
+pub(crate) fn parse(info: &str) -> (&str, Attributes) {
+    let info = info.trim();
+    let end = info
+        .find(|c: char| c.is_whitespace() || c == ',')
+        .unwrap_or(info.len());
+    let (language, rest) = info.split_at(end);
+
+    let mut attrs = vec![];
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|&c| c.is_whitespace() || c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == ',' || c == '=' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+
+        let mut value = String::from("true");
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            value.clear();
+            match chars.peek() {
+                Some(&quote) if quote == '"' || quote == '\'' => {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if c == quote {
+                            break;
+                        }
+                        value.push(c);
+                    }
+                }
+                _ => {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || c == ',' {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                }
+            }
+        }
+
+        if !key.is_empty() {
+            attrs.push((key, value));
+        }
+    }
+
+    (language, Attributes(attrs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    #[test]
+    fn language_only() {
+        let (language, attrs) = parse("wavedrom");
+        assert_eq!("wavedrom", language);
+        assert_eq!(None, attrs.get("colors"));
+    }
+
+    #[test]
+    fn quoted_and_bare_values() {
+        let (language, attrs) = parse("wavedrom colors=\"req:blue, ack:orange\" scale=2 'x'");
+        assert_eq!("wavedrom", language);
+        assert_eq!(Some("req:blue, ack:orange"), attrs.get("colors"));
+        assert_eq!(Some("2"), attrs.get("scale"));
+        assert_eq!(Some("true"), attrs.get("'x'"));
+    }
+
+    #[test]
+    fn comma_separated_like_mdbook() {
+        let (language, attrs) = parse("wavedrom,colors='req:blue',hidden");
+        assert_eq!("wavedrom", language);
+        assert_eq!(Some("req:blue"), attrs.get("colors"));
+        assert_eq!(Some("true"), attrs.get("hidden"));
+    }
+
+    #[test]
+    fn names_the_first_flag() {
+        let (_, mut attrs) = parse("wavedrom-include waves/spi.json5 keep");
+        attrs.name_first("src");
+        assert_eq!(Some("waves/spi.json5"), attrs.get("src"));
+        assert_eq!(Some("true"), attrs.get("keep"));
+
+        let (_, mut attrs) = parse("wavedrom-include caption=SPI");
+        attrs.name_first("src");
+        assert_eq!(None, attrs.get("src"));
+    }
+
+    #[test]
+    fn last_value_wins() {
+        let (_, attrs) = parse("wavedrom colors=a:red colors=a:green");
+        assert_eq!(Some("a:green"), attrs.get("colors"));
+    }
+}
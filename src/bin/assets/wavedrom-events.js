@@ -0,0 +1,113 @@
+/*
+ * Announces rendered diagrams to the page, for book owners to hook their own behavior onto.
+ *
+ * Every diagram dispatches a bubbling `wavedrom:rendered` CustomEvent once it's drawn, with
+ * `detail.id` the diagram's id in its chapter, like `wavedrom-2`, and `detail.index` its number.
+ * The event's target is the rendered diagram: WaveDrom's display, the image or the frame.
+ * Lazily drawn diagrams dispatch it once they scrolled into view and were drawn. The id is the one
+ * of the diagram's anchor, which the preprocessor gives its elements as `data-wavedrom-id`.
+ *
+ * Zoomable diagrams dispatch `wavedrom:zoom` on their `.wavedrom-zoom` whenever the reader zooms them, with
+ * `detail.zoom` the new zoom, and ones with `show-source` dispatch `wavedrom:tab-change` on their
+ * WaveJSON when the reader opens or closes it, with `detail.tab` `source` or `diagram`.
+ *
+ * A page opened with a link to a diagram, like `#wavedrom-2` or the `#spi-write` of its `id`,
+ * scrolls to it once the diagrams are drawn and briefly highlights it, and so does following such
+ * a link on the page. Readers preferring reduced motion get there without the smooth scrolling
+ * and the fading outline, the theme outlines the diagram for as long instead.
+ */
+(function () {
+    'use strict';
+
+    // The diagram the URL's fragment names, if the preprocessor gave it the id: the diagram below
+    // an anchor like `wavedrom-2`, or the figure or wrapper of an `id`.
+    function linked() {
+        var id = decodeURIComponent(window.location.hash.slice(1));
+        var diagram = id && document.getElementById(id);
+        if (!diagram) return null;
+        if (!diagram.classList.contains('wavedrom-anchor')) {
+            return diagram.matches('.wavedrom-figure, .wavedrom-diagram') ? diagram : null;
+        }
+        while (diagram && diagram.classList.contains('wavedrom-anchor')) {
+            diagram = diagram.nextElementSibling;
+        }
+        return diagram;
+    }
+
+    function reducedMotion() {
+        return !!window.matchMedia && window.matchMedia('(prefers-reduced-motion: reduce)').matches;
+    }
+
+    function highlight() {
+        var diagram = linked();
+        if (!diagram) return;
+        var still = reducedMotion();
+        // Drawing the diagrams moved it from where the browser scrolled to.
+        diagram.scrollIntoView({ block: 'center', behavior: still ? 'auto' : 'smooth' });
+        diagram.classList.add('wavedrom-linked');
+        if (diagram.animate && !still) {
+            diagram.animate([
+                { boxShadow: '0 0 0 4px rgba(255, 196, 0, 0.8)' },
+                { boxShadow: '0 0 0 4px rgba(255, 196, 0, 0)' }
+            ], { duration: 2000, easing: 'ease-out' });
+        }
+        setTimeout(function () {
+            diagram.classList.remove('wavedrom-linked');
+        }, 2000);
+    }
+
+    function dispatch(name, target, diagram, detail) {
+        var id = diagram && diagram.getAttribute('data-wavedrom-id');
+        if (!id) return;
+        detail = detail || {};
+        detail.id = id;
+        detail.index = parseInt(id.slice('wavedrom-'.length), 10);
+        target.dispatchEvent(new CustomEvent(name, { bubbles: true, detail: detail }));
+    }
+
+    // The diagram drawn in the elements before `element`, like the one its WaveJSON is below.
+    function diagramBefore(element) {
+        for (element = element.previousElementSibling; element; element = element.previousElementSibling) {
+            var diagram = element.matches('[data-wavedrom-id]') ? element : element.querySelector('[data-wavedrom-id]');
+            if (diagram) return diagram;
+        }
+        return null;
+    }
+
+    // WaveDrom draws once the page is parsed, so look once it's loaded and all handlers ran.
+    window.addEventListener('load', function () {
+        setTimeout(function () {
+            document.querySelectorAll('script[type="WaveDrom"], .wavedrom-image').forEach(function (diagram) {
+                var target = diagram.tagName === 'SCRIPT' ? diagram.previousElementSibling : diagram;
+                if (target) dispatch('wavedrom:rendered', target, diagram);
+            });
+            highlight();
+        });
+    });
+
+    window.addEventListener('hashchange', highlight);
+
+    document.addEventListener('wavedrom:drawn', function (e) {
+        dispatch('wavedrom:rendered', e.target, e.target);
+    });
+
+    document.addEventListener('wavedrom:zoomed', function (e) {
+        var zoom = parseFloat(e.target.getAttribute('data-zoom'));
+        dispatch('wavedrom:zoom', e.target, e.target.querySelector('[data-wavedrom-id]'), { zoom: zoom });
+    });
+
+    // `toggle` doesn't bubble, so it's caught on its way down.
+    document.addEventListener('toggle', function (e) {
+        var source = e.target;
+        if (!source.classList || !source.classList.contains('wavedrom-source')) return;
+        dispatch('wavedrom:tab-change', source, diagramBefore(source), { tab: source.open ? 'source' : 'diagram' });
+    }, true);
+
+    // Frames report their height once drawn.
+    window.addEventListener('message', function (e) {
+        if (!e.data || typeof e.data.wavedromHeight !== 'number') return;
+        document.querySelectorAll('iframe.wavedrom-frame').forEach(function (frame) {
+            if (frame.contentWindow === e.source) dispatch('wavedrom:rendered', frame, frame);
+        });
+    });
+})();
@@ -0,0 +1,48 @@
+/*
+ * Highlights WaveJSON code listings, like the ones of `render=false` blocks.
+ *
+ * Registers a `wavejson` language with highlight.js and highlights the listings in that language
+ * again, mdbook's own pass over the code blocks runs before this script.
+ */
+(function () {
+    'use strict';
+
+    if (typeof hljs === 'undefined') {
+        return;
+    }
+
+    var KEYS = [
+        'signal', 'name', 'wave', 'data', 'node', 'period', 'phase', 'edge', 'head', 'foot',
+        'text', 'tick', 'tock', 'every', 'config', 'hscale', 'skin', 'reg', 'bits', 'attr',
+        'type', 'lanes', 'descriptions'
+    ];
+
+    hljs.registerLanguage('wavejson', function (hljs) {
+        return {
+            name: 'WaveJSON',
+            aliases: ['wavedrom'],
+            contains: [
+                hljs.C_LINE_COMMENT_MODE,
+                hljs.C_BLOCK_COMMENT_MODE,
+                {
+                    // The keys WaveDrom knows stand out from any other key.
+                    className: 'keyword',
+                    begin: new RegExp('\\b(?:' + KEYS.join('|') + ')\\b(?=\\s*:)')
+                },
+                {
+                    className: 'attr',
+                    begin: /(?:[A-Za-z_$][\w$]*|'[^'\n]*'|"[^"\n]*")(?=\s*:)/,
+                    relevance: 0
+                },
+                hljs.APOS_STRING_MODE,
+                hljs.QUOTE_STRING_MODE,
+                hljs.C_NUMBER_MODE,
+                { className: 'literal', begin: /\b(?:true|false|null)\b/ }
+            ]
+        };
+    });
+
+    document.querySelectorAll('code.language-wavejson').forEach(function (block) {
+        hljs.highlightBlock(block);
+    });
+})();
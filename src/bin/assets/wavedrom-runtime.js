@@ -0,0 +1,379 @@
+/*
+ * What mdbook-wavedrom does around drawing the diagrams of a page, which the hook of
+ * `wavedrome-default.js` draws once it's parsed: it has this script prepare the page before, and
+ * take over the drawn diagrams after.
+ *
+ * Identical diagrams of a chapter share a single definition of their WaveJSON, which is copied
+ * into each of them before they're drawn.
+ *
+ * Diagrams with a `data-dark-skin` are drawn again in that skin while the page has a dark
+ * background, and in their own once the reader picks a light theme again.
+ *
+ * The copy buttons of the diagrams' toolbars copy the WaveJSON their editor link carries, and
+ * their download buttons save the image of the diagram above the toolbar as SVG, or as PNG
+ * rasterized on a canvas over the page's background.
+ *
+ * With `lazy = true` the diagrams are placeholders carrying their WaveJSON instead, each drawn
+ * once it scrolls into view, and all of them before the page is printed. The placeholders of
+ * `thumbnails = true` show a PNG image of their diagram instead, and are drawn once the reader
+ * clicks them, or before the page is printed.
+ *
+ * With `zoom = true` every diagram is in a view scrolling sideways, zoomed by its slider, its fit
+ * button, a pinch or Ctrl and the mouse wheel, and panned by dragging it. The view takes the
+ * keyboard's focus too: the arrow keys pan it, `+` and `-` zoom it and `0` fits it.
+ */
+(function () {
+    'use strict';
+
+    if (typeof document === 'undefined') return;
+
+    var dark = false;
+    // The placeholders of the lazily drawn diagrams of the page.
+    var lazy = [];
+
+    // Whether the page background of the active theme is dark, which covers custom themes too.
+    function isDark() {
+        var rgb = /rgba?\((\d+),\s*(\d+),\s*(\d+)/.exec(getComputedStyle(document.body).backgroundColor);
+        return !!rgb && 0.299 * rgb[1] + 0.587 * rgb[2] + 0.114 * rgb[3] < 128;
+    }
+
+    function reskin() {
+        if (isDark() === dark) return;
+        dark = !dark;
+        // Only the first timing diagram of the page carries the skin's definitions.
+        var notFirstSignal = false;
+        document.querySelectorAll('script[type="WaveDrom"]').forEach(function (script, index) {
+            var skin = script.getAttribute('data-dark-skin');
+            var diagram = WaveDrom.eva('InputJSON_' + index);
+            if (skin && diagram) {
+                if (dark) {
+                    diagram.config = diagram.config || {};
+                    diagram.config.skin = skin;
+                }
+                WaveDrom.RenderWaveForm(index, diagram, 'WaveDrom_Display_', notFirstSignal);
+            }
+            if (diagram && diagram.signal) notFirstSignal = true;
+        });
+        lazy.forEach(function (placeholder) {
+            if (placeholder.hasAttribute('data-drawn') && placeholder.hasAttribute('data-dark-skin')) {
+                drawLazy(placeholder);
+            }
+        });
+    }
+
+    function drawLazy(placeholder) {
+        var diagram;
+        try {
+            diagram = Function('return (' + placeholder.getAttribute('data-wavejson') + ')')();
+        } catch (e) {
+            return;
+        }
+        var skin = placeholder.getAttribute('data-dark-skin');
+        if (dark && skin) {
+            diagram.config = diagram.config || {};
+            diagram.config.skin = skin;
+        }
+        // Any of them may be drawn first, so each carries the skin's definitions.
+        WaveDrom.RenderWaveForm(lazy.indexOf(placeholder), diagram, 'WaveDrom_Lazy_', false);
+        if (!placeholder.hasAttribute('data-drawn')) {
+            placeholder.setAttribute('data-drawn', '');
+            placeholder.dispatchEvent(new CustomEvent('wavedrom:drawn', { bubbles: true }));
+        }
+    }
+
+    function observe() {
+        lazy = Array.prototype.slice.call(document.querySelectorAll('.wavedrom-lazy'));
+        if (!lazy.length) return;
+        lazy.forEach(function (placeholder, index) {
+            placeholder.id = 'WaveDrom_Lazy_' + index;
+        });
+        var drawAll = function (thumbnails) {
+            lazy.forEach(function (placeholder) {
+                if (placeholder.hasAttribute('data-drawn')) return;
+                if (thumbnails || !placeholder.classList.contains('wavedrom-thumbnail')) drawLazy(placeholder);
+            });
+        };
+        window.addEventListener('beforeprint', function () {
+            drawAll(true);
+        });
+        var scrolled = lazy.filter(function (placeholder) {
+            return !placeholder.classList.contains('wavedrom-thumbnail');
+        });
+        if (!scrolled.length) return;
+        if (typeof IntersectionObserver === 'undefined') return drawAll(false);
+        var observer = new IntersectionObserver(function (entries) {
+            entries.forEach(function (entry) {
+                if (!entry.isIntersecting || entry.target.hasAttribute('data-drawn')) return;
+                observer.unobserve(entry.target);
+                drawLazy(entry.target);
+            });
+        }, { rootMargin: '200px 0px' });
+        scrolled.forEach(function (placeholder) {
+            observer.observe(placeholder);
+        });
+    }
+
+    document.addEventListener('click', function (event) {
+        var button = event.target.closest && event.target.closest('.wavedrom-upgrade');
+        var placeholder = button && button.closest('.wavedrom-thumbnail');
+        if (placeholder && lazy.indexOf(placeholder) >= 0) drawLazy(placeholder);
+    });
+
+    // Identical diagrams of the page share the definition of their WaveJSON.
+    function beforeDraw() {
+        document.querySelectorAll('script[type="WaveDrom"][data-source]').forEach(function (script) {
+            var source = document.getElementById(script.getAttribute('data-source'));
+            if (source) script.textContent = source.textContent;
+        });
+    }
+
+    function afterDraw() {
+        observe();
+        if (!document.querySelector('script[data-dark-skin], .wavedrom-lazy[data-dark-skin]')) return;
+        reskin();
+        // mdbook's theme picker sets the theme as a class of the root element.
+        new MutationObserver(reskin).observe(document.documentElement, {
+            attributes: true,
+            attributeFilter: ['class']
+        });
+    }
+
+    document.addEventListener('click', function (event) {
+        var button = event.target.closest && event.target.closest('.wavedrom-copy');
+        var link = button && button.parentNode.querySelector('.wavedrom-edit');
+        if (!link || !navigator.clipboard) return;
+        var href = link.getAttribute('href');
+        var source = decodeURIComponent(href.slice(href.indexOf('?') + 1));
+        navigator.clipboard.writeText(source).then(function () {
+            button.textContent = 'Copied';
+            setTimeout(function () {
+                button.textContent = 'Copy';
+            }, 1500);
+        });
+    });
+
+    // The image of the diagram a toolbar is below: the first drawn in the elements before it.
+    function imageAbove(toolbar) {
+        for (var element = toolbar.previousElementSibling; element; element = element.previousElementSibling) {
+            var frame = element.querySelector('iframe');
+            var root = frame && frame.contentDocument ? frame.contentDocument : element;
+            var image = root.querySelector('svg, img[src^="data:image/svg+xml"]');
+            if (image) return image;
+        }
+        return null;
+    }
+
+    function save(blob, name) {
+        var link = document.createElement('a');
+        link.href = URL.createObjectURL(blob);
+        link.download = name;
+        document.body.appendChild(link);
+        link.click();
+        link.remove();
+        setTimeout(function () {
+            URL.revokeObjectURL(link.href);
+        }, 0);
+    }
+
+    function download(button) {
+        var image = imageAbove(button.parentNode);
+        if (!image) return;
+        var name = button.getAttribute('data-name');
+        // A pre-rendered image carries the bytes of its file, base64-encoded.
+        var svg = image.tagName.toLowerCase() === 'img'
+            ? Uint8Array.from(atob(image.getAttribute('src').split(',')[1]), function (c) {
+                return c.charCodeAt(0);
+            })
+            : new XMLSerializer().serializeToString(image);
+        var blob = new Blob([svg], { type: 'image/svg+xml' });
+        if (button.getAttribute('data-format') === 'svg') return save(blob, name + '.svg');
+
+        var box = image.getBoundingClientRect();
+        // Twice the size on screen, sharp enough for slides.
+        var scale = 2 * (window.devicePixelRatio || 1);
+        var raster = new Image();
+        raster.onload = function () {
+            var canvas = document.createElement('canvas');
+            canvas.width = Math.ceil(box.width * scale);
+            canvas.height = Math.ceil(box.height * scale);
+            var context = canvas.getContext('2d');
+            // The diagram's colors are picked for the page, which a transparent image would lose.
+            context.fillStyle = getComputedStyle(document.body).backgroundColor;
+            context.fillRect(0, 0, canvas.width, canvas.height);
+            context.drawImage(raster, 0, 0, canvas.width, canvas.height);
+            URL.revokeObjectURL(raster.src);
+            canvas.toBlob(function (png) {
+                if (png) save(png, name + '.png');
+            }, 'image/png');
+        };
+        raster.src = URL.createObjectURL(blob);
+    }
+
+    document.addEventListener('click', function (event) {
+        var button = event.target.closest && event.target.closest('.wavedrom-download');
+        if (button) download(button);
+    });
+
+    var MIN_ZOOM = 0.25;
+    var MAX_ZOOM = 4;
+    // Share of the view's width an arrow key pans it by.
+    var PAN_STEP = 0.1;
+
+    // The images of a view, each at its own size times `zoom`.
+    function zoomTo(wrapper, zoom) {
+        zoom = Math.min(MAX_ZOOM, Math.max(MIN_ZOOM, zoom));
+        var changed = zoom !== zoomOf(wrapper);
+        wrapper.setAttribute('data-zoom', zoom);
+        wrapper.querySelector('.wavedrom-zoom-level').value = Math.round(zoom * 100);
+        wrapper.querySelectorAll('.wavedrom-zoom-view svg, .wavedrom-zoom-view img').forEach(function (image) {
+            // Only the outermost images, WaveDrom nests some in its drawing.
+            if (image.parentNode.closest('svg')) return;
+            if (!image.hasAttribute('data-width')) {
+                var img = image.tagName.toLowerCase() === 'img';
+                if (img && !image.complete) {
+                    image.addEventListener('load', function () {
+                        zoomTo(wrapper, zoomOf(wrapper));
+                    }, { once: true });
+                    return;
+                }
+                // Their own size, which the page's styles may have shrunk to fit its width.
+                var rect = image.getBoundingClientRect();
+                var box = img
+                    ? { width: image.naturalWidth, height: image.naturalHeight }
+                    : {
+                        width: parseFloat(image.getAttribute('width')) || rect.width,
+                        height: parseFloat(image.getAttribute('height')) || rect.height
+                    };
+                if (!box.width) return;
+                image.setAttribute('data-width', box.width);
+                image.setAttribute('data-height', box.height);
+                if (!img && !image.hasAttribute('viewBox')) {
+                    image.setAttribute('viewBox', '0 0 ' + box.width + ' ' + box.height);
+                }
+            }
+            image.style.maxWidth = 'none';
+            image.style.width = image.getAttribute('data-width') * zoom + 'px';
+            image.style.height = image.getAttribute('data-height') * zoom + 'px';
+        });
+        if (changed) wrapper.dispatchEvent(new CustomEvent('wavedrom:zoomed', { bubbles: true }));
+    }
+
+    function zoomOf(wrapper) {
+        return parseFloat(wrapper.getAttribute('data-zoom')) || 1;
+    }
+
+    function fit(wrapper) {
+        var view = wrapper.querySelector('.wavedrom-zoom-view');
+        var widest = 0;
+        view.querySelectorAll('[data-width]').forEach(function (image) {
+            widest = Math.max(widest, parseFloat(image.getAttribute('data-width')));
+        });
+        if (widest) zoomTo(wrapper, view.clientWidth / widest);
+    }
+
+    function zoomable(wrapper) {
+        var view = wrapper.querySelector('.wavedrom-zoom-view');
+        wrapper.setAttribute('data-ready', '');
+        zoomTo(wrapper, 1);
+        // Diagrams drawn late, or again in another skin, are new images to zoom.
+        new MutationObserver(function () {
+            zoomTo(wrapper, zoomOf(wrapper));
+        }).observe(view, { childList: true, subtree: true });
+
+        wrapper.querySelector('.wavedrom-zoom-level').addEventListener('input', function (event) {
+            zoomTo(wrapper, event.target.value / 100);
+        });
+        wrapper.querySelector('.wavedrom-zoom-fit').addEventListener('click', function () {
+            fit(wrapper);
+        });
+        // Trackpads report pinches as wheel events with the Ctrl key held.
+        view.addEventListener('wheel', function (event) {
+            if (!event.ctrlKey) return;
+            event.preventDefault();
+            zoomTo(wrapper, zoomOf(wrapper) * Math.exp(-event.deltaY / 200));
+        }, { passive: false });
+
+        var pinch = null;
+        var distance = function (touches) {
+            return Math.hypot(touches[0].clientX - touches[1].clientX, touches[0].clientY - touches[1].clientY);
+        };
+        view.addEventListener('touchstart', function (event) {
+            if (event.touches.length === 2) pinch = { distance: distance(event.touches), zoom: zoomOf(wrapper) };
+        });
+        view.addEventListener('touchmove', function (event) {
+            if (!pinch || event.touches.length !== 2) return;
+            event.preventDefault();
+            zoomTo(wrapper, pinch.zoom * distance(event.touches) / pinch.distance);
+        }, { passive: false });
+        view.addEventListener('touchend', function () {
+            pinch = null;
+        });
+
+        // Touch pans the view natively, a mouse drags it.
+        var drag = null;
+        view.addEventListener('pointerdown', function (event) {
+            if (event.pointerType !== 'mouse' || event.button !== 0) return;
+            if (view.scrollWidth <= view.clientWidth) return;
+            drag = { x: event.clientX, scroll: view.scrollLeft };
+            view.setPointerCapture(event.pointerId);
+            view.setAttribute('data-dragging', '');
+        });
+        view.addEventListener('pointermove', function (event) {
+            if (drag) view.scrollLeft = drag.scroll - (event.clientX - drag.x);
+        });
+        var release = function () {
+            drag = null;
+            view.removeAttribute('data-dragging');
+        };
+        view.addEventListener('pointerup', release);
+        view.addEventListener('pointercancel', release);
+
+        view.addEventListener('keydown', function (event) {
+            if (event.altKey || event.ctrlKey || event.metaKey) return;
+            var step = Math.round(view.clientWidth * PAN_STEP);
+            var still = window.matchMedia && window.matchMedia('(prefers-reduced-motion: reduce)').matches;
+            var behavior = still ? 'auto' : 'smooth';
+            switch (event.key) {
+                case 'ArrowLeft':
+                    view.scrollBy({ left: -step, behavior: behavior });
+                    break;
+                case 'ArrowRight':
+                    view.scrollBy({ left: step, behavior: behavior });
+                    break;
+                case 'Home':
+                    view.scrollTo({ left: 0, behavior: behavior });
+                    break;
+                case 'End':
+                    view.scrollTo({ left: view.scrollWidth, behavior: behavior });
+                    break;
+                case '+':
+                case '=':
+                    zoomTo(wrapper, zoomOf(wrapper) * 1.25);
+                    break;
+                case '-':
+                    zoomTo(wrapper, zoomOf(wrapper) / 1.25);
+                    break;
+                case '0':
+                    fit(wrapper);
+                    break;
+                default:
+                    return;
+            }
+            event.preventDefault();
+        });
+    }
+
+    function zoomAll() {
+        document.querySelectorAll('.wavedrom-zoom').forEach(zoomable);
+    }
+
+    if (document.readyState === 'loading') {
+        document.addEventListener('DOMContentLoaded', zoomAll);
+    } else {
+        zoomAll();
+    }
+
+    window.wavedromRuntime = { beforeDraw: beforeDraw, afterDraw: afterDraw };
+    // Loaded after the hook drew the diagrams, like into a page that was parsed already.
+    if (window.wavedromDrawn) afterDraw();
+})();
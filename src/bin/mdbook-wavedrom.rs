@@ -1,30 +1,107 @@
-use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use clap::{crate_version, App, Arg, ArgMatches, Shell, SubCommand};
 use mdbook::errors::Error;
-use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
-use mdbook_wavedrom::Wavedrom;
+use mdbook::preprocess::Preprocessor;
+use mdbook::Config;
+use mdbook_wavedrom::check::{self, Severity};
+use mdbook_wavedrom::convert::{self, Syntax};
+use mdbook_wavedrom::diff::{self, Change};
+use mdbook_wavedrom::doctor::{self, Status};
+use mdbook_wavedrom::sync::{self, Direction};
+use mdbook_wavedrom::{
+    assets, compat, export, grep, supports, watch, Format, Outcome, Wavedrom, WavedromConfig,
+    NO_CACHE_ENV, PROFILE_ENV, STRICT_ENV, WAVEDROM_CDN,
+};
 use toml_edit::{value, Array, Document, Item, Table, Value};
 
 use std::{
-    fs::{self, File},
-    io::{self, Write},
-    path::PathBuf,
+    env, fs,
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    path::{Component, Path, PathBuf},
     process,
 };
 
 const WAVEDROM_JS: &[u8] = include_bytes!("assets/wavedrom.min.js");
 const WAVEDROM_DEFAULT_JS: &[u8] = include_bytes!("assets/wavedrome-default.js");
-const WAVEDROM_FILES: &[(&str, &[u8])] = &[
-    ("wavedrom.min.js", WAVEDROM_JS),
-    ("wavedrome-default.js", WAVEDROM_DEFAULT_JS),
+const WAVEDROM_RUNTIME_JS: &[u8] = include_bytes!("assets/wavedrom-runtime.js");
+const WAVEDROM_EVENTS_JS: &[u8] = include_bytes!("assets/wavedrom-events.js");
+const WAVEDROM_HIGHLIGHT_JS: &[u8] = include_bytes!("assets/wavedrom-highlight.js");
+const WAVEDROM_THEME_CSS: &[u8] = include_bytes!("assets/wavedrom-theme.css");
+/// Chapter `install --example` adds to the book.
+const EXAMPLE_CHAPTER: &str = include_str!("assets/wavedrom-example.md");
+const EXAMPLE_FILE: &str = "wavedrom-example.md";
+/// Files copied into the book, with the `additional-*` list they are added to.
+const WAVEDROM_FILES: &[(&str, &str, &[u8])] = &[
+    ("wavedrom.min.js", "js", WAVEDROM_JS),
+    ("wavedrome-default.js", "js", WAVEDROM_DEFAULT_JS),
+    ("wavedrom-runtime.js", "js", WAVEDROM_RUNTIME_JS),
+    ("wavedrom-events.js", "js", WAVEDROM_EVENTS_JS),
+    ("wavedrom-highlight.js", "js", WAVEDROM_HIGHLIGHT_JS),
+    ("wavedrom-theme.css", "css", WAVEDROM_THEME_CSS),
+];
+/// Skins copied into the book, along with the files, when its configuration picks them.
+const WAVEDROM_SKINS: &[(&str, &str, &[u8])] = &[
+    (
+        "narrow",
+        "wavedrom-skin-narrow.js",
+        include_bytes!("assets/wavedrom-skin-narrow.js"),
+    ),
+    (
+        "lowkey",
+        "wavedrom-skin-lowkey.js",
+        include_bytes!("assets/wavedrom-skin-lowkey.js"),
+    ),
 ];
 
 pub fn make_app() -> App<'static, 'static> {
     App::new("mdbook-wavedrom")
         .version(crate_version!())
         .about("mdbook preprocessor to add wavedrom support")
+        .arg(
+            Arg::with_name("dump-dir")
+                .long("dump-dir")
+                .value_name("DIR")
+                .help("Write every chapter's markdown before and after preprocessing into DIR"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("NAME")
+                .global(true)
+                .help("Use the options of [preprocessor.wavedrom.profile.NAME] on top of the others"),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .global(true)
+                .help("Fail on any warning, as with `strict = true`"),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .global(true)
+                .help("Render every diagram anew, as with `render-cache = false`"),
+        )
+        .arg(jobs_arg())
         .subcommand(
             SubCommand::with_name("supports")
-                .arg(Arg::with_name("renderer").required(true))
+                .arg(
+                    Arg::with_name("renderer")
+                        .required_unless("json")
+                        .multiple(true)
+                        .help("Renderers to check, the book's renderers with `--json` unless given"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print what the preprocessor does for every renderer as JSON"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Root directory of the book whose renderers `--json` describes"),
+                )
                 .about("Check whether a renderer is supported by this preprocessor"),
         )
         .subcommand(
@@ -34,91 +111,1128 @@ pub fn make_app() -> App<'static, 'static> {
                     .default_value(".")
                     .help("Root directory for the book,\nshould contain the configuration file (`book.toml`)")
                     )
+                .arg(
+                    Arg::with_name("no-discover")
+                    .long("no-discover")
+                    .help("Don't search parent directories for the configuration file")
+                    )
+                .arg(
+                    Arg::with_name("config-only")
+                    .long("config-only")
+                    .conflicts_with("assets-only")
+                    .help("Only add the configuration to `book.toml`, without copying the files")
+                    )
+                .arg(
+                    Arg::with_name("assets-only")
+                    .long("assets-only")
+                    .help("Only copy the files, leaving `book.toml` untouched")
+                    )
+                .arg(
+                    Arg::with_name("example")
+                    .long("example")
+                    .help("Add a chapter with example diagrams to the book's `SUMMARY.md`")
+                    )
+                .arg(
+                    Arg::with_name("cdn")
+                    .long("cdn")
+                    .help("Load the WaveDrom runtime from a CDN, rather than copying any scripts into the book")
+                    )
+                .arg(
+                    Arg::with_name("skin")
+                    .long("skin")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("name")
+                    .help("Also copy the skin `name`, for diagrams picking it themselves, like `lowkey`")
+                    )
+                .arg(
+                    Arg::with_name("assets-dir")
+                    .long("assets-dir")
+                    .takes_value(true)
+                    .value_name("path")
+                    .help("Directory below the book's root to copy the files to, like `theme/wavedrom`")
+                    )
+                .arg(
+                    Arg::with_name("repin")
+                    .long("repin")
+                    .conflicts_with("assets-only")
+                    .help("Pin the hashes of the files as they are now, after changing them on purpose")
+                    )
+                .arg(
+                    Arg::with_name("upgrade")
+                    .long("upgrade")
+                    .conflicts_with_all(&["config-only", "assets-only", "example", "cdn", "skin", "assets-dir", "repin"])
+                    .help("Only overwrite the files the book has from an older version with the ones of this version")
+                    )
+                .arg(
+                    Arg::with_name("yes")
+                    .long("yes")
+                    .short("y")
+                    .help("Don't ask before changing `book.toml` or overwriting files,\nas when not run in a terminal")
+                    )
                 .about("Install the required assset files and include it in the config"),
         )
+        .subcommand(
+            SubCommand::with_name("uninstall")
+                .arg(
+                    Arg::with_name("dir")
+                    .default_value(".")
+                    .help("Root directory for the book,\nshould contain the configuration file (`book.toml`)")
+                    )
+                .arg(
+                    Arg::with_name("no-discover")
+                    .long("no-discover")
+                    .help("Don't search parent directories for the configuration file")
+                    )
+                .arg(
+                    Arg::with_name("keep-files")
+                    .long("keep-files")
+                    .help("Only remove the configuration from `book.toml`, leaving the files in place")
+                    )
+                .arg(
+                    Arg::with_name("yes")
+                    .long("yes")
+                    .short("y")
+                    .help("Don't ask before changing `book.toml` or deleting changed files,\nas when not run in a terminal")
+                    )
+                .about("Remove the configuration and the files `install` added"),
+        )
+        .subcommand(
+            SubCommand::with_name("export-images")
+                .arg(
+                    Arg::with_name("dir")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("DIR")
+                        .default_value("images")
+                        .help("Directory to write the images into"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["svg", "png"])
+                        .default_value("svg")
+                        .help("Image format"),
+                )
+                .arg(jobs_arg())
+                .about("Render every diagram of the book to an image file"),
+        )
+        .subcommand(
+            SubCommand::with_name("render")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .help("WaveJSON file of the diagram, like `waves/spi.json5`"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Image file to write, instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["svg", "png"])
+                        .help("Image format [default: the extension of the output, or svg]"),
+                )
+                .about("Render a single diagram file to an image, with the configuration of the book it's in"),
+        )
+        .subcommand(
+            SubCommand::with_name("grep")
+                .arg(
+                    Arg::with_name("signal")
+                        .required(true)
+                        .help("Name of the signal, or a regular expression with --regex"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .arg(
+                    Arg::with_name("regex")
+                        .short("e")
+                        .long("regex")
+                        .help("Match signal names against a regular expression"),
+                )
+                .about("List the diagrams showing a signal"),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .arg(
+                    Arg::with_name("dir")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .arg(
+                    Arg::with_name("pull")
+                        .long("pull")
+                        .conflicts_with("push")
+                        .help("Update the copies in the chapters from their files"),
+                )
+                .arg(
+                    Arg::with_name("push")
+                        .long("push")
+                        .help("Update the files from the copies in the chapters"),
+                )
+                .about("Check the copies in `keep` blocks against their `src` files"),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("File with the diagram, `-` for the standard input"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .possible_values(&["wavejson", "json5", "json"])
+                        .default_value("wavejson")
+                        .help("Syntax of the diagram"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .possible_values(&["wavejson", "json5", "json"])
+                        .default_value("json")
+                        .help("Syntax to write the diagram in"),
+                )
+                .about("Convert a diagram to another syntax, writing it to the standard output"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .arg(
+                    Arg::with_name("dir")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .arg(
+                    Arg::with_name("offline")
+                        .long("offline")
+                        .help("Also check that the diagrams render without network access"),
+                )
+                .arg(
+                    Arg::with_name("render")
+                        .long("render")
+                        .help("Also render every diagram with the render command"),
+                )
+                .arg(
+                    Arg::with_name("golden-dir")
+                        .long("golden-dir")
+                        .value_name("DIR")
+                        .requires("render")
+                        .help("Compare the rendered diagrams against the SVG images in DIR"),
+                )
+                .arg(
+                    Arg::with_name("a11y")
+                        .long("a11y")
+                        .help("Also check that the diagrams and chapters are accessible"),
+                )
+                .about("Check the diagrams of the book"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .arg(
+                    Arg::with_name("old")
+                        .required(true)
+                        .help("Git revision to compare from, like `main`"),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .help("Git revision to compare to [default: the working tree]"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .arg(
+                    Arg::with_name("html")
+                        .long("html")
+                        .value_name("FILE")
+                        .help("Also write a page showing the changed diagrams side by side"),
+                )
+                .about("List the diagrams that changed between two git revisions of the book"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .arg(
+                    Arg::with_name("dir")
+                        .default_value(".")
+                        .help("Root directory for the book"),
+                )
+                .about("Check that the book's diagrams can be rendered the way it's configured"),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&Shell::variants())
+                        .help("Shell to complete the commands in"),
+                )
+                .about("Print the shell completions of the commands and their options"),
+        )
+}
+
+fn jobs_arg() -> Arg<'static, 'static> {
+    Arg::with_name("jobs")
+        .short("j")
+        .long("jobs")
+        .value_name("N")
+        .validator(|n| match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(()),
+            _ => Err("expected a number greater than 0".into()),
+        })
+        .help("Render at most N diagrams at the same time [default: number of CPUs]")
 }
 
 fn main() {
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
     let matches = make_app().get_matches();
+    // The configuration is read in several places, the environment reaches all of them.
+    if let Some(profile) = matches.value_of("profile") {
+        env::set_var(PROFILE_ENV, profile);
+    }
+    if matches.is_present("strict") {
+        env::set_var(STRICT_ENV, "1");
+    }
+    if matches.is_present("no-cache") {
+        env::set_var(NO_CACHE_ENV, "1");
+    }
 
     if let Some(sub_args) = matches.subcommand_matches("supports") {
         handle_supports(sub_args);
     } else if let Some(sub_args) = matches.subcommand_matches("install") {
         handle_install(sub_args);
-    } else if let Err(e) = handle_preprocessing() {
+    } else if let Some(sub_args) = matches.subcommand_matches("uninstall") {
+        handle_uninstall(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("export-images") {
+        handle_export_images(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("render") {
+        handle_render(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("grep") {
+        handle_grep(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("sync") {
+        handle_sync(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("convert") {
+        handle_convert(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("check") {
+        handle_check(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("diff") {
+        handle_diff(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("doctor") {
+        handle_doctor(sub_args);
+    } else if let Some(sub_args) = matches.subcommand_matches("completions") {
+        handle_completions(sub_args);
+    } else if let Err(e) = handle_preprocessing(&matches) {
         eprintln!("{}", e);
         process::exit(1);
     }
 }
 
-fn handle_preprocessing() -> Result<(), Error> {
-    let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
+fn handle_preprocessing(args: &ArgMatches) -> Result<(), Error> {
+    let (mut ctx, book, protocol) = compat::parse_input(io::stdin().lock())?;
+
+    // Options given on the command line win over the ones in `book.toml`.
+    if let Some(dir) = args.value_of("dump-dir") {
+        ctx.config.set(
+            format!("preprocessor.{}.dump-dir", Wavedrom::default().name()),
+            dir,
+        )?;
+    }
+    if let Some(jobs) = args.value_of("jobs") {
+        let jobs: i64 = jobs.parse().expect("Checked by clap");
+        ctx.config.set(
+            format!("preprocessor.{}.jobs", Wavedrom::default().name()),
+            jobs,
+        )?;
+    }
 
-    if ctx.mdbook_version != mdbook::MDBOOK_VERSION {
-        eprintln!(
-            "Warning: The mdbook-wavedrom preprocessor was built against version \
+    let config = WavedromConfig::from_context(&ctx, Wavedrom::default().name()).ok();
+    let allowed = config
+        .as_ref()
+        .is_some_and(|config| config.allows("version-mismatch", None));
+    if !allowed && !same_minor_version(&ctx.mdbook_version, mdbook::MDBOOK_VERSION) {
+        let message = format!(
+            "The mdbook-wavedrom preprocessor was built against version \
              {} of mdbook, but we're being called from version {}",
             mdbook::MDBOOK_VERSION,
             ctx.mdbook_version
         );
+        if config.is_some_and(|config| config.strict) {
+            return Err(Error::msg(
+                message + ", failing the build because of `strict`",
+            ));
+        }
+        eprintln!("Warning: {}", message);
     }
 
-    let processed_book = Wavedrom.run(&ctx, book)?;
-    serde_json::to_writer(io::stdout(), &processed_book)?;
+    let processed_book = Wavedrom::default().run(&ctx, book)?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    compat::write_output(&mut stdout, &processed_book, protocol)?;
+    stdout.flush()?;
 
     Ok(())
 }
 
+/// Patch releases of mdbook don't change the preprocessor protocol.
+fn same_minor_version(a: &str, b: &str) -> bool {
+    a.split('.').take(2).eq(b.split('.').take(2))
+}
+
 fn handle_supports(sub_args: &ArgMatches) -> ! {
-    let renderer = sub_args.value_of("renderer").expect("Required argument");
-    let supported = Wavedrom.supports_renderer(renderer);
+    if !sub_args.is_present("json") {
+        let renderers = sub_args.values_of("renderer").expect("Required argument");
+        // mdbook checks the book's own `renderers` before asking, so the answer is the built-in
+        // list. Signal whether the renderers are supported by exiting with 1 or 0.
+        let config = WavedromConfig::default();
+        let supported = renderers
+            .into_iter()
+            .all(|renderer| config.supports_renderer(renderer));
+        process::exit(if supported { 0 } else { 1 });
+    }
 
-    // Signal whether the renderer is supported by exiting with 1 or 0.
-    if supported {
-        process::exit(0);
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+    let path = dir.join("book.toml");
+    let book = if path.exists() {
+        match Config::from_disk(&path) {
+            Ok(book) => Some(book),
+            Err(e) => {
+                log::warn!("Ignoring configuration in {}: {}", path.display(), e);
+                None
+            }
+        }
     } else {
-        process::exit(1);
+        None
+    };
+    let config = match book
+        .as_ref()
+        .map(|book| WavedromConfig::from_config(book, Wavedrom::default().name()))
+    {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            log::warn!("Ignoring configuration in {}: {}", path.display(), e);
+            WavedromConfig::default()
+        }
+        None => WavedromConfig::default(),
+    };
+
+    let renderers: Vec<String> = match sub_args.values_of("renderer") {
+        Some(renderers) => renderers.map(String::from).collect(),
+        None => book_renderers(book.as_ref(), &config),
+    };
+    let renderers: Vec<_> = renderers
+        .iter()
+        .map(|renderer| supports::supports(&config, renderer))
+        .collect();
+    let json = serde_json::json!({ "renderers": renderers });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json).expect("Serializable")
+    );
+    process::exit(0);
+}
+
+/// The renderers the book builds with, its `[output]` tables or the html renderer, and the ones
+/// the preprocessor is configured for.
+fn book_renderers(book: Option<&Config>, config: &WavedromConfig) -> Vec<String> {
+    let mut renderers: Vec<String> = book
+        .and_then(|book| book.get("output"))
+        .and_then(|output| output.as_table())
+        .map(|output| output.keys().cloned().collect())
+        .unwrap_or_default();
+    if renderers.is_empty() {
+        renderers.push("html".into());
+    }
+    let configured = match &config.renderers {
+        Some(configured) => configured.clone(),
+        None => vec!["html".into(), "linkcheck".into()],
+    };
+    for renderer in configured {
+        if !renderers.contains(&renderer) {
+            renderers.push(renderer);
+        }
     }
+    renderers
 }
 
-fn handle_install(sub_args: &ArgMatches) -> ! {
+fn handle_export_images(sub_args: &ArgMatches) -> ! {
+    let dir = Path::new(sub_args.value_of("dir").expect("Required argument"));
+    let out = Path::new(sub_args.value_of("out").expect("Defaulted argument"));
+    let format: Format = sub_args
+        .value_of("format")
+        .expect("Defaulted argument")
+        .parse()
+        .expect("Checked by clap");
+
+    let jobs = sub_args
+        .value_of("jobs")
+        .map(|jobs| jobs.parse().expect("Checked by clap"));
+
+    let outcome = match export::export_images(dir, out, format, jobs) {
+        Ok(export) => {
+            log::info!("Wrote {} images to {}", export.written.len(), out.display());
+            Outcome::from_counts(0, export.failed)
+        }
+        Err(e) => {
+            log::error!(
+                "Unable to export the diagrams of {}: {:#}",
+                dir.display(),
+                e
+            );
+            Outcome::Internal
+        }
+    };
+    process::exit(outcome.exit_code());
+}
+
+fn handle_render(sub_args: &ArgMatches) -> ! {
+    let input = Path::new(sub_args.value_of("input").expect("Required argument"));
+    let output = sub_args.value_of("output").map(Path::new);
+    let format = match sub_args.value_of("format") {
+        Some(format) => format.parse().expect("Checked by clap"),
+        None if output.and_then(Path::extension) == Some("png".as_ref()) => Format::Png,
+        None => Format::Svg,
+    };
+    // The book the file belongs to, if any, for its configuration.
+    let root = input
+        .parent()
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        })
+        .and_then(find_book_root);
+
+    let image = match export::render_file(input, format, root.as_deref()) {
+        Ok(image) => image,
+        Err(e) => {
+            log::error!("Unable to render {}: {:#}", input.display(), e);
+            process::exit(Outcome::Errors.exit_code());
+        }
+    };
+    let written = match output {
+        Some(output) => fs::write(output, &image),
+        None => io::stdout().write_all(&image),
+    };
+    if let Err(e) = written {
+        log::error!("Unable to write the image: {}", e);
+        process::exit(Outcome::Internal.exit_code());
+    }
+    process::exit(Outcome::Clean.exit_code());
+}
+
+fn handle_grep(sub_args: &ArgMatches) -> ! {
+    let signal = sub_args.value_of("signal").expect("Required argument");
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+
+    match grep::grep(dir, signal, sub_args.is_present("regex")) {
+        Ok(matches) => {
+            for m in &matches {
+                println!(
+                    "{}: {}, diagram {}: {}",
+                    m.location,
+                    m.chapter,
+                    m.diagram,
+                    m.signals.join(", ")
+                );
+            }
+            // Like grep, exit with 1 when nothing matched.
+            process::exit(if matches.is_empty() { 1 } else { 0 });
+        }
+        Err(e) => {
+            log::error!(
+                "Unable to search the diagrams of {}: {:#}",
+                dir.display(),
+                e
+            );
+            process::exit(Outcome::Internal.exit_code());
+        }
+    }
+}
+
+fn handle_sync(sub_args: &ArgMatches) -> ! {
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+    let direction = if sub_args.is_present("pull") {
+        Direction::Pull
+    } else if sub_args.is_present("push") {
+        Direction::Push
+    } else {
+        Direction::Check
+    };
+
+    let outcome = match sync::sync(dir, direction) {
+        Ok(report) => {
+            for drift in &report.drifted {
+                println!("{}: differs from {}", drift.location, drift.file.display());
+            }
+            let unsynced = match direction {
+                Direction::Check => report.drifted.len(),
+                Direction::Pull | Direction::Push => 0,
+            };
+            Outcome::from_counts(unsynced, report.failed)
+        }
+        Err(e) => {
+            log::error!("Unable to sync the diagrams of {}: {:#}", dir.display(), e);
+            Outcome::Internal
+        }
+    };
+    process::exit(outcome.exit_code());
+}
+
+fn handle_convert(sub_args: &ArgMatches) -> ! {
+    let file = sub_args.value_of("file").expect("Required argument");
+    let syntax = |arg| -> Syntax {
+        sub_args
+            .value_of(arg)
+            .expect("Defaulted argument")
+            .parse()
+            .expect("Checked by clap")
+    };
+
+    let source = if file == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        fs::read_to_string(file)
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            log::error!("Unable to read {}: {}", file, e);
+            process::exit(Outcome::Internal.exit_code());
+        }
+    };
+
+    match convert::convert(&source, syntax("from"), syntax("to")) {
+        Ok(output) => {
+            print!("{}", output);
+            process::exit(0);
+        }
+        Err(e) => {
+            log::error!("Unable to convert {}: {:#}", file, e);
+            process::exit(Outcome::Errors.exit_code());
+        }
+    }
+}
+
+fn handle_check(sub_args: &ArgMatches) -> ! {
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+    let options = check::Options {
+        offline: sub_args.is_present("offline"),
+        render: sub_args.is_present("render"),
+        golden_dir: sub_args.value_of("golden-dir").map(PathBuf::from),
+        a11y: sub_args.is_present("a11y"),
+    };
+
+    let outcome = match check::check(dir, &options) {
+        Ok(report) => {
+            for problem in &report.problems {
+                let severity = match problem.severity {
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                };
+                println!("{}: {}: {}", problem.location, severity, problem.message);
+            }
+            report.outcome()
+        }
+        Err(e) => {
+            log::error!("Unable to check the book at {}: {:#}", dir.display(), e);
+            Outcome::Internal
+        }
+    };
+    process::exit(outcome.exit_code());
+}
+
+fn handle_diff(sub_args: &ArgMatches) -> ! {
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+    let old = sub_args.value_of("old").expect("Required argument");
+    let new = sub_args.value_of("new");
+
+    let entries = match diff::diff(dir, old, new) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!(
+                "Unable to compare the diagrams of {}: {:#}",
+                dir.display(),
+                e
+            );
+            process::exit(Outcome::Internal.exit_code());
+        }
+    };
+    for entry in &entries {
+        println!(
+            "{}: {}: {}, diagram {}",
+            entry.location,
+            entry.change.kind(),
+            entry.chapter,
+            entry.diagram
+        );
+        if let Change::Changed(changes) = &entry.change {
+            for change in changes {
+                println!("    {}", change);
+            }
+        }
+    }
+    if let Some(file) = sub_args.value_of("html") {
+        let page = diff::html(&entries, old, new.unwrap_or("the working tree"));
+        if let Err(e) = fs::write(file, page) {
+            log::error!("Unable to write {}: {}", file, e);
+            process::exit(Outcome::Internal.exit_code());
+        }
+    }
+    // Like diff, exit with 1 when something changed.
+    process::exit(if entries.is_empty() { 0 } else { 1 });
+}
+
+fn handle_doctor(sub_args: &ArgMatches) -> ! {
+    let dir = Path::new(sub_args.value_of("dir").expect("Defaulted argument"));
+
+    let outcome = match doctor::doctor(dir) {
+        Ok(report) => {
+            for finding in &report.findings {
+                let status = match finding.status {
+                    Status::Ok => "ok",
+                    Status::Warning => "warning",
+                    Status::Error => "error",
+                };
+                println!("{}: {}", status, finding.message);
+            }
+            report.outcome()
+        }
+        Err(e) => {
+            log::error!("Unable to read the book at {}: {:#}", dir.display(), e);
+            Outcome::Internal
+        }
+    };
+    process::exit(outcome.exit_code());
+}
+
+fn handle_completions(sub_args: &ArgMatches) -> ! {
+    let shell = sub_args
+        .value_of("shell")
+        .and_then(|shell| shell.parse::<Shell>().ok())
+        .expect("Validated argument");
+    make_app().gen_completions_to("mdbook-wavedrom", shell, &mut io::stdout());
+    process::exit(0);
+}
+
+/// The books `install` and `uninstall` change: the one at the `dir` argument, every book per
+/// language below it, or the one it's inside of.
+fn books(sub_args: &ArgMatches) -> Vec<PathBuf> {
     let dir = sub_args.value_of("dir").expect("Required argument");
     let proj_dir = PathBuf::from(dir);
+    let mut books = vec![];
+    if !proj_dir.join("book.toml").exists() {
+        books = language_books(&proj_dir);
+        if !books.is_empty() {
+            log::info!(
+                "Found a book per language in {}: {}",
+                proj_dir.display(),
+                books
+                    .iter()
+                    .filter_map(|book| book.file_name())
+                    .map(|name| name.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        } else if !sub_args.is_present("no-discover") {
+            if let Some(root) = find_book_root(&proj_dir) {
+                log::info!("Found book root at {}", root.display());
+                books.push(root);
+            }
+        }
+    }
+    if books.is_empty() {
+        books.push(proj_dir);
+    }
+    books
+}
+
+fn handle_install(sub_args: &ArgMatches) -> ! {
+    let books = books(sub_args);
+    let prompt = Prompt {
+        interactive: !sub_args.is_present("yes")
+            && io::stdin().is_terminal()
+            && io::stderr().is_terminal(),
+    };
+    let assets_dir = Path::new(sub_args.value_of("assets-dir").unwrap_or_default());
+    if assets_dir
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        log::error!(
+            "`--assets-dir` has to be a directory below the book's root, not {}",
+            assets_dir.display()
+        );
+        process::exit(1);
+    }
+    if sub_args.is_present("upgrade") {
+        let mut upgraded = 0;
+        for book in &books {
+            match upgrade(book, prompt) {
+                Ok(count) => upgraded += count,
+                Err(e) => install_failed(book, &e),
+            }
+        }
+        match upgraded {
+            0 => log::info!("The files of mdbook-wavedrom are up to date"),
+            1 => log::info!("Upgraded 1 file"),
+            _ => log::info!("Upgraded {} files", upgraded),
+        }
+        process::exit(0);
+    }
+    let skins: Vec<&str> = sub_args.values_of("skin").into_iter().flatten().collect();
+    let config = !sub_args.is_present("assets-only");
+    let steps = Steps {
+        config,
+        assets: !sub_args.is_present("config-only"),
+        example: sub_args.is_present("example"),
+        repin: sub_args.is_present("repin"),
+        assets_dir,
+        skins: &skins,
+        cdn: sub_args.is_present("cdn")
+            || (config
+                && prompt.confirm(
+                    "Load the WaveDrom runtime from cdnjs, rather than copying its scripts into the book?",
+                    false,
+                    false,
+                )),
+    };
+    for book in &books {
+        if let Err(e) = install(book, steps, prompt) {
+            install_failed(book, &e);
+        }
+    }
+
+    let installed = match (steps.config, steps.assets) {
+        (true, true) => "Files & configuration",
+        (true, false) => "Configuration",
+        _ => "Files",
+    };
+    log::info!(
+        "{} for mdbook-wavedrom are installed. You can start using it in your book.",
+        installed
+    );
+    let codeblock = r#"```wavedrom
+{signal: [
+  {name: 'clk', wave: 'p.....|...'},
+  {name: 'dat', wave: 'x.345x|=.x', data: ['head', 'body', 'tail', 'data']},
+  {name: 'req', wave: '0.1..0|1.0'},
+  {},
+  {name: 'ack', wave: '1.....|01.'}
+]}
+```"#;
+    log::info!("Add a code block like:\n{}", codeblock);
+    let register = r#"```register lanes=2
+[
+  {bits: 8, name: 'DATA', attr: 'RW'},
+  {bits: 7},
+  {bits: 1, name: 'EN'}
+]
+```"#;
+    log::info!("Or, for the fields of a register:\n{}", register);
+
+    process::exit(0);
+}
+
+/// Report that changing the book at `proj_dir` failed with `e`, and exit.
+fn install_failed(proj_dir: &Path, e: &Error) -> ! {
+    log::error!(
+        "Unable to change the book at {}: {:#}",
+        proj_dir.display(),
+        e
+    );
+    process::exit(1);
+}
+
+/// What `install` does, both unless `--config-only` or `--assets-only` is given.
+#[derive(Debug, Clone, Copy)]
+struct Steps<'a> {
+    /// Add the configuration to `book.toml`.
+    config: bool,
+    /// Copy the files next to it.
+    assets: bool,
+    /// Add the example chapter.
+    example: bool,
+    /// Pin the hashes of the files as they are, even those that changed since they were pinned.
+    repin: bool,
+    /// The directory below the book's root the files are copied to, the root itself if empty.
+    assets_dir: &'a Path,
+    /// Load the WaveDrom runtime from the CDN with `source = "cdn"`, rather than copying any
+    /// scripts.
+    cdn: bool,
+    /// Skins given with `--skin`, copied along with the ones the configuration picks.
+    skins: &'a [&'a str],
+}
+
+/// Whether `install` asks before changing the book, which it does when run in a terminal.
+#[derive(Debug, Clone, Copy)]
+struct Prompt {
+    interactive: bool,
+}
+
+impl Prompt {
+    /// Ask `question`, with the answer given by pressing enter, or `unattended` when not asking.
+    fn confirm(self, question: &str, default: bool, unattended: bool) -> bool {
+        if !self.interactive {
+            return unattended;
+        }
+        let choices = if default { "[Y/n]" } else { "[y/N]" };
+        loop {
+            eprint!("{} {} ", question, choices);
+            let _ = io::stderr().flush();
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return default;
+            }
+            match answer(&line) {
+                Some(answer) => return answer.unwrap_or(default),
+                None => eprintln!("Please answer yes or no."),
+            }
+        }
+    }
+}
+
+impl Steps<'_> {
+    /// The path of the file `name` relative to the book's root, as it's added to `book.toml`.
+    fn asset_path(self, name: &str) -> String {
+        normalize_path(&self.assets_dir.join(name).to_string_lossy())
+    }
+}
+
+/// The answer typed on a `line`: `Some(None)` for none, and `None` if it isn't yes or no.
+fn answer(line: &str) -> Option<Option<bool>> {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "" => Some(None),
+        "y" | "yes" => Some(Some(true)),
+        "n" | "no" => Some(Some(false)),
+        _ => None,
+    }
+}
+
+/// Add the configuration and copy the files into the book at `proj_dir`, as `steps` says.
+///
+/// Every book gets its own copy of the files, next to its `book.toml`, since that's where the
+/// `additional-*` paths are relative to.
+fn install(proj_dir: &Path, steps: Steps, prompt: Prompt) -> Result<(), Error> {
+    let files = asset_files(proj_dir, steps);
+    let configured = steps.config && install_config(proj_dir, steps, &files, prompt)?;
+    if steps.assets {
+        install_assets(proj_dir, steps, &files, prompt)?;
+    }
+    if configured {
+        pin_assets(proj_dir, steps, &files)?;
+    }
+    if steps.example {
+        install_example(proj_dir)?;
+    }
+    Ok(())
+}
+
+/// The content of the `book.toml` at `config`, and the document it parses into.
+fn read_config(config: &Path) -> Result<(String, Document), Error> {
+    let toml = fs::read_to_string(config)
+        .map_err(|e| Error::from(e).context(format!("Unable to read {}", config.display())))?;
+    let doc = toml
+        .parse::<Document>()
+        .map_err(|e| Error::from(e).context(format!("Unable to parse {}", config.display())))?;
+    Ok((toml, doc))
+}
+
+/// Write `contents` to the file at `path`.
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    fs::write(path, contents)
+        .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))
+}
+
+/// Overwrite the files of the book at `proj_dir` that differ from the ones of this version, where
+/// its `additional-*` lists load them from, and pin them anew. Returns the number of files
+/// overwritten.
+///
+/// Files that aren't there are left to `install`. A file that differs from its pin too was
+/// changed by hand, and is only overwritten once confirmed.
+fn upgrade(proj_dir: &Path, prompt: Prompt) -> Result<usize, Error> {
+    let config = proj_dir.join("book.toml");
+    let (toml, mut doc) = read_config(&config)?;
+    let mut entries: Vec<String> = vec![];
+    for kind in ["js", "css"] {
+        if let Some(files) = additional(&mut doc, kind) {
+            entries.extend(files.iter().filter_map(Value::as_str).map(normalize_path));
+        }
+    }
+    let mut hashes = doc
+        .get_mut("preprocessor")
+        .and_then(|table| table.get_mut("wavedrom"))
+        .and_then(|table| table.get_mut("asset-hashes"))
+        .and_then(Item::as_table_like_mut);
+
+    let mut upgraded = 0;
+    for (name, _, content) in all_files() {
+        let entry = entries
+            .iter()
+            .find(|entry| Path::new(entry.as_str()).file_name() == Some(name.as_ref()));
+        let path = match entry {
+            Some(entry) if !entry.contains("://") => entry,
+            _ => continue,
+        };
+        let filepath = proj_dir.join(path);
+        let found = match fs::read(&filepath) {
+            Ok(found) if found == content => continue,
+            Ok(found) => found,
+            Err(_) => {
+                log::warn!("'{}' is missing, run `mdbook-wavedrom install`", path);
+                continue;
+            }
+        };
+        let pinned = hashes
+            .as_ref()
+            .and_then(|hashes| hashes.get(path))
+            .and_then(Item::as_str);
+        let question = format!("{} was changed by hand, overwrite it?", filepath.display());
+        if pinned.is_some_and(|pin| pin != assets::integrity(&found))
+            && !prompt.confirm(&question, false, false)
+        {
+            log::warn!("Keeping '{}', which was changed by hand", path);
+            continue;
+        }
+        log::info!("Upgrading {}", filepath.display());
+        write_file(&filepath, content)?;
+        if let Some(hashes) = hashes.as_mut().filter(|hashes| hashes.contains_key(path)) {
+            hashes.insert(path, value(assets::integrity(content)));
+        }
+        upgraded += 1;
+    }
+
+    if doc.to_string() != toml {
+        log::info!("Saving the hashes of the files to {}", config.display());
+        write_file(&config, doc.to_string())?;
+    }
+    Ok(upgraded)
+}
+
+/// The files `install` copies into the book at `proj_dir`: all of `WAVEDROM_FILES`, and the
+/// skins its `skin` and `dark-skin` or `--skin` pick. Only the stylesheets when the runtime comes
+/// from the CDN, with `--cdn` or `source = "cdn"`.
+fn asset_files(proj_dir: &Path, steps: Steps) -> Vec<(&'static str, &'static str, &'static [u8])> {
+    let doc = fs::read_to_string(proj_dir.join("book.toml"))
+        .ok()
+        .and_then(|toml| toml.parse::<Document>().ok());
+    let preprocessor = doc
+        .as_ref()
+        .and_then(|doc| doc.get("preprocessor"))
+        .and_then(|table| table.get("wavedrom"));
+    let configured = ["skin", "dark-skin"]
+        .iter()
+        .filter_map(|key| preprocessor?.get(key)?.as_str());
+    let cdn = steps.cdn
+        || preprocessor
+            .and_then(|table| table.get("source"))
+            .and_then(Item::as_str)
+            == Some("cdn");
+    if cdn {
+        return WAVEDROM_FILES
+            .iter()
+            .copied()
+            .filter(|(_, additional_type, _)| *additional_type == "css")
+            .collect();
+    }
+
+    let mut files = WAVEDROM_FILES.to_vec();
+    for skin in configured.chain(steps.skins.iter().copied()) {
+        match WAVEDROM_SKINS.iter().find(|(name, _, _)| *name == skin) {
+            Some((_, file, content)) if !files.iter().any(|(added, _, _)| added == file) => {
+                files.push((file, "js", content));
+            }
+            Some(_) => {}
+            None if skin == "default" => {}
+            None => log::warn!(
+                "The skin '{}' isn't bundled with mdbook-wavedrom, add a script registering it to 'additional-js'",
+                skin
+            ),
+        }
+    }
+    files
+}
+
+/// Add the preprocessor and the `files` to the `book.toml` in `proj_dir`, once confirmed.
+///
+/// Returns whether the book is configured, false when saving the changes was declined.
+fn install_config(
+    proj_dir: &Path,
+    steps: Steps,
+    files: &[(&str, &str, &[u8])],
+    prompt: Prompt,
+) -> Result<bool, Error> {
     let config = proj_dir.join("book.toml");
 
     if !config.exists() {
-        log::error!("Configuration file '{}' missing", config.display());
-        process::exit(1);
+        return Err(Error::msg(format!(
+            "Configuration file '{}' missing",
+            config.display()
+        )));
     }
 
     log::info!("Reading configuration file {}", config.display());
-    let toml = fs::read_to_string(&config).expect("can't read configuration file");
-    let mut doc = toml
-        .parse::<Document>()
-        .expect("configuration is not valid TOML");
+    let (_, mut doc) = read_config(&config)?;
 
     let has_pre = has_preprocessor(&mut doc);
     if !has_pre {
         log::info!("Adding preprocessor configuration");
-        add_preprocessor(&mut doc);
+        add_preprocessor(&mut doc)?;
     }
+    let added_source = steps.cdn && set_cdn_source(&mut doc);
 
-    let added_files = add_additional_files(&mut doc);
+    let added_files = add_additional_files(&mut doc, steps, files)?;
+    let added_dirs = add_watch_dirs(&mut doc, proj_dir);
 
-    if !has_pre || added_files {
+    if !has_pre || added_source || added_files || added_dirs {
+        let question = format!("Save these changes to {}?", config.display());
+        if !prompt.confirm(&question, true, true) {
+            log::info!("Leaving {} unchanged", config.display());
+            return Ok(false);
+        }
         log::info!("Saving changed configuration to {}", config.display());
-        let toml = doc.to_string();
-        let mut file = File::create(config).expect("can't open configuration file for writing.");
-        file.write_all(toml.as_bytes())
-            .expect("can't write configuration");
+        write_file(&config, doc.to_string())?;
     }
+    Ok(true)
+}
 
+/// Copy the `files` the configuration refers to into the `--assets-dir` of `proj_dir`, keeping
+/// the ones there unless overwriting them is confirmed.
+fn install_assets(
+    proj_dir: &Path,
+    steps: Steps,
+    files: &[(&str, &str, &[u8])],
+    prompt: Prompt,
+) -> Result<(), Error> {
     let mut printed = false;
-    for (name, content) in WAVEDROM_FILES {
-        let filepath = proj_dir.join(name);
-        if filepath.exists() {
+    let dir = proj_dir.join(steps.assets_dir);
+    for (name, _, content) in files {
+        let filepath = dir.join(name);
+        let replace = |filepath: &Path| {
+            let changed = fs::read(filepath).map_or(true, |old| old != *content);
+            let question = format!(
+                "{} differs from the one of this version, overwrite it?",
+                filepath.display()
+            );
+            changed && prompt.confirm(&question, false, false)
+        };
+        if filepath.exists() && !replace(&filepath) {
             log::debug!(
                 "'{}' already exists (Path: {}). Skipping.",
                 name,
@@ -129,60 +1243,376 @@ fn handle_install(sub_args: &ArgMatches) -> ! {
                 printed = true;
                 log::info!(
                     "Writing additional files to project directory at {}",
-                    proj_dir.display()
+                    dir.display()
                 );
+                fs::create_dir_all(&dir).map_err(|e| {
+                    Error::from(e).context(format!("Unable to create {}", dir.display()))
+                })?;
             }
             log::debug!("Writing content for '{}' into {}", name, filepath.display());
-            let mut file = File::create(filepath).expect("can't open file for writing");
-            file.write_all(content)
-                .expect("can't write content to file");
+            write_file(&filepath, content)?;
         }
     }
+    Ok(())
+}
 
-    log::info!("Files & configuration for mdbook-wavedrom are installed. You can start using it in your book.");
-    let codeblock = r#"```wavedrom
-{signal: [
-  {name: 'clk', wave: 'p.....|...'},
-  {name: 'dat', wave: 'x.345x|=.x', data: ['head', 'body', 'tail', 'data']},
-  {name: 'req', wave: '0.1..0|1.0'},
-  {},
-  {name: 'ack', wave: '1.....|01.'}
-]}
-```"#;
-    log::info!("Add a code block like:\n{}", codeblock);
+/// Pin the hashes of the files in the `asset-hashes` of the `book.toml` in `proj_dir`, for every
+/// build to check them.
+///
+/// A file that isn't there yet is pinned as it will be copied. A file that changed since it was
+/// pinned keeps its pin unless it's the one of this version or `--repin` is given, which would
+/// otherwise vouch for whatever replaced it.
+fn pin_assets(proj_dir: &Path, steps: Steps, files: &[(&str, &str, &[u8])]) -> Result<(), Error> {
+    let config = proj_dir.join("book.toml");
+    let (_, mut doc) = read_config(&config)?;
+    // The preprocessor wasn't added, without it nothing checks the pins.
+    let preprocessor = match doc
+        .get_mut("preprocessor")
+        .and_then(|table| table.get_mut("wavedrom"))
+        .and_then(Item::as_table_mut)
+    {
+        Some(preprocessor) => preprocessor,
+        None => return Ok(()),
+    };
+    let hashes = preprocessor
+        .entry("asset-hashes")
+        .or_insert_with(|| Item::Table(Table::new()));
+    let hashes = match hashes.as_table_like_mut() {
+        Some(hashes) => hashes,
+        None => {
+            log::warn!("'asset-hashes' isn't a table, not pinning the files");
+            return Ok(());
+        }
+    };
 
+    let mut changed = false;
+    for (name, _, content) in files {
+        let path = steps.asset_path(name);
+        let found = fs::read(proj_dir.join(&path)).ok();
+        let hash = assets::integrity(found.as_deref().unwrap_or(content));
+        let pinned = hashes.get(&path).and_then(Item::as_str);
+        match pinned {
+            Some(pinned) if pinned == hash => continue,
+            Some(_) if !steps.repin && found.as_deref() != Some(content) => {
+                log::warn!(
+                    "'{}' changed since it was pinned, keeping its pin. Run `mdbook-wavedrom install --repin` if it was changed on purpose",
+                    path
+                );
+                continue;
+            }
+            _ => {}
+        }
+        log::debug!("Pinning '{}' as {}", path, hash);
+        hashes.insert(&path, value(hash));
+        changed = true;
+    }
+
+    if changed {
+        log::info!("Saving the hashes of the files to {}", config.display());
+        write_file(&config, doc.to_string())?;
+    }
+    Ok(())
+}
+
+/// Write the example chapter into the `src` directory of the book at `proj_dir` and append it
+/// to its `SUMMARY.md`, unless the chapter is there already.
+fn install_example(proj_dir: &Path) -> Result<(), Error> {
+    let src = fs::read_to_string(proj_dir.join("book.toml"))
+        .ok()
+        .and_then(|toml| toml.parse::<Document>().ok())
+        .and_then(|doc| {
+            let src = doc.get("book")?.get("src")?;
+            src.as_str().map(PathBuf::from)
+        })
+        .unwrap_or_else(|| PathBuf::from("src"));
+    let src_dir = proj_dir.join(src);
+    let chapter = src_dir.join(EXAMPLE_FILE);
+    if chapter.exists() {
+        log::debug!(
+            "'{}' already exists (Path: {}). Skipping.",
+            EXAMPLE_FILE,
+            chapter.display()
+        );
+        return Ok(());
+    }
+    let summary_path = src_dir.join("SUMMARY.md");
+    let mut summary = fs::read_to_string(&summary_path).map_err(|e| {
+        Error::from(e).context(format!("Unable to read {}", summary_path.display()))
+    })?;
+
+    log::info!("Writing an example chapter to {}", chapter.display());
+    write_file(&chapter, EXAMPLE_CHAPTER)?;
+    if !summary.contains(&format!("({})", EXAMPLE_FILE)) {
+        if !summary.is_empty() && !summary.ends_with('\n') {
+            summary.push('\n');
+        }
+        summary.push_str(&format!("- [Timing diagrams]({})\n", EXAMPLE_FILE));
+        write_file(&summary_path, summary)?;
+    }
+    Ok(())
+}
+
+fn handle_uninstall(sub_args: &ArgMatches) -> ! {
+    let prompt = Prompt {
+        interactive: !sub_args.is_present("yes")
+            && io::stdin().is_terminal()
+            && io::stderr().is_terminal(),
+    };
+    for book in books(sub_args) {
+        if let Err(e) = uninstall(&book, !sub_args.is_present("keep-files"), prompt) {
+            install_failed(&book, &e);
+        }
+    }
+    log::info!("mdbook-wavedrom is uninstalled.");
     process::exit(0);
 }
 
-fn add_additional_files(doc: &mut Document) -> bool {
+/// Remove the preprocessor and the files from the `book.toml` in `proj_dir`, and delete the files
+/// from where `additional-js` loads them if `delete_files`.
+///
+/// A file that differs from both the one of this version and its pin was changed by hand, and is
+/// only deleted once confirmed.
+fn uninstall(proj_dir: &Path, delete_files: bool, prompt: Prompt) -> Result<(), Error> {
+    let config = proj_dir.join("book.toml");
+    if !config.exists() {
+        return Err(Error::msg(format!(
+            "Configuration file '{}' missing",
+            config.display()
+        )));
+    }
+
+    log::info!("Reading configuration file {}", config.display());
+    let (_, mut doc) = read_config(&config)?;
+
+    let additional_js: Vec<PathBuf> = additional(&mut doc, "js")
+        .map(|files| {
+            files
+                .iter()
+                .filter_map(Value::as_str)
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let dir = PathBuf::from(assets::dir(&additional_js));
+    let pins: Vec<(String, String)> = doc
+        .get("preprocessor")
+        .and_then(|table| table.get("wavedrom"))
+        .and_then(|table| table.get("asset-hashes"))
+        .and_then(Item::as_table_like)
+        .map(|hashes| {
+            hashes
+                .iter()
+                .filter_map(|(file, hash)| Some((file.to_string(), hash.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let removed_pre = remove_preprocessor(&mut doc);
+    let removed_files = remove_additional_files(&mut doc, &dir);
+    if removed_pre || removed_files {
+        let question = format!("Save these changes to {}?", config.display());
+        if !prompt.confirm(&question, true, true) {
+            log::info!("Leaving {} unchanged", config.display());
+            return Ok(());
+        }
+        log::info!("Saving changed configuration to {}", config.display());
+        write_file(&config, doc.to_string())?;
+    } else {
+        log::info!("mdbook-wavedrom isn't configured in {}", config.display());
+    }
+    if !delete_files {
+        return Ok(());
+    }
+
+    for (name, _, content) in all_files() {
+        let path = normalize_path(&dir.join(name).to_string_lossy());
+        let filepath = proj_dir.join(&path);
+        let found = match fs::read(&filepath) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        let hash = assets::integrity(&found);
+        let pinned = pins.iter().any(|(file, pin)| *file == path && *pin == hash);
+        let question = format!("{} was changed, delete it anyway?", filepath.display());
+        if found != *content && !pinned && !prompt.confirm(&question, false, false) {
+            log::info!("Keeping '{}', which was changed", filepath.display());
+            continue;
+        }
+        log::debug!("Deleting '{}' (Path: {})", name, filepath.display());
+        if let Err(e) = fs::remove_file(&filepath) {
+            log::warn!("Unable to delete {}: {}", filepath.display(), e);
+        }
+    }
+    // Leave the `--assets-dir` if something else is in there.
+    if dir.components().next().is_some() {
+        let _ = fs::remove_dir(proj_dir.join(&dir));
+    }
+    Ok(())
+}
+
+/// The books of a translated book kept as one book per language, e.g. `en/book.toml` and
+/// `de/book.toml` below `dir`, sorted by their directory.
+fn language_books(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut books: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("book.toml").is_file())
+        .collect();
+    books.sort();
+    books
+}
+
+/// Walk up from `dir` to the closest directory containing a `book.toml`.
+fn find_book_root(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    dir.ancestors()
+        .find(|dir| dir.join("book.toml").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// The WaveDrom runtime on the CDN, as `install --cdn` added it to `additional-js` before it set
+/// `source = "cdn"` instead.
+fn cdn_script() -> String {
+    format!("{}wavedrom.min.js", WAVEDROM_CDN)
+}
+
+/// Add the `files` to the `additional-*` lists, at their paths in the `--assets-dir`.
+fn add_additional_files(
+    doc: &mut Document,
+    steps: Steps,
+    files: &[(&str, &str, &[u8])],
+) -> Result<bool, Error> {
     let mut changed = false;
     let mut printed = false;
 
-    let file = "wavedrom.min.js";
-    let additional_js = additional(doc, "js");
-    if has_file(&additional_js, file) {
-        log::debug!("'{}' already in 'additional-js'. Skipping", file)
-    } else {
-        printed = true;
-        log::info!("Adding additional files to configuration");
-        log::debug!("Adding '{}' to 'additional-js'", file);
-        insert_additional(doc, "js", file);
-        changed = true;
+    for (file, additional_type, _) in files {
+        let mut additional_files = additional(doc, additional_type);
+        // The runtime an older `install --cdn` added will do.
+        if *file == "wavedrom.min.js" && has_file(&additional_files, &cdn_script()) {
+            log::debug!("'{}' already in 'additional-js'. Skipping", cdn_script());
+            continue;
+        }
+        let path = steps.asset_path(file);
+        let file = path.as_str();
+        if has_file(&additional_files, file) {
+            log::debug!(
+                "'{}' already in 'additional-{}'. Skipping",
+                file,
+                additional_type
+            );
+            if let Some(ref mut additional_files) = additional_files {
+                changed |= remove_duplicates(additional_files, additional_type, file);
+            }
+        } else {
+            if !printed {
+                printed = true;
+                log::info!("Adding additional files to configuration");
+            }
+            log::debug!("Adding '{}' to 'additional-{}'", file, additional_type);
+            insert_additional(doc, additional_type, file)?;
+            changed = true;
+        }
     }
 
-    let file = "wavedrome-default.js";
-    let additional_js = additional(doc, "js");
-    if has_file(&additional_js, file) {
-        log::debug!("'{}' already in 'additional-js'. Skipping", file)
-    } else {
-        if !printed {
-            log::info!("Adding additional files to configuration");
+    Ok(changed)
+}
+
+/// Add the directories outside `src` the diagrams read files from to `build.extra-watch-dirs`,
+/// for `mdbook serve` to rebuild when they change.
+fn add_watch_dirs(doc: &mut Document, proj_dir: &Path) -> bool {
+    let dirs = match watch::watch_dirs(proj_dir) {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            log::debug!("Not looking for the files of the diagrams: {:#}", e);
+            return false;
+        }
+    };
+    let mut changed = false;
+    for dir in dirs {
+        let dir = dir.to_string_lossy().replace('\\', "/");
+        let build = doc
+            .as_table_mut()
+            .entry("build")
+            .or_insert(Item::Table(Table::default()));
+        let build = match build.as_table_mut() {
+            Some(build) => build,
+            None => {
+                log::warn!("`build` isn't a table, not adding '{}'", dir);
+                return changed;
+            }
+        };
+        let watched = build
+            .entry("extra-watch-dirs")
+            .or_insert(value(Array::default()));
+        let watched = match watched.as_array_mut() {
+            Some(watched) => watched,
+            None => {
+                log::warn!(
+                    "`build.extra-watch-dirs` isn't a list, not adding '{}'",
+                    dir
+                );
+                return changed;
+            }
+        };
+        if watched.iter().any(|elem| is_file(elem, &dir)) {
+            log::debug!("'{}' already in 'build.extra-watch-dirs'. Skipping", dir);
+            continue;
         }
-        log::debug!("Adding '{}' to 'additional-js'", file);
-        insert_additional(doc, "js", file);
+        log::info!("Adding '{}' to 'build.extra-watch-dirs'", dir);
+        watched.push(dir.as_str());
         changed = true;
     }
+    changed
+}
+
+/// Every file `install` may have copied, the bundled skins included.
+fn all_files() -> impl Iterator<Item = (&'static str, &'static str, &'static [u8])> {
+    let skins = WAVEDROM_SKINS
+        .iter()
+        .map(|(_, file, content)| (*file, "js", *content));
+    WAVEDROM_FILES.iter().copied().chain(skins)
+}
 
+/// Remove the files below `dir` from the `additional-*` lists, along with the runtime loaded
+/// from the CDN. Lists left empty are removed.
+fn remove_additional_files(doc: &mut Document, dir: &Path) -> bool {
+    let mut changed = false;
+    for (file, additional_type, _) in all_files() {
+        let path = dir.join(file).to_string_lossy().into_owned();
+        let runtime = file == "wavedrom.min.js";
+        let cdn = cdn_script();
+        let files = match additional(doc, additional_type) {
+            Some(files) => files,
+            None => continue,
+        };
+        let installed: Vec<_> = files
+            .iter()
+            .enumerate()
+            .filter(|(_, elem)| {
+                is_file(elem, &path) || (runtime && elem.as_str() == Some(cdn.as_str()))
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        for idx in installed.iter().rev() {
+            log::debug!("Removing '{}' from 'additional-{}'", file, additional_type);
+            files.remove(*idx);
+        }
+        changed |= !installed.is_empty();
+
+        if files.is_empty() {
+            if let Some(html) = doc
+                .get_mut("output")
+                .and_then(|output| output.get_mut("html"))
+                .and_then(Item::as_table_like_mut)
+            {
+                html.remove(&format!("additional-{}", additional_type));
+            }
+        }
+    }
     changed
 }
 
@@ -204,7 +1634,26 @@ fn has_preprocessor(doc: &mut Document) -> bool {
         .unwrap_or(false)
 }
 
-fn add_preprocessor(doc: &mut Document) {
+/// Remove the `[preprocessor.wavedrom]` table, and the `[preprocessor]` table if it's left empty.
+fn remove_preprocessor(doc: &mut Document) -> bool {
+    let preprocessors = match doc
+        .get_mut("preprocessor")
+        .and_then(Item::as_table_like_mut)
+    {
+        Some(preprocessors) => preprocessors,
+        None => return false,
+    };
+    let removed = preprocessors.remove("wavedrom").is_some();
+    if removed {
+        log::info!("Removing preprocessor configuration");
+    }
+    if preprocessors.is_empty() {
+        doc.as_table_mut().remove("preprocessor");
+    }
+    removed
+}
+
+fn add_preprocessor(doc: &mut Document) -> Result<(), Error> {
     let doc = doc.as_table_mut();
 
     let empty_table = Item::Table(Table::default());
@@ -212,23 +1661,69 @@ fn add_preprocessor(doc: &mut Document) {
     let item = doc.entry("preprocessor").or_insert(empty_table.clone());
     let item = item
         .as_table_mut()
-        .unwrap()
+        .ok_or_else(|| Error::msg("`preprocessor` isn't a table"))?
         .entry("wavedrom")
         .or_insert(empty_table);
-    item["command"] = value("mdbook-wavedrom");
+    item.as_table_like_mut()
+        .ok_or_else(|| Error::msg("`preprocessor.wavedrom` isn't a table"))?
+        .insert("command", value("mdbook-wavedrom"));
+    Ok(())
+}
+
+/// Set `source = "cdn"` in the `[preprocessor.wavedrom]` table, returning whether it changed.
+fn set_cdn_source(doc: &mut Document) -> bool {
+    let preprocessor = &mut doc["preprocessor"]["wavedrom"];
+    if preprocessor.get("source").and_then(Item::as_str) == Some("cdn") {
+        return false;
+    }
+    log::info!("Loading the WaveDrom runtime from the CDN");
+    preprocessor["source"] = value("cdn");
+    true
+}
+
+/// Normalize a path for comparison, so that `./theme//x.js` and `theme\\x.js` are equal.
+fn normalize_path(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_file(elem: &Value, file: &str) -> bool {
+    elem.as_str()
+        .map(|s| normalize_path(s) == normalize_path(file))
+        .unwrap_or(false)
 }
 
 fn has_file(elem: &Option<&mut Array>, file: &str) -> bool {
     match elem {
-        Some(elem) => elem.iter().any(|elem| match elem.as_str() {
-            None => true,
-            Some(s) => s.ends_with(file),
-        }),
+        Some(elem) => elem.iter().any(|elem| is_file(elem, file)),
         None => false,
     }
 }
 
-fn insert_additional(doc: &mut Document, additional_type: &str, file: &str) {
+/// Keep only the first entry for `file`, leaving all other entries in their order.
+fn remove_duplicates(elem: &mut Array, additional_type: &str, file: &str) -> bool {
+    let duplicates: Vec<_> = elem
+        .iter()
+        .enumerate()
+        .filter(|(_, elem)| is_file(elem, file))
+        .map(|(idx, _)| idx)
+        .skip(1)
+        .collect();
+
+    for idx in duplicates.iter().rev() {
+        log::debug!(
+            "Removing duplicate '{}' from 'additional-{}'",
+            file,
+            additional_type
+        );
+        elem.remove(*idx);
+    }
+    !duplicates.is_empty()
+}
+
+fn insert_additional(doc: &mut Document, additional_type: &str, file: &str) -> Result<(), Error> {
     let doc = doc.as_table_mut();
 
     let empty_table = Item::Table(Table::default());
@@ -236,18 +1731,18 @@ fn insert_additional(doc: &mut Document, additional_type: &str, file: &str) {
     let item = doc.entry("output").or_insert(empty_table.clone());
     let item = item
         .as_table_mut()
-        .unwrap()
+        .ok_or_else(|| Error::msg("`output` isn't a table"))?
         .entry("html")
         .or_insert(empty_table);
+    let key = format!("additional-{}", additional_type);
     let array = item
         .as_table_mut()
-        .unwrap()
-        .entry(&format!("additional-{}", additional_type))
+        .ok_or_else(|| Error::msg("`output.html` isn't a table"))?
+        .entry(&key)
         .or_insert(empty_array);
-    let _ = array
-        .as_value_mut()
-        .unwrap()
+    array
         .as_array_mut()
-        .unwrap()
+        .ok_or_else(|| Error::msg(format!("`output.html.{}` isn't a list", key)))?
         .push(file);
+    Ok(())
 }
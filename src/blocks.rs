@@ -0,0 +1,278 @@
+//! Finding the wavedrom code blocks of a chapter.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{CodeBlockKind::*, Event, Parser, Tag};
+
+use crate::attrs::{self, Attributes};
+
+/// A wavedrom code block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Block {
+    /// Byte range of the whole block in the chapter, fences included.
+    pub(crate) span: Range<usize>,
+    /// Attributes from the info string.
+    pub(crate) attrs: Attributes,
+    /// The diagram's source, without the indentation or markers of the containers it is in.
+    pub(crate) source: String,
+    /// Byte range of the source in the chapter, starting after the container markers of its
+    /// first line. Empty sources are at the start of the closing fence.
+    pub(crate) content: Range<usize>,
+    /// The key of the list the source can be alone, `reg` for the fields of a `register` block
+    /// and `assign` for the expressions of a `wavedrom-assign` one.
+    pub(crate) list: Option<&'static str>,
+}
+
+impl Block {
+    /// Whether the block is drawn as a diagram, rather than shown as its source with
+    /// `render=false`.
+    pub(crate) fn is_rendered(&self) -> bool {
+        self.attrs.get("render") != Some("false")
+    }
+}
+
+/// The languages of wavedrom code blocks, unless the configuration names others.
+pub(crate) const LANGUAGES: &[&str] = &["wavedrom", INCLUDE, REGISTER, ASSIGN];
+
+/// The language of blocks including their diagram from a file, as in
+/// ```` ```wavedrom-include waves/spi.json5 ````, which is short for `wavedrom src=waves/spi.json5`.
+pub(crate) const INCLUDE: &str = "wavedrom-include";
+
+/// The language of register diagrams, as in ```` ```register ```` followed by the `reg` list of
+/// fields, which is short for a `wavedrom` block with `{reg: [...]}`.
+pub(crate) const REGISTER: &str = "register";
+
+/// The language of logic diagrams, as in ```` ```wavedrom-assign ```` followed by the `assign`
+/// list of expressions, which is short for a `wavedrom` block with `{assign: [...]}`.
+pub(crate) const ASSIGN: &str = "wavedrom-assign";
+
+/// The HTML comment after which blocks aren't diagrams, e.g. to show their markdown as an example.
+pub(crate) const OFF: &str = "<!-- wavedrom:off -->";
+
+/// The HTML comment after which blocks are diagrams again.
+pub(crate) const ON: &str = "<!-- wavedrom:on -->";
+
+/// All wavedrom code blocks in `content` fenced with one of the `languages`, in order.
+pub(crate) fn find(content: &str, languages: &[&str]) -> Vec<Block> {
+    find_fenced(content, languages)
+        .into_iter()
+        .map(diagram)
+        .collect()
+}
+
+/// All wavedrom code blocks of `content` fenced with one of the `languages`, by the `events` it
+/// parses into.
+pub(crate) fn find_in(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    languages: &[&str],
+) -> Vec<Block> {
+    find_fenced_in(content, events, languages)
+        .into_iter()
+        .map(diagram)
+        .collect()
+}
+
+/// The block of a diagram fenced with `language`, with the path of an included file as its `src`.
+pub(crate) fn diagram((language, mut block): (String, Block)) -> Block {
+    if language == INCLUDE {
+        block.attrs.name_first("src");
+    }
+    block.list = match language.as_str() {
+        REGISTER => Some("reg"),
+        ASSIGN => Some("assign"),
+        _ => None,
+    };
+    block
+}
+
+/// All code blocks in `content` fenced with one of the `languages`, in order, with their
+/// language. Blocks after an [`OFF`] comment are left out, up to the next [`ON`] one.
+pub(crate) fn find_fenced(content: &str, languages: &[&str]) -> Vec<(String, Block)> {
+    let events: Vec<_> = Parser::new_ext(content, crate::parser_options())
+        .into_offset_iter()
+        .collect();
+    find_fenced_in(content, &events, languages)
+}
+
+fn find_fenced_in(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    languages: &[&str],
+) -> Vec<(String, Block)> {
+    let mut blocks = vec![];
+    let mut current: Option<(String, Block)> = None;
+    let mut off = false;
+
+    for (e, span) in events {
+        match e {
+            Event::Html(html) if html.trim() == OFF => off = true,
+            Event::Html(html) if html.trim() == ON => off = false,
+            Event::Start(Tag::CodeBlock(Fenced(code))) if !off => {
+                log::debug!("e={:?}, span={:?}", e, span);
+                let (language, attrs) = attrs::parse(code);
+                if languages.contains(&language) {
+                    let block = Block {
+                        span: span.clone(),
+                        attrs,
+                        source: String::new(),
+                        content: 0..0,
+                        list: None,
+                    };
+                    current = Some((language.to_string(), block));
+                }
+            }
+            // The parser already removed the indentation and blockquote markers of nested blocks.
+            Event::Text(text) => {
+                if let Some((_, block)) = &mut current {
+                    if block.source.is_empty() {
+                        block.content.start = span.start;
+                    }
+                    block.source.push_str(text);
+                    block.content.end = span.end;
+                }
+            }
+            // Code blocks don't nest, so this can only be the end of the diagram's block.
+            // Unterminated fences end with the document, or with the container they are in.
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((language, mut block)) = current.take() {
+                    block.span.end = span.end;
+                    if block.source.is_empty() {
+                        let start = empty_content(content, &block.span);
+                        block.content = start..start;
+                    }
+                    blocks.push((language, block));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// Where the source of an empty block in `span` would go: the start of its closing fence.
+fn empty_content(content: &str, span: &Range<usize>) -> usize {
+    let after_opening = match content[span.clone()].find('\n') {
+        Some(i) => span.start + i + 1,
+        None => return span.end,
+    };
+    let closing = &content[after_opening.min(span.end)..span.end];
+    after_opening + closing.len() - closing.trim_start_matches([' ', '\t', '>']).len()
+}
+
+/// File name for diagram number `index` (starting at 1) of the chapter at `chapter_path`,
+/// next to it: `a/intro.md` gives `a/intro-1.<extension>`.
+pub(crate) fn file_name(chapter_path: &Path, index: usize, extension: &str) -> PathBuf {
+    chapter_path.with_file_name(format!(
+        "{}-{}.{}",
+        chapter_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        index,
+        extension
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{file_name, find, LANGUAGES};
+
+    #[test]
+    fn finds_wavedrom_blocks_only() {
+        let content =
+            "```rust\nfn main() {}\n```\n\n> ```wavedrom colors=\"a:red\"\n> {signal: []}\n> ```\n";
+        let blocks = find(content, LANGUAGES);
+
+        assert_eq!(1, blocks.len());
+        assert_eq!(28..content.len() - 1, blocks[0].span);
+        assert_eq!("{signal: []}\n", blocks[0].source);
+        assert_eq!(Some("a:red"), blocks[0].attrs.get("colors"));
+        assert_eq!("{signal: []}\n", &content[blocks[0].content.clone()]);
+    }
+
+    #[test]
+    fn includes_are_blocks_with_a_src() {
+        let content = "```wavedrom-include ../waves/spi.json5 caption=SPI\n```\n";
+        let blocks = find(content, LANGUAGES);
+
+        assert_eq!(1, blocks.len());
+        assert_eq!(Some("../waves/spi.json5"), blocks[0].attrs.get("src"));
+        assert_eq!(Some("SPI"), blocks[0].attrs.get("caption"));
+        assert_eq!("", blocks[0].source);
+    }
+
+    #[test]
+    fn lists_are_marked() {
+        let content = "```register lanes=2\n[{bits: 8}]\n```\n\n```wavedrom\n{reg: []}\n```\n\n\
+                       ```wavedrom-assign\n[['out', ['~', 'a']]]\n```\n";
+        let blocks = find(content, LANGUAGES);
+
+        assert_eq!(
+            vec![Some("reg"), None, Some("assign")],
+            blocks.iter().map(|block| block.list).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("2"), blocks[0].attrs.get("lanes"));
+    }
+
+    #[test]
+    fn content_of_nested_blocks() {
+        let content = "- ```wavedrom\n  {signal: [\n  ]}\n  ```\n\n> ```wavedrom\n> ```\n";
+        let blocks = find(content, LANGUAGES);
+
+        assert_eq!("{signal: [\n  ]}\n", &content[blocks[0].content.clone()]);
+        assert_eq!("{signal: [\n]}\n", blocks[0].source);
+        let closing = content.rfind("```").unwrap();
+        assert_eq!(closing..closing, blocks[1].content);
+    }
+
+    #[test]
+    fn unterminated_blocks() {
+        let content = "```wavedrom\n{signal: []}\n";
+        let blocks = find(content, LANGUAGES);
+        assert_eq!(0..content.len(), blocks[0].span);
+        assert_eq!("{signal: []}\n", blocks[0].source);
+
+        // Ended by their container.
+        let content = "> ```wavedrom\n> {signal: []}\n\nText\n\n```wavedrom\n";
+        let blocks = find(content, LANGUAGES);
+        assert_eq!(2, blocks.len());
+        assert_eq!("{signal: []}\n", blocks[0].source);
+        assert_eq!("", blocks[1].source);
+        assert_eq!(content.len()..content.len(), blocks[1].content);
+    }
+
+    #[test]
+    fn turned_off_blocks() {
+        let content = "```wavedrom\n{signal: []}\n```\n\n<!-- wavedrom:off -->\n\n\
+                       ```wavedrom\n{signal: ['example']}\n```\n\n\
+                       Text <!-- wavedrom:on --> continues\n\n\
+                       ```wavedrom\n{reg: []}\n```\n\n\
+                       ```text\n<!-- wavedrom:off -->\n```\n\n\
+                       ```wavedrom\n{assign: []}\n```\n";
+        let sources: Vec<_> = find(content, LANGUAGES)
+            .into_iter()
+            .map(|block| block.source)
+            .collect();
+        assert_eq!(
+            vec!["{signal: []}\n", "{reg: []}\n", "{assign: []}\n"],
+            sources
+        );
+    }
+
+    #[test]
+    fn file_names_next_to_chapter() {
+        assert_eq!(
+            Path::new("a/b/deep-3.svg"),
+            file_name(Path::new("a/b/deep.md"), 3, "svg")
+        );
+        assert_eq!(
+            Path::new("intro-1.html"),
+            file_name(Path::new("intro.md"), 1, "html")
+        );
+    }
+}
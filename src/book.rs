@@ -0,0 +1,107 @@
+//! Reading the diagrams of a book outside of an mdbook build, for the subcommands.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::{Book, BookItem, Chapter};
+use mdbook::config::HtmlConfig;
+use mdbook::errors::{Error, Result};
+use mdbook::preprocess::Preprocessor;
+use mdbook::{Config, MDBook};
+
+use crate::blocks::{self, Block};
+use crate::{translate, ChapterContext, Wavedrom, WavedromConfig};
+
+/// A book loaded from disk, with the preprocessor's configuration.
+pub(crate) struct Source {
+    pub(crate) book: Book,
+    pub(crate) config: WavedromConfig,
+    pub(crate) src_dir: PathBuf,
+    /// The `[output.html]` table, or its defaults.
+    pub(crate) html: HtmlConfig,
+}
+
+/// Load the book at `root`, without touching its sources.
+pub(crate) fn load(root: &Path) -> Result<Source> {
+    let mut book_config = Config::from_disk(root.join("book.toml"))?;
+    // Reading shouldn't change the sources, not even to add missing chapters.
+    book_config.build.create_missing = false;
+    let src_dir = root.join(&book_config.book.src);
+    let config =
+        WavedromConfig::from_config(&book_config, Wavedrom::default().name())?.relative_to(root);
+    let mut mdbook = MDBook::load_with_config(root, book_config)?;
+    translate::book(&mut mdbook.book, &config, root);
+
+    Ok(Source {
+        html: mdbook.config.html_config().unwrap_or_default(),
+        book: mdbook.book,
+        config,
+        src_dir,
+    })
+}
+
+/// A diagram of a chapter, with its includes resolved.
+pub(crate) struct Diagram<'a> {
+    pub(crate) chapter: &'a Chapter,
+    pub(crate) page: &'a ChapterContext<'a>,
+    /// The chapter's markdown file relative to the book's `src` directory.
+    pub(crate) path: &'a Path,
+    /// Number of the diagram in the chapter, starting at 1.
+    pub(crate) index: usize,
+    pub(crate) block: Block,
+    /// Where the diagram starts, e.g. `chapter/intro.md:12`.
+    pub(crate) location: String,
+    pub(crate) source: String,
+    /// Problems reading the source, like missing includes.
+    pub(crate) warnings: Vec<String>,
+}
+
+/// Call `f` for every diagram of the chapters of `book`, in book order.
+///
+/// Draft chapters have no file to report, and are skipped.
+pub(crate) fn for_each_diagram<F>(book: &Book, config: &WavedromConfig, src_dir: &Path, mut f: F)
+where
+    F: FnMut(&Diagram),
+{
+    for item in book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) if chapter.path.is_some() => chapter,
+            _ => continue,
+        };
+        let page = ChapterContext::new(chapter, config, Some(src_dir));
+        let path = match page.source_path {
+            Some(path) if !config.excludes(Some(path)) => path,
+            _ => continue,
+        };
+
+        for (i, block) in blocks::find(&chapter.content, &config.languages())
+            .into_iter()
+            .enumerate()
+        {
+            let location = page.location(&chapter.content, block.span.start);
+            let mut warnings = vec![];
+            let source = crate::diagram_source(&block, &page, &mut warnings).into_owned();
+            f(&Diagram {
+                chapter,
+                page: &page,
+                path,
+                index: i + 1,
+                block,
+                location,
+                source,
+                warnings,
+            });
+        }
+    }
+}
+
+/// Write `content` to `file`, creating its directory if needed.
+pub(crate) fn write(file: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            Error::from(e).context(format!("Unable to create {}", parent.display()))
+        })?;
+    }
+    fs::write(file, content)
+        .map_err(|e| Error::from(e).context(format!("Unable to write {}", file.display())))
+}
@@ -0,0 +1,235 @@
+//! All diagrams of the book packaged into a zip file, for readers to reuse the figures in their
+//! own documents.
+//!
+//! With `bundle` enabled, the WaveJSON of every diagram and its SVG image go into
+//! `wavedrom-generated/diagrams.zip`, named like the images of `export-images`. The chapter
+//! named by `bundle-chapter` gets a link to download it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::Book;
+use mdbook::errors::{Error, Result};
+
+use crate::html::escape_html;
+use crate::render::{self, Format};
+use crate::{blocks, book, standalone, WavedromConfig};
+
+/// The folder the files of the bundle are in, once it's extracted.
+const FOLDER: &str = "diagrams";
+
+/// The path of the bundle relative to the book's `src` directory.
+pub(crate) fn path() -> PathBuf {
+    Path::new(standalone::DIR).join("diagrams.zip")
+}
+
+/// The bundle of the diagrams of `book`: their WaveJSON, and their SVG images if `render`.
+///
+/// Diagrams that fail to render are left with their WaveJSON, with a warning.
+pub(crate) fn build(
+    book: &Book,
+    config: &WavedromConfig,
+    src_dir: &Path,
+    images: &render::Cache,
+    render: bool,
+) -> Vec<u8> {
+    let mut diagrams = vec![];
+    book::for_each_diagram(book, config, src_dir, |diagram| {
+        if !diagram.block.is_rendered() {
+            return;
+        }
+        // The chapters report the problems with their diagrams when they're processed.
+        let source = crate::transform(
+            &diagram.source,
+            &diagram.block.attrs,
+            diagram.page,
+            &mut vec![],
+            &mut vec![],
+        );
+        diagrams.push((
+            diagram.path.to_path_buf(),
+            diagram.index,
+            source.into_owned(),
+        ));
+    });
+
+    let renderer = config.renderer();
+    if render {
+        let sources: Vec<_> = diagrams
+            .iter()
+            .map(|(_, _, source)| source.clone())
+            .collect();
+        images.render_all(&renderer, &sources, Format::Svg, config.jobs());
+    }
+    let mut files = vec![];
+    let mut failed = 0;
+    for (path, index, source) in &diagrams {
+        files.push((name(path, *index, "json5"), source.as_bytes().to_vec()));
+        if !render {
+            continue;
+        }
+        match images.render(&renderer, source, Format::Svg) {
+            Ok(svg) => files.push((name(path, *index, Format::Svg.extension()), svg)),
+            Err(e) => {
+                log::debug!("Unable to render a diagram for the bundle: {:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 && !config.allows("render-failed", None) {
+        warn!(
+            "{} of the {} diagrams didn't render, the bundle only has their WaveJSON",
+            failed,
+            diagrams.len()
+        );
+    }
+    zip(&files)
+}
+
+/// The name of a file of diagram number `index` of the chapter at `path` in the bundle.
+fn name(path: &Path, index: usize, extension: &str) -> String {
+    let file = blocks::file_name(path, index, extension);
+    format!("{}/{}", FOLDER, file.to_string_lossy().replace('\\', "/"))
+}
+
+/// The link to the bundle from a chapter `path_to_root` away from the book's root.
+pub(crate) fn link(path_to_root: &str) -> String {
+    format!(
+        "\n\n<p class=\"wavedrom-bundle\"><a href=\"{}{}\" download>Download all diagrams</a></p>\n",
+        escape_html(path_to_root),
+        path().to_string_lossy().replace('\\', "/")
+    )
+}
+
+/// Write the bundle `zip` below `src_dir`, unless it didn't change.
+pub(crate) fn write(src_dir: &Path, zip: &[u8]) -> Result<()> {
+    let path = src_dir.join(path());
+    if fs::read(&path).ok().as_deref() == Some(zip) {
+        return Ok(());
+    }
+
+    log::debug!("Writing the diagram bundle {}", path.display());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            Error::from(e).context(format!("Unable to create {}", parent.display()))
+        })?;
+    }
+    fs::write(&path, zip)
+        .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))
+}
+
+/// A zip archive of `files`, stored without compression.
+///
+/// Every file has the same timestamp, so the same diagrams give the same archive and
+/// `mdbook serve` doesn't see a change to the sources.
+fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    // 1980-01-01, the earliest date a zip can have.
+    const DATE: u16 = (1 << 5) | 1;
+    // Names are UTF-8.
+    const FLAGS: u16 = 1 << 11;
+    const VERSION: u16 = 20;
+
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, content) in files {
+        let offset = archive.len() as u32;
+        let crc = crc32(content);
+        let header = |out: &mut Vec<u8>, central: bool| {
+            let signature: u32 = if central { 0x0201_4b50 } else { 0x0403_4b50 };
+            out.extend(signature.to_le_bytes());
+            if central {
+                out.extend(VERSION.to_le_bytes());
+            }
+            for field in [VERSION, FLAGS, 0, 0, DATE] {
+                out.extend(field.to_le_bytes());
+            }
+            for field in [crc, content.len() as u32, content.len() as u32] {
+                out.extend(field.to_le_bytes());
+            }
+            out.extend((name.len() as u16).to_le_bytes());
+            out.extend(0u16.to_le_bytes());
+            if central {
+                // Comment, disk, internal and external attributes, then where the file starts.
+                for field in [0u16, 0, 0] {
+                    out.extend(field.to_le_bytes());
+                }
+                out.extend(0u32.to_le_bytes());
+                out.extend(offset.to_le_bytes());
+            }
+            out.extend(name.as_bytes());
+        };
+        header(&mut archive, false);
+        archive.extend(content);
+        header(&mut directory, true);
+    }
+
+    let start = archive.len() as u32;
+    archive.extend(&directory);
+    archive.extend(0x0605_4b50u32.to_le_bytes());
+    for field in [0, 0, files.len() as u16, files.len() as u16] {
+        archive.extend(field.to_le_bytes());
+    }
+    archive.extend((directory.len() as u32).to_le_bytes());
+    archive.extend(start.to_le_bytes());
+    archive.extend(0u16.to_le_bytes());
+    archive
+}
+
+/// The CRC-32 of `data`, as zip files check their content with.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{crc32, link, name, zip};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn zips_files() {
+        let archive = zip(&[("diagrams/a-1.json5".into(), b"{signal: []}".to_vec())]);
+        // A local header, the file, a central directory entry and the end record.
+        assert_eq!(30 + 18 + 12 + 46 + 18 + 22, archive.len());
+        assert_eq!(b"PK\x03\x04", &archive[..4]);
+        assert_eq!(b"diagrams/a-1.json5{signal: []}", &archive[30..60]);
+        assert_eq!(b"PK\x01\x02", &archive[60..64]);
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(b"PK\x05\x06", &end[..4]);
+        // One entry, its directory entry starting after the file.
+        assert_eq!([1, 0, 1, 0], end[8..12]);
+        assert_eq!(60u32.to_le_bytes(), end[16..20]);
+    }
+
+    #[test]
+    fn names_files_like_the_exported_images() {
+        assert_eq!(
+            "diagrams/a/intro-2.svg",
+            name(Path::new("a/intro.md"), 2, "svg")
+        );
+        assert_eq!(
+            "\n\n<p class=\"wavedrom-bundle\"><a href=\"../wavedrom-generated/diagrams.zip\" download>\
+             Download all diagrams</a></p>\n",
+            link("../")
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Diagrams wrapped into callouts, like the ones of [mdbook-admonish], to match books styled
+//! with it.
+//!
+//! ~~~text
+//! ```wavedrom callout=example callout-title="Burst read"
+//! ~~~
+//!
+//! The markup is the one mdbook-admonish writes for its own blocks, so its stylesheet applies no
+//! matter which of the preprocessors runs first. `callout` and `callout-title` in the configuration
+//! apply to every diagram, blocks opt out with `callout=false`.
+//!
+//! [mdbook-admonish]: https://github.com/tommilligan/mdbook-admonish
+
+use crate::attrs::Attributes;
+use crate::html::escape_html;
+use crate::WavedromConfig;
+
+/// The callout types mdbook-admonish styles, aliases included.
+const TYPES: &[&str] = &[
+    "note",
+    "abstract",
+    "summary",
+    "tldr",
+    "info",
+    "todo",
+    "tip",
+    "hint",
+    "important",
+    "success",
+    "check",
+    "done",
+    "question",
+    "help",
+    "faq",
+    "warning",
+    "caution",
+    "attention",
+    "failure",
+    "fail",
+    "missing",
+    "danger",
+    "error",
+    "bug",
+    "example",
+    "quote",
+    "cite",
+];
+
+/// The callout a diagram goes into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Callout {
+    /// The type, like `example`.
+    pub(crate) kind: String,
+    pub(crate) title: String,
+}
+
+/// The callout for the block with the given attributes, if it gets one.
+///
+/// Types mdbook-admonish doesn't know are used anyway, and reported in `warnings`.
+pub(crate) fn wanted(
+    attrs: &Attributes,
+    config: &WavedromConfig,
+    warnings: &mut Vec<String>,
+) -> Option<Callout> {
+    let kind = attrs.get("callout").or(config.callout.as_deref())?;
+    if kind == "false" {
+        return None;
+    }
+    if !TYPES.contains(&kind) {
+        warnings.push(format!(
+            "Unknown callout type '{}', mdbook-admonish styles {}",
+            kind,
+            TYPES.join(", ")
+        ));
+    }
+    let title = attrs
+        .get("callout-title")
+        .or(config.callout_title.as_deref())
+        .map_or_else(|| default_title(kind), String::from);
+    Some(Callout {
+        kind: kind.to_string(),
+        title,
+    })
+}
+
+/// The title of a callout without one, its type capitalized, like mdbook-admonish does.
+fn default_title(kind: &str) -> String {
+    let mut chars = kind.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Wrap the `diagram` markup into the `callout`, with `id` to link to it.
+pub(crate) fn wrap(diagram: &str, callout: &Callout, id: &str) -> String {
+    let kind = escape_html(&callout.kind);
+    format!(
+        "<div id=\"{id}\" class=\"admonition admonish-{kind}\" role=\"note\" aria-labelledby=\"{id}-title\">\n\
+         <div class=\"admonition-title\">\n\
+         <div id=\"{id}-title\">\n\
+         {title}\n\
+         </div>\n\
+         <a class=\"admonition-anchor-link\" href=\"#{id}\"></a>\n\
+         </div>\n\
+         <div>\n\n\
+         {diagram}</div>\n\
+         </div>\n\n",
+        id = id,
+        kind = kind,
+        title = escape_html(&callout.title),
+        diagram = diagram,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{wanted, Callout};
+    use crate::attrs;
+    use crate::WavedromConfig;
+
+    #[test]
+    fn picks_type_and_title() {
+        let config = WavedromConfig::default();
+        let callout = |info: &str, config: &WavedromConfig| {
+            let mut warnings = vec![];
+            let callout = wanted(&attrs::parse(info).1, config, &mut warnings);
+            (callout, warnings.len())
+        };
+        let example = |title: &str| {
+            Some(Callout {
+                kind: "example".into(),
+                title: title.into(),
+            })
+        };
+
+        assert_eq!((None, 0), callout("wavedrom", &config));
+        assert_eq!(
+            (example("Example"), 0),
+            callout("wavedrom callout=example", &config)
+        );
+        assert_eq!(
+            (example("Burst read"), 0),
+            callout(
+                "wavedrom callout=example callout-title='Burst read'",
+                &config
+            )
+        );
+        assert_eq!(1, callout("wavedrom callout=timing", &config).1);
+
+        let config = WavedromConfig {
+            callout: Some("example".into()),
+            callout_title: Some("Timing".into()),
+            ..Default::default()
+        };
+        assert_eq!((example("Timing"), 0), callout("wavedrom", &config));
+        assert_eq!((None, 0), callout("wavedrom callout=false", &config));
+    }
+}
@@ -0,0 +1,474 @@
+//! Checking a book's diagrams and the assets they need, for CI.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::BookItem;
+use mdbook::errors::Result;
+use regex::Regex;
+
+use crate::render::{self, Format};
+use crate::{
+    a11y, blocks, book, contrast, figures, lint, wavejson, ChapterContext, Outcome, WavedromConfig,
+};
+
+/// How bad a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found by [`check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Where the problem is, e.g. `chapter/intro.md:12` or `book.toml`.
+    pub location: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// What [`check`] found.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// The outcome to exit with.
+    pub fn outcome(&self) -> Outcome {
+        let count = |severity| {
+            self.problems
+                .iter()
+                .filter(|problem| problem.severity == severity)
+                .count()
+        };
+        Outcome::from_counts(count(Severity::Warning), count(Severity::Error))
+    }
+
+    fn push(&mut self, location: &str, severity: Severity, message: String) {
+        self.problems.push(Problem {
+            location: location.to_string(),
+            severity,
+            message,
+        });
+    }
+}
+
+/// What to check on top of the diagrams themselves.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Check that the book renders its diagrams without network access.
+    pub offline: bool,
+    /// Render every diagram with the `render-command`, reporting the ones that fail.
+    pub render: bool,
+    /// Compare the rendered diagrams against the SVG images in this directory, which mirrors
+    /// the chapters like the images of `export-images` do.
+    pub golden_dir: Option<PathBuf>,
+    /// Check that the diagrams have captions, `alt` texts and descriptions of their signals, and
+    /// that the chapters' own widgets can be used with the keyboard.
+    pub a11y: bool,
+}
+
+/// Check the diagrams of the book at `root`.
+pub fn check(root: &Path, options: &Options) -> Result<Report> {
+    let source = book::load(root)?;
+    let mut report = Report::default();
+    let mut skins = vec![];
+    let mut diagrams = 0;
+    // The rendered diagrams of every chapter, in book order.
+    let mut chapters: Vec<(PathBuf, usize)> = vec![];
+    // The location, image file name and WaveJSON of the diagrams to render.
+    let mut rendered: Vec<(String, PathBuf, String)> = vec![];
+    // The ids of the diagrams of the chapter so far.
+    let mut ids = (PathBuf::new(), figures::Ids::default());
+    let themes = contrast::Themes::new(&source.html, &source.config);
+
+    book::for_each_diagram(&source.book, &source.config, &source.src_dir, |diagram| {
+        diagrams += 1;
+        if diagram.block.is_rendered() {
+            match chapters.last_mut() {
+                Some((path, count)) if *path == diagram.path => *count += 1,
+                _ => chapters.push((diagram.path.to_path_buf(), 1)),
+            }
+        }
+        let allows = |warning| diagram.page.config.allows(warning, Some(diagram.path));
+        for warning in &diagram.warnings {
+            if !allows("diagram-source") {
+                report.push(&diagram.location, Severity::Warning, warning.clone());
+            }
+        }
+        let parsed = match wavejson::parse(&diagram.source) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                report.push(
+                    &diagram.location,
+                    Severity::Error,
+                    format!("Invalid WaveJSON: {}", wavejson::describe_error(&e)),
+                );
+                return;
+            }
+        };
+        if diagram.block.is_rendered() {
+            if ids.0 != diagram.path {
+                let content = &diagram.chapter.content;
+                let blocks = blocks::find(content, &source.config.languages());
+                ids = (
+                    diagram.path.to_path_buf(),
+                    figures::Ids::new(content, &blocks),
+                );
+            }
+            if let Err(message) = ids.1.next(&diagram.block.attrs) {
+                report.push(&diagram.location, Severity::Error, message);
+            }
+        }
+        // Problems with the block's attributes, like a view that doesn't fit its diagram.
+        let mut rewrites = vec![];
+        let transformed = crate::transform(
+            &diagram.source,
+            &diagram.block.attrs,
+            diagram.page,
+            &mut vec![],
+            &mut rewrites,
+        );
+        if diagram.block.is_rendered() {
+            let warnings = wavejson::parse(&transformed)
+                .map(|transformed| contrast::check(&transformed, &themes))
+                .unwrap_or_default();
+            for warning in warnings.into_iter().filter(|w| !allows(w.lint)) {
+                report.push(
+                    &diagram.location,
+                    Severity::Warning,
+                    format!("{} [{}]", warning.message, warning.lint),
+                );
+            }
+        }
+        if options.render && diagram.block.is_rendered() {
+            let file = blocks::file_name(diagram.path, diagram.index, Format::Svg.extension());
+            rendered.push((diagram.location.clone(), file, transformed.into_owned()));
+        }
+        for warning in rewrites {
+            if !allows("diagram-source") {
+                report.push(&diagram.location, Severity::Warning, warning);
+            }
+        }
+        for warning in lint::check(&parsed).into_iter().filter(|w| !allows(w.lint)) {
+            report.push(
+                &diagram.location,
+                Severity::Warning,
+                format!("{} [{}]", warning.message, warning.lint),
+            );
+        }
+        if options.a11y && diagram.block.is_rendered() {
+            let warnings = a11y::check(&parsed, &diagram.block.attrs);
+            for warning in warnings.into_iter().filter(|w| !allows(w.lint)) {
+                report.push(
+                    &diagram.location,
+                    Severity::Warning,
+                    format!("{} [{}]", warning.message, warning.lint),
+                );
+            }
+        }
+        // A `skin` attribute wins over the diagram's own, which wins over the book's.
+        let skin = diagram
+            .block
+            .attrs
+            .get("skin")
+            .or_else(|| {
+                parsed
+                    .pointer("/config/skin")
+                    .and_then(|skin| skin.as_str())
+            })
+            .or_else(|| {
+                parsed
+                    .get("signal")
+                    .and(diagram.page.config.skin.as_deref())
+            });
+        if let Some(skin) = skin {
+            skins.push((diagram.location.clone(), skin.to_string()));
+        }
+    });
+
+    for (path, count) in chapters {
+        if let Some(warning) = lint::diagram_budget(count, &source.config) {
+            if !source.config.allows(warning.lint, Some(&path)) {
+                report.push(
+                    &path.display().to_string(),
+                    Severity::Warning,
+                    format!("{} [{}]", warning.message, warning.lint),
+                );
+            }
+        }
+    }
+
+    if options.a11y {
+        keyboard_paths(&source, &mut report);
+    }
+    if let Some(skin) = &source.config.dark_skin {
+        if diagrams > 0 {
+            skins.push(("book.toml".into(), skin.clone()));
+        }
+    }
+    if options.offline {
+        offline(root, &source, diagrams > 0, &skins, &mut report);
+    }
+    if options.render {
+        let golden_dir = options.golden_dir.as_deref();
+        render(&source.config, &rendered, golden_dir, &mut report);
+    }
+    if source.config.strict {
+        for problem in &mut report.problems {
+            problem.severity = Severity::Error;
+        }
+    }
+    Ok(report)
+}
+
+/// Check that the widgets of the chapters can be used with the keyboard.
+fn keyboard_paths(source: &book::Source, report: &mut Report) {
+    for item in source.book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) => chapter,
+            _ => continue,
+        };
+        let page = ChapterContext::new(chapter, &source.config, Some(&source.src_dir));
+        for (start, warning) in a11y::keyboard_paths(&chapter.content) {
+            if !source.config.allows(warning.lint, page.source_path) {
+                report.push(
+                    &page.location(&chapter.content, start),
+                    Severity::Warning,
+                    format!("{} [{}]", warning.message, warning.lint),
+                );
+            }
+        }
+    }
+}
+
+/// Render the `diagrams`, and compare them against their images in `golden_dir`, if given.
+fn render(
+    config: &WavedromConfig,
+    diagrams: &[(String, PathBuf, String)],
+    golden_dir: Option<&Path>,
+    report: &mut Report,
+) {
+    let renderer = config.renderer();
+    let images = render::Cache::default();
+    let sources: Vec<_> = diagrams
+        .iter()
+        .map(|(_, _, source)| source.clone())
+        .collect();
+    images.render_all(&renderer, &sources, Format::Svg, config.jobs());
+
+    for (location, file, source) in diagrams {
+        let image = match images.render(&renderer, source, Format::Svg) {
+            Ok(image) => image,
+            Err(e) => {
+                report.push(
+                    location,
+                    Severity::Error,
+                    format!("Unable to render the diagram: {:#}", e),
+                );
+                continue;
+            }
+        };
+        let golden = match golden_dir {
+            Some(dir) => dir.join(file),
+            None => continue,
+        };
+        match fs::read(&golden) {
+            Ok(expected) if same_image(&expected, &image) => {}
+            Ok(_) => report.push(
+                location,
+                Severity::Error,
+                format!(
+                    "The diagram renders differently than its golden image {}",
+                    golden.display()
+                ),
+            ),
+            Err(_) => report.push(
+                location,
+                Severity::Error,
+                format!(
+                    "There's no golden image {}, write it with `export-images`",
+                    golden.display()
+                ),
+            ),
+        }
+    }
+}
+
+/// Whether two SVG images are the same, regardless of the line endings of a checkout.
+fn same_image(a: &[u8], b: &[u8]) -> bool {
+    let lines =
+        |image: &[u8]| -> Vec<u8> { image.iter().copied().filter(|&b| b != b'\r').collect() };
+    lines(a) == lines(b)
+}
+
+/// Check that everything the diagrams need at runtime comes with the book.
+///
+/// The WaveDrom runtime shipped by `install` doesn't fetch anything by itself, it uses system
+/// fonts and the skins loaded next to it. What's left are the book's own `additional-*` entries.
+fn offline(
+    root: &Path,
+    source: &book::Source,
+    has_diagrams: bool,
+    skins: &[(String, String)],
+    report: &mut Report,
+) {
+    const LOCATION: &str = "book.toml";
+    let to_check = [
+        ("additional-js", &source.html.additional_js),
+        ("additional-css", &source.html.additional_css),
+    ];
+
+    let mut runtime_loaded = source.config.inline_runtime;
+    let mut bundled_skins = BTreeSet::new();
+    if source.config.inline_runtime {
+        bundled_skins.insert("default".to_string());
+    }
+    if source.config.runtime_from_cdn() && has_diagrams && !source.config.mode.is_prerendered() {
+        report.push(
+            LOCATION,
+            Severity::Error,
+            format!(
+                "`source = \"cdn\"` loads the WaveDrom runtime from {}",
+                source.config.cdn_url()
+            ),
+        );
+        // Reported once, rather than as missing as well.
+        runtime_loaded = true;
+        bundled_skins.insert("default".to_string());
+        let config = &source.config;
+        bundled_skins.extend(config.skin.iter().chain(config.dark_skin.iter()).cloned());
+    }
+
+    for (key, files) in to_check.iter() {
+        for file in files.iter() {
+            let name = file.to_string_lossy();
+            if is_remote(&name) {
+                report.push(
+                    LOCATION,
+                    Severity::Error,
+                    format!("`{}` loads {} from the network", key, name),
+                );
+                continue;
+            }
+            let path = root.join(file);
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(_) => {
+                    report.push(
+                        LOCATION,
+                        Severity::Error,
+                        format!(
+                            "`{}` refers to {}, which doesn't exist",
+                            key,
+                            path.display()
+                        ),
+                    );
+                    continue;
+                }
+            };
+            if *key == "additional-js" {
+                runtime_loaded |= file.file_name() == Some("wavedrom.min.js".as_ref());
+                bundled_skins.extend(defined_skins(&content));
+            } else {
+                for url in remote_imports(&content) {
+                    report.push(
+                        &file.display().to_string(),
+                        Severity::Error,
+                        format!("Fetches {} from the network", url),
+                    );
+                }
+            }
+        }
+    }
+
+    // Pre-rendered diagrams only need the runtime where rendering failed.
+    if !has_diagrams || source.config.mode.is_prerendered() {
+        return;
+    }
+    if !runtime_loaded {
+        report.push(
+            LOCATION,
+            Severity::Error,
+            "The WaveDrom runtime isn't part of the book, run `mdbook-wavedrom install`".into(),
+        );
+    }
+    for (location, skin) in skins {
+        if !bundled_skins.contains(skin) {
+            report.push(
+                location,
+                Severity::Warning,
+                format!(
+                    "The skin `{}` isn't bundled with the book, the diagram is drawn with the default skin",
+                    skin
+                ),
+            );
+        }
+    }
+}
+
+/// A parse error on a single line, the pest errors of `json5` span several.
+fn is_remote(file: &str) -> bool {
+    file.starts_with("http://") || file.starts_with("https://") || file.starts_with("//")
+}
+
+/// The skins a script registers, like `default` for `WaveSkin.default=[...]`.
+fn defined_skins(script: &str) -> Vec<String> {
+    let re = Regex::new(r#"WaveSkin(?:\.(\w+)|\[['"]([\w-]+)['"]\])\s*="#).unwrap();
+    re.captures_iter(script)
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|m| m.as_str().to_string())
+        .collect()
+}
+
+/// The URLs a stylesheet imports or refers to on other hosts, like fonts.
+fn remote_imports(css: &str) -> Vec<String> {
+    let re = Regex::new(r#"(?:@import\s+|url\(\s*)['"]?((?:https?:)?//[^'")\s;]+)"#).unwrap();
+    re.captures_iter(css).map(|c| c[1].to_string()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{defined_skins, remote_imports, same_image};
+
+    #[test]
+    fn finds_skins() {
+        assert_eq!(
+            vec!["default"],
+            defined_skins(include_str!("bin/assets/wavedrome-default.js"))
+        );
+        assert_eq!(
+            vec!["lowkey"],
+            defined_skins(include_str!("bin/assets/wavedrom-skin-lowkey.js"))
+        );
+        assert_eq!(
+            vec!["narrow", "dark-mode"],
+            defined_skins("WaveSkin.narrow = [];\nWaveSkin['dark-mode']=[]; WaveSkin.x;")
+        );
+    }
+
+    #[test]
+    fn compares_images() {
+        assert!(same_image(b"<svg>\n</svg>\n", b"<svg>\r\n</svg>\r\n"));
+        assert!(!same_image(b"<svg><rect/></svg>", b"<svg></svg>"));
+    }
+
+    #[test]
+    fn finds_remote_imports() {
+        assert!(remote_imports(include_str!("bin/assets/wavedrom-theme.css")).is_empty());
+        assert_eq!(
+            vec![
+                "https://fonts.example.com/css?family=Mono",
+                "//cdn.example.com/a.woff2"
+            ],
+            remote_imports(
+                "@import 'https://fonts.example.com/css?family=Mono';\n\
+                 @font-face { src: url(//cdn.example.com/a.woff2) format('woff2'), url(local.woff2); }\n\
+                 .a { background: url(\"img/a.png\") }"
+            )
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Per-diagram color overrides, e.g. `colors="req:blue,ack:orange"`.
+//!
+//! Each listed signal gets its name drawn in the color, and its data bricks (`=`, `2` to `9`)
+//! switched to the matching WaveDrom fill class. The skin isn't touched.
+//!
+//! Colors can also be named by their meaning in the book's `palette`, like `bus = 5`, and given
+//! to a lane as its `color`, or to all data bricks of a diagram with `color=bus`.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+use crate::config::PaletteColor;
+use crate::wavejson;
+
+/// The named colors of the book's configuration.
+pub(crate) type Palette = BTreeMap<String, PaletteColor>;
+
+/// The key of a lane's color.
+const KEY: &str = "color";
+
+/// Colors that can be given by name: the wave character selecting the skin's fill class,
+/// and the color the signal name is drawn in.
+const PALETTE: &[(&str, char, &str)] = &[
+    ("white", '2', "#555555"),
+    ("yellow", '3', "#b38f00"),
+    ("orange", '4', "#d96c00"),
+    ("blue", '5', "#0066cc"),
+    ("cyan", '6', "#008b8b"),
+    ("green", '7', "#2e8b22"),
+    ("purple", '8', "#9b30c8"),
+    ("magenta", '8', "#9b30c8"),
+    ("red", '9', "#c0392b"),
+    ("pink", '9', "#c0392b"),
+];
+
+/// Look up a color of the `palette`, or a built-in one by name or by its wave character, `2`
+/// to `9`.
+fn lookup(color: &str, palette: &Palette) -> Option<(char, &'static str)> {
+    match palette.get(color.trim()) {
+        Some(PaletteColor::Digit(digit)) => builtin(&digit.to_string()),
+        Some(PaletteColor::Name(name)) => builtin(name),
+        None => builtin(color),
+    }
+}
+
+fn builtin(color: &str) -> Option<(char, &'static str)> {
+    let color = color.trim().to_ascii_lowercase();
+    PALETTE
+        .iter()
+        .find(|(name, digit, _)| *name == color || color.len() == 1 && color.starts_with(*digit))
+        .map(|&(_, digit, text)| (digit, text))
+}
+
+/// Apply the `colors` attribute `spec` to the diagram.
+///
+/// Returns a warning for every entry that couldn't be applied.
+pub(crate) fn apply(diagram: &mut Value, spec: &str, palette: &Palette) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut overrides = vec![];
+    for entry in spec.split(',').filter(|e| !e.trim().is_empty()) {
+        let (signal, color) = match entry.split_once(':') {
+            Some((signal, color)) => (signal.trim(), color.trim()),
+            None => {
+                warnings.push(format!(
+                    "Invalid color override '{}', expected 'signal:color'",
+                    entry.trim()
+                ));
+                continue;
+            }
+        };
+        match lookup(color, palette) {
+            Some(color) => overrides.push((signal, color, false)),
+            None => warnings.push(format!("Unknown color '{}' for signal '{}'", color, signal)),
+        }
+    }
+
+    wavejson::for_each_lane(diagram, |lane| {
+        let name = match lane.get("name").and_then(|n| n.as_str()) {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+        let (digit, text) = match overrides.iter_mut().find(|(signal, ..)| *signal == name) {
+            Some((_, color, used)) => {
+                *used = true;
+                *color
+            }
+            None => return,
+        };
+
+        recolor(lane, digit);
+        style_name(lane, &name, text);
+    });
+
+    for (signal, _, used) in overrides {
+        if !used {
+            warnings.push(format!("No signal named '{}' to color", signal));
+        }
+    }
+    warnings
+}
+
+/// Apply the `color` of the diagram's lanes, taking it out of them.
+///
+/// Returns whether any lane had a color, and a warning for every color that's unknown.
+pub(crate) fn apply_lanes(diagram: &mut Value, palette: &Palette) -> (bool, Vec<String>) {
+    let mut colored = false;
+    let mut warnings = vec![];
+    wavejson::for_each_lane(diagram, |lane| {
+        let color = match lane.remove(KEY) {
+            Some(Value::String(color)) => color,
+            Some(_) | None => return,
+        };
+        colored = true;
+        match lookup(&color, palette) {
+            Some((digit, text)) => {
+                recolor(lane, digit);
+                if let Some(name) = lane.get("name").and_then(Value::as_str) {
+                    let name = name.to_string();
+                    style_name(lane, &name, text);
+                }
+            }
+            None => warnings.push(format!("Unknown color '{}' of a lane", color)),
+        }
+    });
+    (colored, warnings)
+}
+
+/// Switch all data bricks of the diagram to `color`, as attribute `color` gave it.
+///
+/// Returns a warning if the color is unknown.
+pub(crate) fn apply_all(diagram: &mut Value, color: &str, palette: &Palette) -> Option<String> {
+    let (digit, _) = match lookup(color, palette) {
+        Some(color) => color,
+        None => return Some(format!("Unknown color '{}'", color)),
+    };
+    wavejson::for_each_lane(diagram, |lane| recolor(lane, digit));
+    None
+}
+
+/// Switch the data bricks of `lane` to the fill class of `digit`.
+fn recolor(lane: &mut Map<String, Value>, digit: char) {
+    if let Some(Value::String(wave)) = lane.get_mut("wave") {
+        *wave = wave
+            .chars()
+            .map(|c| match c {
+                '=' | '2'..='9' => digit,
+                c => c,
+            })
+            .collect();
+    }
+}
+
+/// Draw the `name` of `lane` in the color `text`.
+fn style_name(lane: &mut Map<String, Value>, name: &str, text: &str) {
+    let style = format!("fill:{};font-weight:bold", text);
+    lane.insert("name".into(), json!(["tspan", { "style": style }, name]));
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{apply, apply_all, apply_lanes, Palette};
+    use crate::config::PaletteColor;
+    use crate::wavejson;
+
+    #[test]
+    fn colors_names_and_data() {
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'clk', wave: 'p..'}, {name: 'req', wave: '0.1'}, \
+             ['bus', {name: 'dat', wave: 'x=3x', data: ['a', 'b']}]]}",
+        )
+        .unwrap();
+        let warnings = apply(&mut diagram, "req:blue, dat:9", &Palette::new());
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: 'p..'}, \
+             {name: ['tspan', {style: 'fill:#0066cc;font-weight:bold'}, 'req'], wave: '0.1'}, \
+             ['bus', {name: ['tspan', {style: 'fill:#c0392b;font-weight:bold'}, 'dat'], wave: 'x99x', data: ['a', 'b']}]]}",
+            wavejson::to_string(&diagram)
+        );
+    }
+
+    #[test]
+    fn warns_about_unusable_entries() {
+        let mut diagram = wavejson::parse("{signal: [{name: 'req', wave: '01'}]}").unwrap();
+        let warnings = apply(
+            &mut diagram,
+            "req:chartreuse,ack:green,oops",
+            &Palette::new(),
+        );
+        assert_eq!(
+            vec![
+                "Unknown color 'chartreuse' for signal 'req'",
+                "Invalid color override 'oops', expected 'signal:color'",
+                "No signal named 'ack' to color",
+            ],
+            warnings
+        );
+        assert_eq!(
+            "{signal: [{name: 'req', wave: '01'}]}",
+            wavejson::to_string(&diagram)
+        );
+    }
+
+    #[test]
+    fn colors_from_the_palette() {
+        let palette: Palette = vec![
+            ("bus".to_string(), PaletteColor::Digit(5)),
+            ("error".to_string(), PaletteColor::Name("red".into())),
+        ]
+        .into_iter()
+        .collect();
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'a', wave: 'x=x', color: 'error'}, {wave: '3', color: 'oops'}, \
+             {name: 'b', wave: '=.2'}]}",
+        )
+        .unwrap();
+
+        let (colored, warnings) = apply_lanes(&mut diagram, &palette);
+        assert!(colored);
+        assert_eq!(vec!["Unknown color 'oops' of a lane".to_string()], warnings);
+        assert_eq!(None, apply_all(&mut diagram, "bus", &palette));
+        assert_eq!(
+            "{signal: [{name: ['tspan', {style: 'fill:#c0392b;font-weight:bold'}, 'a'], wave: 'x5x'}, \
+             {wave: '5'}, {name: 'b', wave: '5.5'}]}",
+            wavejson::to_string(&diagram)
+        );
+        assert!(apply(&mut diagram, "b:bus", &palette).is_empty());
+        assert!(apply_all(&mut diagram, "busy", &palette).is_some());
+    }
+}
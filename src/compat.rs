@@ -0,0 +1,362 @@
+//! Tolerant handling of the preprocessor JSON protocol.
+//!
+//! `mdbook` hands preprocessors a `[context, book]` pair on stdin and expects the processed book
+//! back on stdout. The shape of that JSON has shifted slightly between releases: older versions
+//! don't send `source_path`, `parent_names` or the `__non_exhaustive` markers, newer ones list the
+//! book's items under `items` instead of `sections`.
+//!
+//! [`parse_input`] fills in what's missing before handing the data to the `mdbook` types this
+//! crate was built against, and [`write_output`] answers in the shape the caller used.
+//!
+//! Large books pass through both item by item: the input isn't parsed into a JSON tree as a
+//! whole, and the output is written as it's serialized. The book itself is held in memory once,
+//! from reading the last chapter until writing the first: figure numbers, signal links and the
+//! bundle span all chapters, so none can be written before all are read.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use mdbook::book::{Book, BookItem};
+use mdbook::errors::{Error, Result};
+use mdbook::preprocess::PreprocessorContext;
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{Map, Value};
+
+/// Key of the `__non_exhaustive` marker of `Book`.
+const NON_EXHAUSTIVE: &str = "__non_exhaustive";
+
+/// The shape of the book JSON sent by the calling `mdbook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protocol {
+    /// The book's items are listed under `items` rather than `sections`.
+    items_key: bool,
+    /// The book carries the `__non_exhaustive` marker.
+    non_exhaustive: bool,
+}
+
+impl Protocol {
+    /// Whether this is the exact shape the bundled `mdbook` version produces.
+    pub fn is_native(&self) -> bool {
+        !self.items_key && self.non_exhaustive
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol {
+            items_key: false,
+            non_exhaustive: true,
+        }
+    }
+}
+
+/// Parse the `[context, book]` input of a preprocessor run.
+///
+/// The input is read as it comes, one item of the book at a time, so the book is only held once
+/// as the `mdbook` types, never as a JSON tree of the whole input as well.
+pub fn parse_input<R: Read>(reader: R) -> Result<(PreprocessorContext, Book, Protocol)> {
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let input = de
+        .deserialize_seq(InputVisitor)
+        .and_then(|input| de.end().map(|_| input))
+        .map_err(|e| Error::from(e).context("Unable to parse the input"))?;
+    Ok(input)
+}
+
+/// Write the processed book in the shape described by `protocol`.
+///
+/// The book is written as it's serialized, whatever its shape.
+pub fn write_output<W: Write>(writer: W, book: &Book, protocol: Protocol) -> Result<()> {
+    if protocol.is_native() {
+        serde_json::to_writer(writer, book)?;
+    } else {
+        serde_json::to_writer(writer, &Output { book, protocol })?;
+    }
+    Ok(())
+}
+
+/// The `[context, book]` pair of the input.
+struct InputVisitor;
+
+impl<'de> Visitor<'de> for InputVisitor {
+    type Value = (PreprocessorContext, Book, Protocol);
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a [context, book] pair")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut ctx: Value = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        normalize_context(&mut ctx).map_err(de::Error::custom)?;
+        let ctx = serde_json::from_value(ctx).map_err(|e| {
+            de::Error::custom(format!("Unable to parse the preprocessor context: {}", e))
+        })?;
+        let (book, protocol) = seq
+            .next_element_seed(BookVisitor)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(3, &self));
+        }
+        Ok((ctx, book, protocol))
+    }
+}
+
+/// The book, with its items under either `sections` or `items`.
+struct BookVisitor;
+
+impl<'de> DeserializeSeed<'de> for BookVisitor {
+    type Value = (Book, Protocol);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BookVisitor {
+    type Value = (Book, Protocol);
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the book as an object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut book = Book::new();
+        let mut sections = false;
+        let mut protocol = Protocol {
+            items_key: false,
+            non_exhaustive: false,
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "sections" => {
+                    book.sections = map.next_value_seed(ItemsVisitor)?;
+                    sections = true;
+                    protocol.items_key = false;
+                }
+                // `sections` wins if a book has both.
+                "items" if !sections => {
+                    book.sections = map.next_value_seed(ItemsVisitor)?;
+                    protocol.items_key = true;
+                }
+                NON_EXHAUSTIVE => {
+                    map.next_value::<IgnoredAny>()?;
+                    protocol.non_exhaustive = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !sections && !protocol.items_key {
+            return Err(de::Error::missing_field("sections"));
+        }
+        Ok((book, protocol))
+    }
+}
+
+/// The items of the book, each parsed on its own once it's complete.
+struct ItemsVisitor;
+
+impl<'de> DeserializeSeed<'de> for ItemsVisitor {
+    type Value = Vec<BookItem>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ItemsVisitor {
+    type Value = Vec<BookItem>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of book items")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = vec![];
+        while let Some(mut item) = seq.next_element::<Value>()? {
+            normalize_item(&mut item);
+            let item = serde_json::from_value(item)
+                .map_err(|e| de::Error::custom(format!("Unable to parse the book: {}", e)))?;
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+/// The book in the shape of a `protocol` other than the native one.
+struct Output<'a> {
+    book: &'a Book,
+    protocol: Protocol,
+}
+
+impl Serialize for Output<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut book = serializer.serialize_map(None)?;
+        let key = if self.protocol.items_key {
+            "items"
+        } else {
+            "sections"
+        };
+        book.serialize_entry(key, &self.book.sections)?;
+        if self.protocol.non_exhaustive {
+            book.serialize_entry(NON_EXHAUSTIVE, &())?;
+        }
+        book.end()
+    }
+}
+
+fn normalize_context(ctx: &mut Value) -> Result<()> {
+    let ctx = object(ctx, "preprocessor context")?;
+    ctx.entry("renderer").or_insert_with(|| "html".into());
+    ctx.entry("mdbook_version")
+        .or_insert_with(|| "unknown".into());
+    ctx.entry("config")
+        .or_insert_with(|| Value::Object(Map::new()));
+    Ok(())
+}
+
+fn normalize_item(item: &mut Value) {
+    let chapter = match item.get_mut("Chapter") {
+        Some(Value::Object(chapter)) => chapter,
+        _ => return,
+    };
+
+    chapter.entry("number").or_insert(Value::Null);
+    chapter
+        .entry("sub_items")
+        .or_insert_with(|| Value::Array(vec![]));
+    chapter
+        .entry("parent_names")
+        .or_insert_with(|| Value::Array(vec![]));
+    let path = chapter.entry("path").or_insert(Value::Null).clone();
+    chapter.entry("source_path").or_insert(path);
+
+    if let Some(Value::Array(items)) = chapter.get_mut("sub_items") {
+        items.iter_mut().for_each(normalize_item);
+    }
+}
+
+fn object<'a>(value: &'a mut Value, what: &str) -> Result<&'a mut Map<String, Value>> {
+    value.as_object_mut().ok_or_else(|| {
+        Error::msg(format!(
+            "Unable to parse the input: the {} is not an object",
+            what
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use mdbook::book::BookItem;
+    use serde_json::{json, Value};
+
+    use super::{parse_input, write_output};
+
+    fn context() -> Value {
+        json!({
+            "root": "/book",
+            "config": { "book": { "title": "Test" } },
+            "renderer": "html",
+            "mdbook_version": "0.4.28",
+        })
+    }
+
+    #[test]
+    fn native_protocol_roundtrip() {
+        let book = json!({
+            "sections": [{ "Chapter": {
+                "name": "Intro",
+                "content": "# Intro",
+                "number": [1],
+                "sub_items": [],
+                "path": "intro.md",
+                "source_path": "intro.md",
+                "parent_names": [],
+            }}, "Separator"],
+            "__non_exhaustive": null,
+        });
+        let input = json!([context(), book]).to_string();
+
+        let (ctx, parsed, protocol) = parse_input(input.as_bytes()).unwrap();
+        assert_eq!("html", ctx.renderer);
+        assert!(protocol.is_native());
+
+        let mut output = vec![];
+        write_output(&mut output, &parsed, protocol).unwrap();
+        let output: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(book, output);
+    }
+
+    #[test]
+    fn fills_in_fields_of_older_releases() {
+        let book = json!({
+            "sections": [{ "Chapter": {
+                "name": "Intro",
+                "content": "# Intro",
+                "path": "intro.md",
+                "sub_items": [{ "Chapter": {
+                    "name": "Nested",
+                    "content": "",
+                    "path": "nested.md",
+                }}],
+            }}],
+        });
+        let mut ctx = context();
+        ctx.as_object_mut().unwrap().remove("mdbook_version");
+        let input = json!([ctx, book]).to_string();
+
+        let (ctx, parsed, protocol) = parse_input(input.as_bytes()).unwrap();
+        assert_eq!("unknown", ctx.mdbook_version);
+        match &parsed.sections[0] {
+            BookItem::Chapter(chapter) => {
+                assert_eq!(Some("intro.md".into()), chapter.source_path);
+                assert!(chapter.parent_names.is_empty());
+                assert_eq!(1, chapter.sub_items.len());
+            }
+            item => panic!("expected a chapter, got {:?}", item),
+        }
+
+        let mut output = vec![];
+        write_output(&mut output, &parsed, protocol).unwrap();
+        let output: Value = serde_json::from_slice(&output).unwrap();
+        assert!(output.get("__non_exhaustive").is_none());
+    }
+
+    #[test]
+    fn answers_with_items_key() {
+        let book = json!({ "items": ["Separator", { "PartTitle": "Part 1" }] });
+        let input = json!([context(), book]).to_string();
+
+        let (_, parsed, protocol) = parse_input(input.as_bytes()).unwrap();
+        assert_eq!(2, parsed.sections.len());
+
+        let mut output = vec![];
+        write_output(&mut output, &parsed, protocol).unwrap();
+        let output: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(book, output);
+    }
+
+    #[test]
+    fn takes_book_keys_in_any_order() {
+        let book = json!({ "__non_exhaustive": null, "sections": ["Separator"] });
+        let input = json!([context(), book]).to_string();
+
+        let (_, parsed, protocol) = parse_input(input.as_bytes()).unwrap();
+        assert_eq!(1, parsed.sections.len());
+        assert!(protocol.is_native());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(parse_input(&b"{}"[..]).is_err());
+        assert!(parse_input(&b"[1, 2]"[..]).is_err());
+        let extra = json!([context(), { "sections": [] }, 3]).to_string();
+        assert!(parse_input(extra.as_bytes()).is_err());
+        let no_items = json!([context(), {}]).to_string();
+        assert!(parse_input(no_items.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,917 @@
+use mdbook::errors::{Error, Result};
+use mdbook::preprocess::PreprocessorContext;
+use std::collections::BTreeMap;
+use std::env;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use mdbook::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::font::Font;
+use crate::numbers::{self, Numbers};
+use crate::{a11y, blocks, defines, lint, options, render};
+
+/// Renderers the preprocessor takes part in unless `renderers` says otherwise.
+const DEFAULT_RENDERERS: &[&str] = &["html", "linkcheck", "epub", "latex", "pandoc", "typst"];
+
+/// Word the numbers of captioned diagrams are prefixed with unless `caption-prefix` is set.
+const DEFAULT_CAPTION_PREFIX: &str = "Figure";
+
+/// The release of WaveDrom `install` copies, on cdnjs.
+pub const WAVEDROM_CDN: &str = "https://cdnjs.cloudflare.com/ajax/libs/wavedrom/2.6.8/";
+
+/// The online WaveDrom editor, which takes the WaveJSON to open as its query.
+const DEFAULT_EDITOR_URL: &str = "https://wavedrom.com/editor.html";
+
+/// Label of captioned diagrams unless `caption-format` is set.
+const DEFAULT_CAPTION_FORMAT: &str = "{prefix} {number}: {caption}";
+
+/// Environment variable naming the profile to use, also set by the `--profile` option.
+pub const PROFILE_ENV: &str = "MDBOOK_WAVEDROM_PROFILE";
+
+/// Environment variable turning on `strict`, also set by the `--strict` option.
+pub const STRICT_ENV: &str = "MDBOOK_WAVEDROM_STRICT";
+
+/// Environment variable turning off the `render-cache`, also set by the `--no-cache` option.
+pub const NO_CACHE_ENV: &str = "MDBOOK_WAVEDROM_NO_CACHE";
+
+/// Directory below the book's root rendered images are kept in unless `render-cache-dir` is set.
+const DEFAULT_CACHE_DIR: &str = ".wavedrom-cache";
+
+/// Environment variable marking a build as a live preview, like the ones of `mdbook serve`.
+pub const PREVIEW_ENV: &str = "MDBOOK_WAVEDROM_PREVIEW";
+
+/// Warnings that can be allowed besides the lints, see [`WavedromConfig::allows`].
+const WARNINGS: &[&str] = &[
+    "version-mismatch",
+    "misplaced-diagram",
+    "diagram-source",
+    "render-failed",
+    "translate-failed",
+    "unknown-figure",
+    "duplicate-figure",
+];
+
+/// Key of the table of named profiles.
+const PROFILES: &str = "profile";
+
+/// How diagrams are embedded into the page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// `<script type="WaveDrom">` tags, rendered by the WaveDrom runtime of the page.
+    #[default]
+    Script,
+    /// Every diagram in its own sandboxed `<iframe>` with a copy of the WaveDrom runtime,
+    /// isolated from the page.
+    Iframe,
+    /// Diagrams rendered to SVG at build time with the `render-command`, embedded as
+    /// `<img src="data:image/svg+xml;base64,…">`. Needs no JavaScript on the page.
+    DataUri,
+    /// Diagrams rendered to SVG at build time like `data-uri`, inlined into the page as `<svg>`
+    /// markup, whose text search engines and the browser's find see.
+    InlineSvg,
+}
+
+/// How captioned diagrams are numbered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FigureNumbers {
+    /// From 1 in every chapter.
+    #[default]
+    Chapter,
+    /// Through the whole book, in the order of its chapters.
+    Book,
+    /// From 1 in every chapter, after the number of the chapter, like `2.1.3`.
+    Section,
+}
+
+impl Mode {
+    /// Whether the diagrams are rendered at build time, needing no runtime on the page.
+    pub fn is_prerendered(self) -> bool {
+        matches!(self, Mode::DataUri | Mode::InlineSvg)
+    }
+}
+
+/// Where the chapters load the WaveDrom runtime from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeSource {
+    /// The files `install` copied into the book and added to `additional-js`.
+    #[default]
+    Files,
+    /// The release at `cdn-url`, loaded by every chapter with diagrams, with the few scripts of
+    /// mdbook-wavedrom inlined next to it. Nothing is copied into the book.
+    Cdn,
+}
+
+/// What to do when the book doesn't load the WaveDrom runtime the diagrams need.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingAssets {
+    /// Log a warning and build the book anyway.
+    #[default]
+    Warn,
+    /// Fail the build.
+    Error,
+    /// Don't check, e.g. when the runtime comes from a custom theme.
+    Ignore,
+}
+
+/// What to do when a file pinned in `asset-hashes` doesn't match its hash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangedAssets {
+    /// Fail the build.
+    #[default]
+    Error,
+    /// Log a warning and build the book anyway.
+    Warn,
+}
+
+/// A known warning not to log, in all chapters or only in some.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Allow {
+    /// The name of a lint or warning, like `dangling-edge`.
+    Everywhere(String),
+    /// A warning allowed in the chapters below `path` of the book's `src` directory, given as
+    /// `{ warning = "dangling-edge", path = "appendix" }`.
+    Below { warning: String, path: PathBuf },
+}
+
+impl Allow {
+    fn warning(&self) -> &str {
+        match self {
+            Allow::Everywhere(warning) | Allow::Below { warning, .. } => warning,
+        }
+    }
+}
+
+/// A color of the `palette`: a WaveDrom fill class, `2` to `9`, or a color name like `blue`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteColor {
+    Digit(u8),
+    Name(String),
+}
+
+/// Options read from the `[preprocessor.wavedrom]` table of `book.toml`.
+///
+/// Every option is optional, a missing table gives the defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct WavedromConfig {
+    /// Log a summary of the slowest chapters once preprocessing is done.
+    pub timings: bool,
+    /// Leave a chapter that fails to process as it is authored and build the others, rather
+    /// than failing the build.
+    pub keep_going: bool,
+    /// Fail the build on any warning, for releases that mustn't have any.
+    pub strict: bool,
+    /// Fail the build on a diagram whose WaveJSON doesn't parse, rather than showing an error in
+    /// its place.
+    pub fail_on_error: bool,
+    /// Renderers to run for, replacing the built-in list.
+    ///
+    /// This is the same key mdbook itself consults before asking `supports`.
+    pub renderers: Option<Vec<String>>,
+    /// How diagrams are embedded into the page.
+    pub mode: Mode,
+    /// Fence languages of the blocks that are diagrams, `wavedrom` unless set.
+    ///
+    /// Blocks fenced with `wavedrom-include`, `register` or `wavedrom-assign` are always
+    /// diagrams.
+    pub block_names: Option<Vec<String>>,
+    /// Another fence language of the blocks that are diagrams, next to the `block-names`, like
+    /// `timing`.
+    pub keyword: Option<String>,
+    /// Glob patterns of the chapters to leave as they are, relative to the book's `src`
+    /// directory, like `examples/*.md`.
+    pub exclude: Vec<String>,
+    /// Skin of the timing diagrams that don't pick one in their `config`.
+    pub skin: Option<String>,
+    /// Skin of the diagrams drawn by the runtime while the reader has a dark theme picked.
+    pub dark_skin: Option<String>,
+    /// Scale of the cycles of the timing diagrams that don't set one in their `config`.
+    pub hscale: Option<NonZeroU32>,
+    /// Format of the image files the diagrams are rendered to for the renderers other than
+    /// html, like `epub` or `latex`, PNG unless set.
+    pub image_format: Option<render::Format>,
+    /// Also write a standalone page for every diagram, linked from below it.
+    pub standalone: bool,
+    /// Directory to write every chapter's markdown to, before and after preprocessing.
+    ///
+    /// Relative paths are relative to the book's root.
+    pub dump_dir: Option<PathBuf>,
+    /// Command that renders a diagram to an image, `wavedrom-cli` unless set.
+    ///
+    /// Split at whitespace, it is called with `-i <input>` and `-s <svg>` or `-p <png>` added.
+    pub render_command: Option<String>,
+    /// Seconds a single diagram may take to render, 60 unless set, 0 for no limit.
+    pub render_timeout: Option<u64>,
+    /// Largest image in bytes a single diagram may render to, 16 MiB unless set, 0 for no limit.
+    pub render_max_size: Option<u64>,
+    /// Font family to set the text of rendered SVG images in.
+    pub render_font: Option<String>,
+    /// Font file of the `render-font` to embed into the SVG images, relative to the book's root.
+    pub render_font_file: Option<PathBuf>,
+    /// Keep rendered images across builds in the `render-cache-dir`, true unless set.
+    pub render_cache: Option<bool>,
+    /// Directory of the rendered images kept across builds, relative to the book's root,
+    /// `.wavedrom-cache` unless set.
+    pub render_cache_dir: Option<PathBuf>,
+    /// Word the number of a captioned diagram is prefixed with, `Figure` unless set.
+    pub caption_prefix: Option<String>,
+    /// Label of a captioned diagram, with `{prefix}`, `{number}` and `{caption}` filled in.
+    ///
+    /// Defaults to `{prefix} {number}: {caption}`.
+    pub caption_format: Option<String>,
+    /// How captioned diagrams are numbered, from 1 in every chapter unless set.
+    pub figure_numbers: FigureNumbers,
+    /// Link inline code naming a signal to the first diagram of the book that has it.
+    pub link_signals: bool,
+    /// Inline the WaveDrom runtime into every chapter with diagrams, instead of loading it from
+    /// `additional-js`.
+    pub inline_runtime: bool,
+    /// Where the chapters load the WaveDrom runtime from, the `additional-js` files unless set.
+    /// `inline-runtime` wins over it.
+    pub source: RuntimeSource,
+    /// Directory of the WaveDrom release `source = "cdn"` loads `wavedrom.min.js` and
+    /// `skins/<name>.js` from, the one `install` copies on cdnjs unless set.
+    pub cdn_url: Option<String>,
+    /// What to do when the `additional-js` files of the runtime are missing.
+    pub missing_assets: MissingAssets,
+    /// Hashes of the asset files `install` copied, by their path relative to the book's root,
+    /// like `"wavedrom.min.js" = "sha256-…"`.
+    pub asset_hashes: BTreeMap<String, String>,
+    /// What to do when a file of `asset-hashes` was changed or replaced.
+    pub changed_assets: ChangedAssets,
+    /// Text stamped into the foot of every diagram, e.g. a revision or `CONFIDENTIAL`.
+    pub watermark: Option<String>,
+    /// Number of diagrams rendered, and of chapters processed, at the same time at build time, the
+    /// number of CPUs unless set.
+    pub jobs: Option<usize>,
+    /// Skip the slow steps in live previews like `mdbook serve`, true unless set.
+    pub fast_preview: Option<bool>,
+    /// Keep every line of the chapter where it was, for the renderers and preprocessors after
+    /// this one to report the author's line numbers.
+    pub preserve_lines: bool,
+    /// Number of diagrams a chapter may have before it's reported by the `diagram-budget` lint.
+    pub diagram_budget: Option<usize>,
+    /// Known warnings not to log, by the name of the lint or warning.
+    pub allow: Vec<Allow>,
+    /// Show the caption or head text of a diagram when hovering it, true unless set.
+    pub tooltips: Option<bool>,
+    /// Show the WaveJSON of every diagram in a collapsed listing below it.
+    pub show_source: bool,
+    /// Put a toolbar below every diagram, opening it in the WaveDrom editor or copying it.
+    pub toolbar: bool,
+    /// Put buttons downloading the drawn diagram as SVG or PNG below every diagram.
+    pub downloads: bool,
+    /// Draw the diagrams of a page once they scroll into view, rather than all of them as soon
+    /// as the page is loaded.
+    pub lazy: bool,
+    /// Show the diagrams of `mode = "script"` as PNG images rendered at build time and loaded
+    /// once they're about to scroll into view, drawn by the runtime once the reader clicks them.
+    pub thumbnails: bool,
+    /// Let readers zoom and scroll every diagram, for the ones wider than the page.
+    pub zoom: bool,
+    /// Address of the WaveDrom editor the toolbars link to, the online one unless set.
+    pub editor_url: Option<String>,
+    /// Type of the mdbook-admonish callout to wrap every diagram into, like `example`.
+    pub callout: Option<String>,
+    /// Title of the callouts, the type capitalized unless set.
+    pub callout_title: Option<String>,
+    /// Colors named by their meaning, like `bus = 5`, for `colors`, `color` and lanes.
+    pub palette: BTreeMap<String, PaletteColor>,
+    /// Render a diagram of every chapter to a PNG for link previews, with the Open Graph meta
+    /// tags pointing to it.
+    pub og_image: bool,
+    /// Names to show signals under in all diagrams, like `rst_n = "RESETn"`.
+    pub rename: BTreeMap<String, String>,
+    /// WaveJSON fragments the diagrams put in with a `{{name}}` placeholder, like
+    /// `clk = "{name: 'clk', wave: 'p...'}"`.
+    pub defines: BTreeMap<String, serde_json::Value>,
+    /// Package the WaveJSON and SVG images of all diagrams into a zip in the book's output.
+    pub bundle: bool,
+    /// Chapter linking to the bundle, relative to the book's `src` directory.
+    pub bundle_chapter: Option<PathBuf>,
+    /// Commands translating the blocks of other fence languages, by the language, like
+    /// `timing = "timing2wavejson"`.
+    pub translators: BTreeMap<String, String>,
+    /// Size in bytes of the WaveJSON of a diagram above which it's written to a file of its own
+    /// and fetched by the page, in `script` mode.
+    pub spill_size: Option<usize>,
+    /// Locale to write the numbers in diagrams in, like `de` or `fr-CA`.
+    pub locale: Option<String>,
+    /// Page backgrounds of the book's themes for the `low-contrast` lint, like
+    /// `navy = "#161923"`, overriding the ones of mdbook's themes.
+    pub backgrounds: BTreeMap<String, String>,
+}
+
+impl WavedromConfig {
+    /// Read the configuration from the `[preprocessor.<name>]` table of the book.
+    pub fn from_context(ctx: &PreprocessorContext, name: &str) -> Result<Self> {
+        Ok(Self::from_config(&ctx.config, name)?.relative_to(&ctx.root))
+    }
+
+    /// The configuration with its paths relative to the book's `root` resolved.
+    pub(crate) fn relative_to(mut self, root: &Path) -> Self {
+        self.render_font_file = self.render_font_file.map(|file| root.join(file));
+        self.render_cache_dir = Some(
+            root.join(
+                self.render_cache_dir
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR)),
+            ),
+        );
+        self
+    }
+
+    /// Read the configuration from the `[preprocessor.<name>]` table of a loaded `book.toml`.
+    ///
+    /// The profile named by `MDBOOK_WAVEDROM_PROFILE`, if set, overrides the options of the table,
+    /// `MDBOOK_WAVEDROM_STRICT` turns on `strict` and `MDBOOK_WAVEDROM_NO_CACHE` turns off
+    /// `render-cache`.
+    pub fn from_config(config: &Config, name: &str) -> Result<Self> {
+        let profile = env::var(PROFILE_ENV)
+            .ok()
+            .filter(|profile| !profile.is_empty());
+        let mut wavedrom = Self::with_profile(config, name, profile.as_deref())?;
+        if env::var(STRICT_ENV).is_ok_and(|strict| !strict.is_empty() && strict != "0") {
+            wavedrom.strict = true;
+        }
+        if env::var(NO_CACHE_ENV).is_ok_and(|no_cache| !no_cache.is_empty() && no_cache != "0") {
+            wavedrom.render_cache = Some(false);
+        }
+        Ok(wavedrom)
+    }
+
+    /// Read the configuration like [`WavedromConfig::from_config`], with the options of the
+    /// `[preprocessor.<name>.profile.<profile>]` table on top.
+    pub fn with_profile(config: &Config, name: &str, profile: Option<&str>) -> Result<Self> {
+        let mut table = match config.get_preprocessor(name) {
+            Some(table) => table.clone(),
+            None if profile.is_none() => return Ok(Self::default()),
+            None => Default::default(),
+        };
+
+        let profiles = table.remove(PROFILES);
+        if let Some(profile) = profile {
+            let options = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(profile))
+                .ok_or_else(|| {
+                    Error::msg(format!(
+                        "there is no [preprocessor.{}.{}.{}] profile",
+                        name, PROFILES, profile
+                    ))
+                })?;
+            let options = options.as_table().ok_or_else(|| {
+                Error::msg(format!(
+                    "invalid [preprocessor.{}.{}.{}] profile, expected a table",
+                    name, PROFILES, profile
+                ))
+            })?;
+            table.extend(options.clone());
+        }
+
+        let wavedrom: Self = toml::Value::Table(table).try_into().map_err(|e| {
+            Error::from(e).context(format!("invalid [preprocessor.{}] configuration", name))
+        })?;
+        wavedrom.validate().map_err(|e| {
+            Error::msg(e).context(format!("invalid [preprocessor.{}] configuration", name))
+        })?;
+        Ok(wavedrom)
+    }
+
+    /// Check the options the types of the fields don't already.
+    fn validate(&self) -> std::result::Result<(), String> {
+        let names = self
+            .block_names
+            .iter()
+            .flatten()
+            .map(|name| ("block-names", name));
+        for (option, name) in names.chain(self.keyword.iter().map(|name| ("keyword", name))) {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ',') {
+                return Err(format!(
+                    "`{}` has \"{}\", which isn't a fence language, those are a single word",
+                    option, name
+                ));
+            }
+            if self.translators.contains_key(name) {
+                return Err(format!(
+                    "`{}` has \"{}\", which is translated by `translators` already",
+                    option, name
+                ));
+            }
+        }
+        if let Some(name) = self.defines.keys().find(|name| !defines::is_name(name)) {
+            return Err(format!(
+                "`defines` has \"{}\", which isn't a name of letters, digits, `-`, `_` and `.`",
+                name
+            ));
+        }
+        for pattern in &self.exclude {
+            if let Err(e) = glob::Pattern::new(pattern) {
+                return Err(format!(
+                    "`exclude` has \"{}\", which isn't a glob pattern: {}",
+                    pattern, e.msg
+                ));
+            }
+        }
+        let skins = [("skin", &self.skin), ("dark-skin", &self.dark_skin)];
+        for (option, skin) in skins {
+            if let Some(skin) = skin {
+                if !options::is_name(skin) {
+                    return Err(format!(
+                        "`{} = \"{}\"` isn't the name of a skin",
+                        option, skin
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The fence languages of the blocks that are diagrams.
+    pub(crate) fn languages(&self) -> Vec<&str> {
+        match &self.block_names {
+            Some(names) => names
+                .iter()
+                .map(String::as_str)
+                .chain([blocks::INCLUDE, blocks::REGISTER, blocks::ASSIGN])
+                .chain(self.keyword.as_deref())
+                .collect(),
+            None => blocks::LANGUAGES
+                .iter()
+                .copied()
+                .chain(self.keyword.as_deref())
+                .collect(),
+        }
+    }
+
+    /// Whether the preprocessor should run for `renderer`.
+    pub fn supports_renderer(&self, renderer: &str) -> bool {
+        match &self.renderers {
+            Some(renderers) => renderers.iter().any(|r| r == renderer),
+            None => DEFAULT_RENDERERS.contains(&renderer),
+        }
+    }
+
+    /// The configuration for a live preview: without rendering at build time, or the book-wide
+    /// index of signals, unless `fast-preview` is turned off.
+    pub(crate) fn for_preview(mut self) -> Self {
+        if !self.fast_preview.unwrap_or(true) {
+            return self;
+        }
+        if self.mode.is_prerendered() {
+            // The book may not have the runtime installed, pre-rendering needs none.
+            self.mode = Mode::Script;
+            self.inline_runtime = true;
+        }
+        self.link_signals = false;
+        self.og_image = false;
+        self.thumbnails = false;
+        self
+    }
+
+    /// The configuration for a renderer getting the diagrams as image files, without the
+    /// features only the html renderer has a use for.
+    pub(crate) fn for_images(mut self) -> Self {
+        self.link_signals = false;
+        self.og_image = false;
+        self.standalone = false;
+        self.bundle = false;
+        self.spill_size = None;
+        self
+    }
+
+    /// The format the diagrams are rendered to for the renderers other than html.
+    pub(crate) fn image_format(&self) -> render::Format {
+        self.image_format.unwrap_or(render::Format::Png)
+    }
+
+    /// Whether the chapter at `path`, relative to the book's `src` directory, is left as it is
+    /// because `exclude` matches it.
+    pub fn excludes(&self, path: Option<&Path>) -> bool {
+        let path = match path {
+            Some(path) => path,
+            None => return false,
+        };
+        self.exclude.iter().any(|pattern| {
+            glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path(path))
+        })
+    }
+
+    /// Whether `warning` is allowed for the chapter at `path`, relative to the book's `src`
+    /// directory. Warnings about the whole book have no `path`.
+    pub fn allows(&self, warning: &str, path: Option<&Path>) -> bool {
+        self.allow.iter().any(|allow| match allow {
+            Allow::Everywhere(allowed) => allowed == warning,
+            Allow::Below {
+                warning: allowed,
+                path: below,
+            } => allowed == warning && path.is_some_and(|path| path.starts_with(below)),
+        })
+    }
+
+    /// The entries of `allow` that don't name any lint or warning.
+    pub(crate) fn unknown_allows(&self) -> Vec<&str> {
+        self.allow
+            .iter()
+            .map(Allow::warning)
+            .filter(|warning| {
+                !WARNINGS.contains(warning)
+                    && !lint::NAMES.contains(warning)
+                    && !a11y::NAMES.contains(warning)
+            })
+            .collect()
+    }
+
+    /// How numbers are written in the `locale`, as in Rust if there's none or it isn't known.
+    pub(crate) fn numbers(&self) -> Numbers {
+        self.locale
+            .as_deref()
+            .and_then(numbers::for_locale)
+            .unwrap_or_default()
+    }
+
+    /// The `locale`, if it isn't one [`WavedromConfig::numbers`] knows.
+    pub(crate) fn unknown_locale(&self) -> Option<&str> {
+        self.locale
+            .as_deref()
+            .filter(|locale| numbers::for_locale(locale).is_none())
+    }
+
+    /// How many render commands, or chapters, to work on at the same time, at least one.
+    pub(crate) fn jobs(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1)
+    }
+
+    /// Whether diagrams get a `title` to show when hovering them.
+    pub(crate) fn tooltips(&self) -> bool {
+        self.tooltips.unwrap_or(true)
+    }
+
+    /// Whether the chapters load the runtime from `cdn-url`, rather than inlining it or having it
+    /// in `additional-js`.
+    pub(crate) fn runtime_from_cdn(&self) -> bool {
+        self.source == RuntimeSource::Cdn && !self.inline_runtime
+    }
+
+    /// The directory of the WaveDrom release on the CDN, with a trailing `/`.
+    pub(crate) fn cdn_url(&self) -> String {
+        let url = self.cdn_url.as_deref().unwrap_or(WAVEDROM_CDN);
+        if url.ends_with('/') {
+            url.to_string()
+        } else {
+            format!("{}/", url)
+        }
+    }
+
+    /// The address of the WaveDrom editor, see [`WavedromConfig::editor_url`].
+    pub(crate) fn editor_url(&self) -> &str {
+        self.editor_url.as_deref().unwrap_or(DEFAULT_EDITOR_URL)
+    }
+
+    /// The word the numbers of captioned diagrams are prefixed with.
+    pub(crate) fn caption_prefix(&self) -> &str {
+        self.caption_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_CAPTION_PREFIX)
+    }
+
+    /// The format of the labels of captioned diagrams.
+    pub(crate) fn caption_format(&self) -> &str {
+        self.caption_format
+            .as_deref()
+            .unwrap_or(DEFAULT_CAPTION_FORMAT)
+    }
+
+    /// The program and leading arguments of the render command.
+    pub(crate) fn render_command(&self) -> Vec<String> {
+        self.render_command
+            .as_deref()
+            .unwrap_or(render::DEFAULT_COMMAND)
+            .split_whitespace()
+            .map(String::from)
+            .collect()
+    }
+
+    /// The images rendered at build time, kept in the `render-cache-dir` unless the cache is
+    /// turned off or the directory wasn't resolved against the book's root.
+    pub(crate) fn render_cache(&self) -> render::Cache {
+        let dir = self
+            .render_cache_dir
+            .clone()
+            .filter(|dir| self.render_cache.unwrap_or(true) && dir.is_absolute());
+        render::Cache::new(dir)
+    }
+
+    /// The render command with its limits, where 0 stands for no limit.
+    pub(crate) fn renderer(&self) -> render::Renderer {
+        let timeout = self.render_timeout.unwrap_or(render::DEFAULT_TIMEOUT);
+        let max_size = self.render_max_size.unwrap_or(render::DEFAULT_MAX_SIZE);
+        render::Renderer {
+            command: self.render_command(),
+            timeout: Some(Duration::from_secs(timeout)).filter(|_| timeout > 0),
+            max_size: Some(max_size).filter(|&max_size| max_size > 0),
+            font: self.render_font.clone().map(|family| Font {
+                family,
+                file: self.render_font_file.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use mdbook::preprocess::PreprocessorContext;
+    use mdbook::Config;
+    use serde_json::json;
+
+    use super::{MissingAssets, Mode, PaletteColor, WavedromConfig};
+
+    fn context(book_toml: &str) -> PreprocessorContext {
+        let config: Config = book_toml.parse().unwrap();
+        let ctx = serde_json::json!({
+            "root": "/book",
+            "config": config,
+            "renderer": "html",
+            "mdbook_version": mdbook::MDBOOK_VERSION,
+        });
+        serde_json::from_value(ctx).unwrap()
+    }
+
+    #[test]
+    fn defaults_without_table() {
+        let ctx = context("[book]\ntitle = \"Test\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert!(!config.timings);
+    }
+
+    #[test]
+    fn reads_preprocessor_table() {
+        let ctx = context(
+            r#"
+[preprocessor.wavedrom]
+command = "mdbook-wavedrom"
+timings = true
+"#,
+        );
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert!(config.timings);
+    }
+
+    #[test]
+    fn checks_diagram_defaults() {
+        let ctx = context(
+            "[preprocessor.wavedrom]\nkeyword = \"timing\"\nskin = \"narrow\"\nhscale = 2\n",
+        );
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(
+            vec![
+                "wavedrom",
+                "wavedrom-include",
+                "register",
+                "wavedrom-assign",
+                "timing"
+            ],
+            config.languages()
+        );
+
+        let ctx = context("[preprocessor.wavedrom]\nskin = \"my skin\"\n");
+        let err = WavedromConfig::from_context(&ctx, "wavedrom").unwrap_err();
+        assert_eq!(
+            "invalid [preprocessor.wavedrom] configuration: `skin = \"my skin\"` isn't the name of a skin",
+            format!("{:#}", err)
+        );
+        let ctx = context("[preprocessor.wavedrom]\ndark-skin = \"dark.js\"\n");
+        let err = WavedromConfig::from_context(&ctx, "wavedrom").unwrap_err();
+        assert_eq!(
+            "invalid [preprocessor.wavedrom] configuration: `dark-skin = \"dark.js\"` isn't the name of a skin",
+            format!("{:#}", err)
+        );
+        let ctx = context("[preprocessor.wavedrom]\nhscale = 0\n");
+        assert!(WavedromConfig::from_context(&ctx, "wavedrom").is_err());
+        let ctx = context("[preprocessor.wavedrom]\nkeyword = \"wave form\"\n");
+        assert!(WavedromConfig::from_context(&ctx, "wavedrom").is_err());
+    }
+
+    #[test]
+    fn excluded_chapters() {
+        let ctx = context("[preprocessor.wavedrom]\nexclude = [\"examples/*\", \"raw.md\"]\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert!(config.excludes(Some(Path::new("examples/fences.md"))));
+        assert!(config.excludes(Some(Path::new("raw.md"))));
+        assert!(!config.excludes(Some(Path::new("intro.md"))));
+        assert!(!config.excludes(None));
+
+        let ctx = context("[preprocessor.wavedrom]\nexclude = [\"examples/***\"]\n");
+        let err = WavedromConfig::from_context(&ctx, "wavedrom").unwrap_err();
+        assert!(format!("{:#}", err)
+            .contains("`exclude` has \"examples/***\", which isn't a glob pattern"));
+    }
+
+    #[test]
+    fn block_names() {
+        let ctx = context("[preprocessor.wavedrom]\nblock-names = [\"wavejson\", \"bitfield\"]\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(
+            vec![
+                "wavejson",
+                "bitfield",
+                "wavedrom-include",
+                "register",
+                "wavedrom-assign"
+            ],
+            config.languages()
+        );
+
+        let ctx = context(
+            "[preprocessor.wavedrom]\nblock-names = [\"wavedrom\", \"timing\"]\n\
+             [preprocessor.wavedrom.translators]\ntiming = \"timing2wavejson\"\n",
+        );
+        let err = WavedromConfig::from_context(&ctx, "wavedrom").unwrap_err();
+        assert_eq!(
+            "invalid [preprocessor.wavedrom] configuration: `block-names` has \"timing\", \
+             which is translated by `translators` already",
+            format!("{:#}", err)
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        let ctx = context(
+            r#"
+[preprocessor.wavedrom]
+mode = "data-uri"
+missing-assets = "error"
+
+[preprocessor.wavedrom.profile.draft]
+mode = "script"
+render-timeout = 5
+"#,
+        );
+
+        let release = WavedromConfig::with_profile(&ctx.config, "wavedrom", None).unwrap();
+        assert_eq!(Mode::DataUri, release.mode);
+        assert_eq!(None, release.render_timeout);
+
+        let draft = WavedromConfig::with_profile(&ctx.config, "wavedrom", Some("draft")).unwrap();
+        assert_eq!(Mode::Script, draft.mode);
+        assert_eq!(MissingAssets::Error, draft.missing_assets);
+        assert_eq!(Some(5), draft.render_timeout);
+
+        let e = WavedromConfig::with_profile(&ctx.config, "wavedrom", Some("fast")).unwrap_err();
+        assert_eq!(
+            "there is no [preprocessor.wavedrom.profile.fast] profile",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let ctx = context("[preprocessor.wavedrom]\nmode = \"data-uri\"\nlink-signals = true\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+
+        let preview = config.clone().for_preview();
+        assert_eq!(Mode::Script, preview.mode);
+        assert!(preview.inline_runtime);
+        assert!(!preview.link_signals);
+
+        let full = WavedromConfig {
+            fast_preview: Some(false),
+            ..config
+        }
+        .for_preview();
+        assert_eq!(Mode::DataUri, full.mode);
+        assert!(full.link_signals);
+    }
+
+    #[test]
+    fn allowed_warnings() {
+        let ctx = context(
+            r#"
+[preprocessor.wavedrom]
+allow = ["version-mismatch", { warning = "dangling-edge", path = "appendix" }, "no-such-lint"]
+"#,
+        );
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+
+        assert!(config.allows("version-mismatch", None));
+        assert!(config.allows("dangling-edge", Some(Path::new("appendix/regs.md"))));
+        assert!(!config.allows("dangling-edge", Some(Path::new("appendix.md"))));
+        assert!(!config.allows("dangling-edge", None));
+        assert!(!config.allows("render-failed", None));
+        assert_eq!(vec!["no-such-lint"], config.unknown_allows());
+    }
+
+    #[test]
+    fn palette() {
+        let ctx = context("[preprocessor.wavedrom.palette]\nbus = 5\nerror = \"red\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Some(&PaletteColor::Digit(5)), config.palette.get("bus"));
+        assert_eq!(
+            Some(&PaletteColor::Name("red".into())),
+            config.palette.get("error")
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let ctx = context("[preprocessor.wavedrom.rename]\nrst_n = \"RESETn\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Some(&"RESETn".to_string()), config.rename.get("rst_n"));
+    }
+
+    #[test]
+    fn defines() {
+        let ctx = context(
+            "[preprocessor.wavedrom.defines]\nclk = \"{name: 'clk'}\"\nconfig = { hscale = 2 }\n",
+        );
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Some(&json!("{name: 'clk'}")), config.defines.get("clk"));
+        assert_eq!(Some(&json!({"hscale": 2})), config.defines.get("config"));
+
+        let ctx = context("[preprocessor.wavedrom.defines]\n\"a b\" = 1\n");
+        let err = WavedromConfig::from_context(&ctx, "wavedrom").unwrap_err();
+        assert!(format!("{:#}", err).contains("`defines` has \"a b\", which isn't a name"));
+    }
+
+    #[test]
+    fn renderer_allowlist() {
+        let config = WavedromConfig::default();
+        assert!(config.supports_renderer("html"));
+        assert!(config.supports_renderer("linkcheck"));
+        assert!(config.supports_renderer("epub"));
+        assert!(!config.supports_renderer("markdown"));
+
+        let ctx = context("[preprocessor.wavedrom]\nrenderers = [\"epub\", \"markdown\"]\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert!(!config.supports_renderer("html"));
+        assert!(config.supports_renderer("epub"));
+        assert!(config.supports_renderer("markdown"));
+    }
+
+    #[test]
+    fn render_limits() {
+        let renderer = WavedromConfig::default().renderer();
+        assert_eq!(Some(Duration::from_secs(60)), renderer.timeout);
+        assert_eq!(Some(16 * 1024 * 1024), renderer.max_size);
+
+        let ctx = context("[preprocessor.wavedrom]\nrender-timeout = 0\nrender-max-size = 1024\n");
+        let renderer = WavedromConfig::from_context(&ctx, "wavedrom")
+            .unwrap()
+            .renderer();
+        assert_eq!(None, renderer.timeout);
+        assert_eq!(Some(1024), renderer.max_size);
+    }
+
+    #[test]
+    fn reads_mode() {
+        let ctx = context("[preprocessor.wavedrom]\nmode = \"iframe\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Mode::Iframe, config.mode);
+        assert_eq!(Mode::Script, WavedromConfig::default().mode);
+    }
+
+    #[test]
+    fn render_command() {
+        assert_eq!(
+            vec!["wavedrom-cli"],
+            WavedromConfig::default().render_command()
+        );
+
+        let ctx = context("[preprocessor.wavedrom]\nrender-command = \"npx wavedrom-cli\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(vec!["npx", "wavedrom-cli"], config.render_command());
+    }
+
+    #[test]
+    fn reads_data_uri_mode() {
+        let ctx = context("[preprocessor.wavedrom]\nmode = \"data-uri\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Mode::DataUri, config.mode);
+
+        let ctx = context("[preprocessor.wavedrom]\nmode = \"inline-svg\"\n");
+        let config = WavedromConfig::from_context(&ctx, "wavedrom").unwrap();
+        assert_eq!(Mode::InlineSvg, config.mode);
+        assert!(config.mode.is_prerendered());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let ctx = context("[preprocessor.wavedrom]\ntimings = \"yes\"\n");
+        assert!(WavedromConfig::from_context(&ctx, "wavedrom").is_err());
+    }
+}
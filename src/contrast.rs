@@ -0,0 +1,333 @@
+//! The `low-contrast` lint: diagrams whose text or lines are hard to make out on the page
+//! background of the book's themes, like the black lines of pre-rendered images in a dark theme.
+//!
+//! The colors a diagram is drawn in depend on how it's embedded. With the theme stylesheet
+//! `install` adds, diagrams of `script` mode take the colors of the active mdbook theme. Images
+//! of `data-uri` and `inline-svg` mode and frames keep the colors of the default skin, on a
+//! transparent background. Text colored by `colors` or a lane's `color` keeps its color either way.
+//!
+//! The themes checked are the `default-theme` and `preferred-dark-theme` the book sets, and the
+//! ones given a background with `backgrounds`. Text needs a contrast ratio of 4.5:1 with the page
+//! background, waves and arrows 3:1, as WCAG asks.
+
+use mdbook::config::HtmlConfig;
+use serde_json::Value;
+
+use crate::config::Mode;
+use crate::lint::Warning;
+use crate::WavedromConfig;
+
+/// The stylesheet drawing diagrams in the colors of the theme.
+const THEME_CSS: &str = "wavedrom-theme.css";
+
+/// Contrast ratios WCAG asks for.
+const TEXT: f64 = 4.5;
+const GRAPHICS: f64 = 3.0;
+
+/// The page background, text, icon and link colors of mdbook's themes.
+const MDBOOK_THEMES: &[(&str, [&str; 4])] = &[
+    ("light", ["#ffffff", "#000000", "#747474", "#20609f"]),
+    ("rust", ["#e1e1db", "#262625", "#737480", "#2b79a2"]),
+    ("coal", ["#131516", "#98a3ad", "#43484d", "#2b79a2"]),
+    ("navy", ["#161923", "#bcbdd0", "#737480", "#2b79a2"]),
+    ("ayu", ["#0f141a", "#c5c5c5", "#737480", "#0096cf"]),
+];
+
+/// The colors of the default skin, and the theme stylesheet's fallbacks.
+const SKIN: Colors = Colors {
+    text: "#000000",
+    muted: "#aaaaaa",
+    accent: "#0041c4",
+};
+
+/// The colors diagrams are drawn in. Waves are drawn in the text color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Colors {
+    text: &'static str,
+    muted: &'static str,
+    accent: &'static str,
+}
+
+/// A theme the book's pages can be shown in.
+#[derive(Debug, Clone, PartialEq)]
+struct Theme {
+    name: String,
+    background: Rgb,
+    colors: Colors,
+}
+
+/// The themes of a book, with the colors its diagrams are drawn in.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Themes {
+    themes: Vec<Theme>,
+    /// Whether diagrams take the colors of the theme, or keep the ones of the skin.
+    themed: bool,
+}
+
+impl Themes {
+    pub(crate) fn new(html: &HtmlConfig, config: &WavedromConfig) -> Self {
+        let themed = config.mode == Mode::Script
+            && html
+                .additional_css
+                .iter()
+                .any(|file| file.file_name() == Some(THEME_CSS.as_ref()));
+        let names = html
+            .default_theme
+            .iter()
+            .chain(&html.preferred_dark_theme)
+            .chain(config.backgrounds.keys())
+            .map(|name| name.to_ascii_lowercase());
+        let mut themes: Vec<Theme> = vec![];
+        for name in names {
+            if themes.iter().any(|theme| theme.name == name) {
+                continue;
+            }
+            let builtin = MDBOOK_THEMES
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, colors)| colors);
+            let background = config
+                .backgrounds
+                .get(&name)
+                .map(String::as_str)
+                .or_else(|| builtin.map(|colors| colors[0]))
+                .and_then(Rgb::parse);
+            let background = match background {
+                Some(background) => background,
+                None => continue,
+            };
+            let colors = match builtin {
+                Some(colors) if themed => Colors {
+                    text: colors[1],
+                    muted: colors[2],
+                    accent: colors[3],
+                },
+                _ => SKIN,
+            };
+            themes.push(Theme {
+                name,
+                background,
+                colors,
+            });
+        }
+        Themes { themes, themed }
+    }
+}
+
+/// A color, by its red, green and blue channels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rgb([u8; 3]);
+
+impl Rgb {
+    /// A color written like `#1e90ff` or `#fff`.
+    fn parse(color: &str) -> Option<Self> {
+        let hex = color.trim().strip_prefix('#')?;
+        let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+        match hex.len() {
+            3 => {
+                let digit = |i: usize| channel(&hex[i..i + 1].repeat(2));
+                Some(Rgb([digit(0)?, digit(1)?, digit(2)?]))
+            }
+            6 => Some(Rgb([
+                channel(&hex[0..2])?,
+                channel(&hex[2..4])?,
+                channel(&hex[4..6])?,
+            ])),
+            _ => None,
+        }
+    }
+
+    /// The relative luminance, as WCAG defines it.
+    fn luminance(self) -> f64 {
+        let linear = |channel: u8| {
+            let c = f64::from(channel) / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let [r, g, b] = self.0;
+        0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+    }
+
+    /// The contrast ratio with `other`, from 1 to 21.
+    fn contrast(self, other: Rgb) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+}
+
+/// What a diagram draws in a color: the part, its color and the contrast it needs.
+type Use = (&'static str, String, f64);
+
+/// The parts of the `diagram` drawn on the page background, in the `colors`.
+fn uses(diagram: &Value, colors: Colors) -> Vec<Use> {
+    let mut uses: Vec<Use> = vec![];
+    let lanes = crate::wavejson::lanes(diagram);
+    if !lanes.is_empty() {
+        uses.push(("signal names", colors.text.into(), TEXT));
+    }
+    if lanes.iter().any(|lane| lane.contains_key("wave")) {
+        uses.push(("waves", colors.text.into(), GRAPHICS));
+    }
+    let edges = diagram.get("edge").and_then(Value::as_array);
+    if edges.is_some_and(|edges| !edges.is_empty()) {
+        uses.push(("arrows", colors.accent.into(), GRAPHICS));
+    }
+    let ticks = ["/head/tick", "/head/tock", "/foot/tick", "/foot/tock"];
+    if ticks.iter().any(|tick| diagram.pointer(tick).is_some()) || has_muted_text(diagram) {
+        uses.push(("muted text", colors.muted.into(), TEXT));
+    }
+    let mut fills = vec![];
+    text_fills(diagram, &mut fills);
+    for fill in fills {
+        if !uses.iter().any(|(_, color, _)| *color == fill) {
+            uses.push(("colored text", fill, TEXT));
+        }
+    }
+    uses
+}
+
+/// Whether the `value` has text in the skin's muted class, like a watermark.
+fn has_muted_text(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => {
+            object.get("class").and_then(Value::as_str) == Some("muted")
+                || object.values().any(has_muted_text)
+        }
+        Value::Array(items) => items.iter().any(has_muted_text),
+        _ => false,
+    }
+}
+
+/// The `fill` colors of the text markup of `value`, like the `tspan` of a colored name.
+fn text_fills(value: &Value, fills: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(fill) = object.get("fill").and_then(Value::as_str) {
+                fills.push(fill.to_ascii_lowercase());
+            }
+            object.values().for_each(|value| text_fills(value, fills));
+        }
+        Value::Array(items) => items.iter().for_each(|value| text_fills(value, fills)),
+        _ => {}
+    }
+}
+
+/// Check the parts of the rendered `diagram` against the page backgrounds of the `themes`.
+pub(crate) fn check(diagram: &Value, themes: &Themes) -> Vec<Warning> {
+    let skin = diagram
+        .pointer("/config/skin")
+        .and_then(Value::as_str)
+        .unwrap_or("default");
+    // Other skins have colors of their own, only the stylesheet's are known.
+    if skin != "default" && !themes.themed {
+        return vec![];
+    }
+
+    let mut warnings = vec![];
+    for theme in &themes.themes {
+        for (part, color, needed) in uses(diagram, theme.colors) {
+            let rgb = match Rgb::parse(&color) {
+                Some(rgb) => rgb,
+                None => continue,
+            };
+            let ratio = rgb.contrast(theme.background);
+            if ratio < needed {
+                warnings.push(Warning {
+                    lint: "low-contrast",
+                    message: format!(
+                        "the contrast of the {} in {} with the background of the `{}` theme is \
+                         {:.1}:1, less than the {}:1 WCAG asks for",
+                        part, color, theme.name, ratio, needed
+                    ),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use mdbook::config::HtmlConfig;
+    use pretty_assertions::assert_eq;
+
+    use super::{check, Rgb, Themes};
+    use crate::config::Mode;
+    use crate::{wavejson, WavedromConfig};
+
+    fn lints(diagram: &str, themes: &Themes) -> Vec<String> {
+        check(&wavejson::parse(diagram).unwrap(), themes)
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn computes_contrast() {
+        let black = Rgb::parse("#000").unwrap();
+        let white = Rgb::parse("#ffffff").unwrap();
+        assert_eq!(21.0, black.contrast(white));
+        assert_eq!(1.0, white.contrast(white));
+        assert_eq!(None, Rgb::parse("navy"));
+    }
+
+    #[test]
+    fn checks_the_themes() {
+        let diagram = "{signal: [{name: 'clk', wave: 'p.'}], edge: ['a->b']}";
+        let data_uri = WavedromConfig {
+            mode: Mode::DataUri,
+            ..Default::default()
+        };
+        let html = HtmlConfig {
+            preferred_dark_theme: Some("navy".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "the contrast of the signal names in #000000 with the background of the `navy` \
+                 theme is 1.2:1, less than the 4.5:1 WCAG asks for",
+                "the contrast of the waves in #000000 with the background of the `navy` theme \
+                 is 1.2:1, less than the 3:1 WCAG asks for",
+                "the contrast of the arrows in #0041c4 with the background of the `navy` theme \
+                 is 2.1:1, less than the 3:1 WCAG asks for",
+            ],
+            lints(diagram, &Themes::new(&html, &data_uri))
+        );
+
+        // Themed, the diagrams take the colors of the theme.
+        let html = HtmlConfig {
+            additional_css: vec![PathBuf::from("wavedrom-theme.css")],
+            ..html
+        };
+        let config = WavedromConfig::default();
+        assert!(lints(diagram, &Themes::new(&html, &config)).is_empty());
+        assert_eq!(
+            vec![
+                "the contrast of the colored text in #0066cc with the background of the `navy` \
+                 theme is 3.2:1, less than the 4.5:1 WCAG asks for"
+            ],
+            lints(
+                "{signal: [{name: ['tspan', {fill: '#0066cc'}, 'req']}]}",
+                &Themes::new(&html, &config)
+            )
+        );
+
+        // Backgrounds of custom themes, `navy` and `paper` failing, and skins of their own.
+        assert!(lints(diagram, &Themes::new(&HtmlConfig::default(), &data_uri)).is_empty());
+        let mut config = data_uri;
+        config.backgrounds.insert("light".into(), "#fafafa".into());
+        config.backgrounds.insert("paper".into(), "#222".into());
+        let themes = Themes::new(&html, &config);
+        assert_eq!(6, lints(diagram, &themes).len());
+        assert!(lints(
+            "{signal: [{wave: '01'}], config: {skin: 'narrow'}}",
+            &themes
+        )
+        .is_empty());
+    }
+}
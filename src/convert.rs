@@ -0,0 +1,85 @@
+//! Converting a diagram between the syntaxes it can be written in.
+//!
+//! The preprocessor reads WaveJSON, JSON5 written in the style of JavaScript object literals.
+//! Strict JSON is a subset of it, and what most other tools read and write.
+
+use std::fmt;
+use std::str::FromStr;
+
+use mdbook::errors::{Error, Result};
+
+use crate::wavejson;
+
+/// A syntax diagrams can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// WaveJSON object literals, like in the wavedrom blocks of a book. Also reads JSON5.
+    WaveJson,
+    /// Strict JSON.
+    Json,
+}
+
+impl FromStr for Syntax {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wavejson" | "json5" => Ok(Syntax::WaveJson),
+            "json" => Ok(Syntax::Json),
+            _ => Err(Error::msg(format!(
+                "unknown syntax `{}`, expected `wavejson` or `json`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Syntax::WaveJson => "wavejson",
+            Syntax::Json => "json",
+        })
+    }
+}
+
+/// Convert the diagram `source` written in `from` to `to`, one lane per line.
+pub fn convert(source: &str, from: Syntax, to: Syntax) -> Result<String> {
+    let diagram = match from {
+        Syntax::WaveJson => wavejson::parse(source).map_err(Error::from),
+        Syntax::Json => serde_json::from_str(source).map_err(Error::from),
+    }
+    .map_err(|e| e.context(format!("invalid {}", from)))?;
+
+    let mut output = match to {
+        Syntax::WaveJson => wavejson::to_string_pretty(&diagram),
+        Syntax::Json => serde_json::to_string_pretty(&diagram)?,
+    };
+    output.push('\n');
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{convert, Syntax};
+
+    #[test]
+    fn converts_both_ways() {
+        let wavejson = "{signal: [\n  {name: 'clk', wave: 'p.'},\n  {name: 'it\\'s', wave: '01'}\n],\nhead: {text: 'A'}}\n";
+        let json = convert(wavejson, Syntax::WaveJson, Syntax::Json).unwrap();
+        assert!(json.starts_with("{\n  \"signal\": [\n    {\n      \"name\": \"clk\","));
+        assert!(json.contains("\"it's\""));
+        assert_eq!(
+            wavejson,
+            convert(&json, Syntax::Json, Syntax::WaveJson).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_is_strict() {
+        assert!(convert("{signal: []}", Syntax::Json, Syntax::WaveJson).is_err());
+        assert!("yaml".parse::<Syntax>().is_err());
+    }
+}
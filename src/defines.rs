@@ -0,0 +1,174 @@
+//! WaveJSON fragments defined once for the whole book, in the `[preprocessor.wavedrom.defines]`
+//! table, and put into any diagram by a `{{name}}` placeholder:
+//!
+//! ```text
+//! [preprocessor.wavedrom.defines]
+//! clk = "{name: 'clk', wave: 'p.......'}"
+//! config = { hscale = 2, skin = "narrow" }
+//!
+//! {signal: [{{clk}}, {name: 'cs_n', wave: '10.....1'}], config: {{config}}}
+//! ```
+//!
+//! Text is put in as it's written, any other value written as WaveJSON. Defines may use other
+//! defines, as long as none ends up using itself.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::wavejson;
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// Whether `name` can be the name of a define: letters, digits, `-`, `_` and `.`.
+pub(crate) fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// The WaveJSON `source` with its `{{name}}` placeholders replaced by the `defines`.
+///
+/// Placeholders of names there's no define for, or of defines that use themselves, are left in
+/// place and reported in `warnings`. Directives like `{{#include}}` aren't placeholders.
+pub(crate) fn expand<'a>(
+    source: Cow<'a, str>,
+    defines: &BTreeMap<String, Value>,
+    warnings: &mut Vec<String>,
+) -> Cow<'a, str> {
+    if !source.contains(OPEN) {
+        return source;
+    }
+    match substitute(&source, defines, &mut vec![], warnings) {
+        Some(expanded) => Cow::Owned(expanded),
+        None => source,
+    }
+}
+
+/// `text` with its placeholders replaced, `None` if none was. `within` are the defines whose
+/// values are being expanded.
+fn substitute<'d>(
+    text: &str,
+    defines: &'d BTreeMap<String, Value>,
+    within: &mut Vec<&'d str>,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let mut output = String::new();
+    let mut last = 0;
+    let mut from = 0;
+    while let Some(found) = text[from..].find(OPEN) {
+        let start = from + found;
+        let name_start = start + OPEN.len();
+        let end = match text[name_start..].find(CLOSE) {
+            Some(end) => name_start + end,
+            None => break,
+        };
+        let name = text[name_start..end].trim();
+        if !is_name(name) {
+            from = name_start;
+            continue;
+        }
+        from = end + CLOSE.len();
+        let (name, value) = match defines.get_key_value(name) {
+            Some(define) => define,
+            None => {
+                warnings.push(format!("There's no define named `{}`", name));
+                continue;
+            }
+        };
+        if within.contains(&name.as_str()) {
+            warnings.push(format!(
+                "The define `{}` uses itself, through {}",
+                name,
+                within
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            continue;
+        }
+
+        let value = match value {
+            Value::String(text) => text.clone(),
+            value => wavejson::to_string(value),
+        };
+        within.push(name);
+        let value = substitute(&value, defines, within, warnings).unwrap_or(value);
+        within.pop();
+        output.push_str(&text[last..start]);
+        output.push_str(&value);
+        last = from;
+    }
+    if last == 0 {
+        return None;
+    }
+    output.push_str(&text[last..]);
+    Some(output)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
+
+    use super::expand;
+
+    fn defines() -> BTreeMap<String, Value> {
+        let mut defines = BTreeMap::new();
+        defines.insert("clk".into(), json!("{name: 'clk', wave: 'p...'}"));
+        defines.insert("config".into(), json!({"hscale": 2, "skin": "narrow"}));
+        defines.insert(
+            "bus".into(),
+            json!("['Bus', {{clk}}, {name: 'd', wave: 'x3x'}]"),
+        );
+        defines.insert("loop".into(), json!("[{{again}}]"));
+        defines.insert("again".into(), json!("{{loop}}"));
+        defines
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let mut warnings = vec![];
+        assert_eq!(
+            "{signal: [['Bus', {name: 'clk', wave: 'p...'}, {name: 'd', wave: 'x3x'}]], \
+             config: {hscale: 2, skin: 'narrow'}}",
+            expand(
+                "{signal: [{{ bus }}], config: {{config}}}".into(),
+                &defines(),
+                &mut warnings
+            )
+        );
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        // Directives and text that isn't a name are no placeholders.
+        let source = "{{#include a.json5}}\n{signal: [{name: '{{ a b }}'}]}\n";
+        assert_eq!(source, expand(source.into(), &defines(), &mut warnings));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn warns_about_unknown_and_looping_defines() {
+        let mut warnings = vec![];
+        assert_eq!(
+            "{signal: [{{nope}}, [{{loop}}]]}",
+            expand(
+                "{signal: [{{nope}}, {{loop}}]}".into(),
+                &defines(),
+                &mut warnings
+            )
+        );
+        assert_eq!(
+            vec![
+                "There's no define named `nope`",
+                "The define `loop` uses itself, through `loop`, `again`",
+            ],
+            warnings
+        );
+    }
+}
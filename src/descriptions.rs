@@ -0,0 +1,144 @@
+//! Descriptions of a diagram's signals, shown as a table below it.
+//!
+//! They are given as a `descriptions` map next to the `signal` list, which WaveDrom itself
+//! doesn't know and never gets to see:
+//!
+//! ```text
+//! {signal: [
+//!   {name: 'req', wave: '0.1..0'},
+//!   {name: 'ack', wave: '1....0'}
+//! ],
+//! descriptions: {req: 'Request from the host', ack: 'Acknowledge, active low'}}
+//! ```
+
+use serde_json::Value;
+
+use crate::html::escape_html;
+use crate::wavejson;
+
+/// Key of the descriptions in the WaveJSON document.
+const KEY: &str = "descriptions";
+
+/// Remove the descriptions from `diagram`, returning `(signal, description)` in the order given.
+///
+/// Entries that aren't text, or don't belong to any signal of the diagram, are reported in
+/// `warnings`.
+pub(crate) fn take(diagram: &mut Value, warnings: &mut Vec<String>) -> Vec<(String, String)> {
+    let descriptions = match diagram.as_object_mut().and_then(|d| d.remove(KEY)) {
+        Some(Value::Object(descriptions)) => descriptions,
+        Some(_) => {
+            warnings.push(format!(
+                "'{}' has to map signal names to their description",
+                KEY
+            ));
+            return vec![];
+        }
+        None => return vec![],
+    };
+
+    let names: Vec<&str> = wavejson::lanes(diagram)
+        .into_iter()
+        .filter_map(|lane| lane.get("name")?.as_str())
+        .collect();
+    let mut found = vec![];
+    for (signal, description) in descriptions {
+        let description = match description {
+            Value::String(description) => description,
+            _ => {
+                warnings.push(format!("The description of '{}' isn't text", signal));
+                continue;
+            }
+        };
+        if !names.contains(&signal.as_str()) {
+            warnings.push(format!("No signal named '{}' to describe", signal));
+        }
+        found.push((signal, description));
+    }
+    found
+}
+
+/// The markdown table listing `descriptions`.
+pub(crate) fn table(descriptions: &[(String, String)]) -> String {
+    let mut table = String::from("| Signal | Description |\n|--------|-------------|\n");
+    for (signal, description) in descriptions {
+        table.push_str(&format!("| `{}` | {} |\n", cell(signal), cell(description)));
+    }
+    table.push('\n');
+    table
+}
+
+/// The descriptions as an HTML table on a single line, with their text escaped.
+pub(crate) fn html_table(descriptions: &[(String, String)]) -> String {
+    let mut table = String::from(
+        "<table class=\"wavedrom-descriptions\"><thead><tr><th>Signal</th><th>Description</th></tr></thead><tbody>",
+    );
+    for (signal, description) in descriptions {
+        table.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td></tr>",
+            escape_html(signal),
+            escape_html(&description.replace('\n', " "))
+        ));
+    }
+    table.push_str("</tbody></table>");
+    table
+}
+
+/// Text that fits into a single table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{table, take};
+    use crate::wavejson;
+
+    #[test]
+    fn takes_descriptions_out_of_the_diagram() {
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'req', wave: '01'}, ['bus', {name: 'dat', wave: 'x='}]],\
+             descriptions: {dat: 'Data | payload', req: 'Request'}}",
+        )
+        .unwrap();
+        let mut warnings = vec![];
+        let descriptions = take(&mut diagram, &mut warnings);
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            "{signal: [{name: 'req', wave: '01'}, ['bus', {name: 'dat', wave: 'x='}]]}",
+            wavejson::to_string(&diagram)
+        );
+        assert_eq!(
+            "| Signal | Description |\n|--------|-------------|\n| `dat` | Data \\| payload |\n| `req` | Request |\n\n",
+            table(&descriptions)
+        );
+    }
+
+    #[test]
+    fn warns_about_unusable_entries() {
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'req'}], descriptions: {req: 1, ack: 'Acknowledge'}}",
+        )
+        .unwrap();
+        let mut warnings = vec![];
+        let descriptions = take(&mut diagram, &mut warnings);
+
+        assert_eq!(
+            vec![("ack".to_string(), "Acknowledge".to_string())],
+            descriptions
+        );
+        assert_eq!(
+            vec![
+                "The description of 'req' isn't text",
+                "No signal named 'ack' to describe"
+            ],
+            warnings
+        );
+
+        let mut diagram = wavejson::parse("{signal: [], descriptions: ['req']}").unwrap();
+        take(&mut diagram, &mut warnings);
+        assert_eq!(3, warnings.len());
+    }
+}
@@ -0,0 +1,82 @@
+use std::cell::Cell;
+
+thread_local! {
+    /// Warnings logged by the thread so far, for `strict`.
+    static WARNINGS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Count a warning, see `warn!`.
+pub(crate) fn count_warning() {
+    WARNINGS.with(|warnings| warnings.set(warnings.get() + 1));
+}
+
+/// Count the `warnings` another thread logged for this one, like the threads processing chapters.
+pub(crate) fn add_warnings(warnings: usize) {
+    WARNINGS.with(|counted| counted.set(counted.get() + warnings));
+}
+
+/// The number of warnings the thread logged so far.
+pub(crate) fn warnings() -> usize {
+    WARNINGS.with(Cell::get)
+}
+
+/// Overall result of an analysis subcommand, reported through the exit code.
+///
+/// The variants are ordered by severity, so the result of several checks is their `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    /// Nothing to report. Exit code 0.
+    Clean,
+    /// Only lint warnings were found. Exit code 1.
+    Warnings,
+    /// At least one diagram failed validation. Exit code 2.
+    Errors,
+    /// The analysis could not be completed, e.g. the book could not be read. Exit code 3.
+    Internal,
+}
+
+impl Outcome {
+    /// Outcome of an analysis that found `warnings` lint warnings and `errors` validation errors.
+    pub fn from_counts(warnings: usize, errors: usize) -> Self {
+        if errors > 0 {
+            Outcome::Errors
+        } else if warnings > 0 {
+            Outcome::Warnings
+        } else {
+            Outcome::Clean
+        }
+    }
+
+    /// The process exit code for this outcome.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Clean => 0,
+            Outcome::Warnings => 1,
+            Outcome::Errors => 2,
+            Outcome::Internal => 3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Outcome;
+
+    #[test]
+    fn outcome_from_counts() {
+        assert_eq!(Outcome::Clean, Outcome::from_counts(0, 0));
+        assert_eq!(Outcome::Warnings, Outcome::from_counts(3, 0));
+        assert_eq!(Outcome::Errors, Outcome::from_counts(0, 1));
+        assert_eq!(Outcome::Errors, Outcome::from_counts(5, 1));
+    }
+
+    #[test]
+    fn worst_outcome_wins() {
+        let outcome = [Outcome::Warnings, Outcome::Internal, Outcome::Clean]
+            .iter()
+            .copied()
+            .max();
+        assert_eq!(Some(Outcome::Internal), outcome);
+        assert_eq!(3, Outcome::Internal.exit_code());
+    }
+}
@@ -0,0 +1,497 @@
+//! Comparing the diagrams of a book between two git revisions, to review changes to them.
+//!
+//! The diagrams of a chapter are matched up in order. Diagrams that are the same in both
+//! revisions anchor the others, which are paired up as changed between the same anchors, or
+//! reported as added or removed. Diagrams are compared the way they are rendered, reformatting
+//! the WaveJSON doesn't change them.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use mdbook::errors::{Error, Result};
+use serde_json::{Map, Value};
+use tempfile::TempDir;
+
+use crate::html::{self, escape_html};
+use crate::{book, wavejson};
+
+/// What happened to a diagram between the revisions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    /// Changed, with what changed, like `lane 'req': wave '01..' -> '0.1.'`.
+    Changed(Vec<String>),
+}
+
+impl Change {
+    /// The change in a word, like `added`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed(_) => "changed",
+        }
+    }
+}
+
+/// A diagram that differs between the revisions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Where the diagram starts, e.g. `chapter/intro.md:12`, in the new revision unless it was
+    /// removed.
+    pub location: String,
+    /// Name of the diagram's chapter.
+    pub chapter: String,
+    /// Number of the diagram in its chapter, starting at 1.
+    pub diagram: usize,
+    pub change: Change,
+    /// The WaveJSON of the diagram in the old revision, unless it was added.
+    pub old: Option<String>,
+    /// The WaveJSON of the diagram in the new revision, unless it was removed.
+    pub new: Option<String>,
+}
+
+/// A diagram of a revision, the way it's rendered.
+struct Shown {
+    location: String,
+    chapter: String,
+    path: PathBuf,
+    index: usize,
+    source: String,
+}
+
+/// A checkout of a revision in a temporary directory, removed with it.
+struct Checkout {
+    _dir: TempDir,
+    /// The book's root in the checkout.
+    root: PathBuf,
+}
+
+/// Compare the diagrams of the book at `root` in the git revision `old` with the ones in `new`,
+/// or in the working tree if `new` isn't given.
+///
+/// Neither the working tree nor the index of the repository are touched.
+pub fn diff(root: &Path, old: &str, new: Option<&str>) -> Result<Vec<Entry>> {
+    let old = revision(root, old)?;
+    let new = match new {
+        Some(new) => revision(root, new)?,
+        None => diagrams(root)?,
+    };
+
+    // The chapters in the order of the new revision, then the ones only the old one has.
+    let mut paths: Vec<&Path> = vec![];
+    for shown in new.iter().chain(&old) {
+        if !paths.contains(&shown.path.as_path()) {
+            paths.push(&shown.path);
+        }
+    }
+    let mut entries = vec![];
+    for path in paths {
+        compare(
+            &in_chapter(&old, path),
+            &in_chapter(&new, path),
+            &mut entries,
+        );
+    }
+    Ok(entries)
+}
+
+fn in_chapter<'a>(diagrams: &'a [Shown], path: &Path) -> Vec<&'a Shown> {
+    diagrams.iter().filter(|d| d.path == path).collect()
+}
+
+/// The diagrams of the book at `root` in revision `rev`.
+fn revision(root: &Path, rev: &str) -> Result<Vec<Shown>> {
+    let checkout = checkout(root, rev)?;
+    diagrams(&checkout.root).map_err(|e| e.context(format!("Unable to load the book at {}", rev)))
+}
+
+/// Check out the files of revision `rev` of the repository the book at `root` is in.
+fn checkout(root: &Path, rev: &str) -> Result<Checkout> {
+    let toplevel = git(root, &["rev-parse", "--show-toplevel"], None)?;
+    let toplevel = Path::new(toplevel.trim());
+    let prefix = git(root, &["rev-parse", "--show-prefix"], None)?;
+
+    // A temporary index keeps the repository's own one as it is.
+    let dir = tempfile::tempdir()?;
+    let index = dir.path().join("index");
+    let files = dir.path().join("files");
+    let commit = git(
+        toplevel,
+        &[
+            "rev-parse",
+            "--verify",
+            "--end-of-options",
+            &format!("{}^{{commit}}", rev),
+        ],
+        None,
+    )
+    .map_err(|e| e.context(format!("Unable to read revision {}", rev)))?;
+    // Only the resolved commit reaches `read-tree`, so a revision can't pass for an option.
+    git(toplevel, &["read-tree", commit.trim()], Some(&index))?;
+    let prefix_arg = format!("--prefix={}/", files.display());
+    git(
+        toplevel,
+        &["checkout-index", "--all", &prefix_arg],
+        Some(&index),
+    )?;
+
+    Ok(Checkout {
+        root: files.join(prefix.trim()),
+        _dir: dir,
+    })
+}
+
+/// Run git in `dir`, with the index file `index` if given, and return its output.
+fn git(dir: &Path, args: &[&str], index: Option<&Path>) -> Result<String> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).args(args);
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let output = command
+        .output()
+        .map_err(|e| Error::from(e).context("Unable to run git"))?;
+    if !output.status.success() {
+        return Err(Error::msg(format!(
+            "'git {}' failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The rendered diagrams of the book at `root`, in book order.
+///
+/// Problems with the diagrams are left to `check` to report.
+fn diagrams(root: &Path) -> Result<Vec<Shown>> {
+    let book = book::load(root)?;
+    let mut diagrams = vec![];
+    book::for_each_diagram(&book.book, &book.config, &book.src_dir, |diagram| {
+        if !diagram.block.is_rendered() {
+            return;
+        }
+        let source = crate::transform(
+            &diagram.source,
+            &diagram.block.attrs,
+            diagram.page,
+            &mut vec![],
+            &mut vec![],
+        );
+        let source = match wavejson::parse(&source) {
+            Ok(parsed) => wavejson::to_string(&parsed),
+            Err(_) => source.trim().to_string(),
+        };
+        diagrams.push(Shown {
+            location: diagram.location.clone(),
+            chapter: diagram.chapter.name.clone(),
+            path: diagram.path.to_path_buf(),
+            index: diagram.index,
+            source,
+        });
+    });
+    Ok(diagrams)
+}
+
+/// Match up the `old` and `new` diagrams of a chapter, and add the ones that differ to
+/// `entries`.
+fn compare(old: &[&Shown], new: &[&Shown], entries: &mut Vec<Entry>) {
+    // The number of diagrams the two revisions have in common from `old[i]` and `new[j]` on.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i].source == new[j].source {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut added) = (vec![], vec![]);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].source == new[j].source {
+            pair(&removed, &added, entries);
+            removed.clear();
+            added.clear();
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            added.push(new[j]);
+            j += 1;
+        } else {
+            removed.push(old[i]);
+            i += 1;
+        }
+    }
+    pair(&removed, &added, entries);
+}
+
+/// Pair up the diagrams `removed` and `added` between the same unchanged ones as changed, and
+/// add them to `entries`.
+fn pair(removed: &[&Shown], added: &[&Shown], entries: &mut Vec<Entry>) {
+    for k in 0..removed.len().max(added.len()) {
+        let (old, new) = (removed.get(k).copied(), added.get(k).copied());
+        let (shown, change) = match (old, new) {
+            (Some(old), Some(new)) => (new, Change::Changed(changes(&old.source, &new.source))),
+            (Some(old), None) => (old, Change::Removed),
+            (None, Some(new)) => (new, Change::Added),
+            (None, None) => unreachable!("within the longer of the lists"),
+        };
+        entries.push(Entry {
+            location: shown.location.clone(),
+            chapter: shown.chapter.clone(),
+            diagram: shown.index,
+            change,
+            old: old.map(|old| old.source.clone()),
+            new: new.map(|new| new.source.clone()),
+        });
+    }
+}
+
+/// What changed from the WaveJSON `old` to `new`, lane by lane.
+fn changes(old: &str, new: &str) -> Vec<String> {
+    let (old, new) = match (wavejson::parse(old), wavejson::parse(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        _ => return vec!["the WaveJSON changed".into()],
+    };
+
+    let mut changes = vec![];
+    let old_lanes = named_lanes(&old);
+    let new_lanes = named_lanes(&new);
+    for (name, lane) in &old_lanes {
+        match new_lanes.iter().find(|(new_name, _)| new_name == name) {
+            Some((_, new_lane)) => {
+                let what = format!("lane '{}': ", name);
+                compare_keys(&what, lane, new_lane, "name", &mut changes);
+            }
+            None => changes.push(format!("removed lane '{}'", name)),
+        }
+    }
+    for (name, _) in &new_lanes {
+        if !old_lanes.iter().any(|(old_name, _)| old_name == name) {
+            changes.push(format!("added lane '{}'", name));
+        }
+    }
+    let order = |lanes: &[(String, _)], other: &[(String, _)]| -> Vec<String> {
+        lanes
+            .iter()
+            .map(|(name, _)| name.clone())
+            .filter(|name| other.iter().any(|(other, _)| other == name))
+            .collect()
+    };
+    if order(&old_lanes, &new_lanes) != order(&new_lanes, &old_lanes) {
+        changes.push("reordered the lanes".into());
+    }
+    if let (Some(old), Some(new)) = (old.as_object(), new.as_object()) {
+        compare_keys("", old, new, "signal", &mut changes);
+    }
+
+    // Like the groups or the styling of the names.
+    if changes.is_empty() {
+        changes.push("the WaveJSON changed".into());
+    }
+    changes
+}
+
+/// The lanes of `diagram` by the text of their name, or by their number if they have none.
+fn named_lanes(diagram: &Value) -> Vec<(String, &Map<String, Value>)> {
+    wavejson::lanes(diagram)
+        .into_iter()
+        .enumerate()
+        .map(|(i, lane)| {
+            let name = lane
+                .get("name")
+                .map(wavejson::text)
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| format!("#{}", i + 1));
+            (name, lane)
+        })
+        .collect()
+}
+
+/// Add the keys that differ between `old` and `new` to `changes`, besides `skip`, with `what`
+/// in front.
+fn compare_keys(
+    what: &str,
+    old: &Map<String, Value>,
+    new: &Map<String, Value>,
+    skip: &str,
+    changes: &mut Vec<String>,
+) {
+    let mut keys: Vec<&String> = vec![];
+    for key in old.keys().chain(new.keys()) {
+        if key != skip && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    for key in keys {
+        match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) if old == new => {}
+            (Some(old), Some(new)) => changes.push(format!(
+                "{}{} {} -> {}",
+                what,
+                key,
+                wavejson::to_string(old),
+                wavejson::to_string(new)
+            )),
+            (Some(_), None) => changes.push(format!("{}removed {}", what, key)),
+            (None, Some(new)) => changes.push(format!(
+                "{}added {} {}",
+                what,
+                key,
+                wavejson::to_string(new)
+            )),
+            (None, None) => {}
+        }
+    }
+}
+
+/// A page showing the diagrams of `entries` side by side, the ones of revision `old` on the left
+/// and the ones of `new` on the right.
+///
+/// The WaveDrom runtime is inlined, so the page works on its own, e.g. attached to a review.
+pub fn html(entries: &[Entry], old: &str, new: &str) -> String {
+    let cell = |source: &Option<String>| match source {
+        Some(source) => format!(
+            "<script type=\"WaveDrom\">{}</script>",
+            html::script_text(source)
+        ),
+        None => String::new(),
+    };
+    let mut body = String::new();
+    for entry in entries {
+        body.push_str(&format!(
+            "<h2>{}: {}, diagram {} ({})</h2>\n",
+            escape_html(&entry.location),
+            escape_html(&entry.chapter),
+            entry.diagram,
+            entry.change.kind()
+        ));
+        if let Change::Changed(changes) = &entry.change {
+            body.push_str("<ul>\n");
+            for change in changes {
+                body.push_str(&format!("<li><code>{}</code></li>\n", escape_html(change)));
+            }
+            body.push_str("</ul>\n");
+        }
+        body.push_str(&format!(
+            "<table>\n<tr><td>{}</td><td>{}</td></tr>\n</table>\n",
+            cell(&entry.old),
+            cell(&entry.new)
+        ));
+    }
+    if entries.is_empty() {
+        body.push_str("<p>No diagram changed.</p>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<title>{title}</title>
+{runtime}
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; table-layout: fixed; }}
+th, td {{ border: 1px solid #ccc; padding: 0.5em; vertical-align: top; overflow: auto; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<table>
+<tr><th>{old}</th><th>{new}</th></tr>
+</table>
+{body}</body>
+</html>
+"#,
+        title = escape_html(&format!("Diagrams changed from {} to {}", old, new)),
+        runtime = html::inline_runtime(),
+        old = escape_html(old),
+        new = escape_html(new),
+        body = body,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{changes, compare, Change, Shown};
+
+    fn shown(index: usize, source: &str) -> Shown {
+        Shown {
+            location: format!("intro.md:{}", index * 10),
+            chapter: "Intro".into(),
+            path: PathBuf::from("intro.md"),
+            index,
+            source: source.into(),
+        }
+    }
+
+    #[test]
+    fn matches_up_diagrams() {
+        let old = [shown(1, "a"), shown(2, "b"), shown(3, "c"), shown(4, "d")];
+        let new = [shown(1, "x"), shown(2, "a"), shown(3, "c'"), shown(4, "d")];
+        let mut entries = vec![];
+        compare(
+            &old.iter().collect::<Vec<_>>(),
+            &new.iter().collect::<Vec<_>>(),
+            &mut entries,
+        );
+
+        let found: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.location.as_str(), entry.change.kind()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("intro.md:10", "added"),
+                ("intro.md:30", "changed"),
+                ("intro.md:30", "removed")
+            ],
+            found
+        );
+        assert_eq!(Some("b"), entries[1].old.as_deref());
+        assert_eq!(Some("c'"), entries[1].new.as_deref());
+    }
+
+    #[test]
+    fn lists_changes_by_lane() {
+        assert_eq!(
+            vec![
+                "lane 'req': wave '01..' -> '0.1.'",
+                "lane 'req': added node '.a..'",
+                "removed lane 'ack'",
+                "added lane 'gnt'",
+                "added edge ['a->b']",
+            ],
+            changes(
+                "{signal: [{name: 'req', wave: '01..'}, {name: 'ack', wave: '0.1.'}]}",
+                "{signal: [{name: 'req', wave: '0.1.', node: '.a..'}, {name: 'gnt', wave: '0..1'}], \
+                 edge: ['a->b']}"
+            )
+        );
+        assert_eq!(
+            vec!["reordered the lanes"],
+            changes(
+                "{signal: [{name: 'a'}, {name: 'b'}]}",
+                "{signal: [{name: 'b'}, {name: 'a'}]}"
+            )
+        );
+        assert_eq!(
+            vec!["the WaveJSON changed"],
+            changes(
+                "{signal: [{name: 'a'}]}",
+                "{signal: [['grp', {name: 'a'}]]}"
+            )
+        );
+        assert_eq!(Change::Added.kind(), "added");
+    }
+}
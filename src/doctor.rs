@@ -0,0 +1,225 @@
+//! Checking that a book's diagrams can be rendered the way it's configured, and explaining why
+//! they can't.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::Result;
+use mdbook::preprocess::Preprocessor;
+use mdbook::Config;
+
+use crate::render::{self, Format};
+use crate::{assets, Outcome, Wavedrom, WavedromConfig};
+
+/// Programs the render command needs besides itself, when it's run through them.
+const NODE_PROGRAMS: &[&str] = &["npx", "wavedrom-cli"];
+
+/// The diagram rendered to try out the render command.
+const PROBE: &str = "{signal: [{name: 'clk', wave: 'p.'}]}";
+
+/// How a check went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of one check of [`doctor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub status: Status,
+    pub message: String,
+}
+
+/// What [`doctor`] found.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// The outcome to exit with.
+    pub fn outcome(&self) -> Outcome {
+        let count = |status| self.findings.iter().filter(|f| f.status == status).count();
+        Outcome::from_counts(count(Status::Warning), count(Status::Error))
+    }
+
+    fn push(&mut self, status: Status, message: String) {
+        self.findings.push(Finding { status, message });
+    }
+}
+
+/// Check the setup of the book at `root`: the render command, and the runtime files.
+pub fn doctor(root: &Path) -> Result<Report> {
+    let book_config = Config::from_disk(root.join("book.toml"))?;
+    let config =
+        WavedromConfig::from_config(&book_config, Wavedrom::default().name())?.relative_to(root);
+    let mut report = Report::default();
+
+    // Only `data-uri` and `inline-svg` depend on the render command for building, otherwise
+    // only `export-images` does.
+    let needed = if config.mode.is_prerendered() {
+        Status::Error
+    } else {
+        Status::Warning
+    };
+    render_command(&config, needed, &mut report);
+
+    if config.mode.is_prerendered() || config.inline_runtime {
+        report.push(
+            Status::Ok,
+            "The WaveDrom runtime doesn't have to be installed, the pages bring what they need"
+                .into(),
+        );
+    } else if config.runtime_from_cdn() {
+        report.push(
+            Status::Ok,
+            format!(
+                "The pages load the WaveDrom runtime from {}",
+                config.cdn_url()
+            ),
+        );
+    } else {
+        let additional_js = book_config
+            .html_config()
+            .map(|html| html.additional_js)
+            .unwrap_or_default();
+        let problems = assets::missing_runtime(root, &additional_js);
+        if problems.is_empty() {
+            report.push(Status::Ok, "The WaveDrom runtime is installed".into());
+        }
+        for problem in problems {
+            report.push(
+                Status::Error,
+                format!("{}, the diagrams would show as WaveJSON text", problem),
+            );
+        }
+    }
+    Ok(report)
+}
+
+/// Check that the render command exists and renders a diagram.
+fn render_command(config: &WavedromConfig, needed: Status, report: &mut Report) {
+    let renderer = config.renderer();
+    let consequence = if needed == Status::Error {
+        "the pre-rendered diagrams fall back to the script"
+    } else {
+        "`export-images` won't work"
+    };
+    let program = match renderer.command.first() {
+        Some(program) => program,
+        None => {
+            report.push(
+                needed,
+                format!("`render-command` is empty, {}", consequence),
+            );
+            return;
+        }
+    };
+
+    let found = match find_program(program) {
+        Some(found) => found,
+        None => {
+            let hint = if program == render::DEFAULT_COMMAND {
+                ", install it with `npm install -g wavedrom-cli`"
+            } else {
+                ""
+            };
+            report.push(
+                needed,
+                format!(
+                    "The render command `{}` isn't on the PATH{}, {}",
+                    program, hint, consequence
+                ),
+            );
+            return;
+        }
+    };
+    report.push(
+        Status::Ok,
+        format!("Found the render command at {}", found.display()),
+    );
+
+    let node_based = Path::new(program)
+        .file_stem()
+        .is_some_and(|stem| NODE_PROGRAMS.iter().any(|p| stem == *p));
+    if node_based && find_program("node").is_none() {
+        report.push(
+            needed,
+            format!(
+                "`{}` needs Node.js, but `node` isn't on the PATH, {}",
+                program, consequence
+            ),
+        );
+        return;
+    }
+
+    match render::render(&renderer, PROBE, Format::Svg) {
+        Ok(_) => report.push(Status::Ok, "The render command renders diagrams".into()),
+        Err(e) => report.push(
+            needed,
+            format!(
+                "The render command fails to render a diagram, {}: {:#}",
+                consequence, e
+            ),
+        ),
+    }
+}
+
+/// Where `program` is run from: as given if it's a path, otherwise the first match on the PATH.
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+    find_in(program, &env::var_os("PATH")?)
+}
+
+fn find_in(program: &str, paths: &OsString) -> Option<PathBuf> {
+    // Windows finds `npx.cmd` for `npx`, in the order of the extensions.
+    let extensions: Vec<String> = if cfg!(windows) {
+        env::var("PATHEXT")
+            .unwrap_or_else(|_| ".EXE;.CMD;.BAT".into())
+            .split(';')
+            .map(String::from)
+            .chain(Some(String::new()))
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    env::split_paths(paths)
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |ext| dir.join(format!("{}{}", program, ext)))
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use super::{find_in, find_program};
+
+    #[test]
+    fn finds_programs() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("wavedrom-cli"), "").unwrap();
+        let paths = env::join_paths([other.path(), dir.path()]).unwrap();
+
+        assert_eq!(
+            Some(dir.path().join("wavedrom-cli")),
+            find_in("wavedrom-cli", &paths)
+        );
+        assert_eq!(None, find_in("npx", &paths));
+
+        let script = dir.path().join("wavedrom-cli");
+        assert_eq!(Some(script.clone()), find_program(script.to_str().unwrap()));
+        assert_eq!(None, find_program(dir.path().join("npx").to_str().unwrap()));
+    }
+}
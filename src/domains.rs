@@ -0,0 +1,174 @@
+//! Clock domains of the lanes, for diagrams of clock domain crossings:
+//!
+//! ~~~text
+//! {signal: [
+//!   {name: 'clk_a', wave: 'p.......', domain: 'A'},
+//!   {name: 'req', wave: '01...0..', node: '.a......', domain: 'A'},
+//!   {name: 'clk_b', wave: 'P...', period: 2, domain: 'B'},
+//!   {name: 'req_sync', wave: '0.1..0..', node: '..b.....', domain: 'B'},
+//! ], edge: ['a~>b']}
+//! ~~~
+//!
+//! Neighbouring lanes of a domain go into a group labeled with the domain, with a spacer between
+//! the domains. The names of the lanes are shaded in a color of their domain, and edges between
+//! nodes of different domains are labeled as asynchronous crossings, unless they have a label.
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Map, Value};
+
+/// The key of a lane's domain.
+const KEY: &str = "domain";
+
+/// The colors of the domains, in the order they first appear.
+const SHADES: &[&str] = &[
+    "#0066cc", "#d96c00", "#2e8b22", "#9b30c8", "#c0392b", "#008b8b",
+];
+
+/// The label of edges crossing domains.
+const CROSSING: &str = "async";
+
+/// Whether `source` may have lanes with domains.
+pub(crate) fn wanted(source: &str) -> bool {
+    source.contains(KEY)
+}
+
+/// Group, shade and connect the lanes of `diagram` by their domain.
+///
+/// Returns whether any lane of the diagram has a domain.
+pub(crate) fn apply(diagram: &mut Value) -> bool {
+    let signal = match diagram.get_mut("signal") {
+        Some(Value::Array(signal)) => signal,
+        _ => return false,
+    };
+    if !signal
+        .iter()
+        .any(|lane| lane.get(KEY).is_some_and(Value::is_string))
+    {
+        return false;
+    }
+
+    let mut domains: Vec<String> = vec![];
+    let mut nodes = BTreeMap::new();
+    let mut grouped: Vec<Value> = vec![];
+    // The domain of the group at the end of `grouped`.
+    let mut open: Option<String> = None;
+    for mut lane in signal.drain(..) {
+        let domain = match lane.as_object_mut().and_then(take_domain) {
+            Some(domain) => domain,
+            None => {
+                open = None;
+                grouped.push(lane);
+                continue;
+            }
+        };
+        let shade = match domains.iter().position(|d| *d == domain) {
+            Some(i) => SHADES[i % SHADES.len()],
+            None => {
+                domains.push(domain.clone());
+                SHADES[(domains.len() - 1) % SHADES.len()]
+            }
+        };
+        let lane_map = lane
+            .as_object_mut()
+            .expect("lanes with a domain are objects");
+        if let Some(Value::String(node)) = lane_map.get("node") {
+            for c in node.chars().filter(|&c| c != '.') {
+                nodes.insert(c, domain.clone());
+            }
+        }
+        if let Some(Value::String(name)) = lane_map.get("name") {
+            let style = format!("fill:{}", shade);
+            let name = json!(["tspan", { "style": style }, name]);
+            lane_map.insert("name".into(), name);
+        }
+
+        match (&open, grouped.last_mut()) {
+            (Some(current), Some(Value::Array(group))) if *current == domain => group.push(lane),
+            _ => {
+                if open.is_some() {
+                    grouped.push(json!({}));
+                }
+                grouped.push(json!([domain.clone(), lane]));
+                open = Some(domain);
+            }
+        }
+    }
+    *signal = grouped;
+
+    if let Some(Value::Array(edges)) = diagram.get_mut("edge") {
+        for edge in edges {
+            if let Value::String(edge) = edge {
+                if crosses(edge, &nodes) {
+                    edge.push(' ');
+                    edge.push_str(CROSSING);
+                }
+            }
+        }
+    }
+    true
+}
+
+/// The domain of `lane`, taken out of it.
+fn take_domain(lane: &mut Map<String, Value>) -> Option<String> {
+    match lane.remove(KEY)? {
+        Value::String(domain) => Some(domain),
+        _ => None,
+    }
+}
+
+/// Whether the unlabeled `edge` connects nodes of two different domains.
+fn crosses(edge: &str, nodes: &BTreeMap<char, String>) -> bool {
+    let mut words = edge.split_whitespace();
+    let arrow = words.next().unwrap_or_default();
+    if words.next().is_some() {
+        return false;
+    }
+    let domain = |c: Option<char>| c.and_then(|c| nodes.get(&c));
+    match (domain(arrow.chars().next()), domain(arrow.chars().last())) {
+        (Some(from), Some(to)) => from != to,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::wavejson;
+
+    #[test]
+    fn groups_lanes_by_domain() {
+        let mut diagram = wavejson::parse(
+            "{signal: [\
+             {name: 'clk_a', wave: 'p...', domain: 'A'},\
+             {name: 'req', wave: '01.0', node: '.a..', domain: 'A'},\
+             {name: 'clk_b', wave: 'n...', domain: 'B'},\
+             {name: 'ack', wave: '0.10', node: '..b.', domain: 'B'},\
+             {name: 'rst', wave: '0...'}],\
+             edge: ['a~>b', 'a-b sync', 'b->a']}",
+        )
+        .unwrap();
+
+        assert!(apply(&mut diagram));
+        assert_eq!(
+            "{signal: [\
+             ['A', {name: ['tspan', {style: 'fill:#0066cc'}, 'clk_a'], wave: 'p...'}, \
+             {name: ['tspan', {style: 'fill:#0066cc'}, 'req'], wave: '01.0', node: '.a..'}], {}, \
+             ['B', {name: ['tspan', {style: 'fill:#d96c00'}, 'clk_b'], wave: 'n...'}, \
+             {name: ['tspan', {style: 'fill:#d96c00'}, 'ack'], wave: '0.10', node: '..b.'}], \
+             {name: 'rst', wave: '0...'}], \
+             edge: ['a~>b async', 'a-b sync', 'b->a async']}",
+            wavejson::to_string(&diagram)
+        );
+    }
+
+    #[test]
+    fn leaves_diagrams_without_domains() {
+        let source = "{signal: [{name: 'domain', wave: '01'}]}";
+        let mut diagram = wavejson::parse(source).unwrap();
+        assert!(!apply(&mut diagram));
+        assert_eq!(source, wavejson::to_string(&diagram));
+    }
+}
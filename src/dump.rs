@@ -0,0 +1,74 @@
+//! Copies of the chapters before and after preprocessing, to see what got spliced in.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::Chapter;
+
+/// Write `<name>.before.md` with the chapter's original content `before`, and `<name>.after.md`
+/// with its current content, into `dir`.
+///
+/// Failures are only logged, debugging output shouldn't break the build.
+pub(crate) fn write(dir: &Path, chapter: &Chapter, before: &str) {
+    let path = dir.join(relative_path(chapter));
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let files = [
+        (path.with_file_name(format!("{}.before.md", stem)), before),
+        (
+            path.with_file_name(format!("{}.after.md", stem)),
+            chapter.content.as_str(),
+        ),
+    ];
+
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!("Unable to create {}: {}", parent.display(), e);
+            return;
+        }
+    }
+    for (file, content) in files.iter() {
+        log::debug!("Dumping '{}' to {}", chapter.name, file.display());
+        if let Err(e) = fs::write(file, content) {
+            warn!("Unable to write {}: {}", file.display(), e);
+        }
+    }
+}
+
+/// The chapter's path, or one made up from its name for draft chapters.
+fn relative_path(chapter: &Chapter) -> PathBuf {
+    match &chapter.path {
+        Some(path) => path.clone(),
+        None => {
+            let name: String = chapter
+                .name
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect();
+            PathBuf::from(format!("draft-{}.md", name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use mdbook::book::Chapter;
+
+    use super::write;
+
+    #[test]
+    fn dumps_before_and_after() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapter = Chapter::new("Deep", "after".into(), "a/deep.md", vec![]);
+        write(dir.path(), &chapter, "before");
+
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        assert_eq!("before", read("a/deep.before.md"));
+        assert_eq!("after", read("a/deep.after.md"));
+
+        let draft = Chapter::new_draft("Not yet: 1", vec![]);
+        write(dir.path(), &draft, "");
+        assert!(dir.path().join("draft-Not-yet--1.after.md").exists());
+    }
+}
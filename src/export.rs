@@ -0,0 +1,148 @@
+//! Rendering every diagram of a book to image files, without building the book, or a single
+//! diagram file outside of any book.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::{Error, Result};
+use mdbook::preprocess::Preprocessor;
+use mdbook::Config;
+
+use crate::render::Format;
+use crate::{blocks, book, defines, include, lint, options, repeat, templates, wavejson};
+use crate::{Wavedrom, WavedromConfig};
+
+/// What [`export_images`] did.
+#[derive(Debug, Default)]
+pub struct Export {
+    /// The images written, in book order.
+    pub written: Vec<PathBuf>,
+    /// Number of diagrams that couldn't be rendered.
+    pub failed: usize,
+}
+
+/// Render every diagram of the book at `root` into `out`, one file per diagram.
+///
+/// The images mirror the chapter tree: the second diagram of `src/a/intro.md` becomes
+/// `<out>/a/intro-2.svg`. Diagrams that fail to render are logged and counted, the
+/// others are still written.
+///
+/// Up to `jobs` diagrams are rendered at the same time, the `jobs` of the configuration unless
+/// given.
+pub fn export_images(
+    root: &Path,
+    out: &Path,
+    format: Format,
+    jobs: Option<usize>,
+) -> Result<Export> {
+    let book = book::load(root)?;
+    let renderer = book.config.renderer();
+    let jobs = jobs.unwrap_or_else(|| book.config.jobs()).max(1);
+
+    let mut diagrams = vec![];
+    book::for_each_diagram(&book.book, &book.config, &book.src_dir, |diagram| {
+        if !diagram.block.is_rendered() {
+            return;
+        }
+        let mut warnings = diagram.warnings.clone();
+        let source = crate::transform(
+            &diagram.source,
+            &diagram.block.attrs,
+            diagram.page,
+            &mut vec![],
+            &mut warnings,
+        );
+        for warning in warnings {
+            if book.config.allows("diagram-source", Some(diagram.path)) {
+                continue;
+            }
+            log::warn!("{}: {}", diagram.location, warning);
+        }
+        let file = out.join(blocks::file_name(
+            diagram.path,
+            diagram.index,
+            format.extension(),
+        ));
+        diagrams.push((diagram.location.clone(), file, source.into_owned()));
+    });
+
+    let images = book.config.render_cache();
+    let sources: Vec<_> = diagrams
+        .iter()
+        .map(|(_, _, source)| source.clone())
+        .collect();
+    images.render_all(&renderer, &sources, format, jobs);
+
+    let mut export = Export::default();
+    for (location, file, source) in diagrams {
+        match images
+            .render(&renderer, &source, format)
+            .and_then(|image| book::write(&file, &image))
+        {
+            Ok(()) => {
+                log::debug!("Wrote {}", file.display());
+                export.written.push(file);
+            }
+            Err(e) => {
+                log::error!("{}: Unable to render the diagram: {:#}", location, e);
+                export.failed += 1;
+            }
+        }
+    }
+    images.prune();
+    Ok(export)
+}
+
+/// Render the diagram in the WaveJSON file `input` to an image, as a block including it would
+/// be, with the configuration of the book at `root` if given.
+///
+/// The file's includes are relative to the file itself. Problems with the diagram are logged as
+/// warnings, which fail the rendering with `strict`, and a diagram that doesn't parse fails it.
+pub fn render_file(input: &Path, format: Format, root: Option<&Path>) -> Result<Vec<u8>> {
+    let config = match root {
+        Some(root) => {
+            let book_config = Config::from_disk(root.join("book.toml"))?;
+            WavedromConfig::from_config(&book_config, Wavedrom::default().name())?.relative_to(root)
+        }
+        None => WavedromConfig::default(),
+    };
+    let content = fs::read_to_string(input)
+        .map_err(|e| Error::from(e).context(format!("Unable to read {}", input.display())))?;
+
+    let mut warnings = vec![];
+    let base = input.parent().unwrap_or_else(|| Path::new(""));
+    let source = include::resolve(&content, base, &mut warnings);
+    let source = defines::expand(source, &config.defines, &mut warnings);
+    let source = templates::resolve(source, &mut warnings);
+    let source = repeat::expand(source, &config.numbers(), &mut warnings);
+    let mut diagram = wavejson::parse(&source).map_err(|e| {
+        Error::msg(format!(
+            "Invalid WaveJSON: {}",
+            wavejson::describe_error(&e)
+        ))
+    })?;
+    let source = if options::defaults(&mut diagram, &config) {
+        Cow::Owned(wavejson::to_string(&diagram))
+    } else {
+        source
+    };
+    let lints = lint::check_source(&source)
+        .into_iter()
+        .filter(|warning| !config.allows(warning.lint, None))
+        .map(|warning| format!("{} [{}]", warning.message, warning.lint));
+    let warnings: Vec<_> = warnings.into_iter().chain(lints).collect();
+    for warning in &warnings {
+        log::warn!("{}: {}", input.display(), warning);
+    }
+    if config.strict && !warnings.is_empty() {
+        return Err(Error::msg(format!(
+            "{} warning{}, failing because of `strict`",
+            warnings.len(),
+            if warnings.len() == 1 { "" } else { "s" }
+        )));
+    }
+    config
+        .render_cache()
+        .render(&config.renderer(), &source, format)
+}
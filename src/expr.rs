@@ -0,0 +1,296 @@
+//! Arithmetic in data labels, like `{{0x40000000 + 0x20*i | hex}}`, evaluated while building.
+//!
+//! Expressions work on whole numbers, given in decimal, hex (`0x`) or binary (`0b`), with
+//! `+ - * / %`, the shifts `<< >>` and parentheses. In a lane template, see [`crate::repeat`],
+//! `i` is the lane's index. The result is written in decimal, unless a format follows after `|`:
+//! `hex`, `HEX`, `bin` or `dec`, optionally with a number of digits to pad to, like `hex:8`,
+//! and a unit, like `dec ns`. Decimal values and their units are written in the book's `locale`,
+//! see [`crate::numbers`].
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::numbers::Numbers;
+
+/// Where expressions start and end in a label.
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+/// Whether `text` has any expressions.
+pub(crate) fn has_expressions(text: &str) -> bool {
+    text.contains(OPEN)
+}
+
+/// `text` with its expressions replaced by their values written as `numbers` are; `i` is the
+/// index of a templated lane.
+pub(crate) fn render(text: &str, i: Option<i64>, numbers: &Numbers) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + OPEN.len()..];
+        let end = after
+            .find(CLOSE)
+            .ok_or_else(|| format!("`{}` without `{}` in '{}'", OPEN, CLOSE, text))?;
+        let expression = &after[..end];
+        let value = evaluate(expression, i, numbers)
+            .map_err(|e| format!("Unable to evaluate `{{{{{}}}}}`: {}", expression.trim(), e))?;
+        output.push_str(&value);
+        rest = &after[end + CLOSE.len()..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// The value of `expression`, like `0x20*i | hex`, in its format.
+fn evaluate(expression: &str, i: Option<i64>, numbers: &Numbers) -> Result<String, String> {
+    let (arithmetic, format) = match expression.split_once('|') {
+        Some((arithmetic, format)) => (arithmetic, format.trim()),
+        None => (expression, "dec"),
+    };
+    let mut parser = Parser {
+        tokens: tokenize(arithmetic)?,
+        position: 0,
+        i,
+    };
+    let value = parser.shift()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(format!("unexpected {}", token));
+    }
+    formatted(value, format, numbers)
+}
+
+/// `value` written in `format`, followed by its unit if the format has one.
+fn formatted(value: i64, format: &str, numbers: &Numbers) -> Result<String, String> {
+    let (format, unit) = match format.rsplit_once(char::is_whitespace) {
+        Some((format, unit))
+            if unit.starts_with(char::is_alphabetic) && !format.trim().is_empty() =>
+        {
+            (format.trim(), Some(unit))
+        }
+        _ => (format, None),
+    };
+    let (name, width) = match format.split_once(':') {
+        Some((name, width)) => {
+            let width = width
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid width in format '{}'", format))?;
+            (name.trim(), width)
+        }
+        None => (format, 0),
+    };
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = value.unsigned_abs();
+    let number = match name {
+        "dec" => format!("{}{}", sign, numbers.whole(magnitude, width)),
+        "hex" => format!("{}0x{:0width$x}", sign, magnitude, width = width),
+        "HEX" => format!("{}0x{:0width$X}", sign, magnitude, width = width),
+        "bin" => format!("{}0b{:0width$b}", sign, magnitude, width = width),
+        _ => {
+            return Err(format!(
+                "unknown format '{}', expected hex, HEX, bin or dec",
+                name
+            ))
+        }
+    };
+    Ok(match unit {
+        Some(unit) => numbers.with_unit(&number, unit),
+        None => number,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Index,
+    Operator(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Index => f.write_str("`i`"),
+            Token::Operator(op) => write!(f, "`{}`", op),
+        }
+    }
+}
+
+const OPERATORS: &[&str] = &["<<", ">>", "+", "-", "*", "/", "%", "(", ")"];
+
+fn tokenize(arithmetic: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = arithmetic.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[op.len()..];
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if rest.starts_with('i')
+            && !rest[1..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        {
+            tokens.push(Token::Index);
+            rest = &rest[1..];
+        } else {
+            let unexpected = rest.split_whitespace().next().unwrap_or(rest);
+            return Err(format!("unexpected '{}'", unexpected));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A number literal, like `32`, `0x20` or `0b1_0000`.
+fn number(literal: &str) -> Result<i64, String> {
+    let digits = literal.replace('_', "");
+    let parsed = match digits.get(..2) {
+        Some("0x") | Some("0X") => i64::from_str_radix(&digits[2..], 16),
+        Some("0b") | Some("0B") => i64::from_str_radix(&digits[2..], 2),
+        _ => digits.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", literal))
+}
+
+/// Recursive descent over the tokens, one method per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    i: Option<i64>,
+}
+
+impl Parser {
+    fn eat(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(op)) if operators.contains(op) => {
+                self.position += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn shift(&mut self) -> Result<i64, String> {
+        let mut value = self.sum()?;
+        while let Some(op) = self.eat(&["<<", ">>"]) {
+            let by = u32::try_from(self.sum()?).map_err(|_| "negative shift".to_string())?;
+            value = match op {
+                "<<" => value.checked_shl(by),
+                _ => value.checked_shr(by),
+            }
+            .ok_or("shift out of range")?;
+        }
+        Ok(value)
+    }
+
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+        while let Some(op) = self.eat(&["+", "-"]) {
+            let other = self.product()?;
+            value = match op {
+                "+" => value.checked_add(other),
+                _ => value.checked_sub(other),
+            }
+            .ok_or("overflow")?;
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.eat(&["*", "/", "%"]) {
+            let other = self.unary()?;
+            if op != "*" && other == 0 {
+                return Err("division by zero".into());
+            }
+            value = match op {
+                "*" => value.checked_mul(other),
+                "/" => value.checked_div(other),
+                _ => value.checked_rem(other),
+            }
+            .ok_or("overflow")?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat(&["-"]).is_some() {
+            return self.unary()?.checked_neg().ok_or_else(|| "overflow".into());
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Index) => self
+                .i
+                .ok_or_else(|| "`i` is only known in a `$repeat` template".into()),
+            Some(Token::Operator("(")) => {
+                let value = self.shift()?;
+                self.eat(&[")"]).ok_or("missing `)`")?;
+                Ok(value)
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::render;
+    use crate::numbers::{self, Numbers};
+
+    #[test]
+    fn evaluates_labels() {
+        let render = |text| render(text, Some(3), &Numbers::default());
+        assert_eq!(
+            Ok("A 0x40000060".to_string()),
+            render("A {{0x40000000 + 0x20*i | hex}}")
+        );
+        assert_eq!(
+            Ok("7, 0x0F".to_string()),
+            render("{{ (i+4) }}, {{15|HEX:2}}")
+        );
+        assert_eq!(
+            Ok("0b0100 -2".to_string()),
+            render("{{1 << 2 | bin:4}} {{-i % 2 - 1}}")
+        );
+        assert_eq!(Ok("no { braces }".to_string()), render("no { braces }"));
+        assert_eq!(Ok("1500 ns".to_string()), render("{{1500 | dec ns}}"));
+    }
+
+    #[test]
+    fn writes_decimals_per_locale() {
+        let de = numbers::for_locale("de").unwrap();
+        let render = |text| render(text, Some(3), &de);
+        assert_eq!(
+            Ok("1.500 ns, 0x5DC".to_string()),
+            render("{{500 * i | dec ns}}, {{500 * i | HEX}}")
+        );
+        assert_eq!(Ok("-1.500".to_string()), render("{{-500 * i}}"));
+        assert_eq!(Ok("001500".to_string()), render("{{500 * i | dec:6}}"));
+    }
+
+    #[test]
+    fn reports_bad_expressions() {
+        let render = |text| render(text, None, &Numbers::default());
+        assert!(render("{{i}}").unwrap_err().contains("$repeat"));
+        assert!(render("{{1 / 0}}").is_err());
+        assert!(render("{{1 +}}").is_err());
+        assert!(render("{{1 2}}").is_err());
+        assert!(render("{{0x | hex}}").is_err());
+        assert!(render("{{1 | oct}}").is_err());
+        assert!(render("{{1").is_err());
+    }
+}
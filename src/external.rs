@@ -0,0 +1,110 @@
+//! Diagrams kept in files of their own, referenced by a block's `src` attribute.
+//!
+//! ~~~text
+//! ```wavedrom src=waves/spi-write.json5
+//! ```
+//! ~~~
+//!
+//! The path is relative to the chapter's markdown file. Blocks marked `keep` also carry a copy of
+//! the file, so the diagram stays readable where the markdown is shown as is. The file is what
+//! gets rendered, `mdbook-wavedrom sync` keeps the copy up to date.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::blocks::Block;
+use crate::ChapterContext;
+
+/// The file a block refers to, if it has a `src` attribute and the chapter has a file.
+pub(crate) fn path(block: &Block, page: &ChapterContext) -> Option<PathBuf> {
+    let src = block.attrs.get("src")?;
+    Some(page.source_dir()?.join(src))
+}
+
+/// Whether the block carries a copy of its `src` file.
+pub(crate) fn is_kept(block: &Block) -> bool {
+    block.attrs.get("keep").is_some_and(|keep| keep != "false")
+}
+
+/// Whether an embedded copy matches the file's `content`, ignoring trailing whitespace.
+pub(crate) fn same(copy: &str, content: &str) -> bool {
+    copy.trim_end() == content.trim_end()
+}
+
+/// The content of the block's `src` file, reporting problems with it in `warnings`.
+///
+/// Returns `None` if the block has no file or it can't be read.
+pub(crate) fn read(
+    block: &Block,
+    page: &ChapterContext,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let path = path(block, page)?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            warnings.push(format!("Unable to read {}: {}", path.display(), e));
+            return None;
+        }
+    };
+
+    if is_kept(block) {
+        if !same(&block.source, &content) {
+            warnings.push(format!(
+                "The copy of {} is out of date, update it with `mdbook-wavedrom sync --pull`",
+                path.display()
+            ));
+        }
+    } else if !block.source.trim().is_empty() {
+        warnings.push(format!(
+            "Ignoring the block's source in favor of {}, mark it `keep` to keep it as a copy",
+            path.display()
+        ));
+    }
+    Some(content)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use mdbook::book::Chapter;
+
+    use super::read;
+    use crate::{blocks, ChapterContext, WavedromConfig};
+
+    #[test]
+    fn reads_file_and_checks_copy() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("a")).unwrap();
+        fs::create_dir(src.path().join("waves")).unwrap();
+        fs::write(
+            src.path().join("waves/a.json5"),
+            "{signal: [{wave: '01'}]}\n",
+        )
+        .unwrap();
+
+        let content = "```wavedrom src=../waves/a.json5\n```\n\n\
+                       ```wavedrom src=../waves/a.json5 keep\n{signal: [{wave: '01'}]}\n```\n\n\
+                       ```wavedrom src=../waves/a.json5 keep\n{signal: []}\n```\n\n\
+                       ```wavedrom src=../waves/a.json5\n{signal: []}\n```\n\n\
+                       ```wavedrom src=missing.json5\n```\n";
+        let config = WavedromConfig::default();
+        let chapter = Chapter::new("Chapter", content.into(), "a/chapter.md", vec![]);
+        let page = ChapterContext::new(&chapter, &config, Some(src.path()));
+
+        let mut warnings = vec![];
+        let sources: Vec<_> = blocks::find(content, blocks::LANGUAGES)
+            .iter()
+            .map(|block| read(block, &page, &mut warnings))
+            .collect();
+
+        assert_eq!(Some("{signal: [{wave: '01'}]}\n"), sources[0].as_deref());
+        assert!(sources[1..4].iter().all(|source| source == &sources[0]));
+        assert_eq!(None, sources[4]);
+        assert_eq!(3, warnings.len(), "{:?}", warnings);
+        assert!(warnings[0].starts_with("The copy of"));
+        assert!(warnings[1].starts_with("Ignoring the block's source"));
+        assert!(warnings[2].starts_with("Unable to read"));
+    }
+}
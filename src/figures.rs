@@ -0,0 +1,473 @@
+//! Numbers of the captioned diagrams, and references to them from the prose.
+//!
+//! A block with a `caption` and an `id`, like `` ```wavedrom caption="SPI write" id=spi-write ``,
+//! is referenced as `{{#figure spi-write}}` anywhere in the book's text, which becomes a link
+//! labelled like the figure, e.g. `[Figure 3](../spi.md#spi-write)`. The figures of the book are
+//! indexed before any chapter is processed, which also gives every chapter the number its figures
+//! start at when they are numbered through the book.
+//!
+//! Captioned diagrams without an `id` get one made from their caption, like `spi-write` for
+//! `caption="SPI write"`, which is just as good for links and references. Those go around the
+//! ids of the chapter's headings and the `id`s given to its other diagrams.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::{Book, BookItem};
+use mdbook::utils::normalize_id;
+use pulldown_cmark::{Event, Parser, Tag};
+
+use crate::attrs::Attributes;
+use crate::blocks::Block;
+use crate::config::FigureNumbers;
+use crate::{blocks, book, html, wavejson, ChapterContext, WavedromConfig};
+
+/// What prose refers to a figure with, followed by its `id` and `}}`.
+const REFERENCE: &str = "{{#figure ";
+
+/// A figure with an `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Figure {
+    /// The chapter's path relative to the book's `src` directory.
+    pub(crate) chapter: PathBuf,
+    /// The figure's number, like `3` or `2.1.3`.
+    pub(crate) number: String,
+}
+
+/// The ids of the diagrams of a chapter, in the order they appear in.
+#[derive(Debug, Default)]
+pub(crate) struct Ids {
+    /// The ids of the chapter's headings.
+    headings: HashSet<String>,
+    /// The `id`s of the chapter's drawn diagrams, and the ids made from captions so far.
+    taken: HashSet<String>,
+    /// The `id`s of the diagrams so far.
+    given: HashSet<String>,
+}
+
+impl Ids {
+    /// The ids of the chapter with `content`, whose diagrams are `blocks`.
+    pub(crate) fn new(content: &str, blocks: &[Block]) -> Self {
+        let taken = blocks
+            .iter()
+            .filter(|block| block.is_rendered())
+            .filter_map(|block| block.attrs.get("id"))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ids {
+            headings: headings(content),
+            taken,
+            given: HashSet::new(),
+        }
+    }
+
+    /// The id of the next diagram of the chapter, with the attributes `attrs`: its `id`, made
+    /// from its `caption`, or none.
+    ///
+    /// Ids made from captions get a number if a heading or another diagram has them, like
+    /// `spi-write-2`, but giving a taken `id` is an error, since links to it would be ambiguous.
+    pub(crate) fn next(&mut self, attrs: &Attributes) -> Result<Option<String>, String> {
+        if let Some(id) = attrs.get("id").filter(|id| !id.is_empty()) {
+            if is_anchor(id) {
+                return Err(format!(
+                    "The id `{}` is taken by the anchor of a diagram, ids like `wavedrom-<n>` are",
+                    id
+                ));
+            }
+            if self.headings.contains(id) {
+                return Err(format!(
+                    "A heading of the chapter has the id `{}` already",
+                    id
+                ));
+            }
+            if !self.given.insert(id.to_string()) {
+                return Err(format!(
+                    "Another diagram of the chapter has the id `{}` already",
+                    id
+                ));
+            }
+            self.taken.insert(id.to_string());
+            return Ok(Some(id.to_string()));
+        }
+        let base = match attrs.get("caption").map(normalize_id) {
+            Some(base) if !base.is_empty() => base,
+            _ => return Ok(None),
+        };
+        let mut id = base.clone();
+        for n in 2.. {
+            if !self.taken.contains(&id) && !self.headings.contains(&id) && !is_anchor(&id) {
+                break;
+            }
+            id = format!("{}-{}", base, n);
+        }
+        self.taken.insert(id.clone());
+        Ok(Some(id))
+    }
+}
+
+/// Whether `id` is like the anchors in front of the diagrams, `wavedrom-<n>`.
+fn is_anchor(id: &str) -> bool {
+    id.strip_prefix("wavedrom-")
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The ids mdbook gives the headings of `content`: their text as `normalize_id` makes it,
+/// with a number after the first of the same text, like `usage-1`.
+fn headings(content: &str) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    let mut counts = HashMap::new();
+    let mut text = None;
+    for event in Parser::new_ext(content, crate::parser_options()) {
+        match (event, &mut text) {
+            (Event::Start(Tag::Heading(..)), _) => text = Some(String::new()),
+            (Event::Text(t), Some(text)) | (Event::Code(t), Some(text)) => text.push_str(&t),
+            (Event::End(Tag::Heading(..)), _) => {
+                let text = text.take().unwrap_or_default();
+                let id = normalize_id(text.trim().trim_start_matches('#').trim());
+                let count = counts.entry(id.clone()).or_insert(0);
+                ids.insert(match *count {
+                    0 => id,
+                    n => format!("{}-{}", id, n),
+                });
+                *count += 1;
+            }
+            _ => (),
+        }
+    }
+    ids
+}
+
+/// The figures of the book with an `id`, and where the numbers of every chapter start.
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+    figures: HashMap<String, Figure>,
+    /// Number of figures in the chapters before, by the chapter's path.
+    offsets: HashMap<PathBuf, usize>,
+}
+
+impl Index {
+    /// Index the figures of all chapters of `book`, in book order.
+    pub(crate) fn build(book: &Book, config: &WavedromConfig, src_dir: &Path) -> Self {
+        let mut index = Index::default();
+        let mut figures = 0;
+        let mut chapter = None;
+        let mut ids = Ids::default();
+        book::for_each_diagram(book, config, src_dir, |diagram| {
+            if chapter != diagram.chapter.path {
+                chapter = diagram.chapter.path.clone();
+                index.offsets.insert(diagram.path.to_path_buf(), figures);
+                let content = &diagram.chapter.content;
+                ids = Ids::new(content, &blocks::find(content, &config.languages()));
+            }
+            // Diagrams that aren't drawn or don't parse aren't figures, like when processing.
+            if !diagram.block.is_rendered() || wavejson::parse(&diagram.source).is_err() {
+                return;
+            }
+            // Taken ids are reported while processing the chapter.
+            let id = ids.next(&diagram.block.attrs).ok().flatten();
+            if diagram.block.attrs.get("caption").is_none() {
+                return;
+            }
+            figures += 1;
+            let id = match id {
+                Some(id) => id,
+                None => return,
+            };
+            let local = figures - index.offsets[diagram.path];
+            let section = diagram.page.section.as_deref().unwrap_or_default();
+            let figure = Figure {
+                chapter: diagram.chapter.path.clone().unwrap_or_default(),
+                number: number_in(config.figure_numbers, local, figures, section),
+            };
+            let taken = index.figures.insert(id.clone(), figure).is_some();
+            if taken
+                && !diagram
+                    .page
+                    .config
+                    .allows("duplicate-figure", Some(diagram.path))
+            {
+                warn!(
+                    "{}: Another figure has the id `{}` already, references link to this one",
+                    diagram.location, id
+                );
+            }
+        });
+        index
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Figure> {
+        self.figures.get(id)
+    }
+}
+
+/// Whether any chapter of `book` refers to a figure.
+pub(crate) fn referenced(book: &Book) -> bool {
+    book.iter().any(|item| match item {
+        BookItem::Chapter(chapter) => chapter.content.contains(REFERENCE),
+        _ => false,
+    })
+}
+
+/// The number of figure number `local` (starting at 1) of the chapter, as `figure-numbers` says.
+pub(crate) fn number(local: usize, page: &ChapterContext) -> String {
+    let offset = page
+        .figures
+        .zip(page.source_path)
+        .and_then(|(index, path)| index.offsets.get(path))
+        .copied()
+        .unwrap_or(0);
+    let section = page.section.as_deref().unwrap_or_default();
+    number_in(page.config.figure_numbers, local, offset + local, section)
+}
+
+fn number_in(numbers: FigureNumbers, local: usize, global: usize, section: &str) -> String {
+    match numbers {
+        FigureNumbers::Chapter => local.to_string(),
+        FigureNumbers::Book => global.to_string(),
+        // Section numbers end with a dot already, like `2.1.`.
+        FigureNumbers::Section => format!("{}{}", section, local),
+    }
+}
+
+/// Replace the references to figures in the text of `content` by links to them.
+///
+/// References in code are left as they are. So are the ones to figures the index doesn't have,
+/// reported in `warnings`, unless `link_unknown` links them to their id on the page anyway, for
+/// a link checker to report as well.
+pub(crate) fn link<'a>(
+    content: &'a str,
+    index: &Index,
+    page: &ChapterContext,
+    link_unknown: bool,
+    warnings: &mut Vec<(usize, String)>,
+) -> Cow<'a, str> {
+    if !content.contains(REFERENCE) {
+        return Cow::Borrowed(content);
+    }
+    let mut references: Vec<(Range<usize>, &str)> = vec![];
+    let mut skip = 0;
+    for (e, span) in Parser::new_ext(content, crate::parser_options()).into_offset_iter() {
+        match e {
+            Event::Start(Tag::CodeBlock(_))
+            | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) => skip += 1,
+            Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::Link(..))
+            | Event::End(Tag::Image(..)) => skip -= 1,
+            Event::Text(_) if skip == 0 => {
+                let text = &content[span.clone()];
+                let mut start = 0;
+                while let Some(found) = text[start..].find(REFERENCE) {
+                    let id_start = start + found + REFERENCE.len();
+                    let end = match text[id_start..].find("}}") {
+                        Some(end) => end,
+                        None => break,
+                    };
+                    let id = text[id_start..id_start + end].trim();
+                    let reference = span.start + start + found..span.start + id_start + end + 2;
+                    references.push((reference, id));
+                    start = id_start + end + 2;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut output = String::with_capacity(content.len());
+    let mut last = 0;
+    for (span, id) in references {
+        let figure = match index.get(id) {
+            Some(figure) => figure,
+            None => {
+                warnings.push((
+                    span.start,
+                    format!("There's no figure with the id `{}`", id),
+                ));
+                if link_unknown {
+                    output.push_str(&content[last..span.start]);
+                    output.push_str(&format!(
+                        "[{} ?](#{})",
+                        page.config.caption_prefix(),
+                        html::escape_html(id)
+                    ));
+                    last = span.end;
+                }
+                continue;
+            }
+        };
+        output.push_str(&content[last..span.start]);
+        output.push_str(&format!(
+            "[{} {}]({}#{})",
+            page.config.caption_prefix(),
+            figure.number,
+            target(figure, page),
+            html::escape_html(id)
+        ));
+        last = span.end;
+    }
+    output.push_str(&content[last..]);
+    Cow::Owned(output)
+}
+
+/// The link to the chapter of `figure`, relative to `page`. Empty for the page itself.
+fn target(figure: &Figure, page: &ChapterContext) -> String {
+    if page.path == Some(figure.chapter.as_path()) {
+        return String::new();
+    }
+    format!(
+        "{}{}",
+        page.path_to_root,
+        figure.chapter.to_string_lossy().replace('\\', "/")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use mdbook::book::{Book, Chapter, SectionNumber};
+    use pretty_assertions::assert_eq;
+
+    use super::{link, number, Ids, Index};
+    use crate::config::{Allow, FigureNumbers};
+    use crate::{attrs, blocks, diagnostics};
+    use crate::{ChapterContext, WavedromConfig};
+
+    fn book() -> Book {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Intro",
+            "```wavedrom caption=Clock\n{signal: [{name: 'clk'}]}\n```\n\n```wavedrom\n{signal: []}\n```\n\n```wavedrom caption=Reset id=reset\n{signal: [{name: 'rst'}]}\n```\n".into(),
+            "intro.md",
+            vec![],
+        ));
+        let mut deep = Chapter::new(
+            "Deep",
+            "```wavedrom caption=Write id=write\n{signal: [{name: 'req'}]}\n```\n".into(),
+            "a/deep.md",
+            vec![],
+        );
+        deep.number = Some(SectionNumber(vec![2, 1]));
+        book.push_item(deep);
+        book
+    }
+
+    #[test]
+    fn warns_about_duplicate_figures() {
+        let mut book = book();
+        book.push_item(Chapter::new(
+            "Again",
+            "```wavedrom caption=Again id=reset\n{signal: []}\n```\n".into(),
+            "again.md",
+            vec![],
+        ));
+        let warned = diagnostics::warnings();
+        let index = Index::build(&book, &WavedromConfig::default(), "/nowhere".as_ref());
+        assert_eq!("1", index.get("reset").unwrap().number);
+        assert_eq!(1, diagnostics::warnings() - warned);
+
+        let config = WavedromConfig {
+            allow: vec![Allow::Everywhere("duplicate-figure".into())],
+            ..Default::default()
+        };
+        let warned = diagnostics::warnings();
+        Index::build(&book, &config, "/nowhere".as_ref());
+        assert_eq!(0, diagnostics::warnings() - warned);
+    }
+
+    #[test]
+    fn numbers_the_figures() {
+        let mut config = WavedromConfig::default();
+        let index = Index::build(&book(), &config, "/nowhere".as_ref());
+        assert_eq!("2", index.get("reset").unwrap().number);
+        assert_eq!("1", index.get("write").unwrap().number);
+        assert!(index.get("clk").is_none());
+
+        config.figure_numbers = FigureNumbers::Book;
+        let index = Index::build(&book(), &config, "/nowhere".as_ref());
+        assert_eq!("3", index.get("write").unwrap().number);
+        let deep = Chapter::new("Deep", String::new(), "a/deep.md", vec![]);
+        let mut page = ChapterContext::new(&deep, &config, None);
+        page.figures = Some(&index);
+        assert_eq!("4", number(2, &page));
+
+        config.figure_numbers = FigureNumbers::Section;
+        let index = Index::build(&book(), &config, "/nowhere".as_ref());
+        assert_eq!("2.1.1", index.get("write").unwrap().number);
+    }
+
+    #[test]
+    fn ids_of_diagrams() {
+        let content = "# Usage\n\n## SPI write\n\n## Usage\n\n```wavedrom caption=Other id=spi-read\n{signal: []}\n```\n";
+        let mut ids = Ids::new(content, &blocks::find(content, &["wavedrom"]));
+        let mut next = |info| ids.next(&attrs::parse(info).1);
+        assert_eq!(
+            Ok(Some("spi-write-2".into())),
+            next("wavedrom caption=\"SPI write\"")
+        );
+        assert_eq!(Ok(None), next("wavedrom"));
+        assert_eq!(
+            Ok(Some("spi-read-2".into())),
+            next("wavedrom caption=\"SPI read\"")
+        );
+        assert_eq!(
+            Ok(Some("spi-read".into())),
+            next("wavedrom caption=Other id=spi-read")
+        );
+        assert_eq!(
+            Err("Another diagram of the chapter has the id `spi-read` already".into()),
+            next("wavedrom id=spi-read")
+        );
+        assert_eq!(
+            Err("A heading of the chapter has the id `usage-1` already".into()),
+            next("wavedrom id=usage-1")
+        );
+        assert!(next("wavedrom id=wavedrom-2").is_err());
+        assert_eq!(
+            Ok(Some("wavedrom-3-2".into())),
+            next("wavedrom caption=\"wavedrom 3\"")
+        );
+        assert_eq!(
+            Ok(Some("wavedrom-x".into())),
+            next("wavedrom id=wavedrom-x")
+        );
+    }
+
+    #[test]
+    fn references_to_captions() {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Intro",
+            "```wavedrom caption=\"SPI write\"\n{signal: []}\n```\n".into(),
+            "intro.md",
+            vec![],
+        ));
+        let index = Index::build(&book, &WavedromConfig::default(), "/nowhere".as_ref());
+        assert_eq!("1", index.get("spi-write").unwrap().number);
+    }
+
+    #[test]
+    fn links_references() {
+        let config = WavedromConfig::default();
+        let index = Index::build(&book(), &config, "/nowhere".as_ref());
+        let chapter = Chapter::new("Deep", String::new(), "a/deep.md", vec![]);
+        let page = ChapterContext::new(&chapter, &config, None);
+
+        let content = "See {{#figure reset}} and {{#figure write}}, not `{{#figure write}}`.\n\n\
+                       {{#figure nope}}\n";
+        let mut warnings = vec![];
+        assert_eq!(
+            "See [Figure 2](../intro.md#reset) and [Figure 1](#write), not `{{#figure write}}`.\n\n\
+             {{#figure nope}}\n",
+            link(content, &index, &page, false, &mut warnings)
+        );
+        assert_eq!(
+            vec![(
+                content.find("{{#figure nope").unwrap(),
+                "There's no figure with the id `nope`".to_string()
+            )],
+            warnings
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! A font for the SVG images rendered at build time, so figures look the same on machines
+//! without it, like the ones converting a book to PDF or EPUB.
+//!
+//! With `render-font` set, the text of the rendered images is set in that family. With
+//! `render-font-file` as well, the font is embedded into every image as a data URI. TrueType
+//! fonts are subset first: the outlines of the glyphs the image doesn't use are dropped, which
+//! keeps the glyph numbers and therefore every other table as it is. Other fonts, like OpenType
+//! fonts with CFF outlines and WOFF files, are embedded whole.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+
+use mdbook::errors::{Error, Result};
+
+/// The font rendered images are set in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Font {
+    pub(crate) family: String,
+    /// The font file to embed, if any.
+    pub(crate) file: Option<PathBuf>,
+}
+
+/// The rendered `svg` with its text set in `font`, which it embeds if it has a file.
+pub(crate) fn embed(svg: Vec<u8>, font: &Font) -> Result<Vec<u8>> {
+    let mut svg = match String::from_utf8(svg) {
+        Ok(svg) => svg,
+        Err(e) => return Ok(e.into_bytes()),
+    };
+    let end = match svg.rfind("</svg>") {
+        Some(end) => end,
+        None => return Ok(svg.into_bytes()),
+    };
+    let family: String = font
+        .family
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\\' | '<' | '>' | '&'))
+        .collect();
+
+    let mut style = String::from("<style>");
+    if let Some(file) = &font.file {
+        let data = fs::read(file).map_err(|e| {
+            Error::from(e).context(format!(
+                "Unable to read the `render-font-file` {}",
+                file.display()
+            ))
+        })?;
+        let data = subset(&data, &characters(&svg)).unwrap_or(data);
+        style.push_str(&format!(
+            "@font-face{{font-family:\"{}\";src:url(data:{};base64,{})}}",
+            family,
+            mime_type(&data),
+            base64::encode(&data)
+        ));
+    }
+    // More specific than the `text` rules of the skins, which name their own family.
+    style.push_str(&format!(
+        "svg text,svg tspan{{font-family:\"{}\"}}</style>",
+        family
+    ));
+    svg.insert_str(end, &style);
+    Ok(svg.into_bytes())
+}
+
+/// The media type of the font `data`, by the tag it starts with.
+fn mime_type(data: &[u8]) -> &'static str {
+    match data.get(..4) {
+        Some(b"OTTO") => "font/otf",
+        Some(b"wOFF") => "font/woff",
+        Some(b"wOF2") => "font/woff2",
+        _ => "font/ttf",
+    }
+}
+
+/// The characters of the text in `svg`, outside its tags.
+fn characters(svg: &str) -> BTreeSet<char> {
+    let mut characters = BTreeSet::new();
+    let mut rest = svg;
+    while let Some(start) = rest.find('>') {
+        rest = &rest[start + 1..];
+        let text = &rest[..rest.find('<').unwrap_or(rest.len())];
+        characters.extend(unescape(text).chars());
+    }
+    characters.insert(' ');
+    characters
+}
+
+/// `text` with its character references replaced by the characters.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let reference = &rest[1..end];
+        let character = match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match (reference.strip_prefix("#x"), reference.strip_prefix('#')) {
+                (Some(hex), _) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                (None, Some(decimal)) => decimal.parse().ok().and_then(char::from_u32),
+                _ => None,
+            },
+        };
+        match character {
+            Some(character) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The tables of a font, by their tag.
+struct Tables<'a> {
+    tables: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> Tables<'a> {
+    fn read(data: &'a [u8]) -> Option<Self> {
+        let count = usize::from(u16_at(data, 4)?);
+        let tables = (0..count)
+            .map(|i| {
+                let record = 12 + 16 * i;
+                let tag = data.get(record..record + 4)?;
+                let offset = u32_at(data, record + 8)? as usize;
+                let length = u32_at(data, record + 12)? as usize;
+                Some((
+                    [tag[0], tag[1], tag[2], tag[3]],
+                    data.get(offset..offset + length)?,
+                ))
+            })
+            .collect::<Option<_>>()?;
+        Some(Tables { tables })
+    }
+
+    fn get(&self, tag: &[u8; 4]) -> Option<&'a [u8]> {
+        self.tables
+            .iter()
+            .find(|(other, _)| other == tag)
+            .map(|(_, table)| *table)
+    }
+}
+
+/// The TrueType font `data` with the outlines of the glyphs not needed for `characters` dropped,
+/// or `None` if it isn't a TrueType font that can be read.
+fn subset(data: &[u8], characters: &BTreeSet<char>) -> Option<Vec<u8>> {
+    if !matches!(data.get(..4)?, [0, 1, 0, 0] | b"true") {
+        return None;
+    }
+    let tables = Tables::read(data)?;
+    let head = tables.get(b"head")?;
+    let glyf = tables.get(b"glyf")?;
+    let loca = tables.get(b"loca")?;
+    let glyph_count = usize::from(u16_at(tables.get(b"maxp")?, 4)?);
+    let long_offsets = u16_at(head, 50)? == 1;
+    let glyph = |id: usize| -> Option<&[u8]> {
+        let (start, end) = if long_offsets {
+            (
+                u32_at(loca, 4 * id)? as usize,
+                u32_at(loca, 4 * id + 4)? as usize,
+            )
+        } else {
+            (
+                usize::from(u16_at(loca, 2 * id)?) * 2,
+                usize::from(u16_at(loca, 2 * id + 2)?) * 2,
+            )
+        };
+        glyf.get(start..end)
+    };
+
+    // The glyphs of the characters, the missing glyph, and the parts of composite glyphs.
+    let cmap = glyph_ids(tables.get(b"cmap")?, characters)?;
+    let mut kept: BTreeSet<usize> = cmap
+        .into_iter()
+        .filter(|&id| id < glyph_count)
+        .chain(Some(0))
+        .collect();
+    let mut todo: Vec<usize> = kept.iter().copied().collect();
+    while let Some(id) = todo.pop() {
+        for component in components(glyph(id)?)? {
+            if component < glyph_count && kept.insert(component) {
+                todo.push(component);
+            }
+        }
+    }
+
+    let mut new_glyf = vec![];
+    let mut new_loca = Vec::with_capacity(4 * (glyph_count + 1));
+    for id in 0..glyph_count {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if kept.contains(&id) {
+            new_glyf.extend_from_slice(glyph(id)?);
+            new_glyf.resize(new_glyf.len().div_ceil(4) * 4, 0);
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+    let mut new_head = head.to_vec();
+    // No checksum adjustment yet, and long offsets in `loca`.
+    new_head.get_mut(8..12)?.copy_from_slice(&[0; 4]);
+    new_head
+        .get_mut(50..52)?
+        .copy_from_slice(&1u16.to_be_bytes());
+
+    let tables: Vec<([u8; 4], &[u8])> = tables
+        .tables
+        .iter()
+        // The signature doesn't hold for the subset.
+        .filter(|(tag, _)| tag != b"DSIG")
+        .map(|(tag, table)| match tag {
+            b"head" => (*tag, &new_head[..]),
+            b"glyf" => (*tag, &new_glyf[..]),
+            b"loca" => (*tag, &new_loca[..]),
+            _ => (*tag, *table),
+        })
+        .collect();
+    Some(write(data.get(..4)?, &tables))
+}
+
+/// The glyphs the `cmap` table maps the `characters` to, from its format 4 or 12 subtable.
+fn glyph_ids(cmap: &[u8], characters: &BTreeSet<char>) -> Option<Vec<usize>> {
+    let count = usize::from(u16_at(cmap, 2)?);
+    let mut best: Option<(u16, usize)> = None;
+    for i in 0..count {
+        let record = 4 + 8 * i;
+        let (platform, encoding) = (u16_at(cmap, record)?, u16_at(cmap, record + 2)?);
+        let offset = u32_at(cmap, record + 4)? as usize;
+        let format = u16_at(cmap, offset)?;
+        let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+        if unicode && (format == 4 || format == 12) && best.is_none_or(|(f, _)| f < format) {
+            best = Some((format, offset));
+        }
+    }
+    let (format, offset) = best?;
+    let table = cmap.get(offset..)?;
+    let mut ids = vec![];
+    if format == 12 {
+        let groups = u32_at(table, 12)? as usize;
+        for group in 0..groups {
+            let record = 16 + 12 * group;
+            let (start, end) = (u32_at(table, record)?, u32_at(table, record + 4)?);
+            let first = u32_at(table, record + 8)?;
+            let (Some(low), Some(high)) = (char::from_u32(start), char::from_u32(end)) else {
+                continue;
+            };
+            if low <= high {
+                ids.extend(
+                    characters
+                        .range(low..=high)
+                        .map(|&c| first.wrapping_add(c as u32 - start) as usize),
+                );
+            }
+        }
+        return Some(ids);
+    }
+
+    let segments = usize::from(u16_at(table, 6)? / 2);
+    let (ends, starts, deltas, range_offsets) =
+        (14, 16 + 2 * segments, 16 + 4 * segments, 16 + 6 * segments);
+    for &character in characters {
+        let code = match u16::try_from(character as u32) {
+            Ok(code) => code,
+            Err(_) => continue,
+        };
+        for segment in 0..segments {
+            let end = u16_at(table, ends + 2 * segment)?;
+            if code > end {
+                continue;
+            }
+            let start = u16_at(table, starts + 2 * segment)?;
+            if code < start {
+                break;
+            }
+            let delta = u16_at(table, deltas + 2 * segment)?;
+            let range_offset = usize::from(u16_at(table, range_offsets + 2 * segment)?);
+            let id = if range_offset == 0 {
+                code.wrapping_add(delta)
+            } else {
+                let at = range_offsets + 2 * segment + range_offset + 2 * usize::from(code - start);
+                match u16_at(table, at)? {
+                    0 => 0,
+                    id => id.wrapping_add(delta),
+                }
+            };
+            ids.push(usize::from(id));
+            break;
+        }
+    }
+    Some(ids)
+}
+
+/// The glyphs the composite `glyph` is made of, none for a simple one.
+fn components(glyph: &[u8]) -> Option<Vec<usize>> {
+    const ARGS_ARE_WORDS: u16 = 0x1;
+    const SCALE: u16 = 0x8;
+    const MORE_COMPONENTS: u16 = 0x20;
+    const X_AND_Y_SCALE: u16 = 0x40;
+    const TWO_BY_TWO: u16 = 0x80;
+
+    let mut components = vec![];
+    if glyph.is_empty() || (u16_at(glyph, 0)? as i16) >= 0 {
+        return Some(components);
+    }
+    let mut offset = 10;
+    loop {
+        let flags = u16_at(glyph, offset)?;
+        components.push(usize::from(u16_at(glyph, offset + 2)?));
+        offset += 4 + if flags & ARGS_ARE_WORDS != 0 { 4 } else { 2 };
+        offset += if flags & TWO_BY_TWO != 0 {
+            8
+        } else if flags & X_AND_Y_SCALE != 0 {
+            4
+        } else if flags & SCALE != 0 {
+            2
+        } else {
+            0
+        };
+        if flags & MORE_COMPONENTS == 0 {
+            return Some(components);
+        }
+    }
+}
+
+/// The sum of `data` as big-endian 32-bit words, padded with zeros.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// A font file with the `version` tag and the `tables`, in their order.
+fn write(version: &[u8], tables: &[([u8; 4], &[u8])]) -> Vec<u8> {
+    let count = tables.len() as u16;
+    let mut power = 1u16;
+    while power * 2 <= count {
+        power *= 2;
+    }
+    let mut font = version.to_vec();
+    for value in [
+        count,
+        power * 16,
+        power.trailing_zeros() as u16,
+        count * 16 - power * 16,
+    ] {
+        font.extend_from_slice(&value.to_be_bytes());
+    }
+
+    let mut offset = 12 + 16 * tables.len();
+    let mut head = None;
+    for (tag, table) in tables {
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(table).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        if tag == b"head" {
+            head = Some(offset);
+        }
+        offset += table.len().div_ceil(4) * 4;
+    }
+    for (_, table) in tables {
+        font.extend_from_slice(table);
+        font.resize(font.len().div_ceil(4) * 4, 0);
+    }
+    if let Some(head) = head {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+        font[head + 8..head + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{characters, checksum, embed, subset, write, Font, Tables};
+
+    /// A TrueType font with the glyphs `A` and `B`, and a composite `C` made of both.
+    fn font() -> Vec<u8> {
+        let mut head = vec![0; 54];
+        head[50..52].copy_from_slice(&0u16.to_be_bytes());
+        let mut maxp = vec![0, 0, 0x50, 0];
+        maxp.extend_from_slice(&4u16.to_be_bytes());
+
+        // Format 4: `A` to `C` on glyphs 1 to 3, and the closing segment.
+        let mut cmap = vec![0, 0, 0, 1, 0, 3, 0, 1, 0, 0, 0, 12];
+        for value in [4u16, 32, 0, 4, 0, 0, 0, b'C' as u16, 0xFFFF, 0] {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+        for value in [b'A' as u16, 0xFFFF, 1u16.wrapping_sub(b'A' as u16), 1, 0, 0] {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let simple = |marker: u8| {
+            let mut glyph = vec![0, 1];
+            glyph.extend_from_slice(&[marker; 10]);
+            glyph
+        };
+        let mut composite = vec![0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0];
+        composite.extend_from_slice(&[0, 0x20, 0, 1, 0, 0]);
+        composite.extend_from_slice(&[0, 0x01, 0, 2, 0, 0, 0, 0]);
+        let glyphs = [simple(0xEE), simple(0xAA), simple(0xBB), composite];
+        let mut glyf = vec![];
+        let mut loca = vec![];
+        for glyph in &glyphs {
+            loca.extend_from_slice(&((glyf.len() / 2) as u16).to_be_bytes());
+            glyf.extend_from_slice(glyph);
+        }
+        loca.extend_from_slice(&((glyf.len() / 2) as u16).to_be_bytes());
+
+        write(
+            &[0, 1, 0, 0],
+            &[
+                (*b"cmap", &cmap),
+                (*b"glyf", &glyf),
+                (*b"head", &head),
+                (*b"loca", &loca),
+                (*b"maxp", &maxp),
+            ],
+        )
+    }
+
+    #[test]
+    fn finds_the_characters() {
+        let expected: BTreeSet<char> = " <Clk".chars().collect();
+        assert_eq!(
+            expected,
+            characters("<svg><text>Clk</text><text>&lt;</text></svg>")
+        );
+    }
+
+    #[test]
+    fn subsets_truetype_fonts() {
+        let font = font();
+        let has = |data: &[u8], marker: u8| data.windows(10).any(|w| w == [marker; 10]);
+        let only_a = subset(&font, &"A".chars().collect()).unwrap();
+        assert!(has(&only_a, 0xAA));
+        assert!(!has(&only_a, 0xBB));
+        assert!(has(&only_a, 0xEE));
+        assert_eq!(0xB1B0_AFBA, checksum(&only_a));
+        let tables = Tables::read(&only_a).unwrap();
+        assert_eq!(4 * 5, tables.get(b"loca").unwrap().len());
+
+        // The composite brings its parts along.
+        let only_c = subset(&font, &"C".chars().collect()).unwrap();
+        assert!(has(&only_c, 0xAA));
+        assert!(has(&only_c, 0xBB));
+
+        assert_eq!(None, subset(b"wOF2rest", &BTreeSet::new()));
+    }
+
+    #[test]
+    fn embeds_the_font() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("font.ttf");
+        std::fs::write(&file, font()).unwrap();
+        let svg = b"<svg><style>text{font-family:Helvetica}</style><text>A</text></svg>".to_vec();
+
+        let font = Font {
+            family: "Inter".into(),
+            file: None,
+        };
+        assert_eq!(
+            "<svg><style>text{font-family:Helvetica}</style><text>A</text>\
+             <style>svg text,svg tspan{font-family:\"Inter\"}</style></svg>",
+            String::from_utf8(embed(svg.clone(), &font).unwrap()).unwrap()
+        );
+
+        let font = Font {
+            file: Some(file),
+            ..font
+        };
+        let embedded = String::from_utf8(embed(svg, &font).unwrap()).unwrap();
+        assert!(embedded.contains(
+            "<style>@font-face{font-family:\"Inter\";src:url(data:font/ttf;base64,AAEAAAAF"
+        ));
+
+        let font = Font {
+            file: Some(dir.path().join("missing.ttf")),
+            ..font
+        };
+        assert!(embed(b"<svg></svg>".to_vec(), &font).is_err());
+    }
+}
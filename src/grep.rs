@@ -0,0 +1,78 @@
+//! Finding the diagrams of a book that show a signal.
+
+use std::path::Path;
+
+use mdbook::errors::{Error, Result};
+use regex::Regex;
+
+use crate::{book, wavejson};
+
+/// A diagram with matching signals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Where the diagram starts, e.g. `chapter/intro.md:12`.
+    pub location: String,
+    /// Name of the diagram's chapter.
+    pub chapter: String,
+    /// Number of the diagram in its chapter, starting at 1.
+    pub diagram: usize,
+    /// The matching signals, in the order of the diagram's lanes.
+    pub signals: Vec<String>,
+}
+
+/// Search the diagrams of the book at `root` for signals named `pattern`.
+///
+/// With `regex` the pattern is a regular expression matching anywhere in the name, like for
+/// `grep`, otherwise it has to be the whole name.
+pub fn grep(root: &Path, pattern: &str, regex: bool) -> Result<Vec<Match>> {
+    let matcher = if regex {
+        Some(Regex::new(pattern).map_err(|e| Error::from(e).context("invalid pattern"))?)
+    } else {
+        None
+    };
+    let matches_name = |name: &str| match &matcher {
+        Some(matcher) => matcher.is_match(name),
+        None => name == pattern,
+    };
+
+    let book = book::load(root)?;
+    let mut matches = vec![];
+    book::for_each_diagram(&book.book, &book.config, &book.src_dir, |diagram| {
+        for warning in &diagram.warnings {
+            if diagram
+                .page
+                .config
+                .allows("diagram-source", Some(diagram.path))
+            {
+                continue;
+            }
+            log::warn!("{}: {}", diagram.location, warning);
+        }
+        let parsed = match wavejson::parse(&diagram.source) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                log::warn!(
+                    "{}: Unable to parse the diagram, not searching it: {}",
+                    diagram.location,
+                    e
+                );
+                return;
+            }
+        };
+        let signals: Vec<String> = wavejson::lanes(&parsed)
+            .into_iter()
+            .filter_map(|lane| lane.get("name")?.as_str())
+            .filter(|name| matches_name(name))
+            .map(String::from)
+            .collect();
+        if !signals.is_empty() {
+            matches.push(Match {
+                location: diagram.location.clone(),
+                chapter: diagram.chapter.name.clone(),
+                diagram: diagram.index,
+                signals,
+            });
+        }
+    });
+    Ok(matches)
+}
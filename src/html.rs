@@ -0,0 +1,536 @@
+//! The HTML emitted in place of a wavedrom code block.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::config::Mode;
+use crate::wavejson;
+use crate::{ChapterContext, WavedromConfig};
+
+/// The WaveDrom runtime, in the order `install` adds its files to `additional-js`.
+const RUNTIME: &[(&str, &str)] = &[
+    (
+        "wavedrom.min.js",
+        include_str!("bin/assets/wavedrom.min.js"),
+    ),
+    (
+        "wavedrome-default.js",
+        include_str!("bin/assets/wavedrome-default.js"),
+    ),
+    (
+        "wavedrom-runtime.js",
+        include_str!("bin/assets/wavedrom-runtime.js"),
+    ),
+];
+
+/// Dispatches the `wavedrom:*` events on the page, installed as `additional-js` unless inlined.
+const EVENTS: &str = include_str!("bin/assets/wavedrom-events.js");
+
+/// Resizes sandboxed frames to the height they report once their diagram is rendered.
+///
+/// The frames run with an opaque origin, so the page can't measure them itself.
+const FRAME_RESIZER: &str = "<script>window.addEventListener('message', function (e) { \
+    if (!e.data || typeof e.data.wavedromHeight !== 'number') return; \
+    document.querySelectorAll('iframe.wavedrom-frame').forEach(function (frame) { \
+    if (frame.contentWindow === e.source) frame.style.height = e.data.wavedromHeight + 'px'; }); });</script>";
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut output = String::new();
+    for c in s.chars() {
+        match c {
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '&' => output.push_str("&amp;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// The WaveJSON `source` as the text of a `<script type="WaveDrom">`.
+///
+/// The runtime reads the script's text as it is, without decoding any entities, so only what
+/// would end the script is changed: `<` becomes the `\x3C` escape, which is the same character
+/// in the strings that can hold it, like `'</script>'`.
+pub(crate) fn script_text(source: &str) -> String {
+    source.replace('<', "\\x3C")
+}
+
+/// The HTML replacing a single diagram with the given WaveJSON `source`.
+pub(crate) fn diagram(source: &str, page: &ChapterContext) -> String {
+    let script = script_source(source, page);
+    let dark_skin = dark_skin(page);
+    match page.config.mode {
+        Mode::Iframe => frame(&script_text(&script), page),
+        // `wavedrom-runtime.js` draws these once they scroll into view.
+        _ if page.config.lazy => format!(
+            "<div class=\"wavedrom-lazy\" data-wavejson=\"{}\"{}></div>\n\n",
+            lazy_source(&script),
+            dark_skin
+        ),
+        // Pre-rendered diagrams fall back to the script when rendering failed. The hook of
+        // `wavedrome-default.js` draws the scripts once the page is parsed. Identical diagrams
+        // refer to the definition they share, which `wavedrom-runtime.js` copies in first.
+        Mode::Script | Mode::DataUri | Mode::InlineSvg => {
+            match page.shared.borrow().refer(source, page) {
+                Some(id) => format!(
+                    "<script type=\"WaveDrom\"{} data-source=\"{}\"></script>\n\n",
+                    dark_skin, id
+                ),
+                None => format!(
+                    "<script type=\"WaveDrom\"{}>{}</script>\n\n",
+                    dark_skin,
+                    script_text(&script)
+                ),
+            }
+        }
+    }
+}
+
+/// The WaveJSON `source` as the page carries it, on a single line with `preserve-lines`.
+fn script_source<'a>(source: &'a str, page: &ChapterContext) -> Cow<'a, str> {
+    if page.config.preserve_lines {
+        wavejson::one_line(source)
+    } else {
+        Cow::Borrowed(source)
+    }
+}
+
+/// The WaveJSON of the diagrams a chapter draws more than once, like the ones of a shared
+/// include, which their scripts share a single definition of instead of each carrying it.
+#[derive(Debug, Default)]
+pub(crate) struct Shared {
+    /// The shared sources in the order they first come up, and whether a script refers to them.
+    sources: Vec<(String, Cell<bool>)>,
+}
+
+impl Shared {
+    /// The sources among the WaveJSON `parts` of a chapter's diagrams that come up more than once.
+    pub(crate) fn new<'a>(parts: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut sources = vec![];
+        for part in parts {
+            let count = counts.entry(part).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                sources.push((part.clone(), Cell::new(false)));
+            }
+        }
+        Shared { sources }
+    }
+
+    /// The id of the definition of the shared WaveJSON `source` for a script to refer to, if
+    /// it's shared.
+    fn refer(&self, source: &str, page: &ChapterContext) -> Option<String> {
+        let (_, referred) = self.sources.iter().find(|(shared, _)| shared == source)?;
+        referred.set(true);
+        Some(shared_id(source, page))
+    }
+
+    /// The definitions of the shared sources the chapter's scripts refer to, as script text,
+    /// if there are any.
+    ///
+    /// These aren't run, `wavedrom-runtime.js` copies them into the diagrams before drawing them.
+    pub(crate) fn definitions(&self, page: &ChapterContext) -> Option<String> {
+        let definitions: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|(_, referred)| referred.get())
+            .map(|(source, _)| {
+                format!(
+                    "<script type=\"application/json5\" id=\"{}\">{}</script>",
+                    shared_id(source, page),
+                    script_text(&script_source(source, page))
+                )
+            })
+            .collect();
+        if definitions.is_empty() {
+            None
+        } else {
+            Some(definitions.join("\n"))
+        }
+    }
+}
+
+/// The id of the definition of the shared WaveJSON `source`, from a hash of it and the chapter.
+///
+/// mdbook's print page has all chapters, each with its own definitions, so the ids of identical
+/// diagrams of different chapters differ.
+fn shared_id(source: &str, page: &ChapterContext) -> String {
+    let mut hasher = Sha256::new();
+    match page.source_path {
+        Some(path) => hasher.update(path.to_string_lossy().as_bytes()),
+        None => hasher.update(page.name.as_bytes()),
+    }
+    hasher.update([0]);
+    hasher.update(source.as_bytes());
+    let hash = hasher.finalize();
+    format!(
+        "wavedrom-source-{:02x}{:02x}{:02x}{:02x}",
+        hash[0], hash[1], hash[2], hash[3]
+    )
+}
+
+/// The placeholder of a diagram with the WaveJSON `source` for `thumbnails`, showing its PNG
+/// image at `href`, `size` pixels large if known, and described by `alt`.
+///
+/// The browser loads the image once it's about to scroll into view, and `wavedrom-runtime.js`
+/// draws the diagram in its place once the reader clicks it.
+pub(crate) fn thumbnail(
+    source: &str,
+    href: &str,
+    size: Option<(u32, u32)>,
+    alt: &str,
+    page: &ChapterContext,
+) -> String {
+    let source = script_source(source, page);
+    let size = size.map_or_else(String::new, |(width, height)| {
+        format!(" width=\"{}\" height=\"{}\"", width, height)
+    });
+    format!(
+        "<div class=\"wavedrom-lazy wavedrom-thumbnail\" data-wavejson=\"{}\"{}>\
+         <button type=\"button\" class=\"wavedrom-upgrade\" title=\"Draw the diagram\">\
+         <img loading=\"lazy\" alt=\"{}\" src=\"{}\"{}></button></div>\n\n",
+        lazy_source(&source),
+        dark_skin(page),
+        alt,
+        escape_html(href),
+        size
+    )
+}
+
+/// The `data-dark-skin` of the diagrams, which `wavedrom-runtime.js` draws again in it once the
+/// reader switches to a dark theme.
+fn dark_skin(page: &ChapterContext) -> String {
+    match &page.config.dark_skin {
+        Some(skin) => format!(" data-dark-skin=\"{}\"", escape_html(skin)),
+        None => String::new(),
+    }
+}
+
+/// The WaveJSON `source` as the `data-wavejson` of a placeholder, on a single line.
+fn lazy_source(source: &str) -> String {
+    escape_html(source.trim_end()).replace('\n', "&#10;")
+}
+
+/// A pre-rendered `svg` image of a diagram, embedded as a data URI and described by `alt`.
+pub(crate) fn data_uri_image(svg: &[u8], alt: &str) -> String {
+    format!(
+        "<div class=\"wavedrom-image\"><img alt=\"{}\" src=\"data:image/svg+xml;base64,{}\"></div>\n\n",
+        alt,
+        base64::encode(svg)
+    )
+}
+
+/// A visible error in place of a diagram whose WaveJSON `source` doesn't parse, with the
+/// `message` why.
+///
+/// The source stays on one line, a blank line in it would end the HTML block in markdown.
+pub(crate) fn invalid(message: &str, source: &str) -> String {
+    format!(
+        "<div class=\"wavedrom-error\" role=\"alert\"><p>{}</p><pre><code>{}</code></pre></div>\n\n",
+        escape_html(message),
+        escape_html(source.trim_end()).replace('\n', "&#10;")
+    )
+}
+
+/// The link to the standalone page of a diagram, placed below it.
+pub(crate) fn standalone_link(href: &str) -> String {
+    format!(
+        "<p class=\"wavedrom-standalone\"><a href=\"{}\" title=\"Open this diagram on its own page\">\
+         &#x29C9; open standalone</a></p>\n\n",
+        escape_html(href)
+    )
+}
+
+/// Wrap the `diagram` markup into a figure captioned as number `number`.
+///
+/// The label follows the configured caption format. The prefix and caption are text, the format
+/// itself may contain markup.
+pub(crate) fn figure(
+    diagram: &str,
+    number: &str,
+    caption: &str,
+    id: Option<&str>,
+    page: &ChapterContext,
+) -> String {
+    // The caption goes in last, so placeholders in the author's text stay as they are.
+    let label = page
+        .config
+        .caption_format()
+        .replace("{prefix}", &escape_html(page.config.caption_prefix()))
+        .replace("{number}", number)
+        .replace("{caption}", &escape_html(caption));
+    let mut attrs = String::new();
+    if let Some(id) = id {
+        attrs.push_str(&format!(" id=\"{}\"", escape_html(id)));
+    }
+    if page.config.tooltips() {
+        attrs.push_str(&format!(" title=\"{}\"", escape_html(caption)));
+    }
+    format!(
+        "<figure class=\"wavedrom-figure\"{}>\n\n{}<figcaption>{}</figcaption>\n</figure>\n\n",
+        attrs, diagram, label
+    )
+}
+
+/// The WaveJSON `source` of a diagram in a collapsed listing, to be shown below it.
+///
+/// The listing is a single line, its line breaks written as character references, so neither
+/// blank lines of the source nor `preserve-lines` break the HTML block apart.
+pub(crate) fn source(source: &str) -> String {
+    let code = escape_html(source.trim_end_matches('\n')).replace('\n', "&#10;");
+    format!(
+        "<details class=\"wavedrom-source\"><summary>WaveJSON</summary>\n<pre><code class=\"language-wavejson\">{}</code></pre>\n</details>\n\n",
+        code
+    )
+}
+
+/// The toolbar below a diagram with the WaveJSON `source`, linking to the diagram in the
+/// editor at `editor_url`. The copy button is wired up by `wavedrom-runtime.js`, which copies
+/// the link's query.
+///
+/// With a `download` name, like `intro-2`, the toolbar has the buttons of [`downloads`] too.
+pub(crate) fn toolbar(source: &str, editor_url: &str, download: Option<&str>) -> String {
+    let buttons = download.map(|name| format!(" {}", download_buttons(name)));
+    format!(
+        "<div class=\"wavedrom-toolbar\"><a class=\"wavedrom-edit\" href=\"{}?{}\" target=\"_blank\" rel=\"noopener\">Open in WaveDrom editor</a> \
+         <button type=\"button\" class=\"wavedrom-copy\" title=\"Copy the WaveJSON\">Copy</button>{}</div>\n\n",
+        escape_html(editor_url),
+        percent_encode(source.trim_end_matches('\n')),
+        buttons.unwrap_or_default()
+    )
+}
+
+/// The toolbar below a diagram with buttons downloading it as drawn, as an SVG or PNG file
+/// called `name`. They are wired up by `wavedrom-runtime.js`, which takes the image of the
+/// diagram above the toolbar.
+pub(crate) fn downloads(name: &str) -> String {
+    format!(
+        "<div class=\"wavedrom-toolbar\">{}</div>\n\n",
+        download_buttons(name)
+    )
+}
+
+fn download_buttons(name: &str) -> String {
+    ["svg", "png"]
+        .iter()
+        .map(|format| {
+            format!(
+                "<button type=\"button\" class=\"wavedrom-download\" data-format=\"{0}\" data-name=\"{1}\" \
+                 title=\"Download the diagram as {2}\">Download {2}</button>",
+                format,
+                escape_html(name),
+                format.to_uppercase()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `text` with everything but the unreserved characters of URLs percent-encoded, the way
+/// `encodeURIComponent` would.
+fn percent_encode(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'!'
+            | b'~'
+            | b'*'
+            | b'\''
+            | b'('
+            | b')' => output.push(byte as char),
+            _ => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+/// Wrap the `diagram` markup into an element with the `id` links go to, showing `title` when
+/// hovering it.
+pub(crate) fn wrapper(diagram: &str, id: Option<&str>, title: Option<&str>) -> String {
+    let mut attrs = String::new();
+    if let Some(id) = id {
+        attrs.push_str(&format!(" id=\"{}\"", escape_html(id)));
+    }
+    if let Some(title) = title {
+        attrs.push_str(&format!(" title=\"{}\"", escape_html(title)));
+    }
+    format!(
+        "<div class=\"wavedrom-diagram\"{}>\n\n{}</div>\n\n",
+        attrs, diagram
+    )
+}
+
+/// Wrap the `diagram` markup into an image screen readers announce by its `alt` text.
+///
+/// Diagrams the runtime draws show the text instead where scripts don't run, if `fallback` is
+/// set. It goes on the opening line, which markdown leaves alone as part of the HTML block, unlike
+/// a `<noscript>` line of its own.
+pub(crate) fn labelled(diagram: &str, alt: &str, fallback: bool) -> String {
+    let alt = escape_html(alt);
+    let fallback = if fallback {
+        format!(
+            "<noscript><p class=\"wavedrom-fallback\">{}</p></noscript>",
+            alt
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "<div class=\"wavedrom-alt\" role=\"img\" aria-label=\"{}\">{}\n\n{}</div>\n\n",
+        alt, fallback, diagram
+    )
+}
+
+/// The `diagram` in a view scrolling sideways once it's wider than the page, below a slider
+/// zooming it. Both are wired up by `wavedrom-runtime.js`, which shows the controls.
+pub(crate) fn zoomable(diagram: &str) -> String {
+    format!(
+        "<div class=\"wavedrom-zoom\"><div class=\"wavedrom-zoom-controls\">\
+         <label>Zoom <input type=\"range\" class=\"wavedrom-zoom-level\" min=\"25\" max=\"400\" step=\"5\" value=\"100\"></label> \
+         <button type=\"button\" class=\"wavedrom-zoom-fit\" title=\"Fit the diagram to the page's width\">Fit</button></div>\
+         <div class=\"wavedrom-zoom-view\" tabindex=\"0\">\n\n{}</div></div>\n\n",
+        diagram
+    )
+}
+
+/// The `markup` of a diagram on a single line, for chapters keeping their line numbers.
+///
+/// Sources in the markup have to be on their own single line already, see
+/// [`wavejson::one_line`].
+pub(crate) fn one_line(markup: &str) -> String {
+    markup.lines().map(str::trim).collect()
+}
+
+/// The id of the anchor of diagram number `index` (starting at 1) of a chapter.
+pub(crate) fn anchor_id(index: usize) -> String {
+    format!("wavedrom-{}", index)
+}
+
+/// The `markup` of a part of diagram number `index` (starting at 1) of a chapter, with the id of
+/// the diagram's anchor as the `data-wavedrom-id` of its element, for `wavedrom-events.js`.
+pub(crate) fn identified(markup: &str, index: usize) -> String {
+    // The attribute values of the element are escaped, its tag ends at the first `>`.
+    match markup.find('>') {
+        Some(end) => format!(
+            "{} data-wavedrom-id=\"{}\"{}",
+            &markup[..end],
+            anchor_id(index),
+            &markup[end..]
+        ),
+        None => markup.to_string(),
+    }
+}
+
+/// An empty element to link to diagram number `index` of a chapter, placed above it.
+pub(crate) fn anchor(index: usize) -> String {
+    format!(
+        "<div class=\"wavedrom-anchor\" id=\"{}\"></div>\n\n",
+        anchor_id(index)
+    )
+}
+
+/// Markup needed once per chapter, ahead of its first diagram.
+pub(crate) fn chapter_prelude(page: &ChapterContext) -> Option<String> {
+    match page.config.mode {
+        Mode::Script | Mode::DataUri | Mode::InlineSvg if page.config.inline_runtime => {
+            Some(inline_runtime())
+        }
+        Mode::Script | Mode::DataUri | Mode::InlineSvg if page.config.runtime_from_cdn() => {
+            Some(cdn_runtime(page.config))
+        }
+        Mode::Script | Mode::DataUri | Mode::InlineSvg => None,
+        Mode::Iframe => Some(FRAME_RESIZER.to_string()),
+    }
+}
+
+/// The WaveDrom runtime and the script dispatching its events, inlined into `<script>` tags.
+pub(crate) fn inline_runtime() -> String {
+    RUNTIME
+        .iter()
+        .map(|(_, script)| script)
+        .chain(Some(&EVENTS))
+        .map(|script| format!("<script>{}</script>", script.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The WaveDrom runtime loaded from the `cdn-url`, with the skins the configuration picks, and
+/// the scripts of mdbook-wavedrom inlined.
+pub(crate) fn cdn_runtime(config: &WavedromConfig) -> String {
+    let url = escape_html(&config.cdn_url());
+    let mut scripts = vec![format!("<script src=\"{}{}\"></script>", url, RUNTIME[0].0)];
+    for skin in config.skin.iter().chain(config.dark_skin.iter()) {
+        if skin != "default" {
+            scripts.push(format!(
+                "<script src=\"{}skins/{}.js\"></script>",
+                url,
+                escape_html(skin)
+            ));
+        }
+    }
+    for script in RUNTIME[1..]
+        .iter()
+        .map(|(_, script)| script)
+        .chain(Some(&EVENTS))
+    {
+        scripts.push(format!("<script>{}</script>", script.trim_end()));
+    }
+    scripts.join("\n")
+}
+
+/// The scripts loading the WaveDrom runtime into a frame, inlined, from the CDN or from where
+/// `install` copied them.
+fn frame_scripts(page: &ChapterContext) -> String {
+    RUNTIME
+        .iter()
+        .map(|(file, script)| {
+            if page.config.inline_runtime {
+                format!("<script>{}</script>", script)
+            } else if page.config.runtime_from_cdn() && *file == RUNTIME[0].0 {
+                format!(
+                    "<script src=\"{}{}\"></script>",
+                    escape_html(&page.config.cdn_url()),
+                    file
+                )
+            } else if page.config.runtime_from_cdn() {
+                format!("<script>{}</script>", script)
+            } else {
+                format!(
+                    "<script src=\"{}{}{}\"></script>",
+                    page.path_to_root, page.assets_dir, file
+                )
+            }
+        })
+        .collect()
+}
+
+/// A sandboxed frame containing the WaveDrom runtime and a single diagram.
+fn frame(source: &str, page: &ChapterContext) -> String {
+    let scripts = frame_scripts(page);
+    let document = format!(
+        "<!DOCTYPE html><html><head>{}</head>\
+         <body style=\"margin: 0\" onload=\"parent.postMessage({{ wavedromHeight: document.documentElement.scrollHeight }}, '*')\">\
+         <script type=\"WaveDrom\">{}</script></body></html>",
+        scripts, source
+    );
+
+    // The frame has to stay on a single line, a blank line would end the HTML block in markdown.
+    let srcdoc = escape_html(&document).replace('\n', "&#10;");
+    format!(
+        "<iframe class=\"wavedrom-frame\" sandbox=\"allow-scripts\" \
+         style=\"border: none; width: 100%;\" srcdoc=\"{}\"></iframe>\n\n",
+        srcdoc
+    )
+}
@@ -0,0 +1,115 @@
+//! Diagrams as image files, for the renderers other than html, like `epub` or `latex`.
+//!
+//! These don't run the WaveDrom runtime, and most of them don't understand the markup of the
+//! other modes either. Every diagram is rendered to an `image-format` file with the
+//! `render-command` instead, written below the book's `src` directory, and the block is replaced
+//! by a markdown image linking to it. Captions become an emphasized paragraph below the image.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::{Error, Result};
+
+use crate::render::Format;
+use crate::{blocks, standalone, WavedromConfig};
+
+/// An image of a diagram, not yet written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Image {
+    /// Path of the image relative to the book's `src` directory.
+    pub(crate) path: PathBuf,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Image {
+    /// The path of image number `index` (starting at 1) of the chapter at `chapter_path`.
+    pub(crate) fn path(chapter_path: &Path, index: usize, format: Format) -> PathBuf {
+        Path::new(standalone::DIR)
+            .join("images")
+            .join(blocks::file_name(chapter_path, index, format.extension()))
+    }
+
+    /// The markdown image showing this image, in a chapter `path_to_root` away from the book's
+    /// root, described by `alt`.
+    pub(crate) fn markdown(&self, path_to_root: &str, alt: &str) -> String {
+        let alt = alt.replace('[', "\\[").replace(']', "\\]");
+        format!(
+            "![{}](<{}{}>)\n\n",
+            alt,
+            path_to_root,
+            self.path.to_string_lossy().replace('\\', "/")
+        )
+    }
+}
+
+/// The width and height of the PNG `image` in pixels, if it is one.
+pub(crate) fn png_size(image: &[u8]) -> Option<(u32, u32)> {
+    // The signature, then the header chunk's length and type, then its width and height.
+    if image.len() < 24 || !image.starts_with(b"\x89PNG\r\n\x1a\n") || &image[12..16] != b"IHDR" {
+        return None;
+    }
+    let number =
+        |at: usize| u32::from_be_bytes([image[at], image[at + 1], image[at + 2], image[at + 3]]);
+    Some((number(16), number(20)))
+}
+
+/// The caption below the diagram numbered `number`, labelled as the `caption-format` says.
+pub(crate) fn caption(number: &str, caption: &str, config: &WavedromConfig) -> String {
+    let label = config
+        .caption_format()
+        .replace("{prefix}", config.caption_prefix())
+        .replace("{number}", number)
+        .replace("{caption}", caption);
+    format!("*{}*\n\n", label)
+}
+
+/// Write `images` below `src_dir`, leaving the ones that didn't change alone.
+pub(crate) fn write_all(src_dir: &Path, images: &[Image]) -> Result<()> {
+    for image in images {
+        let path = src_dir.join(&image.path);
+        if fs::read(&path).ok().as_deref() == Some(image.data.as_slice()) {
+            continue;
+        }
+
+        log::debug!("Writing image {}", path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                Error::from(e).context(format!("Unable to create {}", parent.display()))
+            })?;
+        }
+        fs::write(&path, &image.data)
+            .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{caption, Image};
+    use crate::render::Format;
+    use crate::WavedromConfig;
+
+    #[test]
+    fn links_the_image() {
+        let image = Image {
+            path: Image::path(Path::new("a/intro.md"), 2, Format::Png),
+            data: vec![],
+        };
+        assert_eq!(
+            Path::new("wavedrom-generated/images/a/intro-2.png"),
+            image.path
+        );
+        assert_eq!(
+            "![The \\[read\\] cycle](<../wavedrom-generated/images/a/intro-2.png>)\n\n",
+            image.markdown("../", "The [read] cycle")
+        );
+        assert_eq!(
+            "*Figure 3: Reset*\n\n",
+            caption("3", "Reset", &WavedromConfig::default())
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! `{{#include path}}` lines inside wavedrom blocks, for sharing waveform fragments.
+//!
+//! The syntax follows mdbook's own include: `{{#include file}}`, a line range
+//! `{{#include file:2:10}}`, a single line `{{#include file:5}}` or an anchor
+//! `{{#include file:name}}`. Paths are relative to the chapter's markdown file.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::utils::{take_anchored_lines, take_lines};
+
+/// Included files that include further files are followed this many levels deep.
+const MAX_DEPTH: usize = 10;
+
+/// Replace the include lines of `source`, resolved relative to the directory `base`.
+///
+/// Includes that can't be resolved are left in place and reported as warnings.
+pub(crate) fn resolve<'a>(
+    source: &'a str,
+    base: &Path,
+    warnings: &mut Vec<String>,
+) -> Cow<'a, str> {
+    resolve_nested(source, base, 0, warnings)
+}
+
+/// The files the include lines of `source` read, followed into the files they include.
+pub(crate) fn files(source: &str, base: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    files_nested(source, base, 0, &mut files);
+    files
+}
+
+fn files_nested(source: &str, base: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    if depth >= MAX_DEPTH {
+        return;
+    }
+    for directive in source.lines().filter_map(parse) {
+        let path = base.join(directive.path);
+        if let Ok(content) = fs::read_to_string(&path) {
+            files_nested(&content, path.parent().unwrap_or(base), depth + 1, files);
+        }
+        files.push(path);
+    }
+}
+
+fn resolve_nested<'a>(
+    source: &'a str,
+    base: &Path,
+    depth: usize,
+    warnings: &mut Vec<String>,
+) -> Cow<'a, str> {
+    if !source.contains("{{#include") {
+        return Cow::Borrowed(source);
+    }
+
+    let mut output = String::with_capacity(source.len());
+    for line in source.split_inclusive('\n') {
+        let directive = match parse(line) {
+            Some(directive) => directive,
+            None => {
+                output.push_str(line);
+                continue;
+            }
+        };
+        if depth >= MAX_DEPTH {
+            warnings.push(format!(
+                "Not including '{}', includes are nested more than {} levels deep",
+                directive.path, MAX_DEPTH
+            ));
+            output.push_str(line);
+            continue;
+        }
+
+        let path = base.join(directive.path);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                warnings.push(format!("Unable to include {}: {}", path.display(), e));
+                output.push_str(line);
+                continue;
+            }
+        };
+
+        let mut included = match directive.selection {
+            Selection::All => content,
+            Selection::Lines(start, end) => match end {
+                Some(end) => take_lines(&content, start..end),
+                None => take_lines(&content, start..),
+            },
+            Selection::Anchor(anchor) => take_anchored_lines(&content, anchor),
+        };
+        let nested_base = path.parent().unwrap_or(base);
+        included = resolve_nested(&included, nested_base, depth + 1, warnings).into_owned();
+
+        output.push_str(&included);
+        if line.ends_with('\n') && !included.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+    Cow::Owned(output)
+}
+
+struct Directive<'a> {
+    path: &'a str,
+    selection: Selection<'a>,
+}
+
+enum Selection<'a> {
+    All,
+    /// Zero-based line range.
+    Lines(usize, Option<usize>),
+    Anchor(&'a str),
+}
+
+/// Parse a line consisting of a single include directive.
+fn parse(line: &str) -> Option<Directive<'_>> {
+    let inner = line
+        .trim()
+        .strip_prefix("{{#include")?
+        .strip_suffix("}}")?
+        .trim();
+    if inner.is_empty() {
+        return None;
+    }
+
+    let mut parts = inner.splitn(3, ':');
+    let path = parts.next()?.trim();
+    let selection = match (parts.next(), parts.next()) {
+        (None, _) => Selection::All,
+        (Some(anchor), None) if anchor.parse::<usize>().is_err() && !anchor.is_empty() => {
+            Selection::Anchor(anchor.trim())
+        }
+        (Some(line), None) => {
+            let line: usize = line.trim().parse().ok()?;
+            Selection::Lines(line.saturating_sub(1), Some(line))
+        }
+        (Some(start), Some(end)) => {
+            let start = match start.trim() {
+                "" => 0,
+                start => start.parse::<usize>().ok()?.saturating_sub(1),
+            };
+            let end = match end.trim() {
+                "" => None,
+                end => Some(end.parse().ok()?),
+            };
+            Selection::Lines(start, end)
+        }
+    };
+    Some(Directive { path, selection })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use super::resolve;
+
+    #[test]
+    fn includes_files_ranges_and_anchors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("waves")).unwrap();
+        fs::write(
+            dir.path().join("waves/clk.json"),
+            "{name: 'clk', wave: 'p...'},\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("waves/bus.json"),
+            "// ANCHOR: bus\n{name: 'dat', wave: 'x=x'},\n// ANCHOR_END: bus\n{name: 'x'},\n{{#include clk.json}}\n",
+        )
+        .unwrap();
+
+        let source = "{signal: [\n  {{#include waves/clk.json}}\n{{#include waves/bus.json:bus}}\n{{#include waves/bus.json:4}}\n{{#include waves/bus.json:5:}}\n]}\n";
+        let mut warnings = vec![];
+        let resolved = resolve(source, dir.path(), &mut warnings);
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            "{signal: [\n{name: 'clk', wave: 'p...'},\n{name: 'dat', wave: 'x=x'},\n{name: 'x'},\n{name: 'clk', wave: 'p...'},\n]}\n",
+            resolved
+        );
+    }
+
+    #[test]
+    fn missing_and_recursive_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("self.json"), "{{#include self.json}}\n").unwrap();
+
+        let mut warnings = vec![];
+        let resolved = resolve(
+            "{{#include missing.json}}\n{{#include self.json}}\n",
+            dir.path(),
+            &mut warnings,
+        );
+        assert_eq!(
+            "{{#include missing.json}}\n{{#include self.json}}\n",
+            resolved
+        );
+        assert_eq!(2, warnings.len());
+        assert!(warnings[0].starts_with("Unable to include"));
+        assert!(warnings[1].contains("nested more than 10 levels deep"));
+    }
+
+    #[test]
+    fn sources_without_includes_are_borrowed() {
+        let mut warnings = vec![];
+        let source = "{signal: []}\n";
+        assert!(matches!(
+            resolve(source, "/".as_ref(), &mut warnings),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+}
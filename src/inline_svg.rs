@@ -0,0 +1,168 @@
+//! Pre-rendered diagrams inlined into the page as `<svg>` markup, in `inline-svg` mode.
+//!
+//! An inlined image shares the page with the book and with the other diagrams, unlike one in an
+//! `<img>`. The ids of every image are prefixed by a hash of it, so `url(#…)` references find
+//! the image's own definitions, and the rules of its stylesheet are scoped to the image, so the
+//! skin's `text` or `.muted` rules don't restyle the rest of the page. Identical diagrams share
+//! their prefix, which is harmless since their definitions are the same.
+
+use sha2::{Digest, Sha256};
+
+/// The markup of the rendered `svg` of a diagram, as an HTML block of the chapter labelled
+/// `label`.
+pub(crate) fn markup(svg: &[u8], label: &str) -> String {
+    let svg = String::from_utf8_lossy(svg);
+    // The XML declaration and doctype only belong at the top of a file.
+    let svg = svg.find("<svg").map_or(&*svg, |start| &svg[start..]);
+    let hash = Sha256::digest(svg.as_bytes());
+    let prefix = format!(
+        "wavedrom-svg-{:02x}{:02x}{:02x}{:02x}",
+        hash[0], hash[1], hash[2], hash[3]
+    );
+
+    let mut svg = scope_ids(svg, &prefix);
+    let root = match root_id(&svg) {
+        Some(id) => id,
+        None => {
+            svg.insert_str("<svg".len(), &format!(" id=\"{}\"", prefix));
+            prefix
+        }
+    };
+    let svg = scope_styles(&svg, &root);
+    // A blank line would end the HTML block, and markdown would take over the rest.
+    let svg: Vec<&str> = svg.lines().filter(|line| !line.trim().is_empty()).collect();
+    format!(
+        "<div class=\"wavedrom-image\" role=\"img\" aria-label=\"{}\">{}</div>\n\n",
+        label,
+        svg.join("\n")
+    )
+}
+
+/// The values of the `id` attributes in `svg`.
+fn ids(svg: &str) -> Vec<&str> {
+    let mut ids = vec![];
+    let mut rest = svg;
+    while let Some(start) = rest.find(" id=\"") {
+        rest = &rest[start + " id=\"".len()..];
+        if let Some(end) = rest.find('"') {
+            ids.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+/// The `svg` with its ids, and the references to them, prefixed by `prefix`.
+fn scope_ids(svg: &str, prefix: &str) -> String {
+    let mut scoped = svg.to_string();
+    for id in ids(svg) {
+        let renamed = format!("{}-{}", prefix, id);
+        scoped = scoped
+            .replace(&format!(" id=\"{}\"", id), &format!(" id=\"{}\"", renamed))
+            .replace(&format!("\"#{}\"", id), &format!("\"#{}\"", renamed))
+            .replace(&format!("url(#{})", id), &format!("url(#{})", renamed));
+    }
+    scoped
+}
+
+/// The `id` of the root element of `svg`.
+fn root_id(svg: &str) -> Option<String> {
+    let tag = &svg[..svg.find('>')?];
+    let start = tag.find(" id=\"")? + " id=\"".len();
+    let end = start + tag[start..].find('"')?;
+    Some(tag[start..end].to_string())
+}
+
+/// The `svg` with the rules of its `<style>` elements limited to the element with the id `root`.
+fn scope_styles(svg: &str, root: &str) -> String {
+    let mut output = String::with_capacity(svg.len());
+    let mut rest = svg;
+    while let Some(open) = rest.find("<style") {
+        let start = match rest[open..].find('>') {
+            Some(end) => open + end + 1,
+            None => break,
+        };
+        let end = match rest[start..].find("</style>") {
+            Some(end) => start + end,
+            None => break,
+        };
+        output.push_str(&rest[..start]);
+        output.push_str(&scope_rules(&rest[start..end], root));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The CSS `rules` with every selector limited to the descendants of `#root`.
+///
+/// At-rules and their blocks are kept as they are.
+fn scope_rules(rules: &str, root: &str) -> String {
+    let mut output = String::with_capacity(rules.len());
+    let mut rest = rules;
+    while let Some(open) = rest.find('{') {
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close + 1,
+            None => break,
+        };
+        let selectors = &rest[..open];
+        if selectors.trim_start().starts_with('@') {
+            output.push_str(&rest[..close]);
+        } else {
+            let scoped: Vec<String> = selectors
+                .split(',')
+                .map(|selector| format!("#{} {}", root, selector.trim()))
+                .collect();
+            output.push_str(&scoped.join(","));
+            output.push_str(&rest[open..close]);
+        }
+        rest = &rest[close..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{markup, scope_rules};
+
+    #[test]
+    fn scopes_the_image() {
+        let svg = "<?xml version=\"1.0\"?>\n<svg id=\"svgcontent_0\" xmlns=\"http://www.w3.org/2000/svg\">\n\n\
+                   <style>text{font-size:11pt} .muted, .info{fill:#aaa}</style>\n\
+                   <defs><g id=\"socket\"/></defs><use xlink:href=\"#socket\"/>\
+                   <path marker-end=\"url(#socket)\"/></svg>\n";
+        let output = markup(svg.as_bytes(), "Timing diagram");
+        let prefix = &output[output.find("wavedrom-svg-").unwrap()..][..21];
+        assert_eq!(
+            format!(
+                "<div class=\"wavedrom-image\" role=\"img\" aria-label=\"Timing diagram\">\
+                 <svg id=\"{0}-svgcontent_0\" xmlns=\"http://www.w3.org/2000/svg\">\n\
+                 <style>#{0}-svgcontent_0 text{{font-size:11pt}}#{0}-svgcontent_0 .muted,\
+                 #{0}-svgcontent_0 .info{{fill:#aaa}}</style>\n\
+                 <defs><g id=\"{0}-socket\"/></defs><use xlink:href=\"#{0}-socket\"/>\
+                 <path marker-end=\"url(#{0}-socket)\"/></svg></div>\n\n",
+                prefix
+            ),
+            output
+        );
+
+        // The same image gets the same ids, an image without ids one for its root.
+        assert_eq!(output, markup(svg.as_bytes(), "Timing diagram"));
+        assert!(markup(b"<svg><text>1</text></svg>", "Register diagram").starts_with(
+            "<div class=\"wavedrom-image\" role=\"img\" aria-label=\"Register diagram\"><svg id=\"wavedrom-svg-"
+        ));
+    }
+
+    #[test]
+    fn keeps_at_rules() {
+        assert_eq!(
+            "@font-face{font-family:a}#r text{fill:red}",
+            scope_rules("@font-face{font-family:a}text{fill:red}", "r")
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! The chapters as `mdbook-linkcheck` gets them.
+//!
+//! The link checker reads the markdown, not the pages, so the diagrams are left as they are.
+//! What would become links once they're drawn is turned into markdown it can check instead:
+//! the captions of figures are put below their diagrams, and `{{#figure id}}` references are
+//! linked to their figures, or to the missing id for the checker to report.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use mdbook::book::{Book, BookItem};
+use mdbook::errors::Result;
+
+use crate::config::FigureNumbers;
+use crate::{blocks, figures, html, images, pipeline, wavejson, ChapterContext, WavedromConfig};
+
+/// Turn the captions and figure references of the chapters of `book` into markdown links.
+pub(crate) fn book(book: &mut Book, config: &WavedromConfig, src_dir: &Path) -> Result<()> {
+    let index = if config.figure_numbers == FigureNumbers::Book || figures::referenced(book) {
+        Some(figures::Index::build(book, config, src_dir))
+    } else {
+        None
+    };
+    let mut result = Ok(());
+    book.for_each_mut(|item| {
+        let chapter = match item {
+            BookItem::Chapter(chapter) if result.is_ok() => chapter,
+            _ => return,
+        };
+        let mut page = ChapterContext::new(chapter, config, Some(src_dir));
+        if config.excludes(page.source_path) {
+            return;
+        }
+        page.figures = index.as_ref();
+        let mut md = match captions(&chapter.content, &page) {
+            Ok(Cow::Owned(md)) => Some(md),
+            Ok(Cow::Borrowed(_)) => None,
+            Err(e) => {
+                result = Err(e);
+                return;
+            }
+        };
+        if let Some(index) = &index {
+            let content = md.as_deref().unwrap_or(&chapter.content);
+            let mut warnings = vec![];
+            let linked = match figures::link(content, index, &page, true, &mut warnings) {
+                Cow::Owned(linked) => Some(linked),
+                Cow::Borrowed(_) => None,
+            };
+            for (offset, warning) in warnings {
+                if !config.allows("unknown-figure", page.source_path) {
+                    warn!("{}: {}", page.location(content, offset), warning);
+                }
+            }
+            if linked.is_some() {
+                md = linked;
+            }
+        }
+        if let Some(md) = md {
+            chapter.content = md;
+        }
+    });
+    result
+}
+
+/// The `content` of the chapter with the captions of its figures below their diagrams.
+pub(crate) fn captions<'a>(content: &'a str, page: &ChapterContext) -> Result<Cow<'a, str>> {
+    let blocks = blocks::find(content, &page.config.languages());
+    let mut ids = figures::Ids::new(content, &blocks);
+    let mut figures = 0;
+    let mut edits = vec![];
+    for block in blocks {
+        // Only what's drawn is a figure, as when processing the chapter for other renderers.
+        if !block.is_rendered() {
+            continue;
+        }
+        let source = crate::diagram_source(&block, page, &mut vec![]);
+        if wavejson::parse(&source).is_err() {
+            continue;
+        }
+        let id = ids.next(&block.attrs).ok().flatten();
+        let caption = match block.attrs.get("caption") {
+            Some(caption) => caption,
+            None => continue,
+        };
+        figures += 1;
+        let number = figures::number(figures, page);
+        let mut text = String::from("\n\n");
+        if let Some(id) = &id {
+            text.push_str(&format!("<a id=\"{}\"></a>\n\n", html::escape_html(id)));
+        }
+        text.push_str(images::caption(&number, caption, page.config).trim_end());
+
+        let end = match content[..block.span.end].strip_suffix('\n') {
+            Some(before) => before.len(),
+            None => block.span.end,
+        };
+        let line_start = content[..block.span.start].rfind('\n').map_or(0, |i| i + 1);
+        let prefix = crate::continuation_prefix(&content[line_start..block.span.start]);
+        let mut markup = String::with_capacity(text.len());
+        crate::push_indented(&mut markup, &text, &prefix);
+        edits.push(("wavedrom", pipeline::Edit::new(end..end, markup)));
+    }
+    if edits.is_empty() {
+        return Ok(Cow::Borrowed(content));
+    }
+    pipeline::apply(content, edits).map(Cow::Owned)
+}
+
+#[cfg(test)]
+mod test {
+    use mdbook::book::Chapter;
+    use pretty_assertions::assert_eq;
+
+    use super::captions;
+    use crate::{ChapterContext, WavedromConfig};
+
+    #[test]
+    fn captions_stay_in_their_list_item() {
+        let content = "- Item\n\n  ```wavedrom caption=\"[Clock](clk.md)\"\n  {signal: []}\n  ```\n- Next\n\n\
+                       ```wavedrom caption=Broken\n{signal: [\n```\n";
+        let chapter = Chapter::new("Chapter", content.into(), "chapter.md", vec![]);
+        let config = WavedromConfig::default();
+        let page = ChapterContext::new(&chapter, &config, None);
+        assert_eq!(
+            "- Item\n\n  ```wavedrom caption=\"[Clock](clk.md)\"\n  {signal: []}\n  ```\n\n  \
+             <a id=\"clockclkmd\"></a>\n\n  *Figure 1: [Clock](clk.md)*\n- Next\n\n\
+             ```wavedrom caption=Broken\n{signal: [\n```\n",
+            captions(content, &page).unwrap()
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Checks for diagrams that WaveDrom renders without complaint, but not as intended.
+
+use std::collections::BTreeSet;
+
+use serde_json::{Map, Value};
+
+use crate::{wavejson, WavedromConfig};
+
+/// Groups nested deeper than this are reported by `deep-group`.
+///
+/// WaveDrom draws a bracket per level into the left margin, which pushes the lane names out
+/// of view long before the nesting becomes unreadable in the source.
+const MAX_GROUP_DEPTH: usize = 3;
+
+/// Largest `period` and `phase` that could be meant, in bricks.
+const MAX_BRICKS: f64 = 64.0;
+
+/// The keys of a diagram WaveDrom or the preprocessor reads, any other is reported by
+/// `unknown-key`.
+const DIAGRAM_KEYS: &[&str] = &[
+    "signal",
+    "edge",
+    "config",
+    "head",
+    "foot",
+    "reg",
+    "assign",
+    "descriptions",
+];
+
+/// The keys of a lane WaveDrom or the preprocessor reads.
+const LANE_KEYS: &[&str] = &[
+    "name", "wave", "data", "node", "period", "phase", "color", "domain", "$repeat",
+];
+
+/// The names of all lints.
+pub(crate) const NAMES: &[&str] = &[
+    "dangling-edge",
+    "malformed-group",
+    "deep-group",
+    "bad-period",
+    "bad-phase",
+    "phase-on-non-clock",
+    "diagram-budget",
+    "low-contrast",
+    "unknown-key",
+    "data-mismatch",
+];
+
+/// A problem found in a diagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Warning {
+    /// Name of the lint that found the problem, e.g. `dangling-edge`.
+    pub(crate) lint: &'static str,
+    pub(crate) message: String,
+}
+
+impl Warning {
+    fn new(lint: &'static str, message: String) -> Self {
+        Warning { lint, message }
+    }
+}
+
+/// Run all lints on the parsed `diagram`.
+pub(crate) fn check(diagram: &Value) -> Vec<Warning> {
+    let mut warnings = vec![];
+    if let Value::Object(diagram) = diagram {
+        unknown_keys(diagram, DIAGRAM_KEYS, "the diagram", &mut warnings);
+    }
+    dangling_edges(diagram, &mut warnings);
+    if let Some(Value::Array(signal)) = diagram.get("signal") {
+        groups(signal, 0, &mut warnings);
+    }
+    for lane in wavejson::lanes(diagram) {
+        let name = lane.get("name").and_then(Value::as_str).unwrap_or("");
+        unknown_keys(lane, LANE_KEYS, &format!("lane '{}'", name), &mut warnings);
+        period_and_phase(lane, &mut warnings);
+        data_labels(lane, &mut warnings);
+    }
+    warnings
+}
+
+/// Keys of `object` that aren't `known`, which WaveDrom ignores, like a misspelled `wave`.
+fn unknown_keys(
+    object: &Map<String, Value>,
+    known: &[&str],
+    owner: &str,
+    warnings: &mut Vec<Warning>,
+) {
+    for key in object.keys().filter(|key| !known.contains(&key.as_str())) {
+        let hint = known
+            .iter()
+            .find(|known| distance(key, known) <= 2)
+            .map(|known| format!(", did you mean '{}'?", known))
+            .unwrap_or_default();
+        warnings.push(Warning::new(
+            "unknown-key",
+            format!(
+                "{} has the key '{}', which WaveDrom ignores{}",
+                owner, key, hint
+            ),
+        ));
+    }
+}
+
+/// The edit distance of `a` and `b`, ignoring case.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(ca != cb);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// `edge` entries referring to a node that no lane defines.
+///
+/// WaveDrom skips these arrows without a trace.
+fn dangling_edges(diagram: &Value, warnings: &mut Vec<Warning>) {
+    let edges = match diagram.get("edge") {
+        Some(Value::Array(edges)) => edges,
+        _ => return,
+    };
+
+    let nodes: BTreeSet<char> = wavejson::lanes(diagram)
+        .iter()
+        .filter_map(|lane| lane.get("node").and_then(Value::as_str))
+        .flat_map(|node| node.chars())
+        .filter(|&c| c != '.')
+        .collect();
+
+    for edge in edges.iter().filter_map(Value::as_str) {
+        // `a~>b label`: the arrow runs from the first to the last character of the first word.
+        let arrow = edge.split_whitespace().next().unwrap_or("");
+        let mut ends: Vec<char> = arrow.chars().take(1).chain(arrow.chars().last()).collect();
+        ends.dedup();
+        for end in ends {
+            if !nodes.contains(&end) {
+                warnings.push(Warning::new(
+                    "dangling-edge",
+                    format!(
+                        "edge '{}' refers to node '{}', which no lane defines in its 'node' string",
+                        edge, end
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Groups are written as `['label', lane, lane, ...]`. WaveDrom takes the first element as the
+/// label whatever it is, so a forgotten label silently swallows the first lane.
+fn groups(lanes: &[Value], depth: usize, warnings: &mut Vec<Warning>) {
+    for lane in lanes {
+        let group = match lane {
+            Value::Array(group) => group,
+            Value::Object(_) => continue,
+            other => {
+                warnings.push(Warning::new(
+                    "malformed-group",
+                    format!(
+                        "expected a lane or a group, found {}",
+                        wavejson::to_string(other)
+                    ),
+                ));
+                continue;
+            }
+        };
+
+        let (label, members) = match group.split_first() {
+            Some((Value::String(label), members)) => (label.as_str(), members),
+            Some((first, _)) => {
+                warnings.push(Warning::new(
+                    "malformed-group",
+                    format!(
+                        "group starts with {} instead of its label, \
+                         WaveDrom won't draw it as a lane",
+                        wavejson::to_string(first)
+                    ),
+                ));
+                continue;
+            }
+            None => {
+                warnings.push(Warning::new("malformed-group", "empty group".into()));
+                continue;
+            }
+        };
+
+        if members.is_empty() {
+            warnings.push(Warning::new(
+                "malformed-group",
+                format!("group '{}' has no lanes", label),
+            ));
+        }
+        if depth + 1 > MAX_GROUP_DEPTH {
+            warnings.push(Warning::new(
+                "deep-group",
+                format!(
+                    "group '{}' is nested {} levels deep, at most {} are readable",
+                    label,
+                    depth + 1,
+                    MAX_GROUP_DEPTH
+                ),
+            ));
+        }
+        groups(members, depth + 1, warnings);
+    }
+}
+
+/// `period` and `phase` values WaveDrom would happily draw, stretched or shifted out of shape.
+fn period_and_phase(lane: &Map<String, Value>, warnings: &mut Vec<Warning>) {
+    let name = lane.get("name").and_then(Value::as_str).unwrap_or("");
+    let wave = lane.get("wave").and_then(Value::as_str).unwrap_or("");
+
+    if let Some(period) = lane.get("period") {
+        match period.as_f64() {
+            None => warnings.push(Warning::new(
+                "bad-period",
+                format!(
+                    "lane '{}' has period {}, which is not a number",
+                    name,
+                    wavejson::to_string(period)
+                ),
+            )),
+            Some(period) if period <= 0.0 || period > MAX_BRICKS => warnings.push(Warning::new(
+                "bad-period",
+                format!(
+                    "lane '{}' has period {}, expected more than 0 and at most {}",
+                    name, period, MAX_BRICKS
+                ),
+            )),
+            Some(_) => {}
+        }
+    }
+
+    if let Some(phase) = lane.get("phase") {
+        // A phase beyond the length of the wave shifts the whole lane out of view.
+        let limit = if wave.is_empty() {
+            MAX_BRICKS
+        } else {
+            wave.chars().count() as f64
+        };
+        match phase.as_f64() {
+            None => warnings.push(Warning::new(
+                "bad-phase",
+                format!(
+                    "lane '{}' has phase {}, which is not a number",
+                    name,
+                    wavejson::to_string(phase)
+                ),
+            )),
+            Some(phase) if phase.abs() > limit => warnings.push(Warning::new(
+                "bad-phase",
+                format!(
+                    "lane '{}' has phase {}, which shifts it by more than its {} bricks",
+                    name, phase, limit
+                ),
+            )),
+            Some(_) => {}
+        }
+
+        if !wave.is_empty() && !wave.contains(['p', 'n', 'P', 'N']) {
+            warnings.push(Warning::new(
+                "phase-on-non-clock",
+                format!(
+                    "lane '{}' has a phase but no clock in its wave '{}'",
+                    name, wave
+                ),
+            ));
+        }
+    }
+}
+
+/// `data` with more or fewer labels than the wave has bricks to put them on.
+///
+/// WaveDrom labels the `=` and `2` to `9` bricks in order, leaves the ones without a label
+/// blank and drops the labels left over.
+fn data_labels(lane: &Map<String, Value>, warnings: &mut Vec<Warning>) {
+    let labels = match lane.get("data") {
+        Some(Value::Array(labels)) => labels.len(),
+        Some(Value::String(labels)) => labels.split_whitespace().count(),
+        _ => return,
+    };
+    let wave = match lane.get("wave").and_then(Value::as_str) {
+        Some(wave) => wave,
+        None => return,
+    };
+    let bricks = wave
+        .chars()
+        .filter(|&c| c == '=' || ('2'..='9').contains(&c))
+        .count();
+    if labels != bricks {
+        let name = lane.get("name").and_then(Value::as_str).unwrap_or("");
+        warnings.push(Warning::new(
+            "data-mismatch",
+            format!(
+                "lane '{}' has {} data labels for the {} data bricks of its wave '{}'",
+                name, labels, bricks, wave
+            ),
+        ));
+    }
+}
+
+/// Whether a chapter with `diagrams` rendered diagrams has more than the `diagram-budget`.
+///
+/// Every diagram is drawn by the browser when the page loads, which takes noticeably long with
+/// dozens of them. Pre-rendered ones don't count.
+pub(crate) fn diagram_budget(diagrams: usize, config: &WavedromConfig) -> Option<Warning> {
+    let budget = config.diagram_budget?;
+    if diagrams <= budget || config.mode.is_prerendered() {
+        return None;
+    }
+    Some(Warning::new(
+        "diagram-budget",
+        format!(
+            "the chapter has {} diagrams, more than the budget of {}, which slows down loading \
+             the page: split the chapter or pre-render them with `mode = \"data-uri\"`",
+            diagrams, budget
+        ),
+    ))
+}
+
+/// Lint the WaveJSON `source` of a diagram.
+///
+/// Sources that don't parse aren't linted, WaveDrom reports those itself.
+pub(crate) fn check_source(source: &str) -> Vec<Warning> {
+    wavejson::parse(source)
+        .map(|diagram| check(&diagram))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_source, diagram_budget};
+    use crate::{Mode, WavedromConfig};
+
+    fn lints(source: &str) -> Vec<String> {
+        check_source(source)
+            .into_iter()
+            .map(|w| format!("{}: {}", w.lint, w.message))
+            .collect()
+    }
+
+    #[test]
+    fn diagrams_within_budget() {
+        let config = WavedromConfig {
+            diagram_budget: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(None, diagram_budget(20, &config));
+        assert_eq!("diagram-budget", diagram_budget(21, &config).unwrap().lint);
+        assert_eq!(None, diagram_budget(21, &WavedromConfig::default()));
+
+        let config = WavedromConfig {
+            mode: Mode::DataUri,
+            ..config
+        };
+        assert_eq!(None, diagram_budget(21, &config));
+    }
+
+    #[test]
+    fn edges_between_defined_nodes() {
+        let source = "{signal: [{name: 'a', wave: '01', node: '.a'}, \
+                      ['g', {name: 'b', wave: '10', node: 'b.'}]], \
+                      edge: ['a~>b label', 'b-|>a', 'a<->b', 7]}";
+        assert!(lints(source).is_empty());
+    }
+
+    #[test]
+    fn well_formed_groups() {
+        let source = "{signal: [{name: 'clk'}, {}, ['bus', {name: 'a'}, ['sub', {name: 'b'}]]]}";
+        assert!(lints(source).is_empty());
+    }
+
+    #[test]
+    fn malformed_groups() {
+        let source = "{signal: [[{name: 'a'}, {name: 'b'}], [], ['empty'], 'stray', \
+                      ['1', ['2', ['3', ['4', {name: 'deep'}]]]]]}";
+        assert_eq!(
+            vec![
+                "malformed-group: group starts with {name: 'a'} instead of its label, WaveDrom won't draw it as a lane",
+                "malformed-group: empty group",
+                "malformed-group: group 'empty' has no lanes",
+                "malformed-group: expected a lane or a group, found 'stray'",
+                "deep-group: group '4' is nested 4 levels deep, at most 3 are readable",
+            ],
+            lints(source)
+        );
+    }
+
+    #[test]
+    fn sensible_period_and_phase() {
+        let source = "{signal: [{name: 'clk', wave: 'p...', period: 2, phase: 0.5}, \
+                      {name: 'fast', wave: 'P.......', period: 0.5}]}";
+        assert!(lints(source).is_empty());
+    }
+
+    #[test]
+    fn bad_period_and_phase() {
+        let source = "{signal: [{name: 'a', wave: 'p..', period: '2x'}, \
+                      {name: 'b', wave: 'n..', period: 0}, \
+                      {name: 'c', wave: 'p..', phase: 12}, \
+                      {name: 'd', wave: '01.', phase: 'half'}]}";
+        assert_eq!(
+            vec![
+                "bad-period: lane 'a' has period '2x', which is not a number",
+                "bad-period: lane 'b' has period 0, expected more than 0 and at most 64",
+                "bad-phase: lane 'c' has phase 12, which shifts it by more than its 3 bricks",
+                "bad-phase: lane 'd' has phase 'half', which is not a number",
+                "phase-on-non-clock: lane 'd' has a phase but no clock in its wave '01.'",
+            ],
+            lints(source)
+        );
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let source = "{signal: [{name: 'a', wav: '01', color: 'red'}, {$repeat: {name: 'b'}}], \
+                      Edge: [], descriptions: {}, scale: 2}";
+        assert_eq!(
+            vec![
+                "unknown-key: the diagram has the key 'Edge', which WaveDrom ignores, did you mean 'edge'?",
+                "unknown-key: the diagram has the key 'scale', which WaveDrom ignores",
+                "unknown-key: lane 'a' has the key 'wav', which WaveDrom ignores, did you mean 'wave'?",
+            ],
+            lints(source)
+        );
+    }
+
+    #[test]
+    fn data_matching_the_wave() {
+        let source = "{signal: [{name: 'a', wave: 'x=.3x', data: ['head', 'body']}, \
+                      {name: 'b', wave: 'x45', data: 'one two'}, {name: 'c', wave: 'x=x'}]}";
+        assert!(lints(source).is_empty());
+
+        let source = "{signal: [{name: 'a', wave: 'x==x', data: ['head']}, \
+                      {name: 'b', wave: '01', data: 'one two'}]}";
+        assert_eq!(
+            vec![
+                "data-mismatch: lane 'a' has 1 data labels for the 2 data bricks of its wave 'x==x'",
+                "data-mismatch: lane 'b' has 2 data labels for the 0 data bricks of its wave '01'",
+            ],
+            lints(source)
+        );
+    }
+
+    #[test]
+    fn dangling_edges() {
+        let source = "{signal: [{name: 'a', wave: '01', node: '.a'}], edge: ['a~>c', 'd->d']}";
+        assert_eq!(
+            vec![
+                "dangling-edge: edge 'a~>c' refers to node 'c', which no lane defines in its 'node' string",
+                "dangling-edge: edge 'd->d' refers to node 'd', which no lane defines in its 'node' string",
+            ],
+            lints(source)
+        );
+    }
+}
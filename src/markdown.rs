@@ -0,0 +1,152 @@
+//! The preprocessor's work on a single markdown string, for tools other than mdbook.
+//!
+//! A custom preprocessor, a linter or a static site generator finds the diagrams of its own
+//! markdown with [`find_wavedrom_blocks`], by the same rules the preprocessor does, or replaces
+//! them with [`process_markdown`] like a chapter of a book.
+
+use std::ops::Range;
+
+use mdbook::book::Chapter;
+use mdbook::errors::Result;
+
+use crate::{blocks, ChapterContext, WavedromConfig};
+
+/// A code block of a diagram, found by [`find_wavedrom_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WavedromBlock {
+    /// Byte range of the whole block in the markdown, fences included.
+    pub span: Range<usize>,
+    /// The language the block is fenced with, like `wavedrom` or `register`.
+    pub language: String,
+    /// The attributes of its info string, in the order they were given. Flags read as `true`,
+    /// and the file named by a `wavedrom-include` block is its `src`.
+    pub attributes: Vec<(String, String)>,
+    /// The diagram's source, without the indentation or markers of the containers it is in.
+    pub source: String,
+    /// Byte range of the source in the markdown, starting after the container markers of its
+    /// first line.
+    pub content: Range<usize>,
+}
+
+impl WavedromBlock {
+    /// The value of the attribute `key`. If it was given multiple times, the last one wins.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// The code blocks of diagrams in `content`, in order, fenced with any of the languages of
+/// `config`, like `wavedrom` or the `block-names` it adds.
+///
+/// ```
+/// use mdbook_wavedrom::{find_wavedrom_blocks, WavedromConfig};
+///
+/// let markdown = "# Bus\n\n```wavedrom caption=Clock\n{signal: [{name: 'clk', wave: 'p...'}]}\n```\n";
+/// let blocks: Vec<_> = find_wavedrom_blocks(markdown, &WavedromConfig::default()).collect();
+/// assert_eq!(1, blocks.len());
+/// assert_eq!(Some("Clock"), blocks[0].attribute("caption"));
+/// assert_eq!("{signal: [{name: 'clk', wave: 'p...'}]}\n", blocks[0].source);
+/// ```
+pub fn find_wavedrom_blocks(
+    content: &str,
+    config: &WavedromConfig,
+) -> impl Iterator<Item = WavedromBlock> {
+    blocks::find_fenced(content, &config.languages())
+        .into_iter()
+        .map(|(language, block)| {
+            let block = blocks::diagram((language.clone(), block));
+            WavedromBlock {
+                span: block.span,
+                language,
+                attributes: block.attrs.into_pairs(),
+                source: block.source,
+                content: block.content,
+            }
+        })
+}
+
+/// Replace the diagrams in `content` like the preprocessor does for a chapter of a book,
+/// configured by `config`.
+///
+/// The markdown is treated like a draft chapter, without a file of its own: blocks don't include
+/// other files, since there's nothing they would be relative to, and nothing is written next to
+/// it, like the pages of `standalone` diagrams. Warnings are logged, and fail the call only where
+/// the preprocessor would fail the build, like with `fail-on-error`.
+///
+/// ```
+/// use mdbook_wavedrom::{process_markdown, WavedromConfig};
+///
+/// let markdown = "```wavedrom\n{signal: [{name: 'clk', wave: 'p...'}]}\n```\n";
+/// let html = process_markdown(markdown, &WavedromConfig::default()).unwrap();
+/// assert!(html.contains("<script type=\"WaveDrom\""));
+/// ```
+pub fn process_markdown(content: &str, config: &WavedromConfig) -> Result<String> {
+    let chapter = Chapter::new_draft("", vec![]);
+    let page = ChapterContext::new(&chapter, config, None);
+    crate::add_wavedrom(content, &page).map(|content| content.into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{find_wavedrom_blocks, process_markdown, WavedromBlock};
+    use crate::WavedromConfig;
+
+    #[test]
+    fn finds_the_blocks() {
+        let content = "Text\n\n> ```register lanes=2\n> [{bits: 8}]\n> ```\n\n\
+                       ```wavedrom-include waves/spi.json5 caption=SPI\n```\n\n\
+                       ```rust\nfn main() {}\n```\n";
+        let blocks: Vec<_> = find_wavedrom_blocks(content, &WavedromConfig::default()).collect();
+        assert_eq!(
+            vec![
+                WavedromBlock {
+                    span: 8..47,
+                    language: "register".into(),
+                    attributes: vec![("lanes".into(), "2".into())],
+                    source: "[{bits: 8}]\n".into(),
+                    content: 30..42,
+                },
+                WavedromBlock {
+                    span: 49..100,
+                    language: "wavedrom-include".into(),
+                    attributes: vec![
+                        ("src".into(), "waves/spi.json5".into()),
+                        ("caption".into(), "SPI".into()),
+                    ],
+                    source: String::new(),
+                    content: 97..97,
+                },
+            ],
+            blocks
+        );
+        assert_eq!(Some("waves/spi.json5"), blocks[1].attribute("src"));
+    }
+
+    #[test]
+    fn processes_markdown() {
+        let config = WavedromConfig::default();
+        assert_eq!(
+            "No diagrams.\n",
+            process_markdown("No diagrams.\n", &config).unwrap()
+        );
+        let output = process_markdown(
+            "# Bus\n\n```wavedrom\n{signal: [{name: 'clk', wave: 'p.'}]}\n```\n",
+            &config,
+        )
+        .unwrap();
+        assert!(output.starts_with("# Bus\n\n"));
+        assert!(!output.contains("```wavedrom"));
+        assert!(output.contains("{signal: [{name: 'clk', wave: 'p.'}]}"));
+
+        // An unterminated fence ends with the document.
+        let output = process_markdown("```wavedrom\n{signal: [{name: 'clk'}]}\n", &config).unwrap();
+        assert!(output.contains("{signal: [{name: 'clk'}]}"));
+        assert!(!output.contains("```wavedrom"));
+    }
+}
@@ -0,0 +1,246 @@
+//! Numbers written the way the book's `locale` writes them, so translated books don't show
+//! English-style numbers in their figures.
+//!
+//! The locale sets the decimal separator, the separator grouping the thousands of computed
+//! values, and what goes between a number and its unit. It applies to the values of expressions
+//! in data labels, see [`crate::expr`], and to the tick labels WaveDrom draws from a `tick` or
+//! `tock` of the `head` or `foot`, which are written out as labels of their own. Tick labels
+//! aren't grouped, WaveDrom splits them at any space.
+
+use serde_json::Value;
+
+use crate::wavejson;
+
+/// How numbers are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Numbers {
+    decimal: &'static str,
+    /// Separator of the groups of thousands, none to write the digits in one run.
+    group: Option<&'static str>,
+    /// What goes between a number and its unit.
+    unit: &'static str,
+}
+
+impl Default for Numbers {
+    fn default() -> Self {
+        Numbers {
+            decimal: ".",
+            group: None,
+            unit: " ",
+        }
+    }
+}
+
+/// The separators of the locales there are, by their language or language and region.
+const LOCALES: &[(&[&str], Numbers)] = &[
+    (
+        &["en", "ja", "ko", "zh", "he", "th"],
+        Numbers {
+            decimal: ".",
+            group: Some(","),
+            unit: " ",
+        },
+    ),
+    (
+        &["de-ch", "it-ch", "fr-ch"],
+        Numbers {
+            decimal: ".",
+            group: Some("’"),
+            unit: " ",
+        },
+    ),
+    (
+        &["de", "es", "it", "nl", "pt", "da", "id", "tr", "el", "ro"],
+        Numbers {
+            decimal: ",",
+            group: Some("."),
+            unit: " ",
+        },
+    ),
+    (
+        &[
+            "fr", "ru", "uk", "pl", "cs", "sk", "sv", "fi", "nb", "no", "hu", "bg",
+        ],
+        Numbers {
+            decimal: ",",
+            // Narrow and plain no-break spaces, which keep the number on one line.
+            group: Some("\u{202f}"),
+            unit: "\u{a0}",
+        },
+    ),
+];
+
+/// The separators of `locale`, like `de` or `de-CH`, if it's known.
+pub(crate) fn for_locale(locale: &str) -> Option<Numbers> {
+    let locale = locale.trim().to_ascii_lowercase().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+    let find = |name: &str| {
+        LOCALES
+            .iter()
+            .find(|(names, _)| names.contains(&name))
+            .map(|(_, numbers)| numbers.clone())
+    };
+    find(&locale).or_else(|| find(language))
+}
+
+impl Numbers {
+    /// The whole number `magnitude`, grouped unless it's padded to `width` digits like a code.
+    pub(crate) fn whole(&self, magnitude: u64, width: usize) -> String {
+        let digits = format!("{:0width$}", magnitude, width = width);
+        match self.group {
+            Some(group) if width == 0 => group_thousands(&digits, group),
+            _ => digits,
+        }
+    }
+
+    /// `number` followed by its `unit`.
+    pub(crate) fn with_unit(&self, number: &str, unit: &str) -> String {
+        format!("{}{}{}", number, self.unit, unit)
+    }
+
+    /// A number written by Rust, like `1.5`, with the decimal separator of the locale.
+    fn decimal(&self, number: &str) -> String {
+        number.replacen('.', self.decimal, 1)
+    }
+}
+
+fn group_thousands(digits: &str, group: &str) -> String {
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * group.len());
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(group);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Whether `source` may have tick labels to write as `numbers` are.
+pub(crate) fn wanted(source: &str, numbers: &Numbers) -> bool {
+    numbers.decimal != "." && (source.contains("tick") || source.contains("tock"))
+}
+
+/// Write the numbered `tick` and `tock` labels of the head and foot of `diagram` out in the
+/// locale of `numbers`. Returns whether any were.
+pub(crate) fn apply(diagram: &mut Value, numbers: &Numbers) -> bool {
+    let count = marks(diagram);
+    let mut changed = false;
+    for part in ["head", "foot"] {
+        for key in ["tick", "tock"] {
+            let value = match diagram.get_mut(part).and_then(|part| part.get_mut(key)) {
+                Some(value) => value,
+                None => continue,
+            };
+            if let Some(labels) = labels(value, count, numbers) {
+                // WaveDrom appends the start of the diagram to the labels, which the trailing
+                // space keeps out of the last one.
+                *value = Value::String(labels.join(" ") + " ");
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// At least as many labels as WaveDrom draws ticks: one per brick of the longest lane, and one
+/// more at its end.
+fn marks(diagram: &Value) -> usize {
+    let mut marks = 0;
+    for lane in wavejson::lanes(diagram) {
+        let wave = lane.get("wave").and_then(Value::as_str).unwrap_or_default();
+        let period = lane
+            .get("period")
+            .and_then(Value::as_f64)
+            .unwrap_or(1.0)
+            .max(1.0);
+        marks = marks.max((wave.chars().count() as f64 * period).ceil() as usize);
+    }
+    marks + 1
+}
+
+/// The `count` labels of the tick `value`, unless the locale writes them as WaveDrom does.
+fn labels(value: &Value, count: usize, numbers: &Numbers) -> Option<Vec<String>> {
+    let text = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.clone(),
+        _ => return None,
+    };
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let numbered = |offset: f64, step: f64, decimals: usize| -> Vec<String> {
+        (0..count)
+            .map(|i| numbers.decimal(&format!("{:.*}", decimals, (i as f64 + offset) * step)))
+            .collect()
+    };
+    let labels = match words.as_slice() {
+        // A start, counting up by 1 like WaveDrom does: `0.5` gives `0.5 1.5 2.5`.
+        [offset] => {
+            let offset: f64 = offset.parse().ok()?;
+            numbered(offset, 1.0, decimals(words[0]))
+        }
+        // A start and a step: `0 0.5` gives `0.0 0.5 1.0`, with the decimals of the step.
+        [offset, step] => match (offset.parse::<f64>(), step.parse::<f64>()) {
+            (Ok(offset), Ok(step)) => numbered(offset, step, decimals(words[1])),
+            _ => return None,
+        },
+        // Labels of their own, the numbers among them localized.
+        labels => labels
+            .iter()
+            .map(|label| match label.parse::<f64>() {
+                Ok(_) => numbers.decimal(label),
+                Err(_) => label.to_string(),
+            })
+            .collect(),
+    };
+    if labels.iter().all(|label| !label.contains(numbers.decimal)) {
+        return None;
+    }
+    Some(labels)
+}
+
+/// The number of decimals `number` is written with.
+fn decimals(number: &str) -> usize {
+    number
+        .split_once('.')
+        .map_or(0, |(_, decimals)| decimals.len())
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{apply, for_locale, Numbers};
+    use crate::wavejson;
+
+    #[test]
+    fn formats_per_locale() {
+        let de = for_locale("de-DE").unwrap();
+        assert_eq!("1.234.567", de.whole(1_234_567, 0));
+        assert_eq!("001234", de.whole(1234, 6));
+        assert_eq!("5 ns", de.with_unit("5", "ns"));
+        let fr = for_locale("fr").unwrap();
+        assert_eq!("12\u{202f}000", fr.whole(12_000, 0));
+        assert_eq!("5\u{a0}ns", fr.with_unit("5", "ns"));
+        assert_eq!("1’000", for_locale("de_CH").unwrap().whole(1000, 0));
+        assert_eq!("1234", Numbers::default().whole(1234, 0));
+        assert_eq!(None, for_locale("tlh"));
+    }
+
+    #[test]
+    fn writes_out_tick_labels() {
+        let de = for_locale("de").unwrap();
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'clk', wave: 'p..'}], head: {tick: '0 0.5', tock: 2}, \
+             foot: {tick: 0.5}}",
+        )
+        .unwrap();
+        assert!(apply(&mut diagram, &de));
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: 'p..'}], head: {tick: '0,0 0,5 1,0 1,5 ', tock: 2}, \
+             foot: {tick: '0,5 1,5 2,5 3,5 '}}",
+            wavejson::to_string(&diagram)
+        );
+
+        let mut diagram = wavejson::parse("{signal: [{wave: 'p.'}], head: {tick: 0}}").unwrap();
+        assert!(!apply(&mut diagram, &de));
+    }
+}
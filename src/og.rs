@@ -0,0 +1,177 @@
+//! Link preview images of the chapters, for the apps showing a preview of a shared link.
+//!
+//! With `og-image` enabled, the first diagram of every chapter, or the one marked `og=true`, is
+//! rendered to a PNG with the `render-command`, and the chapter gets the Open Graph meta tags
+//! pointing to it. Diagrams marked `og=false` are never chosen.
+//!
+//! The images are written next to the standalone pages below the book's `src` directory, from
+//! where mdbook copies them into the output.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::{Error, Result};
+
+use crate::attrs::Attributes;
+use crate::html::escape_html;
+use crate::{standalone, wavejson};
+
+/// The attribute choosing the diagram of a chapter's preview.
+const ATTRIBUTE: &str = "og";
+
+/// A preview image, not yet written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Image {
+    /// Path of the image relative to the book's `src` directory.
+    pub(crate) path: PathBuf,
+    pub(crate) png: Vec<u8>,
+}
+
+impl Image {
+    /// The path of the preview image of the chapter at `chapter_path`.
+    pub(crate) fn path(chapter_path: &Path) -> PathBuf {
+        Path::new(standalone::DIR)
+            .join("og")
+            .join(chapter_path.with_extension("png"))
+    }
+}
+
+/// The diagram of a chapter its preview shows, chosen among the diagrams offered in order.
+#[derive(Debug, Default)]
+pub(crate) struct Choice {
+    source: Option<String>,
+    caption: Option<String>,
+    /// Whether the diagram was marked `og=true`, rather than being the first.
+    marked: bool,
+}
+
+impl Choice {
+    /// Offer the diagram with the WaveJSON `source` and the attributes `attrs`.
+    pub(crate) fn offer(&mut self, source: &str, attrs: &Attributes) {
+        let take = match attrs.get(ATTRIBUTE) {
+            Some("true") => !self.marked,
+            Some("false") => false,
+            _ => self.source.is_none(),
+        };
+        if take {
+            self.marked = attrs.get(ATTRIBUTE) == Some("true");
+            self.source = Some(source.to_string());
+            self.caption = attrs.get("caption").map(String::from);
+        }
+    }
+
+    /// The source of the chosen diagram.
+    pub(crate) fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// The text describing the chosen diagram: its caption, or the text of its `head`.
+    pub(crate) fn alt(&self) -> Option<String> {
+        self.caption.clone().or_else(|| {
+            let diagram = wavejson::parse(self.source.as_deref()?).ok()?;
+            wavejson::head_text(&diagram)
+        })
+    }
+}
+
+/// The URL of the image at `path`, below the `site_url` of the book if it has one, otherwise
+/// relative to the chapter `path_to_root` away from the book's root.
+pub(crate) fn url(path: &Path, site_url: Option<&str>, path_to_root: &str) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    match site_url {
+        Some(site_url) => format!("{}/{}", site_url.trim_end_matches('/'), path),
+        None => format!("{}{}", path_to_root, path),
+    }
+}
+
+/// The meta tags of a preview image at `url`, described by `alt`.
+pub(crate) fn meta_tags(url: &str, alt: Option<&str>) -> String {
+    let mut tags = format!(
+        "<meta property=\"og:image\" content=\"{}\">\n\
+         <meta property=\"og:image:type\" content=\"image/png\">\n",
+        escape_html(url)
+    );
+    if let Some(alt) = alt {
+        tags.push_str(&format!(
+            "<meta property=\"og:image:alt\" content=\"{}\">\n",
+            escape_html(alt)
+        ));
+    }
+    tags.push_str("<meta name=\"twitter:card\" content=\"summary_large_image\">\n");
+    tags
+}
+
+/// Write `images` below `src_dir`, leaving the ones that didn't change alone.
+pub(crate) fn write_all(src_dir: &Path, images: &[Image]) -> Result<()> {
+    for image in images {
+        let path = src_dir.join(&image.path);
+        if fs::read(&path).ok().as_deref() == Some(image.png.as_slice()) {
+            continue;
+        }
+
+        log::debug!("Writing preview image {}", path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                Error::from(e).context(format!("Unable to create {}", parent.display()))
+            })?;
+        }
+        fs::write(&path, &image.png)
+            .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{meta_tags, url, Choice, Image};
+    use crate::attrs::{self, Attributes};
+
+    fn attrs(attributes: &str) -> Attributes {
+        attrs::parse(&format!("wavedrom {}", attributes)).1
+    }
+
+    #[test]
+    fn chooses_the_marked_or_first_diagram() {
+        let mut choice = Choice::default();
+        choice.offer(
+            "{signal: [{name: 'a'}], head: {text: 'A'}}",
+            &attrs("og=false"),
+        );
+        assert_eq!(None, choice.source());
+        choice.offer("{signal: [{name: 'b'}], head: {text: 'B'}}", &attrs(""));
+        choice.offer("{signal: [{name: 'c'}]}", &attrs("og=true caption=C"));
+        choice.offer("{signal: [{name: 'd'}]}", &attrs("og=true"));
+        assert_eq!(Some("{signal: [{name: 'c'}]}"), choice.source());
+        assert_eq!(Some("C".to_string()), choice.alt());
+
+        let mut choice = Choice::default();
+        choice.offer("{signal: [{name: 'b'}], head: {text: 'B'}}", &attrs(""));
+        choice.offer("{signal: [{name: 'c'}]}", &attrs(""));
+        assert_eq!(Some("B".to_string()), choice.alt());
+    }
+
+    #[test]
+    fn meta_tags_point_to_the_image() {
+        let path = Image::path(Path::new("a/intro.md"));
+        assert_eq!(Path::new("wavedrom-generated/og/a/intro.png"), path);
+        assert_eq!(
+            "https://spec.example.com/wavedrom-generated/og/a/intro.png",
+            url(&path, Some("https://spec.example.com/"), "../")
+        );
+        assert_eq!(
+            "../wavedrom-generated/og/a/intro.png",
+            url(&path, None, "../")
+        );
+        assert_eq!(
+            "<meta property=\"og:image\" content=\"/x.png\">\n\
+             <meta property=\"og:image:type\" content=\"image/png\">\n\
+             <meta property=\"og:image:alt\" content=\"&lt;SPI&gt;\">\n\
+             <meta name=\"twitter:card\" content=\"summary_large_image\">\n",
+            meta_tags("/x.png", Some("<SPI>"))
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! WaveDrom's `config` of a single diagram, set by attributes of its block:
+//!
+//! ~~~text
+//! ```wavedrom skin=narrow
+//! ```
+//! ~~~
+//!
+//! The attributes win over the block's own `config`, so a diagram shared by several blocks, or
+//! kept in a file of its own, can be drawn differently by each. `hscale` is taken care of by the
+//! [views](crate::views), which it stretches.
+
+use serde_json::{json, Value};
+
+use crate::attrs::Attributes;
+use crate::WavedromConfig;
+
+/// What the value of an option is.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// The name of a skin registered as `WaveSkin.<name>`.
+    Name,
+    /// A whole number greater than 0.
+    Count,
+    Flag,
+}
+
+/// The `config` options set by the attributes of the same name.
+const OPTIONS: &[(&str, Kind)] = &[
+    ("skin", Kind::Name),
+    // Of registers.
+    ("bits", Kind::Count),
+    ("lanes", Kind::Count),
+    ("hspace", Kind::Count),
+    ("vspace", Kind::Count),
+    ("fontsize", Kind::Count),
+    ("compact", Kind::Flag),
+    ("hflip", Kind::Flag),
+    ("vflip", Kind::Flag),
+];
+
+/// Whether the block with `attrs` sets any options of its diagram.
+pub(crate) fn wanted(attrs: &Attributes) -> bool {
+    OPTIONS
+        .iter()
+        .any(|(option, _)| attrs.get(option).is_some())
+}
+
+/// Set the options `attrs` give in the `config` of `diagram`.
+///
+/// Returns a warning for every value its option doesn't take.
+pub(crate) fn apply(diagram: &mut Value, attrs: &Attributes) -> Vec<String> {
+    let mut warnings = vec![];
+    let config = match diagram.as_object_mut() {
+        Some(diagram) => diagram.entry("config").or_insert_with(|| json!({})),
+        None => return warnings,
+    };
+    let config = match config.as_object_mut() {
+        Some(config) => config,
+        None => {
+            warnings.push("The diagram's `config` isn't an object, ignoring its options".into());
+            return warnings;
+        }
+    };
+    for (option, kind) in OPTIONS {
+        let text = match attrs.get(option) {
+            Some(text) => text,
+            None => continue,
+        };
+        match parse(text, *kind) {
+            Ok(value) => {
+                config.insert(option.to_string(), value);
+            }
+            Err(expected) => warnings.push(format!(
+                "Invalid `{}={}`, expected {}",
+                option, text, expected
+            )),
+        }
+    }
+    warnings
+}
+
+/// Whether `text` can be the name of a skin, which is looked up as `WaveSkin.<name>`.
+pub(crate) fn is_name(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Set the `skin` and `hscale` of the book's `config` in the `config` of a timing `diagram`
+/// that doesn't set them itself.
+///
+/// Returns whether the diagram changed.
+pub(crate) fn defaults(diagram: &mut Value, config: &WavedromConfig) -> bool {
+    if config.skin.is_none() && config.hscale.is_none() {
+        return false;
+    }
+    let diagram = match diagram.as_object_mut() {
+        Some(diagram) if diagram.contains_key("signal") => diagram,
+        _ => return false,
+    };
+    let options = match diagram
+        .entry("config")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+    {
+        Some(options) => options,
+        None => return false,
+    };
+    let mut changed = false;
+    if let Some(skin) = &config.skin {
+        if !options.contains_key("skin") {
+            options.insert("skin".into(), json!(skin));
+            changed = true;
+        }
+    }
+    if let Some(hscale) = config.hscale {
+        if !options.contains_key("hscale") {
+            options.insert("hscale".into(), json!(hscale.get()));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The value of an option of `kind` written as `text`, or what was expected instead.
+fn parse(text: &str, kind: Kind) -> Result<Value, &'static str> {
+    match kind {
+        Kind::Name if is_name(text) => Ok(json!(text)),
+        Kind::Name => Err("the name of a skin"),
+        Kind::Count => match text.parse::<u32>() {
+            Ok(count) if count > 0 => Ok(json!(count)),
+            _ => Err("a whole number greater than 0"),
+        },
+        Kind::Flag => match text {
+            "true" => Ok(json!(true)),
+            "false" => Ok(json!(false)),
+            _ => Err("`true` or `false`"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::{attrs, wavejson};
+
+    fn options(source: &str, info: &str) -> (String, Vec<String>) {
+        let mut diagram = wavejson::parse(source).unwrap();
+        let (_, attrs) = attrs::parse(info);
+        let warnings = apply(&mut diagram, &attrs);
+        (wavejson::to_string(&diagram), warnings)
+    }
+
+    #[test]
+    fn overrides_the_config() {
+        assert_eq!(
+            (
+                "{signal: [], config: {hscale: 2, skin: 'narrow'}}".to_string(),
+                vec![]
+            ),
+            options(
+                "{signal: [], config: {hscale: 2, skin: 'lowkey'}}",
+                "wavedrom skin=narrow"
+            )
+        );
+        assert_eq!(
+            (
+                "{reg: [{bits: 8}], config: {lanes: 2, compact: true}}".to_string(),
+                vec![]
+            ),
+            options("{reg: [{bits: 8}]}", "wavedrom lanes=2 compact")
+        );
+    }
+
+    #[test]
+    fn checks_the_values() {
+        let (output, warnings) = options(
+            "{signal: []}",
+            "wavedrom skin=\"my skin\" lanes=0 vflip=yes",
+        );
+        assert_eq!("{signal: [], config: {}}", output);
+        assert_eq!(
+            vec![
+                "Invalid `skin=my skin`, expected the name of a skin".to_string(),
+                "Invalid `lanes=0`, expected a whole number greater than 0".to_string(),
+                "Invalid `vflip=yes`, expected `true` or `false`".to_string(),
+            ],
+            warnings
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Transforms of other preprocessors run along with this one, over the same parse of a chapter.
+//!
+//! Books running several custom preprocessors pay for parsing every chapter in each of them.
+//! A binary wrapping this crate can instead add its own transforms to the preprocessor with
+//! [`Wavedrom::then`](crate::Wavedrom::then): every chapter is parsed once, and the transforms
+//! see the same events as the diagrams are found by. Each transform returns the edits it makes
+//! to the chapter's markdown, by byte ranges of the original, and all edits are applied at once.
+//!
+//! ```no_run
+//! use mdbook::errors::Result;
+//! use mdbook_wavedrom::pipeline::{pulldown_cmark::Event, Chapter, Edit, Transform};
+//! use mdbook_wavedrom::Wavedrom;
+//!
+//! /// Replaces `:tm:` in text by a trademark sign.
+//! struct Trademarks;
+//!
+//! impl Transform for Trademarks {
+//!     fn name(&self) -> &str {
+//!         "trademarks"
+//!     }
+//!
+//!     fn edits(&self, chapter: &Chapter) -> Result<Vec<Edit>> {
+//!         let mut edits = vec![];
+//!         for (event, range) in chapter.events {
+//!             if let Event::Text(text) = event {
+//!                 if let Some(i) = text.find(":tm:") {
+//!                     let start = range.start + i;
+//!                     edits.push(Edit::new(start..start + 4, "™"));
+//!                 }
+//!             }
+//!         }
+//!         Ok(edits)
+//!     }
+//! }
+//!
+//! let preprocessor = Wavedrom::default().then(Trademarks);
+//! ```
+
+use std::ops::Range;
+use std::path::Path;
+
+use mdbook::errors::{Error, Result};
+use pulldown_cmark::Event;
+
+/// The version of `pulldown-cmark` the events are of.
+pub use pulldown_cmark;
+
+/// A chapter parsed for the transforms.
+#[derive(Debug, Clone, Copy)]
+pub struct Chapter<'a> {
+    pub name: &'a str,
+    /// The chapter's path relative to the book's `src` directory, if it has one.
+    pub path: Option<&'a Path>,
+    /// The chapter's markdown.
+    pub content: &'a str,
+    /// The events of `content` with their byte ranges, parsed with the extensions mdbook
+    /// enables.
+    pub events: &'a [(Event<'a>, Range<usize>)],
+}
+
+/// A replacement of the bytes at `range` of a chapter by `text`. An empty range inserts it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Edit {
+            range,
+            text: text.into(),
+        }
+    }
+}
+
+/// A transform of chapters, run along with the diagrams.
+pub trait Transform: Send + Sync {
+    /// The name the transform's errors are reported with.
+    fn name(&self) -> &str;
+
+    /// The edits of the `chapter`, which mustn't overlap with each other or with the diagrams.
+    fn edits(&self, chapter: &Chapter) -> Result<Vec<Edit>>;
+}
+
+/// Apply the `edits` to `content`, each with the name of the transform making it.
+///
+/// Insertions at the same place are kept in their order.
+pub(crate) fn apply(content: &str, mut edits: Vec<(&str, Edit)>) -> Result<String> {
+    edits.sort_by_key(|(_, edit)| (edit.range.start, edit.range.end));
+    let added: usize = edits.iter().map(|(_, edit)| edit.text.len()).sum();
+    let mut output = String::with_capacity(content.len() + added);
+    let mut last = 0;
+    let mut last_by = "";
+    for (by, edit) in &edits {
+        let Range { start, end } = edit.range;
+        if start < last {
+            return Err(Error::msg(format!(
+                "`{}` and `{}` both change bytes {}..{} of the chapter",
+                last_by,
+                by,
+                start,
+                last.min(end)
+            )));
+        }
+        let valid = |i| i <= content.len() && content.is_char_boundary(i);
+        if start > end || !valid(start) || !valid(end) {
+            return Err(Error::msg(format!(
+                "`{}` changes bytes {}..{}, which aren't a range of the chapter",
+                by, start, end
+            )));
+        }
+        output.push_str(&content[last..start]);
+        output.push_str(&edit.text);
+        last = end;
+        last_by = by;
+    }
+    output.push_str(&content[last..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{apply, Edit};
+
+    #[test]
+    fn applies_edits() {
+        let content = "one two three";
+        let edits = vec![
+            ("b", Edit::new(8..13, "3")),
+            ("a", Edit::new(0..3, "1")),
+            ("a", Edit::new(13..13, "!")),
+            ("b", Edit::new(13..13, "?")),
+        ];
+        assert_eq!("1 two 3!?", apply(content, edits).unwrap());
+
+        let overlapping = vec![("a", Edit::new(0..7, "")), ("b", Edit::new(4..13, ""))];
+        assert_eq!(
+            "`a` and `b` both change bytes 4..7 of the chapter",
+            apply(content, overlapping).unwrap_err().to_string()
+        );
+        let outside = vec![("a", Edit::new(10..20, ""))];
+        assert!(apply(content, outside).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Diagrams written where they can't be turned into a block.
+//!
+//! A fence only starts a code block where markdown allows block content. Inside a table cell or a
+//! heading it's read as inline code, and directly inside an HTML block it stays raw text. Either
+//! way the diagram silently shows up as its source, so these places are reported instead.
+
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Tag};
+
+use crate::{attrs, blocks};
+
+/// Where a misplaced diagram was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placement {
+    TableCell,
+    Heading,
+    Html,
+}
+
+impl Placement {
+    /// What to tell the author.
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            Placement::TableCell => {
+                "wavedrom diagram inside a table cell is not supported, move it below the table"
+            }
+            Placement::Heading => {
+                "wavedrom diagram inside a heading is not supported, move it below the heading"
+            }
+            Placement::Html => {
+                "wavedrom diagram inside an HTML block is not rendered, \
+                 separate it from the HTML with blank lines"
+            }
+        }
+    }
+}
+
+/// Find the diagrams of `content` that can't be rendered, with the byte offset of each, by the
+/// `events` it parses into.
+pub(crate) fn misplaced_in(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    languages: &[&str],
+) -> Vec<(usize, Placement)> {
+    let mut found = vec![];
+    let mut in_table_cell = false;
+    let mut in_heading = false;
+    let mut off = false;
+
+    for (e, span) in events {
+        match e {
+            Event::Start(Tag::TableCell) => in_table_cell = true,
+            Event::End(Tag::TableCell) => in_table_cell = false,
+            Event::Start(Tag::Heading(..)) => in_heading = true,
+            Event::End(Tag::Heading(..)) => in_heading = false,
+            Event::Html(html) if html.trim() == blocks::OFF => off = true,
+            Event::Html(html) if html.trim() == blocks::ON => off = false,
+            _ if off => {}
+            Event::Code(code)
+                if is_fenced(&content[span.clone()]) && is_wavedrom(code, languages) =>
+            {
+                if in_table_cell {
+                    found.push((span.start, Placement::TableCell));
+                } else if in_heading {
+                    found.push((span.start, Placement::Heading));
+                }
+            }
+            Event::Html(_) => {
+                if let Some(offset) = fence_in_html(content, span, languages) {
+                    found.push((offset, Placement::Html));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    found
+}
+
+/// Inline code written with a fence's three backticks, rather than as regular inline code.
+fn is_fenced(source: &str) -> bool {
+    source.starts_with("```")
+}
+
+fn is_wavedrom(info: &str, languages: &[&str]) -> bool {
+    languages.contains(&attrs::parse(info).0)
+}
+
+/// The offset of a line opening a wavedrom fence within the HTML at `span` of `content`.
+///
+/// This looks at the source rather than the parsed HTML, so the offset stays right when the HTML
+/// is nested in a list or blockquote and the parser removed their markers.
+fn fence_in_html(content: &str, span: &Range<usize>, languages: &[&str]) -> Option<usize> {
+    let html = content.get(span.clone())?;
+    let mut offset = span.start;
+    for line in html.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(|c: char| c.is_whitespace() || c == '>');
+        let info = trimmed
+            .strip_prefix("```")
+            .or_else(|| trimmed.strip_prefix("~~~"));
+        if let Some(info) = info {
+            if is_wavedrom(info.trim_start_matches(['`', '~']), languages) {
+                return Some(offset);
+            }
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use pulldown_cmark::Parser;
+
+    use super::{misplaced_in, Placement};
+
+    fn misplaced_diagrams(content: &str) -> Vec<(usize, Placement)> {
+        let events: Vec<_> = Parser::new_ext(content, crate::parser_options())
+            .into_offset_iter()
+            .collect();
+        misplaced_in(content, &events, &["wavedrom"])
+    }
+
+    #[test]
+    fn table_cells_and_headings() {
+        let content = "| A | B |\n|---|---|\n| ```wavedrom {signal: []}``` | b |\n\n\
+                       ## ```wavedrom {signal: []}```\n\nThe `wavedrom` fence.\n";
+        assert_eq!(
+            vec![(22, Placement::TableCell), (60, Placement::Heading)],
+            misplaced_diagrams(content)
+        );
+    }
+
+    #[test]
+    fn fence_swallowed_by_html() {
+        let content = "Text\n\n<details>\n```wavedrom\n{signal: []}\n```\n</details>\n";
+        assert_eq!(vec![(16, Placement::Html)], misplaced_diagrams(content));
+    }
+
+    #[test]
+    fn fence_swallowed_by_html_in_blockquote() {
+        let content = "> <div>\n> ```wavedrom\n> {signal: []}\n> ```\n> </div>\n";
+        assert_eq!(vec![(10, Placement::Html)], misplaced_diagrams(content));
+    }
+
+    #[test]
+    fn regular_diagrams_are_fine() {
+        let content = "<details>\n\n```wavedrom\n{signal: []}\n```\n\n</details>\n\n\
+                       - ```wavedrom\n  {signal: []}\n  ```\n";
+        assert!(misplaced_diagrams(content).is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Signals shown under other names in all diagrams of the book, as the `rename` table of the
+//! configuration gives them:
+//!
+//! ~~~toml
+//! [preprocessor.wavedrom.rename]
+//! rst_n = "RESETn"
+//! ~~~
+//!
+//! The blocks keep the names they were written with, so their attributes like `colors` and
+//! `signals` still go by the old names. Only what the page shows is renamed.
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::wavejson;
+
+/// The new names of signals, by their old names.
+pub(crate) type Renames = BTreeMap<String, String>;
+
+/// Whether `source` may name one of the signals of `renames`.
+pub(crate) fn wanted(source: &str, renames: &Renames) -> bool {
+    renames.keys().any(|old| source.contains(old.as_str()))
+}
+
+/// The name `name` is shown under.
+pub(crate) fn get<'a>(name: &'a str, renames: &'a Renames) -> &'a str {
+    renames.get(name).map_or(name, String::as_str)
+}
+
+/// Rename the lanes of `diagram`, and the signals of its `descriptions`.
+///
+/// The name of a lane may be a string, or the markup the colors and domains style it with, like
+/// `['tspan', {style: '…'}, 'req']`. Returns whether anything was renamed.
+pub(crate) fn apply(
+    diagram: &mut Value,
+    descriptions: &mut [(String, String)],
+    renames: &Renames,
+) -> bool {
+    let mut renamed = false;
+    wavejson::for_each_lane(diagram, |lane| {
+        if let Some(name) = lane.get_mut("name") {
+            renamed |= rename(name, renames);
+        }
+    });
+    for (name, _) in descriptions {
+        if let Some(new) = renames.get(name.as_str()) {
+            *name = new.clone();
+            renamed = true;
+        }
+    }
+    renamed
+}
+
+fn rename(name: &mut Value, renames: &Renames) -> bool {
+    match name {
+        Value::String(old) => match renames.get(old.as_str()) {
+            Some(new) => {
+                *old = new.clone();
+                true
+            }
+            None => false,
+        },
+        // The text of a `tspan` follows its tag and attributes.
+        Value::Array(markup) => markup
+            .iter_mut()
+            .skip(1)
+            .fold(false, |renamed, part| rename(part, renames) | renamed),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{apply, Renames};
+    use crate::wavejson;
+
+    #[test]
+    fn renames_lanes_and_descriptions() {
+        let renames: Renames = vec![("rst_n".to_string(), "RESETn".to_string())]
+            .into_iter()
+            .collect();
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'clk', wave: 'p...'}, \
+             ['ctl', {name: ['tspan', {style: 'fill:#0066cc'}, 'rst_n'], wave: '01..'}], \
+             {name: 'rst_n_sync', wave: '0.1.'}]}",
+        )
+        .unwrap();
+        let mut descriptions = vec![("rst_n".to_string(), "Reset".to_string())];
+
+        assert!(apply(&mut diagram, &mut descriptions, &renames));
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: 'p...'}, \
+             ['ctl', {name: ['tspan', {style: 'fill:#0066cc'}, 'RESETn'], wave: '01..'}], \
+             {name: 'rst_n_sync', wave: '0.1.'}]}",
+            wavejson::to_string(&diagram)
+        );
+        assert_eq!(
+            vec![("RESETn".to_string(), "Reset".to_string())],
+            descriptions
+        );
+
+        let mut diagram = wavejson::parse("{signal: [{name: 'clk', wave: 'p.'}]}").unwrap();
+        assert!(!apply(&mut diagram, &mut [], &renames));
+    }
+}
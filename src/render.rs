@@ -0,0 +1,513 @@
+//! Rendering diagrams to images at build time, by running an external command.
+//!
+//! The command is [`wavedrom-cli`](https://github.com/wavedrom/cli) unless `render-command`
+//! says otherwise, and is called like it: `<command> -i diagram.json5 -s diagram.svg`,
+//! or with `-p diagram.png` for PNG.
+//!
+//! Every diagram gets `render-timeout` seconds and may produce an image of up to
+//! `render-max-size` bytes, so a pathological diagram fails on its own instead of hanging or
+//! bloating the build.
+//!
+//! SVG images get the `render-font`, see [`crate::font`].
+//!
+//! What the command wrote is kept in the `render-cache-dir`, by a hash of the command, the
+//! format and the diagram, so rebuilds like the ones of `mdbook serve` only run the command for
+//! diagrams that changed. Images that weren't used for [`MAX_AGE`] are deleted again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use mdbook::errors::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::font::{self, Font};
+
+/// The command diagrams are rendered with by default.
+pub(crate) const DEFAULT_COMMAND: &str = "wavedrom-cli";
+
+/// Seconds a diagram may take to render unless `render-timeout` is set.
+pub(crate) const DEFAULT_TIMEOUT: u64 = 60;
+
+/// Largest image in bytes a diagram may render to unless `render-max-size` is set.
+pub(crate) const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How often a running render command is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a cached image is kept without being used.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The render command, and the limits it runs under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Renderer {
+    /// The program followed by its own arguments.
+    pub(crate) command: Vec<String>,
+    /// How long a single diagram may take, `None` for no limit.
+    pub(crate) timeout: Option<Duration>,
+    /// Largest image in bytes a single diagram may render to, `None` for no limit.
+    pub(crate) max_size: Option<u64>,
+    /// The font to set the text of SVG images in, if not the one of the command.
+    pub(crate) font: Option<Font>,
+}
+
+/// Image format to render diagrams to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Svg,
+    Png,
+}
+
+impl Format {
+    /// File extension of the format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+        }
+    }
+
+    /// The flag selecting the output file for this format.
+    fn flag(self) -> &'static str {
+        match self {
+            Format::Svg => "-s",
+            Format::Png => "-p",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "svg" => Ok(Format::Svg),
+            "png" => Ok(Format::Png),
+            _ => Err(Error::msg(format!(
+                "unknown image format '{}', expected 'svg' or 'png'",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Render the WaveJSON `source` with the `renderer`'s command, within its limits.
+pub(crate) fn render(renderer: &Renderer, source: &str, format: Format) -> Result<Vec<u8>> {
+    let image = run(renderer, source, format)?;
+    finish(renderer, image, format)
+}
+
+/// The image the `renderer`'s command writes for `source`, as it wrote it.
+fn run(renderer: &Renderer, source: &str, format: Format) -> Result<Vec<u8>> {
+    let _span = tracing::debug_span!("render", format = %format).entered();
+    let (program, args) = renderer
+        .command
+        .split_first()
+        .ok_or_else(|| Error::msg("the render command is empty"))?;
+
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("diagram.json5");
+    let output = dir.path().join(format!("diagram.{}", format.extension()));
+    // A file rather than a pipe, which would block a chatty command nobody reads from.
+    let stderr = dir.path().join("stderr");
+    fs::write(&input, source)?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .arg("-i")
+        .arg(&input)
+        .arg(format.flag())
+        .arg(&output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(File::create(&stderr)?)
+        .spawn()
+        .map_err(|e| Error::from(e).context(format!("Unable to run '{}'", program)))?;
+    let status = wait(&mut child, renderer.timeout, program)?;
+    if !status.success() {
+        let stderr = fs::read(&stderr).unwrap_or_default();
+        return Err(Error::msg(format!(
+            "'{}' failed ({}): {}",
+            program,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        )));
+    }
+
+    let size = fs::metadata(&output)
+        .map_err(|e| {
+            Error::from(e).context(format!("'{}' didn't write the {} image", program, format))
+        })?
+        .len();
+    if let Some(max_size) = renderer.max_size.filter(|&max_size| size > max_size) {
+        return Err(Error::msg(format!(
+            "'{}' wrote a {} image of {} bytes, more than the {} bytes `render-max-size` allows",
+            program, format, size, max_size
+        )));
+    }
+    Ok(fs::read(&output)?)
+}
+
+/// The `image` the command wrote, with the `renderer`'s font.
+fn finish(renderer: &Renderer, image: Vec<u8>, format: Format) -> Result<Vec<u8>> {
+    match &renderer.font {
+        Some(font) if format == Format::Svg => font::embed(image, font),
+        _ => Ok(image),
+    }
+}
+
+/// Wait for the `child` running `program` to exit, killing it once it took longer than
+/// `timeout`.
+pub(crate) fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    program: &str,
+) -> Result<ExitStatus> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if let Some(timeout) = timeout.filter(|&timeout| start.elapsed() >= timeout) {
+            // Killing it fails only if it just exited on its own.
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::msg(format!(
+                "'{}' didn't finish within {} seconds, set `render-timeout` to allow more",
+                program,
+                timeout.as_secs_f64()
+            )));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// A rendered image, or why rendering failed.
+type Rendered = std::result::Result<Vec<u8>, String>;
+
+/// The images rendered so far, so a diagram appearing several times in a book, e.g. from a
+/// shared include, is only rendered once.
+///
+/// Failures are remembered as well, their error is reported for every copy of the diagram.
+#[derive(Debug, Default)]
+pub(crate) struct Cache {
+    images: Mutex<HashMap<(Format, String), Rendered>>,
+    /// The directory images are kept in across builds, if any.
+    dir: Option<PathBuf>,
+}
+
+impl Cache {
+    /// A cache keeping the images in `dir` across builds as well, if given.
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        Cache {
+            images: Default::default(),
+            dir,
+        }
+    }
+
+    /// Like [`render`], unless the same `source` was rendered to `format` before.
+    ///
+    /// That may have been in an earlier build, then the image is taken from the directory.
+    pub(crate) fn render(
+        &self,
+        renderer: &Renderer,
+        source: &str,
+        format: Format,
+    ) -> Result<Vec<u8>> {
+        let key = (format, source.to_string());
+        if let Some(image) = self.images.lock().unwrap().get(&key) {
+            log::debug!("Reusing the {} image of an identical diagram", format);
+            return image.clone().map_err(Error::msg);
+        }
+
+        let image = self
+            .render_stored(renderer, source, format)
+            .map_err(|e| format!("{:#}", e));
+        self.images.lock().unwrap().insert(key, image.clone());
+        image.map_err(Error::msg)
+    }
+
+    /// Like [`render`], with the command's image taken from or kept in the directory.
+    ///
+    /// Failures aren't kept, the next build tries again.
+    fn render_stored(&self, renderer: &Renderer, source: &str, format: Format) -> Result<Vec<u8>> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return render(renderer, source, format),
+        };
+        let path = dir.join(file_name(renderer, source, format));
+        let image = match fs::read(&path) {
+            Ok(image) => {
+                log::debug!("Reusing the cached {} image {}", format, path.display());
+                // Used images don't expire.
+                let _ = File::options()
+                    .append(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                image
+            }
+            Err(_) => {
+                let image = run(renderer, source, format)?;
+                if let Err(e) = store(dir, &path, &image) {
+                    log::debug!("Unable to cache {}: {}", path.display(), e);
+                }
+                image
+            }
+        };
+        finish(renderer, image, format)
+    }
+
+    /// Delete the images of the directory that weren't used for [`MAX_AGE`].
+    pub(crate) fn prune(&self) {
+        let entries = match self.dir.as_ref().and_then(|dir| fs::read_dir(dir).ok()) {
+            Some(entries) => entries,
+            None => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let image = path
+                .extension()
+                .is_some_and(|extension| extension == "svg" || extension == "png");
+            let unused = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > MAX_AGE);
+            if image && unused {
+                log::debug!("Deleting the unused cached image {}", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    /// Render all `sources` to `format` ahead of their use, running at most `jobs` commands at
+    /// a time.
+    ///
+    /// Errors are kept for [`Cache::render`] to report, where the diagram is used.
+    pub(crate) fn render_all(
+        &self,
+        renderer: &Renderer,
+        sources: &[String],
+        format: Format,
+        jobs: usize,
+    ) {
+        let mut todo: Vec<&String> = {
+            let images = self.images.lock().unwrap();
+            sources
+                .iter()
+                .filter(|source| !images.contains_key(&(format, source.to_string())))
+                .collect()
+        };
+        todo.sort();
+        todo.dedup();
+        if todo.is_empty() {
+            return;
+        }
+
+        log::debug!("Rendering {} diagrams with {} jobs", todo.len(), jobs);
+        let todo = Mutex::new(todo);
+        thread::scope(|scope| {
+            for _ in 0..jobs.max(1) {
+                scope.spawn(|| loop {
+                    let source = match todo.lock().unwrap().pop() {
+                        Some(source) => source,
+                        None => break,
+                    };
+                    let image = self
+                        .render_stored(renderer, source, format)
+                        .map_err(|e| format!("{:#}", e));
+                    self.images
+                        .lock()
+                        .unwrap()
+                        .insert((format, source.clone()), image);
+                });
+            }
+        });
+    }
+}
+
+/// The name of the cached image of `source` rendered to `format` by the `renderer`'s command.
+fn file_name(renderer: &Renderer, source: &str, format: Format) -> String {
+    let mut hasher = Sha256::new();
+    for arg in &renderer.command {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update(format.extension().as_bytes());
+    hasher.update([0]);
+    hasher.update(source.as_bytes());
+    let hash: String = hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}.{}", hash, format.extension())
+}
+
+/// Keep the `image` at `path` in the cache directory `dir`, creating it if needed.
+///
+/// The image is written next to its place first, so another build never reads half of it.
+fn store(dir: &Path, path: &Path, image: &[u8]) -> std::io::Result<()> {
+    if !dir.is_dir() {
+        fs::create_dir_all(dir)?;
+        // Like `target`, the cache doesn't belong into the book's repository.
+        fs::write(dir.join(".gitignore"), "*\n")?;
+    }
+    let partial = path.with_extension(format!("{}.tmp", process::id()));
+    fs::write(&partial, image)?;
+    fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{render, Cache, Format, Renderer};
+
+    /// A renderer running a shell script with the given `body`, without limits.
+    #[cfg(unix)]
+    fn script(dir: &tempfile::TempDir, body: &str) -> Renderer {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.path().join("render.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Renderer {
+            command: vec![path.display().to_string()],
+            timeout: None,
+            max_size: None,
+            font: None,
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::Png, "png".parse().unwrap());
+        assert_eq!("svg", Format::Svg.to_string());
+        assert!("gif".parse::<Format>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn renders_identical_diagrams_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("calls");
+        let command = script(
+            &dir,
+            &format!("echo run >> '{}'\ncp \"$2\" \"$4\"", log.display()),
+        );
+
+        let cache = Cache::default();
+        for source in &["{signal: []}", "{signal: [{}]}", "{signal: []}"] {
+            assert_eq!(
+                source.as_bytes(),
+                &cache.render(&command, source, Format::Svg).unwrap()[..]
+            );
+        }
+        cache.render(&command, "{signal: []}", Format::Png).unwrap();
+        assert_eq!("run\nrun\nrun\n", std::fs::read_to_string(&log).unwrap());
+
+        let missing = Renderer {
+            command: vec!["mdbook-wavedrom-no-such-renderer".to_string()],
+            ..command.clone()
+        };
+        let first = cache.render(&missing, "{}", Format::Svg).unwrap_err();
+        let second = cache.render(&missing, "{}", Format::Svg).unwrap_err();
+        assert_eq!(format!("{:#}", first), second.to_string());
+
+        let sources: Vec<_> = (0..8)
+            .map(|i| format!("{{signal: [], i: {}}}", i % 4))
+            .collect();
+        cache.render_all(&command, &sources, Format::Svg, 3);
+        assert_eq!(7, std::fs::read_to_string(&log).unwrap().lines().count());
+        assert_eq!(
+            sources[5].as_bytes(),
+            &cache.render(&command, &sources[5], Format::Svg).unwrap()[..]
+        );
+        assert_eq!(7, std::fs::read_to_string(&log).unwrap().lines().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_images_across_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("calls");
+        let command = script(
+            &dir,
+            &format!("echo run >> '{}'\ncp \"$2\" \"$4\"", log.display()),
+        );
+        let cache_dir = dir.path().join("cache");
+
+        for _ in 0..2 {
+            let cache = Cache::new(Some(cache_dir.clone()));
+            assert_eq!(
+                b"{signal: []}".to_vec(),
+                cache.render(&command, "{signal: []}", Format::Svg).unwrap()
+            );
+        }
+        assert_eq!("run\n", std::fs::read_to_string(&log).unwrap());
+        assert_eq!(
+            "*\n",
+            std::fs::read_to_string(cache_dir.join(".gitignore")).unwrap()
+        );
+
+        // Another command renders anew, and failures are tried again.
+        let other = Renderer {
+            command: vec!["sh".into(), command.command[0].clone()],
+            ..command.clone()
+        };
+        Cache::new(Some(cache_dir.clone()))
+            .render(&other, "{signal: []}", Format::Svg)
+            .unwrap();
+        assert_eq!("run\nrun\n", std::fs::read_to_string(&log).unwrap());
+        let failing = script(&dir, "exit 1");
+        for _ in 0..2 {
+            let cache = Cache::new(Some(cache_dir.clone()));
+            assert!(cache.render(&failing, "{}", Format::Svg).is_err());
+        }
+        assert_eq!(3, std::fs::read_dir(&cache_dir).unwrap().count());
+
+        Cache::new(Some(cache_dir.clone())).prune();
+        assert_eq!(3, std::fs::read_dir(&cache_dir).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn enforces_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut slow = script(&dir, "sleep 5\ncp \"$2\" \"$4\"");
+        slow.timeout = Some(Duration::from_millis(100));
+        let err = render(&slow, "{}", Format::Svg).unwrap_err().to_string();
+        assert!(
+            err.ends_with("didn't finish within 0.1 seconds, set `render-timeout` to allow more"),
+            "{}",
+            err
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut big = script(&dir, "head -c 2048 /dev/zero > \"$4\"");
+        assert_eq!(2048, render(&big, "{}", Format::Svg).unwrap().len());
+        big.max_size = Some(1024);
+        let err = render(&big, "{}", Format::Svg).unwrap_err().to_string();
+        assert!(err.ends_with("wrote a svg image of 2048 bytes, more than the 1024 bytes `render-max-size` allows"), "{}", err);
+
+        let failing = script(&dir, "echo 'bad diagram' >&2\nexit 3");
+        let err = render(&failing, "{}", Format::Svg).unwrap_err().to_string();
+        assert!(err.ends_with(": bad diagram"), "{}", err);
+    }
+}
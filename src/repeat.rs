@@ -0,0 +1,233 @@
+//! Lanes generated from a template, for buses with many lanes that only differ by their index:
+//!
+//! ~~~text
+//! {signal: [
+//!   {name: 'clk', wave: 'p...'},
+//!   {$repeat: {name: 'data[{i}]', wave: 'x=.x', data: ['d{i}'], count: 8}},
+//! ]}
+//! ~~~
+//!
+//! The template is repeated `count` times, with `{i}` in its text replaced by the index. The index
+//! starts at `from`, 0 by default, and goes up by `step`, 1 by default; `from: 7, step: -1` counts
+//! down for MSB first buses. Templates can be given in groups as well.
+//!
+//! The expressions in the `data` labels of every lane are evaluated here too, see [`expr`], with
+//! the index as `i` in templates, and written in the book's `locale`.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+use crate::numbers::Numbers;
+use crate::{expr, wavejson};
+
+/// The key of a template lane.
+const KEY: &str = "$repeat";
+
+/// The most lanes a template may generate, against typos like `count: 80000`.
+const MAX_COUNT: i64 = 1024;
+
+/// The WaveJSON `source` with its templates expanded, and the expressions of its labels
+/// evaluated and written as `numbers` are.
+///
+/// Sources without either, or that don't parse, are passed on as they are. Templates that
+/// can't be expanded are dropped, and labels that can't be evaluated are kept as they are, and
+/// reported in `warnings`.
+pub(crate) fn expand<'a>(
+    source: Cow<'a, str>,
+    numbers: &Numbers,
+    warnings: &mut Vec<String>,
+) -> Cow<'a, str> {
+    if !source.contains(KEY) && !expr::has_expressions(&source) {
+        return source;
+    }
+    let mut diagram = match wavejson::parse(&source) {
+        Ok(diagram) => diagram,
+        Err(_) => return source,
+    };
+    match diagram.get_mut("signal") {
+        Some(Value::Array(signal)) => expand_lanes(signal, numbers, warnings),
+        _ => return source,
+    }
+    Cow::Owned(wavejson::to_string(&diagram) + "\n")
+}
+
+fn expand_lanes(lanes: &mut Vec<Value>, numbers: &Numbers, warnings: &mut Vec<String>) {
+    let mut expanded = Vec::with_capacity(lanes.len());
+    for mut lane in lanes.drain(..) {
+        match &mut lane {
+            Value::Object(object) if object.contains_key(KEY) => {
+                match repeat(object, numbers, warnings) {
+                    Ok(generated) => expanded.extend(generated),
+                    Err(e) => warnings.push(e),
+                }
+                continue;
+            }
+            // A group: `['label', lane, lane, ...]`.
+            Value::Array(group) => expand_lanes(group, numbers, warnings),
+            Value::Object(object) => labels(object, None, numbers, warnings),
+            _ => {}
+        }
+        expanded.push(lane);
+    }
+    *lanes = expanded;
+}
+
+/// The lanes of the template in `lane`.
+fn repeat(
+    lane: &Map<String, Value>,
+    numbers: &Numbers,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Value>, String> {
+    if lane.len() > 1 {
+        warnings.push(format!(
+            "A lane with `{}` can't have other keys, ignoring them",
+            KEY
+        ));
+    }
+    let mut template = match &lane[KEY] {
+        Value::Object(template) => template.clone(),
+        _ => return Err(format!("`{}` has to be a lane to repeat", KEY)),
+    };
+    if !template.contains_key("count") {
+        return Err(format!("`{}` needs a `count` of lanes", KEY));
+    }
+    let count = number(&mut template, "count", 0)?;
+    let from = number(&mut template, "from", 0)?;
+    let step = number(&mut template, "step", 1)?;
+    if !(0..=MAX_COUNT).contains(&count) {
+        return Err(format!(
+            "The `count` of `{}` has to be between 0 and {}, not {}",
+            KEY, MAX_COUNT, count
+        ));
+    }
+
+    Ok((0..count)
+        .map(|n| {
+            let index = from + n * step;
+            let mut lane = template.clone();
+            // Before `{i}` is replaced, which `{{i}}` has in it.
+            labels(&mut lane, Some(index), numbers, warnings);
+            let mut lane = Value::Object(lane);
+            substitute(&mut lane, &index.to_string());
+            lane
+        })
+        .collect())
+}
+
+/// Evaluate the expressions in the `data` labels of `lane`, with `i` as the index.
+fn labels(
+    lane: &mut Map<String, Value>,
+    i: Option<i64>,
+    numbers: &Numbers,
+    warnings: &mut Vec<String>,
+) {
+    let texts: Vec<&mut String> = match lane.get_mut("data") {
+        Some(Value::String(text)) => vec![text],
+        Some(Value::Array(items)) => items
+            .iter_mut()
+            .filter_map(|item| match item {
+                Value::String(text) => Some(text),
+                _ => None,
+            })
+            .collect(),
+        _ => return,
+    };
+    for text in texts.into_iter().filter(|text| expr::has_expressions(text)) {
+        match expr::render(text, i, numbers) {
+            Ok(value) => *text = value,
+            // Once for all the lanes of a template.
+            Err(e) if !warnings.contains(&e) => warnings.push(e),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Take the whole number `key` out of the `template`.
+fn number(template: &mut Map<String, Value>, key: &str, default: i64) -> Result<i64, String> {
+    match template.remove(key) {
+        None => Ok(default),
+        Some(value) => value
+            .as_i64()
+            .ok_or_else(|| format!("The `{}` of `{}` has to be a whole number", key, KEY)),
+    }
+}
+
+/// Replace `{i}` in the text of `value` with `index`.
+fn substitute(value: &mut Value, index: &str) {
+    match value {
+        Value::String(text) if text.contains("{i}") => *text = text.replace("{i}", index),
+        Value::Array(items) => items.iter_mut().for_each(|item| substitute(item, index)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|field| substitute(field, index)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use pretty_assertions::assert_eq;
+
+    use super::expand;
+    use crate::numbers::Numbers;
+
+    fn expanded(source: &str) -> (String, Vec<String>) {
+        let mut warnings = vec![];
+        let output = expand(Cow::Borrowed(source), &Numbers::default(), &mut warnings).into_owned();
+        (output, warnings)
+    }
+
+    #[test]
+    fn repeats_lanes() {
+        assert_eq!(
+            (
+                "{signal: [{name: 'clk', wave: 'p.'}, ['bus', {name: 'd[1]', data: ['x1']}, {name: 'd[0]', data: ['x0']}]]}\n"
+                    .to_string(),
+                vec![]
+            ),
+            expanded(
+                "{signal: [{name: 'clk', wave: 'p.'}, \
+                 ['bus', {$repeat: {name: 'd[{i}]', data: ['x{i}'], count: 2, from: 1, step: -1}}]]}"
+            )
+        );
+    }
+
+    #[test]
+    fn evaluates_labels() {
+        assert_eq!(
+            (
+                "{signal: [{name: 'a', data: ['0x40', 'x']}, {name: 'b1', data: '0x21 i'}, {name: 'b2', data: '0x22 i'}]}\n"
+                    .to_string(),
+                vec![]
+            ),
+            expanded(
+                "{signal: [{name: 'a', data: ['{{0x20 * 2 | hex}}', 'x']}, \
+                 {$repeat: {name: 'b{i}', data: '{{0x20+i|hex}} i', count: 2, from: 1}}]}"
+            )
+        );
+
+        let (output, warnings) =
+            expanded("{signal: [{$repeat: {data: ['{{i / 0}}'], count: 2}}, {data: '{{i}}'}]}");
+        assert_eq!(
+            "{signal: [{data: ['{{i / 0}}']}, {data: ['{{i / 0}}']}, {data: '{{i}}'}]}\n",
+            output
+        );
+        assert_eq!(2, warnings.len(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn reports_bad_templates() {
+        let (output, warnings) = expanded("{signal: [{$repeat: {name: 'a'}}, {$repeat: 'b'}]}");
+        assert_eq!("{signal: []}\n", output);
+        assert_eq!(2, warnings.len());
+
+        let (_, warnings) = expanded("{signal: [{$repeat: {count: 100000}}]}");
+        assert_eq!(1, warnings.len());
+
+        let source = "{signal: [{name: '$repeat'}";
+        assert_eq!((source.to_string(), vec![]), expanded(source));
+    }
+}
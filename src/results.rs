@@ -0,0 +1,209 @@
+//! Data lanes filled in from a results file, like the scoreboard of a testbench, referenced by a
+//! block's `results` attribute:
+//!
+//! ~~~text
+//! ```wavedrom results=sim/burst.json
+//! {signal: [{name: 'clk', wave: 'p....'}, {name: 'data', wave: 'x===x'}]}
+//! ```
+//! ~~~
+//!
+//! The file is a JSON object with a list of values for every lane to fill in, by the lane's name,
+//! e.g. `{"data": ["0x10", "0x20", 48]}`. The values replace the lane's `data`. With
+//! `results=sim/run.json#burst` the lanes are taken from the object `burst` of the file, so one
+//! file can hold several transactions. The path is relative to the chapter's markdown file.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+use crate::blocks::Block;
+use crate::{split, wavejson, ChapterContext};
+
+/// The WaveJSON `source` with the values of the block's results file merged in.
+///
+/// Sources without a results file, or that don't parse, are passed on as they are. Problems with
+/// the file, and values that don't fit their lane, are reported in `warnings`.
+pub(crate) fn merge<'a>(
+    source: Cow<'a, str>,
+    block: &Block,
+    page: &ChapterContext,
+    warnings: &mut Vec<String>,
+) -> Cow<'a, str> {
+    let attr = match block.attrs.get("results") {
+        Some(attr) => attr,
+        None => return source,
+    };
+    let results = match read(attr, page) {
+        Ok(results) => results,
+        Err(e) => {
+            warnings.push(e);
+            return source;
+        }
+    };
+    let mut diagram = match wavejson::parse(&source) {
+        Ok(diagram) => diagram,
+        Err(_) => return source,
+    };
+    warnings.extend(apply(&mut diagram, &results, attr));
+    Cow::Owned(wavejson::to_string(&diagram) + "\n")
+}
+
+/// The results file of a block, if it has one and the chapter has a file.
+pub(crate) fn path(block: &Block, page: &ChapterContext) -> Option<PathBuf> {
+    let attr = block.attrs.get("results")?;
+    let file = attr.split_once('#').map_or(attr, |(file, _)| file);
+    Some(page.source_dir()?.join(file))
+}
+
+/// The lanes of the results file `attr` refers to, like `sim/run.json#burst`.
+fn read(attr: &str, page: &ChapterContext) -> Result<Map<String, Value>, String> {
+    let (file, key) = match attr.split_once('#') {
+        Some((file, key)) => (file, Some(key)),
+        None => (attr, None),
+    };
+    let path = page
+        .source_dir()
+        .ok_or_else(|| format!("Unable to find `results={}` without a chapter file", attr))?
+        .join(file);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut results: Value = serde_json::from_str(&content)
+        .map_err(|e| format!("Unable to parse {}: {}", path.display(), e))?;
+    if let Some(key) = key {
+        results = results
+            .get_mut(key)
+            .map(Value::take)
+            .ok_or_else(|| format!("{} has no `{}` results", path.display(), key))?;
+    }
+    match results {
+        Value::Object(results) => Ok(results),
+        _ => Err(format!(
+            "The results of `results={}` have to be an object of lanes",
+            attr
+        )),
+    }
+}
+
+/// Fill in the lanes of `diagram` from `results`.
+///
+/// Returns a warning for every result without a lane, and every lane whose wave doesn't show as
+/// many data bricks as it has results.
+fn apply(diagram: &mut Value, results: &Map<String, Value>, attr: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut used = vec![];
+    wavejson::for_each_lane(diagram, |lane| {
+        let name = match lane.get("name").and_then(Value::as_str) {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+        let values = match results.get(&name) {
+            Some(Value::Array(values)) => values,
+            Some(_) => {
+                warnings.push(format!(
+                    "The results for '{}' have to be a list of values",
+                    name
+                ));
+                return;
+            }
+            None => return,
+        };
+        let data: Vec<Value> = values
+            .iter()
+            .map(|value| match value {
+                Value::String(text) => Value::String(text.clone()),
+                value => Value::String(value.to_string()),
+            })
+            .collect();
+        let bricks = lane.get("wave").and_then(Value::as_str).map_or(0, |wave| {
+            wave.chars()
+                .filter(|c| split::DATA_BRICKS.contains(c))
+                .count()
+        });
+        if bricks != data.len() {
+            warnings.push(format!(
+                "'{}' has {} results, but its wave shows {} data bricks",
+                name,
+                data.len(),
+                bricks
+            ));
+        }
+        lane.insert("data".into(), Value::Array(data));
+        used.push(name);
+    });
+
+    for name in results.keys().filter(|name| !used.contains(name)) {
+        warnings.push(format!(
+            "No signal named '{}' for its results in `results={}`",
+            name, attr
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::fs;
+
+    use mdbook::book::Chapter;
+    use pretty_assertions::assert_eq;
+
+    use super::merge;
+    use crate::{blocks, ChapterContext, WavedromConfig};
+
+    #[test]
+    fn merges_results() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir(src.path().join("sim")).unwrap();
+        fs::write(
+            src.path().join("sim/run.json"),
+            r#"{"burst": {"data": ["0x10", 32, true], "resp": ["OK"]}, "other": []}"#,
+        )
+        .unwrap();
+        let chapter = Chapter::new("Chapter", String::new(), "chapter.md", vec![]);
+        let config = WavedromConfig::default();
+        let page = ChapterContext::new(&chapter, &config, Some(src.path()));
+        let merged = |info: &str, source: &str| {
+            let block = blocks::find(
+                &format!("```{}\n{}\n```\n", info, source),
+                blocks::LANGUAGES,
+            )
+            .remove(0);
+            let mut warnings = vec![];
+            let output = merge(Cow::Borrowed(source), &block, &page, &mut warnings).into_owned();
+            (output, warnings)
+        };
+
+        assert_eq!(
+            (
+                "{signal: [{name: 'data', wave: 'x===', data: ['0x10', '32', 'true']}]}\n"
+                    .to_string(),
+                vec![
+                    "No signal named 'resp' for its results in `results=sim/run.json#burst`"
+                        .to_string()
+                ]
+            ),
+            merged(
+                "wavedrom results=sim/run.json#burst",
+                "{signal: [{name: 'data', wave: 'x===', data: 'a b c'}]}"
+            )
+        );
+
+        let (_, warnings) = merged(
+            "wavedrom results=sim/run.json#burst",
+            "{signal: [{name: 'data', wave: 'x=.'}, {name: 'resp', wave: '='}]}",
+        );
+        assert_eq!(
+            vec!["'data' has 3 results, but its wave shows 1 data bricks".to_string()],
+            warnings
+        );
+
+        for info in ["results=sim/run.json#none", "results=sim/none.json"] {
+            let (output, warnings) = merged(&format!("wavedrom {}", info), "{signal: []}");
+            assert_eq!("{signal: []}", output);
+            assert_eq!(1, warnings.len(), "{:?}", warnings);
+        }
+    }
+}
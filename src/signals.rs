@@ -0,0 +1,180 @@
+//! Links from signal names in the prose to the diagram defining them.
+//!
+//! With `link-signals` enabled, the book's diagrams are indexed before any chapter is processed.
+//! Inline code naming a signal of the index, like `` `dma_irq` ``, then links to the first
+//! diagram of the book that has a lane of that name.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::Book;
+use pulldown_cmark::{Event, Parser, Tag};
+
+use crate::{book, html, rename, wavejson, ChapterContext, WavedromConfig};
+
+/// Where a signal is defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Definition {
+    /// The chapter's path relative to the book's `src` directory.
+    pub(crate) chapter: PathBuf,
+    pub(crate) chapter_name: String,
+    /// Number of the diagram in the chapter, starting at 1.
+    pub(crate) diagram: usize,
+}
+
+/// The first definition of every signal of the book.
+#[derive(Debug, Default)]
+pub(crate) struct Index(HashMap<String, Definition>);
+
+impl Index {
+    /// Index the diagrams of all chapters of `book`, in book order.
+    pub(crate) fn build(book: &Book, config: &WavedromConfig, src_dir: &Path) -> Self {
+        let mut index = Index::default();
+        book::for_each_diagram(book, config, src_dir, |diagram| {
+            if !diagram.block.is_rendered() {
+                return;
+            }
+            // Lints and the actual processing report what's wrong with the diagram.
+            let parsed = match wavejson::parse(&diagram.source) {
+                Ok(parsed) => parsed,
+                Err(_) => return,
+            };
+            for name in wavejson::lanes(&parsed)
+                .into_iter()
+                .filter_map(|lane| lane.get("name")?.as_str())
+            {
+                index
+                    .0
+                    .entry(rename::get(name, &config.rename).to_string())
+                    .or_insert_with(|| Definition {
+                        chapter: diagram.chapter.path.clone().unwrap_or_default(),
+                        chapter_name: diagram.chapter.name.clone(),
+                        diagram: diagram.index,
+                    });
+            }
+        });
+        index
+    }
+
+    pub(crate) fn get(&self, signal: &str) -> Option<&Definition> {
+        self.0.get(signal)
+    }
+}
+
+/// Turn the inline code spans of `content` naming an indexed signal into links to its diagram.
+///
+/// Code in headings, links and images is left alone.
+pub(crate) fn link<'a>(content: &'a str, index: &Index, page: &ChapterContext) -> Cow<'a, str> {
+    let mut links = vec![];
+    let mut skip = 0;
+    for (e, span) in Parser::new_ext(content, crate::parser_options()).into_offset_iter() {
+        match e {
+            Event::Start(Tag::Heading(..))
+            | Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..)) => skip += 1,
+            Event::End(Tag::Heading(..))
+            | Event::End(Tag::Link(..))
+            | Event::End(Tag::Image(..)) => skip -= 1,
+            Event::Code(code) if skip == 0 => {
+                if let Some(definition) = index.get(&code) {
+                    links.push((span, definition));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if links.is_empty() {
+        return Cow::Borrowed(content);
+    }
+
+    let mut output = String::with_capacity(content.len() + links.len() * 64);
+    let mut last = 0;
+    for (span, definition) in links {
+        output.push_str(&content[last..span.start]);
+        output.push_str(&format!(
+            "[{}]({}#{} \"{}, diagram {}\")",
+            &content[span.clone()],
+            target(definition, page),
+            html::anchor_id(definition.diagram),
+            definition.chapter_name.replace('"', "\\\""),
+            definition.diagram
+        ));
+        last = span.end;
+    }
+    output.push_str(&content[last..]);
+    Cow::Owned(output)
+}
+
+/// The link to the chapter of `definition`, relative to `page`. Empty for the page itself.
+fn target(definition: &Definition, page: &ChapterContext) -> String {
+    if page.path == Some(definition.chapter.as_path()) {
+        return String::new();
+    }
+    format!(
+        "{}{}",
+        page.path_to_root,
+        definition.chapter.to_string_lossy().replace('\\', "/")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use mdbook::book::{Book, Chapter};
+    use pretty_assertions::assert_eq;
+
+    use super::{link, Index};
+    use crate::{ChapterContext, WavedromConfig};
+
+    fn book() -> Book {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Intro",
+            "```wavedrom\n{signal: [{name: 'clk'}]}\n```\n\n```wavedrom\n{signal: [['bus', {name: 'req'}], {name: 'clk'}]}\n```\n".into(),
+            "intro.md",
+            vec![],
+        ));
+        book.push_item(Chapter::new(
+            "Deep",
+            "```wavedrom\n{signal: [{name: 'ack'}, {name: 'req'}]}\n```\n".into(),
+            "a/deep.md",
+            vec![],
+        ));
+        book
+    }
+
+    #[test]
+    fn indexes_first_definition() {
+        let index = Index::build(&book(), &WavedromConfig::default(), "/nowhere".as_ref());
+
+        let req = index.get("req").unwrap();
+        assert_eq!(
+            ("intro.md".as_ref(), 2),
+            (req.chapter.as_path(), req.diagram)
+        );
+        assert_eq!(1, index.get("clk").unwrap().diagram);
+        assert_eq!(
+            "a/deep.md",
+            index.get("ack").unwrap().chapter.to_str().unwrap()
+        );
+        assert!(index.get("dat").is_none());
+    }
+
+    #[test]
+    fn links_code_spans() {
+        let book = book();
+        let index = Index::build(&book, &WavedromConfig::default(), "/nowhere".as_ref());
+        let config = WavedromConfig::default();
+        let chapter = Chapter::new("Deep", String::new(), "a/deep.md", vec![]);
+        let page = ChapterContext::new(&chapter, &config, None);
+
+        let content =
+            "# `req`\n\n`req` is acked by `ack`, see [`clk`](x.md); `dat` and clk stay.\n";
+        assert_eq!(
+            "# `req`\n\n[`req`](../intro.md#wavedrom-2 \"Intro, diagram 2\") is acked by \
+             [`ack`](#wavedrom-1 \"Deep, diagram 1\"), see [`clk`](x.md); `dat` and clk stay.\n",
+            link(content, &index, &page)
+        );
+    }
+}
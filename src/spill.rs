@@ -0,0 +1,132 @@
+//! Diagrams too large to embed into their chapter, like the ones derived from VCD dumps.
+//!
+//! With `spill-size` set, the WaveJSON of a diagram larger than that many bytes is written to a
+//! JSON file of its own below `wavedrom-generated/spill/`, and the chapter gets a placeholder with
+//! a small script instead. Once the page has loaded, the script fetches the file and draws the
+//! diagram into the placeholder, so the size of the chapter stays bounded however large its
+//! diagrams get. This applies to `mode = "script"`, frames can't fetch and pre-rendered images
+//! don't carry the WaveJSON.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::{Error, Result};
+
+use crate::config::Mode;
+use crate::html::escape_html;
+use crate::{blocks, standalone, wavejson, ChapterContext};
+
+/// Diagrams drawn from a file get indexes from here on, so the ids WaveDrom derives from them
+/// don't clash with the ones of the diagrams it draws from the page.
+const FIRST_INDEX: usize = 10_000;
+
+/// A diagram's WaveJSON as a JSON file, not yet written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct File {
+    /// Path of the file relative to the book's `src` directory.
+    pub(crate) path: PathBuf,
+    pub(crate) json: String,
+}
+
+/// The placeholder of the WaveJSON `source`, if it's larger than `spill-size`, with its file
+/// added to the chapter's spilled files.
+pub(crate) fn markup(source: &str, page: &ChapterContext) -> Option<String> {
+    let size = page.config.spill_size?;
+    if page.config.mode != Mode::Script || source.len() <= size {
+        return None;
+    }
+    // The browser parses the file as JSON, it can't take the liberties of WaveJSON.
+    let json = serde_json::to_string(&wavejson::parse(source).ok()?).ok()?;
+    let mut spilled = page.spilled.borrow_mut();
+    let number = spilled.len() + 1;
+    let path = Path::new(standalone::DIR)
+        .join("spill")
+        .join(blocks::file_name(page.source_path?, number, "json"));
+    let href = format!(
+        "{}{}",
+        page.path_to_root,
+        path.to_string_lossy().replace('\\', "/")
+    );
+    spilled.push(File { path, json });
+
+    let index = FIRST_INDEX + number;
+    Some(format!(
+        "<div class=\"wavedrom-spill\" id=\"WaveDrom_Spill_{index}\" data-src=\"{href}\"></div>\n\
+         <script>window.addEventListener('load', function () {{ \
+         var e = document.getElementById('WaveDrom_Spill_{index}'); \
+         fetch(e.dataset.src).then(function (r) {{ return r.json(); }})\
+         .then(function (d) {{ WaveDrom.RenderWaveForm({index}, d, 'WaveDrom_Spill_'); }})\
+         .catch(function (x) {{ e.textContent = 'Unable to load the diagram: ' + x; }}); }});</script>\n\n",
+        index = index,
+        href = escape_html(&href),
+    ))
+}
+
+/// Write the spilled `files` below `src_dir`, leaving the ones that didn't change alone.
+pub(crate) fn write_all(src_dir: &Path, files: &[File]) -> Result<()> {
+    for file in files {
+        let path = src_dir.join(&file.path);
+        if fs::read_to_string(&path).ok().as_deref() == Some(file.json.as_str()) {
+            continue;
+        }
+
+        log::debug!("Writing spilled diagram {}", path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                Error::from(e).context(format!("Unable to create {}", parent.display()))
+            })?;
+        }
+        fs::write(&path, &file.json)
+            .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use mdbook::book::Chapter;
+    use pretty_assertions::assert_eq;
+
+    use super::markup;
+    use crate::config::Mode;
+    use crate::{ChapterContext, WavedromConfig};
+
+    #[test]
+    fn spills_large_diagrams() {
+        let chapter = Chapter::new("Test", String::new(), "a/intro.md", vec![]);
+        let config = WavedromConfig {
+            spill_size: Some(20),
+            ..Default::default()
+        };
+        let page = ChapterContext::new(&chapter, &config, None);
+
+        assert_eq!(None, markup("{signal: []}", &page));
+        let html = markup("{signal: [{name: 'clk', wave: 'p...'}]}", &page).unwrap();
+        assert!(html.starts_with(
+            "<div class=\"wavedrom-spill\" id=\"WaveDrom_Spill_10001\" \
+             data-src=\"../wavedrom-generated/spill/a/intro-1.json\"></div>\n<script>"
+        ));
+        assert!(html.contains("WaveDrom.RenderWaveForm(10001, d, 'WaveDrom_Spill_')"));
+        let spilled = page.spilled.borrow();
+        assert_eq!(
+            Path::new("wavedrom-generated/spill/a/intro-1.json"),
+            spilled[0].path
+        );
+        assert_eq!(
+            r#"{"signal":[{"name":"clk","wave":"p..."}]}"#,
+            spilled[0].json
+        );
+
+        let config = WavedromConfig {
+            mode: Mode::Iframe,
+            ..config
+        };
+        let page = ChapterContext::new(&chapter, &config, None);
+        assert_eq!(
+            None,
+            markup("{signal: [{name: 'clk', wave: 'p...'}]}", &page)
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Long waveforms split into diagrams of a number of cycles each, stacked below each other.
+//!
+//! ~~~text
+//! ```wavedrom split=32
+//! ~~~
+//!
+//! Every lane keeps its name, and continues where the previous part left off: a brick running
+//! into the next part starts it again, with its data. Ticks in the `head` and `foot` count on
+//! across the parts, the `head` text goes with the first part and the `foot` text with the last.
+//!
+//! A waveform can also be cropped to a window of cycles, like a part of its own:
+//!
+//! ~~~text
+//! ```wavedrom range=24..56
+//! ~~~
+
+use std::collections::BTreeSet;
+
+use serde_json::{Map, Value};
+
+use crate::wavejson;
+
+/// Wave characters that carry an entry of the lane's `data`.
+pub(crate) const DATA_BRICKS: &[char] = &['=', '2', '3', '4', '5', '6', '7', '8', '9'];
+
+/// Wave characters that continue the brick before them.
+const CONTINUATIONS: &[char] = &['.', '|'];
+
+/// The diagram of WaveJSON `source` split into parts of `split` cycles, as attribute `split`
+/// gave it.
+///
+/// Returns `None` if there's nothing to split, and reports an invalid `split` in `warnings`.
+pub(crate) fn split(source: &str, split: &str, warnings: &mut Vec<String>) -> Option<Vec<String>> {
+    let cycles = match split.parse::<usize>() {
+        Ok(cycles) if cycles > 0 => cycles,
+        _ => {
+            warnings.push(format!(
+                "Invalid `split={}`, expected a number of cycles greater than 0",
+                split
+            ));
+            return None;
+        }
+    };
+    let diagram = wavejson::parse(source).ok()?;
+    let parts = parts(&diagram, cycles);
+    if parts.len() < 2 {
+        return None;
+    }
+    Some(parts.iter().map(wavejson::to_string).collect())
+}
+
+/// The parts of `diagram`, of `cycles` cycles each.
+fn parts(diagram: &Value, cycles: usize) -> Vec<Value> {
+    let count = length(diagram).div_ceil(cycles);
+
+    (0..count)
+        .map(|i| {
+            let mut part = diagram.clone();
+            slice(&mut part, i * cycles, cycles, i + 1 == count);
+            part
+        })
+        .collect()
+}
+
+/// How many cycles the longest lane of `diagram` lasts.
+pub(crate) fn length(diagram: &Value) -> usize {
+    wavejson::lanes(diagram)
+        .iter()
+        .map(|lane| lane_cycles(lane))
+        .max()
+        .unwrap_or_default()
+}
+
+/// The cycles of attribute `range`, like `24..56`, or `24..` ending with the diagram.
+pub(crate) fn range(range: &str) -> Result<(usize, Option<usize>), String> {
+    let invalid = || {
+        format!(
+            "Invalid `range={}`, expected cycles like `24..56`, or `24..` to the end",
+            range
+        )
+    };
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let start = start.trim().parse::<usize>().map_err(|_| invalid())?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse::<usize>().map_err(|_| invalid())?),
+    };
+    if end.is_some_and(|end| end <= start) {
+        return Err(invalid());
+    }
+    Ok((start, end))
+}
+
+/// Crop `diagram` to the cycles from `start` up to `end`, or on to its end.
+pub(crate) fn crop(diagram: &mut Value, start: usize, end: Option<usize>) {
+    slice(
+        diagram,
+        start,
+        end.map_or(usize::MAX, |end| end - start),
+        true,
+    );
+}
+
+/// Cut every lane of `diagram` down to the `cycles` cycles from `start` on, keeping the edges
+/// between the nodes left, and the `foot` text if `last`.
+fn slice(diagram: &mut Value, start: usize, cycles: usize, last: bool) {
+    wavejson::for_each_lane(diagram, |lane| slice_lane(lane, start, cycles));
+    prune_edges(diagram);
+    count_on(diagram, "head", start, start == 0);
+    count_on(diagram, "foot", start, last);
+}
+
+/// The lane's `period`, defaulting to a cycle per wave character.
+fn period(lane: &Map<String, Value>) -> f64 {
+    lane.get("period")
+        .and_then(Value::as_f64)
+        .filter(|&period| period > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// How many cycles the lane's wave lasts.
+fn lane_cycles(lane: &Map<String, Value>) -> usize {
+    let bricks = lane
+        .get("wave")
+        .and_then(Value::as_str)
+        .map_or(0, |wave| wave.chars().count());
+    (bricks as f64 * period(lane)).ceil() as usize
+}
+
+/// Cut `lane` down to the `cycles` cycles from `start` on.
+fn slice_lane(lane: &mut Map<String, Value>, start: usize, cycles: usize) {
+    let wave: Vec<char> = match lane.get("wave").and_then(Value::as_str) {
+        Some(wave) => wave.chars().collect(),
+        None => return,
+    };
+    let period = period(lane);
+    let from = ((start as f64 / period).round() as usize).min(wave.len());
+    let to =
+        ((start.saturating_add(cycles) as f64 / period).round() as usize).clamp(from, wave.len());
+
+    let mut part: Vec<char> = wave[from..to].to_vec();
+    // The brick running into this part, and the first of the lane's data it shows.
+    let running = wave[..from]
+        .iter()
+        .rposition(|c| !CONTINUATIONS.contains(c));
+    let data_before = wave[..from]
+        .iter()
+        .filter(|c| DATA_BRICKS.contains(c))
+        .count();
+    let mut data_from = data_before;
+    if let (Some(first), Some(running)) = (part.first_mut(), running) {
+        if CONTINUATIONS.contains(first) {
+            *first = wave[running];
+            if DATA_BRICKS.contains(&wave[running]) {
+                data_from -= 1;
+            }
+        }
+    }
+
+    if let Some(data) = lane.get("data").map(data_entries) {
+        let shown = part.iter().filter(|c| DATA_BRICKS.contains(c)).count();
+        let data: Vec<Value> = data.into_iter().skip(data_from).take(shown).collect();
+        lane.insert("data".into(), Value::Array(data));
+    }
+    if let Some(Value::String(node)) = lane.get("node") {
+        let node: String = node.chars().skip(from).take(to - from).collect();
+        lane.insert("node".into(), Value::String(node));
+    }
+    if start > 0 {
+        lane.remove("phase");
+    }
+    lane.insert("wave".into(), Value::String(part.into_iter().collect()));
+}
+
+/// The entries of a lane's `data`, given as a list or as words of a string.
+fn data_entries(data: &Value) -> Vec<Value> {
+    match data {
+        Value::Array(data) => data.clone(),
+        Value::String(data) => data
+            .split_whitespace()
+            .map(|entry| Value::String(entry.into()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Drop the edges of `diagram` to nodes none of its lanes have.
+pub(crate) fn prune_edges(diagram: &mut Value) {
+    let nodes: BTreeSet<char> = wavejson::lanes(diagram)
+        .iter()
+        .filter_map(|lane| lane.get("node").and_then(Value::as_str))
+        .flat_map(|node| node.chars().filter(|&c| c != '.'))
+        .collect();
+    if let Some(Value::Array(edges)) = diagram.get_mut("edge") {
+        edges.retain(|edge| edge.as_str().is_some_and(|edge| connects(edge, &nodes)));
+    }
+}
+
+/// Whether the `edge`, like `a~>b label`, connects two of `nodes`.
+fn connects(edge: &str, nodes: &BTreeSet<char>) -> bool {
+    let arrow = edge.split_whitespace().next().unwrap_or_default();
+    match (arrow.chars().next(), arrow.chars().last()) {
+        (Some(from), Some(to)) => nodes.contains(&from) && nodes.contains(&to),
+        _ => false,
+    }
+}
+
+/// Count the ticks of `key`, `head` or `foot`, on from `start`, and keep its text only if
+/// `keep_text`.
+fn count_on(part: &mut Value, key: &str, start: usize, keep_text: bool) {
+    let table = match part.get_mut(key).and_then(Value::as_object_mut) {
+        Some(table) => table,
+        None => return,
+    };
+    for ticks in ["tick", "tock"] {
+        if let Some(tick) = table.get(ticks).and_then(Value::as_i64) {
+            table.insert(ticks.into(), Value::from(tick + start as i64));
+        }
+    }
+    if !keep_text {
+        table.remove("text");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{crop, range, split};
+    use crate::wavejson;
+
+    fn parts(source: &str, cycles: &str) -> Option<Vec<String>> {
+        let mut warnings = vec![];
+        let parts = split(source, cycles, &mut warnings);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        parts
+    }
+
+    #[test]
+    fn carries_lanes_over() {
+        let source = "{signal: [\
+            {name: 'clk', wave: 'p.......'},\
+            ['bus', {name: 'dat', wave: 'x=.=..=.', data: 'a b c'}],\
+            {name: 'req', wave: '01..0...', node: '.a...b..'},\
+            {}],\
+            edge: ['a~>b late'],\
+            head: {text: 'Transfer', tick: 0},\
+            foot: {text: 'End', tock: 1}}";
+
+        assert_eq!(
+            Some(vec![
+                "{signal: [{name: 'clk', wave: 'p...'}, ['bus', {name: 'dat', wave: 'x=.=', data: ['a', 'b']}], \
+                 {name: 'req', wave: '01..', node: '.a..'}, {}], edge: [], head: {text: 'Transfer', tick: 0}, foot: {tock: 1}}"
+                    .to_string(),
+                "{signal: [{name: 'clk', wave: 'p...'}, ['bus', {name: 'dat', wave: '=.=.', data: ['b', 'c']}], \
+                 {name: 'req', wave: '0...', node: '.b..'}, {}], edge: [], head: {tick: 4}, foot: {text: 'End', tock: 5}}"
+                    .to_string(),
+            ]),
+            parts(source, "4")
+        );
+    }
+
+    #[test]
+    fn keeps_edges_within_a_part() {
+        let source =
+            "{signal: [{name: 'a', wave: '010101', node: '.x.y.z'}], edge: ['x->y', 'y->z']}";
+        let parts = parts(source, "4").unwrap();
+        assert!(parts[0].contains("edge: ['x->y']"), "{}", parts[0]);
+        assert!(parts[1].contains("edge: []"), "{}", parts[1]);
+    }
+
+    #[test]
+    fn scales_with_period() {
+        let source =
+            "{signal: [{name: 'clk', wave: 'p...', period: 2}, {name: 'a', wave: '01010101'}]}";
+        let parts = parts(source, "4").unwrap();
+        assert_eq!(2, parts.len());
+        assert!(parts[1].starts_with("{signal: [{name: 'clk', wave: 'p.', period: 2}"));
+    }
+
+    #[test]
+    fn crops_to_a_range() {
+        let crop = |source: &str, spec: &str| {
+            let mut diagram = wavejson::parse(source).unwrap();
+            let (start, end) = range(spec)?;
+            crop(&mut diagram, start, end);
+            Ok::<_, String>(wavejson::to_string(&diagram))
+        };
+        let source =
+            "{signal: [{name: 'dat', wave: 'x=.=..=.', data: 'a b c', node: '..a..b.c'}], \
+                      edge: ['a->b', 'b->c'], head: {text: 'Burst', tick: 0}, foot: {text: 'End'}}";
+
+        assert_eq!(
+            Ok(
+                "{signal: [{name: 'dat', wave: '=.=.', data: ['b', 'c'], node: '.b.c'}], \
+                edge: ['b->c'], head: {tick: 4}, foot: {text: 'End'}}"
+                    .to_string()
+            ),
+            crop(source, "4..")
+        );
+        assert_eq!(
+            Ok(
+                "{signal: [{name: 'dat', wave: '==.', data: ['a', 'b'], node: 'a..'}], \
+                edge: [], head: {tick: 2}, foot: {text: 'End'}}"
+                    .to_string()
+            ),
+            crop(source, "2..5")
+        );
+        assert!(crop(source, "5..2").is_err());
+        assert!(crop(source, "5").is_err());
+    }
+
+    #[test]
+    fn leaves_short_diagrams() {
+        assert_eq!(None, parts("{signal: [{name: 'a', wave: '0101'}]}", "4"));
+        assert_eq!(None, parts("{signal: [", "4"));
+
+        let mut warnings = vec![];
+        assert_eq!(None, split("{signal: []}", "0", &mut warnings));
+        assert_eq!(1, warnings.len());
+    }
+}
@@ -0,0 +1,150 @@
+//! A page of its own for every diagram, to share a single figure.
+//!
+//! The pages are written into the book's `src` directory, from where mdbook copies them into the
+//! output like any other static file. Writing to the output directly doesn't work, the html
+//! renderer clears it after the preprocessors ran.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::errors::{Error, Result};
+use mdbook::utils::fs::path_to_root;
+
+use crate::blocks;
+use crate::html::{self, escape_html};
+
+/// Directory below `src` the pages are written to.
+pub(crate) const DIR: &str = "wavedrom-generated";
+
+/// A generated page, not yet written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Page {
+    /// Path of the page relative to the book's `src` directory.
+    pub(crate) path: PathBuf,
+    pub(crate) html: String,
+}
+
+impl Page {
+    /// The page for diagram number `index` (starting at 1) of the chapter at `chapter_path`,
+    /// loading the runtime from `assets_dir` below the book's root.
+    pub(crate) fn new(
+        chapter_path: &Path,
+        index: usize,
+        title: &str,
+        source: &str,
+        assets_dir: &str,
+    ) -> Self {
+        let path = Path::new(DIR).join(blocks::file_name(chapter_path, index, "html"));
+        let root = path_to_root(&path);
+        let chapter_html = chapter_path.with_extension("html");
+
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<title>{title}</title>
+<script src="{assets}wavedrom.min.js"></script>
+<script src="{assets}wavedrome-default.js"></script>
+<script src="{assets}wavedrom-runtime.js"></script>
+<script src="{assets}wavedrom-events.js"></script>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+pre {{ background: #f6f7f6; padding: 1em; overflow: auto; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<script type="WaveDrom">{script}</script>
+<p><a href="{root}{chapter}">Back to the book</a></p>
+<details>
+<summary>Source</summary>
+<pre><code>{source}</code></pre>
+</details>
+</body>
+</html>
+"#,
+            title = escape_html(title),
+            root = root,
+            assets = format!("{}{}", root, assets_dir),
+            script = html::script_text(source),
+            source = escape_html(source),
+            chapter = chapter_html.to_string_lossy().replace('\\', "/"),
+        );
+
+        Page { path, html }
+    }
+
+    /// The link to this page from a chapter `path_to_root` away from the book's root.
+    pub(crate) fn href(&self, path_to_root: &str) -> String {
+        format!(
+            "{}{}",
+            path_to_root,
+            self.path.to_string_lossy().replace('\\', "/")
+        )
+    }
+}
+
+/// Write `pages` below `src_dir`.
+///
+/// Pages that didn't change are left alone, so `mdbook serve` doesn't see a change to the
+/// sources and rebuild the book in a loop.
+pub(crate) fn write_all(src_dir: &Path, pages: &[Page]) -> Result<()> {
+    for page in pages {
+        let path = src_dir.join(&page.path);
+        if fs::read_to_string(&path).ok().as_deref() == Some(page.html.as_str()) {
+            continue;
+        }
+
+        log::debug!("Writing standalone page {}", path.display());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                Error::from(e).context(format!("Unable to create {}", parent.display()))
+            })?;
+        }
+        fs::write(&path, &page.html)
+            .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{write_all, Page};
+
+    #[test]
+    fn page_next_to_chapter() {
+        let page = Page::new(
+            Path::new("a/b/deep.md"),
+            2,
+            "Deep, diagram 2",
+            "{signal: []}",
+            "theme/",
+        );
+        assert_eq!(Path::new("wavedrom-generated/a/b/deep-2.html"), page.path);
+        assert_eq!("../wavedrom-generated/a/b/deep-2.html", page.href("../"));
+        assert!(page
+            .html
+            .contains("<script src=\"../../../theme/wavedrom.min.js\"></script>"));
+        assert!(page.html.contains("<a href=\"../../../a/b/deep.html\">"));
+        assert!(page.html.contains("<title>Deep, diagram 2</title>"));
+    }
+
+    #[test]
+    fn writes_only_changed_pages() {
+        let src = tempfile::tempdir().unwrap();
+        let page = Page::new(Path::new("intro.md"), 1, "Intro", "{signal: []}", "");
+        write_all(src.path(), std::slice::from_ref(&page)).unwrap();
+
+        let path = src.path().join("wavedrom-generated/intro-1.html");
+        assert_eq!(page.html, fs::read_to_string(&path).unwrap());
+
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write_all(src.path(), &[page]).unwrap();
+        assert_eq!(modified, fs::metadata(&path).unwrap().modified().unwrap());
+    }
+}
@@ -0,0 +1,83 @@
+//! Diagrams showing only some of the lanes of a larger one, e.g. `signals="clk,req,ack"`.
+//!
+//! The listed lanes keep their order in the diagram, and the groups they're in. Every other lane,
+//! the spacers, the groups left empty, and the edges to nodes of lanes left out are dropped.
+
+use serde_json::Value;
+
+use crate::split;
+
+/// Apply the `signals` attribute `spec` to the diagram.
+///
+/// Returns a warning for every listed signal the diagram doesn't have.
+pub(crate) fn apply(diagram: &mut Value, spec: &str) -> Vec<String> {
+    let mut wanted: Vec<(&str, bool)> = names(spec).map(|name| (name, false)).collect();
+
+    if let Some(Value::Array(signal)) = diagram.get_mut("signal") {
+        retain_lanes(signal, &mut wanted);
+    }
+    split::prune_edges(diagram);
+
+    wanted
+        .into_iter()
+        .filter(|(_, found)| !found)
+        .map(|(name, _)| format!("No signal named '{}' to show", name))
+        .collect()
+}
+
+/// The names of the signals the `signals` attribute `spec` lists.
+pub(crate) fn names(spec: &str) -> impl Iterator<Item = &str> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// Keep the `wanted` lanes of `lanes`, and the groups holding any of them.
+fn retain_lanes(lanes: &mut Vec<Value>, wanted: &mut [(&str, bool)]) {
+    lanes.retain_mut(|lane| match lane {
+        Value::Object(lane) => {
+            let name = lane.get("name").and_then(Value::as_str);
+            match wanted.iter_mut().find(|(wanted, _)| Some(*wanted) == name) {
+                Some((_, found)) => {
+                    *found = true;
+                    true
+                }
+                None => false,
+            }
+        }
+        // A group: `['label', lane, lane, ...]`.
+        Value::Array(group) => {
+            retain_lanes(group, wanted);
+            group.iter().any(|item| !item.is_string())
+        }
+        // The label of a group.
+        Value::String(_) => true,
+        _ => false,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::wavejson;
+
+    #[test]
+    fn keeps_listed_lanes() {
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'clk', wave: 'p...'}, {}, \
+             ['bus', {name: 'req', wave: '01.0', node: '.a..'}, {name: 'ack', wave: '0.10', node: '..b.'}], \
+             ['debug', {name: 'state', wave: '=.=.'}]], \
+             edge: ['a->b', 'a~>b done']}",
+        )
+        .unwrap();
+
+        let warnings = apply(&mut diagram, "clk, req,wait");
+        assert_eq!(vec!["No signal named 'wait' to show".to_string()], warnings);
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: 'p...'}, ['bus', {name: 'req', wave: '01.0', node: '.a..'}]], edge: []}",
+            wavejson::to_string(&diagram)
+        );
+    }
+}
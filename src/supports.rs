@@ -0,0 +1,145 @@
+//! What the preprocessor does for each renderer, for build scripts to find out with
+//! `supports --json`.
+
+use serde::Serialize;
+
+use crate::config::Mode;
+use crate::{gets_images, Format, WavedromConfig, LINK_RENDERERS};
+
+/// What happens to the chapters for a renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Behavior {
+    /// The renderer isn't in `renderers`, mdbook doesn't run the preprocessor for it.
+    Skip,
+    /// The diagrams are left as they are, with their captions and the references to them turned
+    /// into markdown links for `mdbook-linkcheck` to check.
+    Links,
+    /// The diagrams are embedded into the chapters.
+    Embed,
+    /// The diagrams are rendered to image files the chapters link to, for renderers other than
+    /// html.
+    Images,
+}
+
+/// Where the pages get the WaveDrom runtime from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Runtime {
+    /// The files `install` added to `additional-js`.
+    AdditionalJs,
+    /// Inlined into every chapter, with `inline-runtime`.
+    Inline,
+    /// Loaded from the `cdn-url` by every chapter, with `source = "cdn"`.
+    Cdn,
+    /// None, the diagrams are rendered at build time.
+    None,
+}
+
+/// How the diagrams are embedded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Embedding {
+    pub mode: Mode,
+    pub runtime: Runtime,
+}
+
+impl Embedding {
+    fn new(config: &WavedromConfig) -> Self {
+        let runtime = if config.mode.is_prerendered() {
+            Runtime::None
+        } else if config.inline_runtime {
+            Runtime::Inline
+        } else if config.runtime_from_cdn() {
+            Runtime::Cdn
+        } else {
+            Runtime::AdditionalJs
+        };
+        Embedding {
+            mode: config.mode,
+            runtime,
+        }
+    }
+}
+
+/// What the preprocessor does for a renderer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Support {
+    pub renderer: String,
+    /// Whether mdbook runs the preprocessor for the renderer.
+    pub supported: bool,
+    pub behavior: Behavior,
+    /// How the diagrams are embedded in a build, for the renderers that get them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Embedding>,
+    /// How they're embedded in a live preview like `mdbook serve`, which skips the slow steps
+    /// unless `fast-preview` is turned off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<Embedding>,
+    /// The format of the image files, for the renderers getting them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_format: Option<Format>,
+}
+
+/// What the preprocessor configured by `config` does for `renderer`.
+pub fn supports(config: &WavedromConfig, renderer: &str) -> Support {
+    let supported = config.supports_renderer(renderer);
+    let behavior = if !supported {
+        Behavior::Skip
+    } else if LINK_RENDERERS.contains(&renderer) {
+        Behavior::Links
+    } else if gets_images(renderer) {
+        Behavior::Images
+    } else {
+        Behavior::Embed
+    };
+    let (embedding, preview) = if behavior == Behavior::Embed {
+        (
+            Some(Embedding::new(config)),
+            Some(Embedding::new(&config.clone().for_preview())),
+        )
+    } else {
+        (None, None)
+    };
+    Support {
+        renderer: renderer.to_string(),
+        supported,
+        behavior,
+        embedding,
+        preview,
+        image_format: (behavior == Behavior::Images).then(|| config.image_format()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::supports;
+    use crate::{Mode, WavedromConfig};
+
+    #[test]
+    fn lists_what_happens_per_renderer() {
+        let config = WavedromConfig {
+            mode: Mode::DataUri,
+            ..Default::default()
+        };
+        let json = |renderer| serde_json::to_string(&supports(&config, renderer)).unwrap();
+        assert_eq!(
+            r#"{"renderer":"html","supported":true,"behavior":"embed","embedding":{"mode":"data-uri","runtime":"none"},"preview":{"mode":"script","runtime":"inline"}}"#,
+            json("html")
+        );
+        assert_eq!(
+            r#"{"renderer":"linkcheck","supported":true,"behavior":"links"}"#,
+            json("linkcheck")
+        );
+        assert_eq!(
+            r#"{"renderer":"epub","supported":true,"behavior":"images","image-format":"png"}"#,
+            json("epub")
+        );
+        assert_eq!(
+            r#"{"renderer":"markdown","supported":false,"behavior":"skip"}"#,
+            json("markdown")
+        );
+    }
+}
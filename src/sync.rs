@@ -0,0 +1,182 @@
+//! Keeping the copies in `keep` blocks in line with their `src` files, see [`external`].
+//!
+//! [`external`]: crate::external
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::BookItem;
+use mdbook::errors::Result;
+
+use crate::{blocks, book, external, ChapterContext};
+
+/// Which side wins when a copy differs from its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Only report the differences.
+    Check,
+    /// Update the copies in the chapters from the files.
+    Pull,
+    /// Update the files from the copies in the chapters.
+    Push,
+}
+
+/// A copy that differs from its file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drift {
+    /// Where the block starts, e.g. `chapter/intro.md:12`.
+    pub location: String,
+    pub file: PathBuf,
+}
+
+/// What [`sync`] found and did.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The copies that differed from their file, whether they were synced or not.
+    pub drifted: Vec<Drift>,
+    /// The chapters and waveform files written.
+    pub written: Vec<PathBuf>,
+    /// Number of blocks that couldn't be synced, e.g. because their file is missing.
+    pub failed: usize,
+}
+
+/// Compare the `keep` blocks of the book at `root` with their `src` files, and sync them in
+/// `direction`.
+pub fn sync(root: &Path, direction: Direction) -> Result<Report> {
+    let book = book::load(root)?;
+    let mut report = Report::default();
+    // What was pushed to each file, to catch copies of the same file that disagree.
+    let mut pushed: HashMap<PathBuf, String> = HashMap::new();
+
+    for item in book.book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) if chapter.path.is_some() => chapter,
+            _ => continue,
+        };
+        let page = ChapterContext::new(chapter, &book.config, Some(&book.src_dir));
+        let chapter_file = match page.source_path {
+            Some(path) if !book.config.excludes(Some(path)) => book.src_dir.join(path),
+            _ => continue,
+        };
+        let mut edits = vec![];
+
+        for block in blocks::find(&chapter.content, &book.config.languages()) {
+            if !external::is_kept(&block) {
+                continue;
+            }
+            let path = match external::path(&block, &page) {
+                Some(path) => path,
+                None => continue,
+            };
+            let location = page.location(&chapter.content, block.span.start);
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(_) if direction == Direction::Push => String::new(),
+                Err(e) => {
+                    log::error!("{}: Unable to read {}: {}", location, path.display(), e);
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            if external::same(&block.source, &content) {
+                continue;
+            }
+            report.drifted.push(Drift {
+                location: location.clone(),
+                file: path.clone(),
+            });
+
+            match direction {
+                Direction::Check => {}
+                Direction::Pull => edits.push((block, content)),
+                Direction::Push => {
+                    let source = with_newline(&block.source);
+                    if let Some(other) = pushed.get(&path) {
+                        if *other != source {
+                            log::error!(
+                                "{}: Another copy of {} differs from this one, not pushing it",
+                                location,
+                                path.display()
+                            );
+                            report.failed += 1;
+                        }
+                        continue;
+                    }
+                    write(&path, &source)?;
+                    report.written.push(path.clone());
+                    pushed.insert(path, source);
+                }
+            }
+        }
+
+        if !edits.is_empty() {
+            write(&chapter_file, &pull(&chapter.content, &edits))?;
+            report.written.push(chapter_file);
+        }
+    }
+    Ok(report)
+}
+
+/// `content` with the sources of the blocks replaced by the given files.
+fn pull(content: &str, edits: &[(blocks::Block, String)]) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut last = 0;
+    for (block, file) in edits {
+        let range = &block.content;
+        output.push_str(&content[last..range.start]);
+
+        let line_start = content[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        let prefix = crate::continuation_prefix(&content[line_start..range.start]);
+        for (i, line) in file.lines().enumerate() {
+            if i > 0 {
+                output.push_str(&prefix);
+            }
+            output.push_str(line);
+            output.push('\n');
+        }
+        // An empty block's source goes right before the closing fence, after its markers.
+        if block.source.is_empty() {
+            output.push_str(&prefix);
+        }
+        last = range.end;
+    }
+    output.push_str(&content[last..]);
+    output
+}
+
+fn with_newline(source: &str) -> String {
+    let mut source = source.to_string();
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    source
+}
+
+fn write(path: &Path, content: &str) -> Result<()> {
+    log::info!("Writing {}", path.display());
+    book::write(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::pull;
+    use crate::blocks;
+
+    #[test]
+    fn pulls_into_nested_blocks() {
+        let content = "- ```wavedrom src=a.json keep\n  {signal: []}\n  ```\n\n> ```wavedrom src=a.json keep\n> ```\n";
+        let edits: Vec<_> = blocks::find(content, blocks::LANGUAGES)
+            .into_iter()
+            .map(|block| (block, "{signal: [\n  {wave: '01'}\n]}\n".to_string()))
+            .collect();
+
+        assert_eq!(
+            "- ```wavedrom src=a.json keep\n  {signal: [\n    {wave: '01'}\n  ]}\n  ```\n\n\
+             > ```wavedrom src=a.json keep\n> {signal: [\n>   {wave: '01'}\n> ]}\n> ```\n",
+            pull(content, &edits)
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Ready-made diagrams of common protocols, for a block whose source is a template directive:
+//!
+//! ~~~text
+//! ```wavedrom
+//! {{#wavedrom-template spi mode=0 bytes=2}}
+//! ```
+//! ~~~
+//!
+//! The directive is replaced by the WaveJSON of the template, drawn with the parameters given
+//! after its name, like the attributes of a block. See [`TEMPLATES`] for what there is.
+
+use std::borrow::Cow;
+
+use serde_json::{json, Value};
+
+use crate::attrs::{self, Attributes};
+use crate::wavejson;
+
+const DIRECTIVE: &str = "{{#wavedrom-template";
+
+/// A template: its name, its parameters with their defaults, and how it's drawn.
+struct Template {
+    name: &'static str,
+    params: &'static [(&'static str, &'static str)],
+    draw: fn(&Params) -> Result<Value, String>,
+}
+
+/// The templates there are.
+const TEMPLATES: &[Template] = &[
+    Template {
+        name: "spi",
+        params: &[("mode", "0"), ("bytes", "1"), ("bits", "8")],
+        draw: spi,
+    },
+    Template {
+        name: "i2c",
+        params: &[("bytes", "1"), ("read", "false")],
+        draw: i2c,
+    },
+    Template {
+        name: "uart",
+        params: &[
+            ("bits", "8"),
+            ("parity", "none"),
+            ("stop", "1"),
+            ("frames", "1"),
+        ],
+        draw: uart,
+    },
+    Template {
+        name: "axi",
+        params: &[("wait", "1"), ("channel", "")],
+        draw: axi,
+    },
+];
+
+/// The WaveJSON `source` with its template directive replaced by the template's diagram.
+///
+/// A directive has to be the whole source. Directives that can't be drawn are left in place
+/// and reported in `warnings`.
+pub(crate) fn resolve<'a>(source: Cow<'a, str>, warnings: &mut Vec<String>) -> Cow<'a, str> {
+    if !source.contains(DIRECTIVE) {
+        return source;
+    }
+    let directive = source.trim();
+    let args = match directive
+        .strip_prefix(DIRECTIVE)
+        .and_then(|rest| rest.strip_suffix("}}"))
+        .filter(|args| !args.contains('\n'))
+    {
+        Some(args) => args,
+        None => {
+            warnings.push(format!(
+                "`{}}}}}` has to be the whole source of the block",
+                DIRECTIVE
+            ));
+            return source;
+        }
+    };
+    match draw(args) {
+        Ok(diagram) => Cow::Owned(wavejson::to_string(&diagram) + "\n"),
+        Err(e) => {
+            warnings.push(e);
+            source
+        }
+    }
+}
+
+/// The diagram of the directive's `args`, like `spi mode=0 bytes=2`.
+fn draw(args: &str) -> Result<Value, String> {
+    let (name, attrs) = attrs::parse(args);
+    let template = TEMPLATES
+        .iter()
+        .find(|template| template.name == name)
+        .ok_or_else(|| {
+            let names: Vec<_> = TEMPLATES.iter().map(|template| template.name).collect();
+            format!(
+                "Unknown template '{}', expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })?;
+    if let Some(key) = attrs
+        .keys()
+        .find(|key| !template.params.iter().any(|(param, _)| param == key))
+    {
+        let params: Vec<_> = template.params.iter().map(|(param, _)| *param).collect();
+        return Err(format!(
+            "The {} template has no parameter '{}', expected one of {}",
+            name,
+            key,
+            params.join(", ")
+        ));
+    }
+    (template.draw)(&Params { template, attrs })
+        .map_err(|e| format!("Unable to draw the {} template: {}", name, e))
+}
+
+/// The parameters given to a template, falling back to its defaults.
+struct Params<'a> {
+    template: &'a Template,
+    attrs: Attributes,
+}
+
+impl Params<'_> {
+    fn text(&self, key: &str) -> &str {
+        self.attrs.get(key).unwrap_or_else(|| {
+            self.template
+                .params
+                .iter()
+                .find(|(param, _)| *param == key)
+                .map_or("", |(_, default)| default)
+        })
+    }
+
+    fn number(&self, key: &str, min: usize, max: usize) -> Result<usize, String> {
+        let text = self.text(key);
+        text.parse::<usize>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| format!("`{}={}` has to be from {} to {}", key, text, min, max))
+    }
+
+    fn flag(&self, key: &str) -> Result<bool, String> {
+        match self.text(key) {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            text => Err(format!("`{}={}` has to be true or false", key, text)),
+        }
+    }
+}
+
+/// An SPI transfer in one of the four modes, most significant bit first.
+fn spi(params: &Params) -> Result<Value, String> {
+    let mode = params.number("mode", 0, 3)?;
+    let bytes = params.number("bytes", 1, 16)?;
+    let bits = params.number("bits", 1, 32)?;
+    let (cpol, cpha) = (mode & 2 != 0, mode & 1 != 0);
+    let cycles = bytes * bits;
+
+    let (idle, clock) = if cpol { ('h', 'n') } else { ('l', 'p') };
+    let sclk = format!("{}{}{}{}", idle, clock, ".".repeat(cycles - 1), idle);
+    let cs = format!("10{}1", ".".repeat(cycles - 1));
+    let data = format!("x{}x", "=".repeat(cycles));
+    let labels: Vec<String> = (0..bytes)
+        .flat_map(|_| (0..bits).rev().map(|bit| bit.to_string()))
+        .collect();
+    let mut sclk = json!({"name": "sclk", "wave": sclk});
+    if !cpha {
+        // Without CPHA the data is there before the first edge, the clock comes half a cycle
+        // after it.
+        sclk["phase"] = json!(-0.5);
+    }
+    Ok(json!({
+        "signal": [
+            {"name": "cs", "wave": cs},
+            sclk,
+            {"name": "mosi", "wave": data, "data": labels},
+            {"name": "miso", "wave": data, "data": labels},
+        ],
+        "head": {"text": format!("SPI mode {}", mode)},
+    }))
+}
+
+/// An I²C write or read of a 7 bit address, from the start to the stop condition.
+fn i2c(params: &Params) -> Result<Value, String> {
+    let bytes = params.number("bytes", 0, 16)?;
+    let read = params.flag("read")?;
+    let cycles = 9 * (bytes + 1);
+
+    let scl = format!("11p{}11", ".".repeat(cycles - 1));
+    // Start, the address and R/W bit, an ACK, every byte with its ACK, and the stop.
+    let sda = format!("10{}4{}01", "3".repeat(8), "555555554".repeat(bytes));
+    let mut labels: Vec<String> = (0..7).rev().map(|bit| format!("A{}", bit)).collect();
+    labels.push(if read { "R" } else { "W" }.into());
+    labels.push("ACK".into());
+    for byte in 0..bytes {
+        labels.extend((0..8).rev().map(|bit| format!("D{}", bit)));
+        // The controller ends a read by not acknowledging the last byte.
+        let last = byte + 1 == bytes;
+        labels.push(if read && last { "NACK" } else { "ACK" }.into());
+    }
+    Ok(json!({
+        "signal": [
+            {"name": "scl", "wave": scl},
+            {"name": "sda", "wave": sda, "data": labels},
+        ],
+        "head": {"text": format!("I²C {}", if read { "read" } else { "write" })},
+    }))
+}
+
+/// UART frames, least significant bit first.
+fn uart(params: &Params) -> Result<Value, String> {
+    let bits = params.number("bits", 5, 9)?;
+    let stop = params.number("stop", 1, 2)?;
+    let frames = params.number("frames", 1, 16)?;
+    let parity = match params.text("parity") {
+        "none" => None,
+        "even" => Some('E'),
+        "odd" => Some('O'),
+        parity => return Err(format!("`parity={}` has to be none, even or odd", parity)),
+    };
+
+    let mut wave = String::from("1");
+    let mut labels = vec![];
+    for _ in 0..frames {
+        wave.push('0');
+        wave.push_str(&"=".repeat(bits));
+        labels.extend((0..bits).map(|bit| format!("D{}", bit)));
+        if parity.is_some() {
+            wave.push('4');
+            labels.push("P".into());
+        }
+        wave.push_str(&"1".repeat(stop));
+    }
+    wave.push('1');
+    Ok(json!({
+        "signal": [{"name": "tx", "wave": wave, "data": labels}],
+        "head": {"text": format!("UART {}{}{}", bits, parity.unwrap_or('N'), stop)},
+    }))
+}
+
+/// An AXI `VALID`/`READY` handshake, the receiver holding off for `wait` cycles.
+fn axi(params: &Params) -> Result<Value, String> {
+    let wait = params.number("wait", 0, 16)?;
+    let channel = params.text("channel");
+    let hold = ".".repeat(wait);
+
+    Ok(json!({
+        "signal": [
+            {"name": "ACLK", "wave": format!("p{}..", hold)},
+            {"name": format!("{}VALID", channel), "wave": format!("01{}0", hold)},
+            {"name": format!("{}READY", channel), "wave": format!("0{}10", hold)},
+            {"name": format!("{}DATA", channel), "wave": format!("x={}x", hold), "data": ["data"]},
+        ],
+        "head": {"text": format!("{} handshake", if channel.is_empty() { "AXI" } else { channel })},
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{resolve, TEMPLATES};
+    use crate::{lint, wavejson};
+
+    fn resolved(source: &str) -> (String, Vec<String>) {
+        let mut warnings = vec![];
+        let output = resolve(Cow::Borrowed(source), &mut warnings).into_owned();
+        (output, warnings)
+    }
+
+    #[test]
+    fn draws_templates() {
+        assert_eq!(
+            (
+                "{signal: [{name: 'ACLK', wave: 'p....'}, {name: 'AWVALID', wave: '01..0'}, \
+                 {name: 'AWREADY', wave: '0..10'}, {name: 'AWDATA', wave: 'x=..x', data: ['data']}], \
+                 head: {text: 'AW handshake'}}\n"
+                    .to_string(),
+                vec![]
+            ),
+            resolved("  {{#wavedrom-template axi wait=2 channel=AW}}\n")
+        );
+
+        let (spi, _) = resolved("{{#wavedrom-template spi mode=3 bytes=2 bits=4}}");
+        assert!(
+            spi.contains("{name: 'sclk', wave: 'hn.......h'}"),
+            "{}",
+            spi
+        );
+        assert!(spi.contains("data: ['3', '2', '1', '0', '3', '2', '1', '0']}"));
+
+        let (spi, _) = resolved("{{#wavedrom-template spi}}");
+        assert!(
+            spi.contains("{name: 'sclk', wave: 'lp.......l', phase: -0.5}"),
+            "{}",
+            spi
+        );
+    }
+
+    #[test]
+    fn templates_are_lane_aligned() {
+        for template in TEMPLATES {
+            let diagram = (template.draw)(&super::Params {
+                template,
+                attrs: Default::default(),
+            })
+            .unwrap();
+            assert_eq!(
+                Vec::<lint::Warning>::new(),
+                lint::check(&diagram),
+                "{}",
+                template.name
+            );
+            let lengths: Vec<usize> = wavejson::lanes(&diagram)
+                .iter()
+                .map(|lane| lane["wave"].as_str().unwrap().len())
+                .collect();
+            assert!(
+                lengths.windows(2).all(|w| w[0] == w[1]),
+                "{}: {:?}",
+                template.name,
+                lengths
+            );
+            let data_bricks = wavejson::lanes(&diagram)
+                .iter()
+                .filter_map(|lane| Some((lane["wave"].as_str()?, lane.get("data")?.as_array()?)))
+                .all(|(wave, data)| {
+                    wave.chars()
+                        .filter(|c| crate::split::DATA_BRICKS.contains(c))
+                        .count()
+                        == data.len()
+                });
+            assert!(data_bricks, "{}", template.name);
+        }
+    }
+
+    #[test]
+    fn reports_bad_directives() {
+        for source in [
+            "{{#wavedrom-template can}}",
+            "{{#wavedrom-template spi mode=4}}",
+            "{{#wavedrom-template uart speed=9600}}",
+            "{{#wavedrom-template i2c read=yes}}",
+            "{signal: [\n{{#wavedrom-template spi}}\n]}",
+        ] {
+            let (output, warnings) = resolved(source);
+            assert_eq!(source, output);
+            assert_eq!(1, warnings.len(), "{}", source);
+        }
+    }
+}
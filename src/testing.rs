@@ -0,0 +1,263 @@
+//! Helpers for testing books against this preprocessor.
+//!
+//! Enable the `testing` feature to use them in a book's own regression tests:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! mdbook-wavedrom = { version = "0.10", features = ["testing"] }
+//! ```
+//!
+//! Snapshots of a whole book are compared with [`assert_book_snapshots`]. Run the tests with
+//! `WAVEDROM_UPDATE_SNAPSHOTS=1` to write the current output as the new snapshots. What the
+//! renderer makes of the diagrams is checked on the pages [`build_book`] writes.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use mdbook::book::{Book, BookItem, Chapter};
+use mdbook::errors::{Error, Result};
+use mdbook::preprocess::{Preprocessor, PreprocessorContext};
+use mdbook::{Config, MDBook};
+
+use crate::{BookContext, Wavedrom, WavedromConfig};
+
+/// Environment variable that makes the snapshot assertions write the snapshots instead.
+pub const UPDATE_SNAPSHOTS: &str = "WAVEDROM_UPDATE_SNAPSHOTS";
+
+/// Process `markdown` as a top-level chapter, with the default configuration.
+pub fn process(markdown: &str) -> String {
+    process_with(markdown, &WavedromConfig::default())
+}
+
+/// Process `markdown` as a top-level chapter, with the given configuration.
+pub fn process_with(markdown: &str, config: &WavedromConfig) -> String {
+    let chapter = Chapter::new("Chapter", markdown.into(), "chapter.md", vec![]);
+    let book = BookContext {
+        src_dir: None,
+        signals: None,
+        figures: None,
+        images: &Default::default(),
+        html: &Default::default(),
+        transforms: &[],
+        image_format: None,
+    };
+    Wavedrom::add_wavedrom(
+        &chapter,
+        &chapter.content,
+        config,
+        &book,
+        &mut Default::default(),
+    )
+    .expect("processing markdown failed")
+    .unwrap_or_else(|| markdown.into())
+}
+
+/// Assert that processing `markdown` with the default configuration gives `expected`.
+#[track_caller]
+pub fn assert_processed_snapshot(markdown: &str, expected: &str) {
+    let processed = process(markdown);
+    if processed != expected {
+        panic!(
+            "processed markdown doesn't match its snapshot\n{}",
+            diff(expected, &processed)
+        );
+    }
+}
+
+/// Load the book at `root` and run the preprocessor on it, like `mdbook build` would for the
+/// html renderer.
+pub fn process_book<P: Into<PathBuf>>(root: P) -> Result<Book> {
+    let mdbook = MDBook::load(root)?;
+    let ctx = serde_json::json!({
+        "root": mdbook.root,
+        "config": mdbook.config,
+        "renderer": "html",
+        "mdbook_version": mdbook::MDBOOK_VERSION,
+    });
+    let ctx: PreprocessorContext = serde_json::from_value(ctx)?;
+    Wavedrom::default().run(&ctx, mdbook.book)
+}
+
+/// A book built by [`build_book`].
+#[derive(Debug, Clone)]
+pub struct BuiltBook {
+    /// The directory the html renderer wrote the book to.
+    pub dest: PathBuf,
+}
+
+impl BuiltBook {
+    /// The rendered page of the chapter at `path`, relative to the book's `src` directory, like
+    /// `intro/setup.md`.
+    #[track_caller]
+    pub fn page<P: AsRef<Path>>(&self, path: P) -> String {
+        let page = self.dest.join(path.as_ref().with_extension("html"));
+        fs::read_to_string(&page)
+            .unwrap_or_else(|e| panic!("Unable to read {}: {}", page.display(), e))
+    }
+}
+
+/// Build the book at `root` with the html renderer, like `mdbook build` would.
+///
+/// The preprocessor runs in this process, configured by the book's `[preprocessor.wavedrom]`
+/// table, rather than as the `mdbook-wavedrom` command the table names, which might not be
+/// installed or be another version.
+pub fn build_book<P: Into<PathBuf>>(root: P) -> Result<BuiltBook> {
+    let root = root.into();
+    let mut config = Config::from_disk(root.join("book.toml"))?;
+    let table = config
+        .get_mut("preprocessor")
+        .and_then(|preprocessors| preprocessors.as_table_mut())
+        .and_then(|preprocessors| preprocessors.remove("wavedrom"));
+
+    let mut mdbook = MDBook::load_with_config(root, config)?;
+    mdbook.with_preprocessor(InProcess { table });
+    mdbook.build()?;
+    Ok(BuiltBook {
+        dest: mdbook.build_dir_for("html"),
+    })
+}
+
+/// The preprocessor as [`build_book`] registers it, seeing the table mdbook doesn't.
+struct InProcess {
+    table: Option<toml::Value>,
+}
+
+impl Preprocessor for InProcess {
+    fn name(&self) -> &str {
+        "wavedrom"
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
+        let mut ctx = ctx.clone();
+        if let Some(table) = &self.table {
+            ctx.config.set("preprocessor.wavedrom", table)?;
+        }
+        Wavedrom::default().run(&ctx, book)
+    }
+}
+
+/// Assert that every chapter of the book at `root` matches its snapshot in `snapshots`.
+///
+/// The snapshot of `src/intro/setup.md` is `<snapshots>/intro/setup.md`.
+#[track_caller]
+pub fn assert_book_snapshots<P: AsRef<Path>, S: AsRef<Path>>(root: P, snapshots: S) {
+    let book = process_book(root.as_ref()).expect("processing the book failed");
+    let update = env::var_os(UPDATE_SNAPSHOTS).is_some();
+
+    let mut mismatches = vec![];
+    for item in book.iter() {
+        let (path, content) = match item {
+            BookItem::Chapter(Chapter {
+                path: Some(path),
+                content,
+                ..
+            }) => (path, content),
+            _ => continue,
+        };
+        let snapshot = snapshots.as_ref().join(path);
+
+        if update {
+            write_snapshot(&snapshot, content).expect("writing the snapshot failed");
+            continue;
+        }
+        match fs::read_to_string(&snapshot) {
+            Ok(expected) if expected == *content => {}
+            Ok(expected) => mismatches.push(format!(
+                "{} doesn't match {}\n{}",
+                path.display(),
+                snapshot.display(),
+                diff(&expected, content)
+            )),
+            Err(e) => mismatches.push(format!(
+                "{} has no snapshot at {} ({}), run with {}=1 to create it",
+                path.display(),
+                snapshot.display(),
+                e,
+                UPDATE_SNAPSHOTS
+            )),
+        }
+    }
+
+    if !mismatches.is_empty() {
+        panic!("{}", mismatches.join("\n\n"));
+    }
+}
+
+fn write_snapshot(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)
+        .map_err(|e| Error::from(e).context(format!("Unable to write {}", path.display())))
+}
+
+/// The lines of `actual` that differ from `expected`, starting at the first difference.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    let first = expected
+        .iter()
+        .zip(&actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected.len().min(actual.len()));
+
+    let mut output = format!("first difference in line {}:\n", first + 1);
+    for line in expected.iter().skip(first).take(5) {
+        output.push_str(&format!("- {}\n", line));
+    }
+    for line in actual.iter().skip(first).take(5) {
+        output.push_str(&format!("+ {}\n", line));
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{assert_book_snapshots, assert_processed_snapshot, diff, process};
+
+    #[test]
+    fn processes_markdown() {
+        let processed = process("```wavedrom\n{signal: []}\n```\n");
+        assert!(processed.contains(
+            "<script type=\"WaveDrom\" data-wavedrom-id=\"wavedrom-1\">{signal: []}\n</script>"
+        ));
+        assert_processed_snapshot("No diagrams\n", "No diagrams\n");
+    }
+
+    #[test]
+    fn diff_starts_at_first_difference() {
+        assert_eq!(
+            "first difference in line 2:\n- b\n+ c\n",
+            diff("a\nb\n", "a\nc\n")
+        );
+    }
+
+    #[test]
+    fn book_snapshots() {
+        let book = tempfile::tempdir().unwrap();
+        fs::create_dir(book.path().join("src")).unwrap();
+        fs::write(book.path().join("book.toml"), "[book]\ntitle = \"Test\"\n").unwrap();
+        fs::write(book.path().join("src/SUMMARY.md"), "- [Intro](intro.md)\n").unwrap();
+        fs::write(
+            book.path().join("src/intro.md"),
+            "```wavedrom\n{signal: []}\n```\n",
+        )
+        .unwrap();
+
+        let snapshots = book.path().join("snapshots");
+        fs::create_dir(&snapshots).unwrap();
+        fs::write(
+            snapshots.join("intro.md"),
+            process("```wavedrom\n{signal: []}\n```\n"),
+        )
+        .unwrap();
+        assert_book_snapshots(book.path(), &snapshots);
+
+        fs::write(snapshots.join("intro.md"), "outdated\n").unwrap();
+        let result = std::panic::catch_unwind(|| assert_book_snapshots(book.path(), &snapshots));
+        assert!(result.is_err());
+    }
+}
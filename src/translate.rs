@@ -0,0 +1,248 @@
+//! Blocks in fence languages of their own, turned into diagrams by external commands, so teams
+//! can plug in generators for their own timing formats:
+//!
+//! ~~~toml
+//! [preprocessor.wavedrom.translators]
+//! timing = "timing2wavejson --strict"
+//! ~~~
+//!
+//! The command runs in the book's root directory. It's sent the fence language on the first line
+//! of its stdin, followed by the content of the block, and prints either WaveJSON or HTML. WaveJSON
+//! turns the block into a `wavedrom` block with the same attributes, drawn like any other. Output
+//! starting with `<` is HTML, which replaces the block as it is.
+//!
+//! Blocks are translated before anything else looks at the chapter, so the diagrams they give
+//! are seen by `check`, `export-images` and the other subcommands as well. Blocks whose command
+//! fails are left as they are, with a `translate-failed` warning.
+
+use std::fs::{self, File};
+use std::ops::Range;
+use std::path::Path;
+use std::process::Command;
+
+use mdbook::book::{Book, BookItem};
+use mdbook::errors::{Error, Result};
+
+use crate::{blocks, render, ChapterContext, WavedromConfig};
+
+/// What a translator printed for a block.
+#[derive(Debug, PartialEq, Eq)]
+enum Output {
+    WaveJson(String),
+    Html(String),
+}
+
+impl Output {
+    fn new(stdout: String) -> Self {
+        if stdout.trim_start().starts_with('<') {
+            Output::Html(stdout)
+        } else {
+            Output::WaveJson(stdout)
+        }
+    }
+}
+
+/// Translate the blocks of all chapters of `book` at `root` with the `translators` of `config`.
+pub(crate) fn book(book: &mut Book, config: &WavedromConfig, root: &Path) {
+    if config.translators.is_empty() {
+        return;
+    }
+    book.for_each_mut(|item| {
+        if let BookItem::Chapter(chapter) = item {
+            let translated = {
+                let page = ChapterContext::new(chapter, config, None);
+                chapter_content(&chapter.content, &page, root)
+            };
+            if let Some(content) = translated {
+                chapter.content = content;
+            }
+        }
+    });
+}
+
+/// The chapter's `content` with its blocks translated, unless it has none.
+fn chapter_content(content: &str, page: &ChapterContext, root: &Path) -> Option<String> {
+    let languages: Vec<&str> = page.config.translators.keys().map(String::as_str).collect();
+    let blocks = blocks::find_fenced(content, &languages);
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    for (language, block) in blocks {
+        let command = &page.config.translators[&language];
+        let output = match translate(command, &language, &block.source, page.config, root) {
+            Ok(output) => output,
+            Err(e) => {
+                if !page.config.allows("translate-failed", page.source_path) {
+                    warn!(
+                        "{}: Unable to translate the `{}` block: {:#}",
+                        page.location(content, block.span.start),
+                        language,
+                        e
+                    );
+                }
+                continue;
+            }
+        };
+        // The lines of the block after the first carry the markers of its containers.
+        let line_start = content[..block.content.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let prefix = &content[line_start..block.content.start];
+        let lines = |text: &str| text.trim_end().replace('\n', &format!("\n{}", prefix));
+        match output {
+            Output::Html(html) => edits.push((block.span, lines(&html))),
+            Output::WaveJson(source) => {
+                let mut source = lines(&source) + "\n";
+                if block.source.is_empty() {
+                    // The source goes before the closing fence, after its markers.
+                    source.push_str(prefix);
+                }
+                let fence = &content[block.span.clone()];
+                let language_start = block.span.start + fence.len()
+                    - fence.trim_start_matches(['`', '~']).trim_start().len();
+                edits.push((block.content, source));
+                edits.push((
+                    language_start..language_start + language.len(),
+                    "wavedrom".into(),
+                ));
+            }
+        }
+    }
+
+    let mut content = content.to_string();
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    for (range, text) in edits {
+        content.replace_range(range, &text);
+    }
+    Some(content)
+}
+
+/// Run the translator `command` for a `language` block with the `source`.
+fn translate(
+    command: &str,
+    language: &str,
+    source: &str,
+    config: &WavedromConfig,
+    root: &Path,
+) -> Result<Output> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| Error::msg(format!("the translator of `{}` is empty", language)))?;
+
+    // Files rather than pipes, which would block a chatty command nobody reads from.
+    let dir = tempfile::tempdir()?;
+    let (input, output, stderr) = (
+        dir.path().join("input"),
+        dir.path().join("output"),
+        dir.path().join("stderr"),
+    );
+    fs::write(&input, format!("{}\n{}", language, source))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(root)
+        .stdin(File::open(&input)?)
+        .stdout(File::create(&output)?)
+        .stderr(File::create(&stderr)?)
+        .spawn()
+        .map_err(|e| Error::from(e).context(format!("Unable to run '{}'", program)))?;
+    let status = render::wait(&mut child, config.renderer().timeout, program)?;
+    if !status.success() {
+        let stderr = fs::read(&stderr).unwrap_or_default();
+        return Err(Error::msg(format!(
+            "'{}' failed ({}): {}",
+            program,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        )));
+    }
+
+    let stdout = fs::read_to_string(&output)
+        .map_err(|e| Error::from(e).context(format!("'{}' didn't print UTF-8", program)))?;
+    if stdout.trim().is_empty() {
+        return Err(Error::msg(format!("'{}' printed nothing", program)));
+    }
+    Ok(Output::new(stdout))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use mdbook::book::Chapter;
+    use pretty_assertions::assert_eq;
+
+    use super::{chapter_content, translate, Output};
+    use crate::{ChapterContext, WavedromConfig};
+
+    fn script(dir: &Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn runs_the_translator() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WavedromConfig::default();
+        // Echoes the language and the first line of the content as a lane.
+        let lane = script(
+            dir.path(),
+            "lane.sh",
+            "read lang; read name; echo \"{signal: [{name: '$lang $name'}]}\"",
+        );
+        assert_eq!(
+            Output::WaveJson("{signal: [{name: 'timing clk'}]}\n".into()),
+            translate(&lane, "timing", "clk\n", &config, dir.path()).unwrap()
+        );
+
+        let html = script(dir.path(), "html.sh", "echo '<svg></svg>'");
+        assert_eq!(
+            Output::Html("<svg></svg>\n".into()),
+            translate(&html, "timing", "", &config, dir.path()).unwrap()
+        );
+
+        let fails = script(dir.path(), "fails.sh", "echo 'bad timing' >&2; exit 3");
+        let error = translate(&fails, "timing", "", &config, dir.path()).unwrap_err();
+        assert!(
+            format!("{:#}", error).ends_with("bad timing"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn rewrites_the_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = WavedromConfig::default();
+        config.translators.insert(
+            "timing".into(),
+            script(
+                dir.path(),
+                "lane.sh",
+                "read lang; echo \"{signal: [\"; echo \"  {name: 'a'}]}\"",
+            ),
+        );
+        config.translators.insert(
+            "figure".into(),
+            script(dir.path(), "html.sh", "echo '<div>'; echo '</div>'"),
+        );
+        let content = "```timing caption=\"A\"\nclk\n```\n\n\
+                       > ```figure\n> x\n> ```\n\n\
+                       - ```timing\n  ```\n";
+        let chapter = Chapter::new("Test", content.into(), "chapter.md", vec![]);
+        let page = ChapterContext::new(&chapter, &config, None);
+
+        let expected = "```wavedrom caption=\"A\"\n{signal: [\n  {name: 'a'}]}\n```\n\n\
+                        > <div>\n> </div>\n\n\
+                        - ```wavedrom\n  {signal: [\n    {name: 'a'}]}\n  ```\n";
+        assert_eq!(
+            Some(expected.to_string()),
+            chapter_content(content, &page, dir.path())
+        );
+    }
+}
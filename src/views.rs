@@ -0,0 +1,128 @@
+//! Views of a larger diagram, like a master waveform kept in a file of its own and shown by
+//! several blocks, each with its own part of it:
+//!
+//! ~~~text
+//! ```wavedrom src=waves/transaction.json5 signals="clk,req,ack" range=0..16 hscale=2
+//! ```
+//! ~~~
+//!
+//! `signals` picks the lanes, `range` crops to a window of cycles, and `hscale` stretches the
+//! cycles. Every view is checked against the diagram it's taken from, so a view that no longer
+//! fits its master after an edit shows up in the build.
+
+use serde_json::{json, Value};
+
+use crate::attrs::Attributes;
+use crate::{split, subset};
+
+/// The attributes selecting a view.
+const ATTRIBUTES: &[&str] = &["signals", "range", "hscale"];
+
+/// Whether the block with `attrs` shows a view of its diagram.
+pub(crate) fn wanted(attrs: &Attributes) -> bool {
+    ATTRIBUTES.iter().any(|attr| attrs.get(attr).is_some())
+}
+
+/// Cut `diagram` down to the view `attrs` select, keeping the `descriptions` of the signals left.
+///
+/// Returns a warning for every part of the view that doesn't fit the diagram.
+pub(crate) fn apply(
+    diagram: &mut Value,
+    attrs: &Attributes,
+    descriptions: &mut Vec<(String, String)>,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    if let Some(lanes) = attrs.get("signals") {
+        warnings.extend(subset::apply(diagram, lanes));
+        descriptions.retain(|(signal, _)| subset::names(lanes).any(|name| name == signal));
+    }
+    if let Some(range) = attrs.get("range") {
+        match split::range(range) {
+            Ok((start, end)) => {
+                let length = split::length(diagram);
+                if start >= length {
+                    warnings.push(format!(
+                        "`range={}` starts after the {} cycles of the diagram",
+                        range, length
+                    ));
+                } else {
+                    if end.is_some_and(|end| end > length) {
+                        warnings.push(format!(
+                            "`range={}` ends after the {} cycles of the diagram",
+                            range, length
+                        ));
+                    }
+                    split::crop(diagram, start, end);
+                }
+            }
+            Err(e) => warnings.push(e),
+        }
+    }
+    if let Some(hscale) = attrs.get("hscale") {
+        match hscale.parse::<u32>() {
+            Ok(scale) if scale > 0 => scale_to(diagram, scale),
+            _ => warnings.push(format!(
+                "Invalid `hscale={}`, expected a whole number greater than 0",
+                hscale
+            )),
+        }
+    }
+    warnings
+}
+
+/// Set the `hscale` of the diagram's `config`.
+fn scale_to(diagram: &mut Value, scale: u32) {
+    let diagram = match diagram.as_object_mut() {
+        Some(diagram) => diagram,
+        None => return,
+    };
+    let config = diagram.entry("config").or_insert_with(|| json!({}));
+    if let Some(config) = config.as_object_mut() {
+        config.insert("hscale".into(), json!(scale));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::{attrs, wavejson};
+
+    fn view(info: &str) -> (String, Vec<String>) {
+        let mut diagram = wavejson::parse(
+            "{signal: [{name: 'clk', wave: 'p.......'}, {name: 'req', wave: '01.....0'}], config: {skin: 'narrow'}}",
+        )
+        .unwrap();
+        let (_, attrs) = attrs::parse(info);
+        let warnings = apply(&mut diagram, &attrs, &mut vec![]);
+        (wavejson::to_string(&diagram), warnings)
+    }
+
+    #[test]
+    fn picks_lanes_and_cycles() {
+        assert_eq!(
+            (
+                "{signal: [{name: 'req', wave: '1.'}], config: {skin: 'narrow', hscale: 2}}"
+                    .to_string(),
+                vec![]
+            ),
+            view("wavedrom signals=req range=2..4 hscale=2")
+        );
+    }
+
+    #[test]
+    fn checks_the_view_fits() {
+        let (_, warnings) = view("wavedrom range=6..12 signals=\"clk,ack\"");
+        assert_eq!(
+            vec![
+                "No signal named 'ack' to show".to_string(),
+                "`range=6..12` ends after the 8 cycles of the diagram".to_string(),
+            ],
+            warnings
+        );
+        let (output, warnings) = view("wavedrom range=8.. hscale=0");
+        assert!(output.contains("'p.......'"));
+        assert_eq!(2, warnings.len());
+    }
+}
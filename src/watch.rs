@@ -0,0 +1,130 @@
+//! The directories outside the book's `src` that its diagrams read files from, for `install` to
+//! add to `build.extra-watch-dirs`, so `mdbook serve` rebuilds when the files change.
+//!
+//! The files are the `src` and `results` files of the blocks and their includes, followed into
+//! the files those include. mdbook watches `src` itself.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use mdbook::errors::Result;
+
+use crate::{book, external, include, results};
+
+/// The directories the book at `root` reads diagram files from outside its `src`, relative to
+/// `root` unless they're outside of it as well, sorted and without the ones inside another.
+pub fn watch_dirs(root: &Path) -> Result<Vec<PathBuf>> {
+    let source = book::load(root)?;
+    let mut files = vec![];
+    book::for_each_diagram(&source.book, &source.config, &source.src_dir, |diagram| {
+        let (block, page) = (&diagram.block, diagram.page);
+        match external::path(block, page) {
+            Some(path) => {
+                if let (Ok(content), Some(base)) = (fs::read_to_string(&path), path.parent()) {
+                    files.extend(include::files(&content, base));
+                }
+                files.push(path);
+            }
+            None => {
+                if let Some(dir) = page.source_dir() {
+                    files.extend(include::files(&block.source, &dir));
+                }
+            }
+        }
+        files.extend(results::path(block, page));
+    });
+
+    let root = normalize(root);
+    let src_dir = normalize(&source.src_dir);
+    let mut dirs: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|file| file.parent())
+        .map(normalize)
+        .filter(|dir| !dir.starts_with(&src_dir))
+        .map(|dir| match dir.strip_prefix(&root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => dir,
+        })
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    let mut outer: Vec<PathBuf> = vec![];
+    for dir in dirs {
+        if !outer.iter().any(|other| dir.starts_with(other)) {
+            outer.push(dir);
+        }
+    }
+    Ok(outer)
+}
+
+/// `path` with its `.` and `..` components resolved, without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{normalize, watch_dirs};
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            Path::new("waves"),
+            normalize(Path::new("./src/ch/../../waves"))
+        );
+        assert_eq!(
+            Path::new("../shared"),
+            normalize(Path::new("src/../../shared"))
+        );
+    }
+
+    #[test]
+    fn finds_the_directories_outside_src() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        for dir in ["src/local", "waves/spi", "sim", "fragments"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("book.toml"), "[book]\n").unwrap();
+        fs::write(root.join("src/SUMMARY.md"), "[Intro](intro.md)\n").unwrap();
+        fs::write(
+            root.join("src/intro.md"),
+            "```wavedrom src=../waves/spi/write.json5\n```\n\n\
+             ```wavedrom results=../sim/run.json#burst\n{{#include local/clk.json5}}\n```\n\n\
+             ```wavedrom src=../waves/read.json5\n```\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("waves/spi/write.json5"),
+            "{{#include ../../fragments/clk.json5}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                PathBuf::from("fragments"),
+                PathBuf::from("sim"),
+                PathBuf::from("waves"),
+            ],
+            watch_dirs(root).unwrap()
+        );
+    }
+}
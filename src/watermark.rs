@@ -0,0 +1,80 @@
+//! A text stamped into the foot of every diagram, like a document revision or `CONFIDENTIAL`.
+//!
+//! The text goes into the diagram's `foot`, so it's drawn by WaveDrom itself, and stays with the
+//! diagram when it's saved as an image. A foot text of the diagram's own comes first.
+
+use serde_json::{json, Value};
+
+/// Whether the diagram with the given block attributes gets a watermark.
+///
+/// Diagrams opt out with `watermark=false`.
+pub(crate) fn wanted(attr: Option<&str>) -> bool {
+    attr != Some("false")
+}
+
+/// Stamp `text` into the foot of the diagram.
+///
+/// Other diagrams than timing diagrams, like registers, have no foot and are left alone.
+/// Returns a warning if the foot is in the way.
+pub(crate) fn apply(diagram: &mut Value, text: &str) -> Option<String> {
+    let diagram = match diagram.as_object_mut() {
+        Some(diagram) if diagram.contains_key("signal") => diagram,
+        _ => return None,
+    };
+    let foot = diagram.entry("foot").or_insert_with(|| json!({}));
+    let foot = match foot.as_object_mut() {
+        Some(foot) => foot,
+        None => {
+            return Some("The diagram's `foot` isn't an object, not adding the watermark".into())
+        }
+    };
+
+    let mark = json!(["tspan", {"class": "muted"}, text]);
+    let text = match foot.remove("text") {
+        Some(own) => json!(["tspan", own, "  ", mark]),
+        None => mark,
+    };
+    foot.insert("text".into(), text);
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::wavejson;
+
+    fn watermarked(source: &str) -> (String, Option<String>) {
+        let mut diagram = wavejson::parse(source).unwrap();
+        let warning = apply(&mut diagram, "Rev. B");
+        (wavejson::to_string(&diagram), warning)
+    }
+
+    #[test]
+    fn stamps_foot() {
+        assert_eq!(
+            (
+                "{signal: [], foot: {text: ['tspan', {class: 'muted'}, 'Rev. B']}}".to_string(),
+                None
+            ),
+            watermarked("{signal: []}")
+        );
+        assert_eq!(
+            (
+                "{signal: [], foot: {tock: 1, text: ['tspan', 'Figure', '  ', ['tspan', {class: 'muted'}, 'Rev. B']]}}"
+                    .to_string(),
+                None
+            ),
+            watermarked("{signal: [], foot: {text: 'Figure', tock: 1}}")
+        );
+    }
+
+    #[test]
+    fn leaves_other_diagrams() {
+        let (output, warning) = watermarked("{reg: [{bits: 8}]}");
+        assert_eq!("{reg: [{bits: 8}]}", output);
+        assert_eq!(None, warning);
+        assert!(watermarked("{signal: [], foot: 'x'}").1.is_some());
+    }
+}
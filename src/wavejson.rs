@@ -0,0 +1,372 @@
+//! Reading and rewriting the WaveJSON source of a diagram.
+//!
+//! Diagrams are written as JavaScript object literals rather than strict JSON, with unquoted keys,
+//! single-quoted strings and trailing commas, so they are parsed as JSON5. Rewritten diagrams are
+//! written back in that same style.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+/// Parse the WaveJSON `source` of a diagram.
+pub(crate) fn parse(source: &str) -> Result<Value, json5::Error> {
+    json5::from_str(source)
+}
+
+/// Why parsing a diagram failed and where, e.g. `expected identifier at line 2, column 5`.
+pub(crate) fn describe_error(e: &json5::Error) -> String {
+    let json5::Error::Message { msg, location } = e;
+    let reason = msg.lines().last().unwrap_or_default();
+    let reason = reason.trim_start().trim_start_matches("= ");
+    match location {
+        Some(at) => format!("{} at line {}, column {}", reason, at.line, at.column),
+        None => reason.to_string(),
+    }
+}
+
+/// The WaveJSON of a block whose source is the `key` list alone, like the `reg` fields of a
+/// `register` block, as a whole diagram.
+///
+/// Blocks with a whole diagram are passed on as they are.
+pub(crate) fn wrap<'a>(key: &str, list: Cow<'a, str>) -> Cow<'a, str> {
+    if list.trim_start().starts_with('[') {
+        Cow::Owned(format!("{{{}: {}}}\n", key, list.trim_end()))
+    } else {
+        list
+    }
+}
+
+/// What the diagram with the WaveJSON `source` shows, as the text standing in for its image.
+pub(crate) fn kind(source: &str) -> &'static str {
+    match parse(source) {
+        Ok(diagram) if diagram.get("reg").is_some() => "Register diagram",
+        Ok(diagram) if diagram.get("assign").is_some() => "Logic diagram",
+        _ => "Timing diagram",
+    }
+}
+
+/// The text standing in for the diagram with the WaveJSON `source` when it has no `alt`: its kind
+/// and the names of what it shows, like `Timing diagram of clk, req and ack`.
+pub(crate) fn describe(source: &str) -> String {
+    let kind = kind(source);
+    let diagram = match parse(source) {
+        Ok(diagram) => diagram,
+        Err(_) => return kind.to_string(),
+    };
+    let names: Vec<&Value> = if let Some(Value::Array(fields)) = diagram.get("reg") {
+        fields
+            .iter()
+            .filter_map(|field| field.get("name"))
+            .collect()
+    } else if let Some(Value::Array(outputs)) = diagram.get("assign") {
+        // Every assignment is `[output, expression]`.
+        outputs.iter().filter_map(|output| output.get(0)).collect()
+    } else {
+        lanes(&diagram)
+            .into_iter()
+            .filter_map(|lane| lane.get("name"))
+            .collect()
+    };
+    let names: Vec<String> = names
+        .into_iter()
+        .map(|name| text(name).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    match names.split_last() {
+        None => kind.to_string(),
+        Some((last, [])) => format!("{} of {}", kind, last),
+        Some((last, rest)) => format!("{} of {} and {}", kind, rest.join(", "), last),
+    }
+}
+
+/// Serialize `value` as a WaveJSON object literal, e.g. `{signal: [{name: 'clk', wave: 'p.'}]}`.
+///
+/// Strings are single-quoted, so the output survives the HTML escaping of the embedded source.
+pub(crate) fn to_string(value: &Value) -> String {
+    let mut output = String::new();
+    write_value(&mut output, value);
+    output
+}
+
+/// The WaveJSON `source` on a single line, rewritten if it spans several.
+///
+/// Sources that don't parse have their line breaks replaced by spaces.
+pub(crate) fn one_line(source: &str) -> Cow<'_, str> {
+    let source = source.trim();
+    if !source.contains('\n') {
+        return Cow::Borrowed(source);
+    }
+    match parse(source) {
+        Ok(value) => Cow::Owned(to_string(&value)),
+        Err(_) => Cow::Owned(source.replace(['\r', '\n'], " ")),
+    }
+}
+
+/// The text of the diagram's `head`, without the markup of rich text like
+/// `['tspan', {class: 'info'}, 'text']`.
+pub(crate) fn head_text(diagram: &Value) -> Option<String> {
+    let text = text(diagram.pointer("/head/text")?);
+    let text = text.trim();
+    Some(text.to_string()).filter(|_| !text.is_empty())
+}
+
+/// The text of a string or rich text, like the name of a lane styled as
+/// `['tspan', {style: '…'}, 'req']`.
+pub(crate) fn text(value: &Value) -> String {
+    fn collect(value: &Value, text: &mut String) {
+        match value {
+            Value::String(s) => text.push_str(s),
+            // The first entry of a JsonML element is its tag.
+            Value::Array(element) => element.iter().skip(1).for_each(|v| collect(v, text)),
+            _ => {}
+        }
+    }
+
+    let mut text = String::new();
+    collect(value, &mut text);
+    text
+}
+
+/// Every lane of the `signal` list, descending into groups.
+pub(crate) fn lanes(value: &Value) -> Vec<&Map<String, Value>> {
+    fn visit<'a>(lanes: &'a [Value], found: &mut Vec<&'a Map<String, Value>>) {
+        for lane in lanes {
+            match lane {
+                Value::Object(lane) => found.push(lane),
+                Value::Array(group) => visit(group, found),
+                _ => {}
+            }
+        }
+    }
+
+    let mut found = vec![];
+    if let Some(Value::Array(signal)) = value.get("signal") {
+        visit(signal, &mut found);
+    }
+    found
+}
+
+/// Like [`to_string`], with every lane of the `signal` list on a line of its own.
+pub(crate) fn to_string_pretty(value: &Value) -> String {
+    let map = match value {
+        Value::Object(map) => map,
+        _ => return to_string(value),
+    };
+    let mut output = String::from("{");
+    for (i, (key, item)) in map.iter().enumerate() {
+        if i > 0 {
+            output.push_str(",\n");
+        }
+        write_key(&mut output, key);
+        match item {
+            Value::Array(lanes) if key == "signal" && !lanes.is_empty() => {
+                write_lanes(&mut output, lanes, 1)
+            }
+            _ => write_value(&mut output, item),
+        }
+    }
+    output.push('}');
+    output
+}
+
+/// Write `lanes` one per line at `depth`, groups with their label on the opening line.
+fn write_lanes(output: &mut String, lanes: &[Value], depth: usize) {
+    output.push('[');
+    let mut lanes = lanes.iter().peekable();
+    if let Some(Value::String(label)) = lanes.peek() {
+        write_string(output, label);
+        lanes.next();
+        if lanes.peek().is_some() {
+            output.push(',');
+        }
+    }
+    let mut first = true;
+    for lane in lanes {
+        if !first {
+            output.push(',');
+        }
+        first = false;
+        output.push('\n');
+        output.push_str(&"  ".repeat(depth));
+        match lane {
+            Value::Array(group) if !group.is_empty() => write_lanes(output, group, depth + 1),
+            _ => write_value(output, lane),
+        }
+    }
+    if !first {
+        output.push('\n');
+        output.push_str(&"  ".repeat(depth - 1));
+    }
+    output.push(']');
+}
+
+/// Call `f` for every lane of the `signal` list, descending into groups.
+pub(crate) fn for_each_lane<F>(value: &mut Value, mut f: F)
+where
+    F: FnMut(&mut Map<String, Value>),
+{
+    if let Some(Value::Array(signal)) = value.get_mut("signal") {
+        visit_lanes(signal, &mut f);
+    }
+}
+
+fn visit_lanes<F>(lanes: &mut [Value], f: &mut F)
+where
+    F: FnMut(&mut Map<String, Value>),
+{
+    for lane in lanes {
+        match lane {
+            Value::Object(lane) => f(lane),
+            // A group: `['label', lane, lane, ...]`.
+            Value::Array(group) => visit_lanes(group, f),
+            _ => {}
+        }
+    }
+}
+
+fn write_value(output: &mut String, value: &Value) {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => output.push_str(&value.to_string()),
+        Value::String(s) => write_string(output, s),
+        Value::Array(items) => {
+            output.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                write_value(output, item);
+            }
+            output.push(']');
+        }
+        Value::Object(map) => {
+            output.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                write_key(output, key);
+                write_value(output, item);
+            }
+            output.push('}');
+        }
+    }
+}
+
+fn write_key(output: &mut String, key: &str) {
+    if is_identifier(key) {
+        output.push_str(key);
+    } else {
+        write_string(output, key);
+    }
+    output.push_str(": ");
+}
+
+fn write_string(output: &mut String, s: &str) {
+    output.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => output.push_str("\\'"),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            _ => output.push(c),
+        }
+    }
+    output.push('\'');
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{describe, for_each_lane, head_text, parse, to_string, to_string_pretty};
+
+    #[test]
+    fn describes_diagrams() {
+        assert_eq!(
+            "Timing diagram of clk, req and ack",
+            describe(
+                "{signal: [{name: 'clk'}, {}, ['Bus', {name: ['tspan', {}, 'req']}, {name: 'ack'}]]}"
+            )
+        );
+        assert_eq!(
+            "Register diagram of enable",
+            describe("{reg: [{bits: 1, name: 'enable'}, {bits: 7}]}")
+        );
+        assert_eq!(
+            "Logic diagram of z and y",
+            describe("{assign: [['z', ['&', 'a', 'b']], ['y', 'a']]}")
+        );
+        assert_eq!("Timing diagram", describe("{signal: [{wave: '01'}]}"));
+        assert_eq!("Timing diagram", describe("{signal: ["));
+    }
+
+    #[test]
+    fn reads_head_text() {
+        let head = |source| head_text(&parse(source).unwrap());
+        assert_eq!(
+            Some("SPI write".into()),
+            head("{head: {text: ' SPI write '}}")
+        );
+        assert_eq!(
+            Some("AXI burst read".into()),
+            head("{head: {text: ['tspan', 'AXI ', ['tspan', {class: 'info h3'}, 'burst'], ' read']}}")
+        );
+        assert_eq!(None, head("{head: {text: ''}, signal: []}"));
+        assert_eq!(None, head("{head: {tick: 0}}"));
+    }
+
+    #[test]
+    fn pretty_puts_lanes_on_lines() {
+        let source = "{signal: [{name: 'clk', wave: 'p.'}, {}, ['bus', {name: 'a'}, ['in', {name: 'b'}]], ['empty']], config: {hscale: 2}}";
+        let pretty = to_string_pretty(&parse(source).unwrap());
+        assert_eq!(
+            "{signal: [\n  {name: 'clk', wave: 'p.'},\n  {},\n  ['bus',\n    {name: 'a'},\n    ['in',\n      {name: 'b'}\n    ]\n  ],\n  ['empty']\n],\nconfig: {hscale: 2}}",
+            pretty
+        );
+        assert_eq!(parse(source).unwrap(), parse(&pretty).unwrap());
+        assert_eq!(
+            "{signal: []}",
+            to_string_pretty(&parse("{signal: []}").unwrap())
+        );
+    }
+
+    #[test]
+    fn roundtrip_keeps_order_and_style() {
+        let source = "{signal: [\n  {name: 'clk', wave: 'p...'},\n  ['grp', {name: \"it's\", wave: '01',},],\n], 'head-text': {tick: 0}}";
+        let value = parse(source).unwrap();
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: 'p...'}, ['grp', {name: 'it\\'s', wave: '01'}]], 'head-text': {tick: 0}}",
+            to_string(&value)
+        );
+        assert_eq!(value, parse(&to_string(&value)).unwrap());
+    }
+
+    #[test]
+    fn visits_lanes_in_groups() {
+        let mut value =
+            parse("{signal: [{name: 'a'}, {}, ['g', {name: 'b'}, ['h', {name: 'c'}]]]}").unwrap();
+        let mut names = vec![];
+        for_each_lane(&mut value, |lane| {
+            if let Some(name) = lane.get("name").and_then(|n| n.as_str()) {
+                names.push(name.to_string());
+            }
+        });
+        assert_eq!(vec!["a", "b", "c"], names);
+    }
+
+    #[test]
+    fn rejects_invalid_source() {
+        assert!(parse("{signal: [").is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Setup and hold windows around a clock edge, given as block attribute `window`:
+//!
+//! ~~~text
+//! ```wavedrom window="data@clk rise setup=2 hold=1"
+//! ~~~
+//!
+//! The window is drawn with nodes on the data lane, `<->` edges labeled `tsu` and `th` across the
+//! setup and hold times, and a line from the clock edge down to the data. `setup` and `hold` are
+//! in bricks of the data lane, `fall` picks a falling edge instead, and `edge=3` the third edge
+//! instead of the first. Several windows are separated by `;`.
+
+use std::collections::BTreeSet;
+
+use serde_json::{json, Map, Value};
+
+use crate::wavejson;
+
+/// Characters to name the nodes of the windows, skipping those the diagram already uses.
+const NODE_NAMES: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// A window of attribute `window`.
+#[derive(Debug, Clone, PartialEq)]
+struct Window<'a> {
+    data: &'a str,
+    clock: &'a str,
+    rising: bool,
+    /// The number of the clock edge, starting at 1.
+    edge: usize,
+    setup: usize,
+    hold: usize,
+}
+
+/// Draw the windows of the attribute `spec` into the diagram.
+///
+/// Returns a warning for every window that can't be drawn.
+pub(crate) fn apply(diagram: &mut Value, spec: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut names = free_names(diagram);
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let drawn = parse(entry).and_then(|window| draw(diagram, &window, &mut names));
+        match drawn {
+            Ok(Some(warning)) => warnings.push(format!("`window={}`: {}", entry, warning)),
+            Ok(None) => {}
+            Err(e) => warnings.push(format!("Unable to draw `window={}`: {}", entry, e)),
+        }
+    }
+    warnings
+}
+
+fn parse(entry: &str) -> Result<Window<'_>, String> {
+    let mut words = entry.split_whitespace();
+    let (data, clock) = words
+        .next()
+        .and_then(|lanes| lanes.split_once('@'))
+        .ok_or("expected `data@clock` first")?;
+    let mut window = Window {
+        data,
+        clock,
+        rising: true,
+        edge: 1,
+        setup: 0,
+        hold: 0,
+    };
+    for word in words {
+        match word.split_once('=') {
+            None if word == "rise" => window.rising = true,
+            None if word == "fall" => window.rising = false,
+            Some((key @ ("setup" | "hold" | "edge"), value)) => {
+                let value = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("`{}` has to be a whole number", key))?;
+                match key {
+                    "setup" => window.setup = value,
+                    "hold" => window.hold = value,
+                    _ if value == 0 => return Err("`edge` starts at 1".into()),
+                    _ => window.edge = value,
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unknown '{}', expected rise, fall, setup=, hold= or edge=",
+                    word
+                ))
+            }
+        }
+    }
+    if window.setup == 0 && window.hold == 0 {
+        return Err("expected `setup=` or `hold=`".into());
+    }
+    Ok(window)
+}
+
+/// Draw `window` into the diagram, naming its nodes from `names`.
+///
+/// Returns a warning if it had to be moved to fit the data lane.
+fn draw(
+    diagram: &mut Value,
+    window: &Window,
+    names: &mut Vec<char>,
+) -> Result<Option<String>, String> {
+    let lanes = wavejson::lanes(diagram);
+    let lane = |name: &str| {
+        lanes
+            .iter()
+            .find(|lane| lane.get("name").and_then(Value::as_str) == Some(name))
+            .copied()
+            .ok_or_else(|| format!("no signal named '{}'", name))
+    };
+    let (clock, data) = (lane(window.clock)?, lane(window.data)?);
+    let kind = if window.rising { "rising" } else { "falling" };
+    let time = edges(clock, window.rising)
+        .get(window.edge - 1)
+        .copied()
+        .ok_or_else(|| format!("'{}' has no {} edge {}", window.clock, kind, window.edge))?;
+
+    let mut warning = None;
+    let at = time / period(data);
+    let edge = at.round() as usize;
+    if (at - edge as f64).abs() > 1e-9 {
+        warning = Some(format!(
+            "the {} edge of '{}' isn't at a brick of '{}', drawing it at brick {}",
+            kind, window.clock, window.data, edge
+        ));
+    }
+    let length = data
+        .get("wave")
+        .and_then(Value::as_str)
+        .map_or(0, |wave| wave.chars().count());
+    if edge > length {
+        return Err(format!(
+            "the {} edge of '{}' is after the {} bricks of '{}'",
+            kind, window.clock, length, window.data
+        ));
+    }
+    let start = edge.saturating_sub(window.setup);
+    let end = (edge + window.hold).min(length);
+    if start + window.setup != edge || end != edge + window.hold {
+        warning = Some(format!(
+            "the window doesn't fit into the {} bricks of '{}', cutting it short",
+            length, window.data
+        ));
+    }
+    // The edge of a clock lane's brick is where its node goes, unless the clock has a `period`.
+    let clock_node = Some(time).filter(|t| t.fract() == 0.0 && period(clock) == 1.0);
+
+    let setup = Some(start).filter(|&start| start < edge);
+    let hold = Some(end).filter(|&end| end > edge);
+    let positions: Vec<usize> = setup.into_iter().chain([edge]).chain(hold).collect();
+    let mut nodes = nodes_on(diagram, window.data, &positions, names)?.into_iter();
+    let setup = setup.and_then(|_| nodes.next());
+    let edge = nodes.next();
+    let hold = hold.and_then(|_| nodes.next());
+
+    let mut new_edges = vec![];
+    if let (Some(setup), Some(edge)) = (setup, edge) {
+        new_edges.push(format!("{}<->{} tsu", setup, edge));
+    }
+    if let (Some(edge), Some(hold)) = (edge, hold) {
+        new_edges.push(format!("{}<->{} th", edge, hold));
+    }
+    if let (Some(time), Some(edge)) = (clock_node, edge) {
+        let clock = nodes_on(diagram, window.clock, &[time as usize], names)?;
+        new_edges.push(format!("{}-{}", clock[0], edge));
+    }
+
+    match diagram
+        .as_object_mut()
+        .map(|diagram| diagram.entry("edge").or_insert_with(|| json!([])))
+    {
+        Some(Value::Array(edges)) => edges.extend(new_edges.into_iter().map(Value::String)),
+        _ => return Err("the diagram's `edge` isn't a list".into()),
+    }
+    Ok(warning)
+}
+
+/// The nodes at brick `positions` of the first lane named `lane`, named from `names` where
+/// there are none yet.
+fn nodes_on(
+    diagram: &mut Value,
+    lane: &str,
+    positions: &[usize],
+    names: &mut Vec<char>,
+) -> Result<Vec<char>, String> {
+    let mut nodes = None;
+    wavejson::for_each_lane(diagram, |candidate| {
+        if nodes.is_none() && candidate.get("name").and_then(Value::as_str) == Some(lane) {
+            nodes = Some(
+                positions
+                    .iter()
+                    .map(|&position| node_at(candidate, position, names))
+                    .collect::<Option<Vec<char>>>(),
+            );
+        }
+    });
+    nodes
+        .flatten()
+        .ok_or_else(|| "ran out of names for the nodes".to_string())
+}
+
+/// The names no node of `diagram` has yet.
+fn free_names(diagram: &Value) -> Vec<char> {
+    let used: BTreeSet<char> = wavejson::lanes(diagram)
+        .iter()
+        .filter_map(|lane| lane.get("node").and_then(Value::as_str))
+        .flat_map(str::chars)
+        .collect();
+    NODE_NAMES
+        .chars()
+        .rev()
+        .filter(|c| !used.contains(c))
+        .collect()
+}
+
+/// The node at brick `position` of `lane`, adding one named from `names` if there is none.
+fn node_at(lane: &mut Map<String, Value>, position: usize, names: &mut Vec<char>) -> Option<char> {
+    let mut node: Vec<char> = lane
+        .get("node")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .chars()
+        .collect();
+    if node.len() <= position {
+        node.resize(position + 1, '.');
+    }
+    if node[position] == '.' {
+        node[position] = names.pop()?;
+    }
+    let name = node[position];
+    lane.insert("node".into(), Value::String(node.into_iter().collect()));
+    Some(name)
+}
+
+/// The lane's `period`, defaulting to a cycle per brick.
+fn period(lane: &Map<String, Value>) -> f64 {
+    lane.get("period")
+        .and_then(Value::as_f64)
+        .filter(|&period| period > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// The times of the rising or falling edges of `lane`, in cycles.
+fn edges(lane: &Map<String, Value>, rising: bool) -> Vec<f64> {
+    let wave = lane.get("wave").and_then(Value::as_str).unwrap_or_default();
+    let period = period(lane);
+    let phase = lane.get("phase").and_then(Value::as_f64).unwrap_or(0.0);
+    // The brick continued by `.`, and whether the lane ends high, if known.
+    let mut current = 'x';
+    let mut high: Option<bool> = None;
+    let mut found = vec![];
+    for (i, c) in wave.chars().enumerate() {
+        let c = if c == '.' || c == '|' { current } else { c };
+        current = c;
+        let t = i as f64;
+        let mut edge = |at: f64, to_high: bool| {
+            if to_high == rising {
+                found.push(at * period - phase);
+            }
+        };
+        match c {
+            'p' | 'P' => {
+                if high != Some(true) {
+                    edge(t, true);
+                }
+                edge(t + 0.5, false);
+                high = Some(false);
+            }
+            'n' | 'N' => {
+                if high != Some(false) {
+                    edge(t, false);
+                }
+                edge(t + 0.5, true);
+                high = Some(true);
+            }
+            '1' | 'h' | 'H' | 'u' => {
+                if high == Some(false) {
+                    edge(t, true);
+                }
+                high = Some(true);
+            }
+            '0' | 'l' | 'L' | 'd' => {
+                if high == Some(true) {
+                    edge(t, false);
+                }
+                high = Some(false);
+            }
+            _ => high = None,
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::wavejson;
+
+    fn windowed(source: &str, spec: &str) -> (String, Vec<String>) {
+        let mut diagram = wavejson::parse(source).unwrap();
+        let warnings = apply(&mut diagram, spec);
+        (wavejson::to_string(&diagram), warnings)
+    }
+
+    #[test]
+    fn draws_setup_and_hold() {
+        assert_eq!(
+            (
+                "{signal: [{name: 'clk', wave: 'p.....', node: '..D'}, \
+                 {name: 'data', wave: 'x=..x.', node: 'A.BC'}], \
+                 edge: ['A<->B tsu', 'B<->C th', 'D-B']}"
+                    .to_string(),
+                vec![]
+            ),
+            windowed(
+                "{signal: [{name: 'clk', wave: 'p.....'}, {name: 'data', wave: 'x=..x.'}]}",
+                "data@clk rise edge=3 setup=2 hold=1"
+            )
+        );
+    }
+
+    #[test]
+    fn reuses_nodes_and_finds_falling_edges() {
+        let (output, warnings) = windowed(
+            "{signal: [{name: 'clk', wave: '0.1.0.'}, {name: 'd', wave: 'x=.=.x', node: '.a.b'}], \
+             edge: ['a~>b']}",
+            "d@clk fall hold=2; d@clk setup=1",
+        );
+        assert_eq!(Vec::<String>::new(), warnings);
+        assert_eq!(
+            "{signal: [{name: 'clk', wave: '0.1.0.', node: '..E.C'}, \
+             {name: 'd', wave: 'x=.=.x', node: '.aDbA.B'}], \
+             edge: ['a~>b', 'A<->B th', 'C-A', 'a<->D tsu', 'E-D']}",
+            output
+        );
+    }
+
+    #[test]
+    fn reports_bad_windows() {
+        let source =
+            "{signal: [{name: 'clk', wave: 'n...', period: 2}, {name: 'd', wave: 'x=.x'}]}";
+        for (spec, warning) in [
+            ("d@clk", "Unable to draw `window=d@clk`: expected `setup=` or `hold=`"),
+            ("d clk setup=1", "Unable to draw `window=d clk setup=1`: expected `data@clock` first"),
+            ("q@clk hold=1", "Unable to draw `window=q@clk hold=1`: no signal named 'q'"),
+            ("d@clk setup=1 edge=9", "Unable to draw `window=d@clk setup=1 edge=9`: 'clk' has no rising edge 9"),
+            ("d@clk setup=3", "`window=d@clk setup=3`: the window doesn't fit into the 4 bricks of 'd', cutting it short"),
+        ] {
+            assert_eq!(vec![warning.to_string()], windowed(source, spec).1);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::Path;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+use mdbook_wavedrom::testing::build_book;
+
+use crate::common::write_book;
+
+const INTRO: &str = "# Intro\n\n\
+```wavedrom caption=Clock id=clock\n{signal: [{name: 'clk', wave: 'p...'}]}\n```\n\n\
+Between, see {{#figure clock}}.\n\n\
+```wavedrom\n{signal: [\n  {name: 'req', wave: '01.0'},\n  {name: 'ack', wave: '0.10'}\n]}\n```\n";
+
+const NESTED: &str = "# Nested\n\n\
+- item\n  - ```wavedrom\n    {signal: [{name: 'a', wave: '01'}]}\n    ```\n- next\n\n\
+> Quoted\n>\n> ```register\n> [{bits: 8, name: 'data'}]\n> ```\n\n\
+1. First\n\n   ```wavedrom\n   {signal: [{name: 'b & c', wave: '10'}]}\n   ```\n\n   More\n";
+
+fn book(dir: &Path, book_toml: &str) {
+    write_book(
+        dir,
+        book_toml,
+        "- [Intro](intro.md)\n- [Nested](nested/structures.md)\n",
+        &[("intro.md", INTRO), ("nested/structures.md", NESTED)],
+    );
+}
+
+const BOOK_TOML: &str = "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\n";
+
+#[test]
+fn renders_several_diagrams_per_page() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path(), BOOK_TOML);
+
+    let page = build_book(tmp.path()).unwrap().page("intro.md");
+    assert_eq!(
+        2,
+        page.matches("<script type=\"WaveDrom\"").count(),
+        "{}",
+        page
+    );
+    assert!(page.contains("<div class=\"wavedrom-anchor\" id=\"wavedrom-1\"></div>"));
+    assert!(page.contains("<div class=\"wavedrom-anchor\" id=\"wavedrom-2\"></div>"));
+    assert!(page.contains("{signal: [{name: 'clk', wave: 'p...'}]}"));
+    assert!(page.contains("{name: 'ack', wave: '0.10'}"));
+    assert!(
+        page.contains("Between, see <a href=\"#clock\">Figure 1</a>."),
+        "{}",
+        page
+    );
+    assert!(!page.contains("```wavedrom"));
+}
+
+#[test]
+fn renders_diagrams_in_nested_structures() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path(), BOOK_TOML);
+
+    let page = build_book(tmp.path()).unwrap().page("nested/structures.md");
+    assert!(
+        page.contains("<ul>\n<li><div class=\"wavedrom-anchor\" id=\"wavedrom-1\"></div>"),
+        "{}",
+        page
+    );
+    assert!(
+        page.contains("</script>\n</div>\n</li>\n</ul>\n</li>\n<li>\n<p>next</p>"),
+        "{}",
+        page
+    );
+    assert!(page.contains("<blockquote>\n<p>Quoted</p>\n<div class=\"wavedrom-anchor\""));
+    assert!(page.contains("{reg: [{bits: 8, name: 'data'}]}\n</script>\n</div>\n</blockquote>"));
+    assert!(
+        page.contains("{signal: [{name: 'b & c', wave: '10'}]}"),
+        "{}",
+        page
+    );
+    assert!(page.contains("<p>More</p>\n</li>\n</ol>"), "{}", page);
+    // Nothing of the markup is left for markdown to mangle into text.
+    assert!(!page.contains("&lt;script"), "{}", page);
+    assert!(!page.contains("&lt;div"), "{}", page);
+}
+
+#[test]
+fn renders_zoomable_diagrams() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\nzoom = true\n",
+    );
+
+    let page = build_book(tmp.path()).unwrap().page("intro.md");
+    assert_eq!(
+        2,
+        page.matches("<div class=\"wavedrom-zoom-view\" tabindex=\"0\">")
+            .count(),
+        "{}",
+        page
+    );
+    assert!(
+        page.contains("</script>\n</div>\n</div></div>\n<figcaption"),
+        "{}",
+        page
+    );
+    assert!(!page.contains("&lt;div"), "{}", page);
+}
+
+#[test]
+fn renders_installed_books() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path(), "[book]\ntitle = \"Test\"\n");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("install").current_dir(tmp.path());
+    cmd.assert().success();
+
+    let built = build_book(tmp.path()).unwrap();
+    for asset in &[
+        "wavedrom.min.js",
+        "wavedrome-default.js",
+        "wavedrom-runtime.js",
+        "wavedrom-events.js",
+    ] {
+        assert!(built.dest.join(asset).exists(), "{} wasn't copied", asset);
+    }
+    let page = built.page("nested/structures.md");
+    assert!(
+        page.contains("<script src=\"../wavedrom.min.js\"></script>"),
+        "{}",
+        page
+    );
+    assert!(page.contains("<link rel=\"stylesheet\" href=\"../wavedrom-theme.css\">"));
+    assert_eq!(3, page.matches("<script type=\"WaveDrom\"").count());
+}
@@ -0,0 +1,342 @@
+use std::fs;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+use crate::common::{run, write_book};
+
+const SUMMARY: &str = "- [Intro](intro.md)\n";
+
+#[test]
+fn reports_invalid_diagrams() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "# Intro\n\n```wavedrom\n{signal: [{name: 'a', wave: '01'}]}\n```\n\n\
+             ```wavedrom\n{signal: [\n```\n",
+        )],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(2), code);
+    assert_eq!(
+        "intro.md:7: error: Invalid WaveJSON: expected array, boolean, null, number, object, \
+         or string at line 2, column 1\n",
+        output
+    );
+}
+
+#[test]
+fn audits_accessibility() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\n\
+         allow = [\"missing-caption\"]\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "# Intro\n\n```wavedrom\n{signal: [{name: 'clk', wave: 'p.'}]}\n```\n\n\
+             ```wavedrom alt=\"A clock\"\n{signal: [{name: 'clk', wave: 'p.'}], \
+             descriptions: {clk: 'The clock'}}\n```\n\n\
+             <span onclick=\"zoom()\">Zoom</span>\n",
+        )],
+    );
+
+    assert_eq!((Some(0), String::new()), run("check", tmp.path(), &[]));
+    let (code, output) = run("check", tmp.path(), &["--a11y"]);
+    assert_eq!(Some(1), code);
+    assert_eq!(
+        "intro.md:3: warning: The diagram has no `alt` text, screen readers can't tell what it shows [missing-alt]\n\
+         intro.md:3: warning: The diagram has no `descriptions` of its signals [missing-descriptions]\n\
+         intro.md:11: warning: The `<span>` reacts to `onclick` but can't be reached with the keyboard, \
+         give it a `tabindex` and a key handler, or use a `<button>` [no-keyboard-path]\n",
+        output
+    );
+}
+
+#[test]
+fn reports_low_contrast_on_dark_themes() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\nmode = \"data-uri\"\n\n\
+         [output.html]\npreferred-dark-theme = \"coal\"\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "# Intro\n\n```wavedrom\n{signal: [{name: 'clk'}]}\n```\n",
+        )],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert_eq!(
+        "intro.md:3: warning: the contrast of the signal names in #000000 with the background of \
+         the `coal` theme is 1.1:1, less than the 4.5:1 WCAG asks for [low-contrast]\n",
+        output
+    );
+}
+
+#[test]
+fn offline_needs_local_runtime_and_skins() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[output.html]\n\
+         additional-js = [\"https://cdn.example.com/wavedrom.min.js\", \"wavedrome-default.js\"]\n\
+         additional-css = [\"theme.css\"]\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "# Intro\n\n```wavedrom\n{signal: [], config: {skin: 'narrow'}}\n```\n",
+        )],
+    );
+    fs::write(
+        tmp.path().join("theme.css"),
+        "@import url('https://fonts.example.com/mono.css');\n",
+    )
+    .unwrap();
+
+    assert_eq!((Some(0), String::new()), run("check", tmp.path(), &[]));
+
+    let (code, output) = run("check", tmp.path(), &["--offline"]);
+    assert_eq!(Some(2), code);
+    let problems: Vec<_> = output.lines().collect();
+    assert_eq!(5, problems.len(), "{}", output);
+    assert_eq!(
+        "book.toml: error: `additional-js` loads https://cdn.example.com/wavedrom.min.js from the network",
+        problems[0]
+    );
+    assert!(problems[1].contains("wavedrome-default.js, which doesn't exist"));
+    assert_eq!(
+        "theme.css: error: Fetches https://fonts.example.com/mono.css from the network",
+        problems[2]
+    );
+    assert!(problems[3].contains("The WaveDrom runtime isn't part of the book"));
+    assert!(problems[4].starts_with("intro.md:3: warning: The skin `narrow` isn't bundled"));
+
+    // What `install` sets up works offline, apart from the skin.
+    fs::write(
+        tmp.path().join("book.toml"),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\n\n[output.html]\n\
+         additional-js = [\"wavedrom.min.js\", \"wavedrome-default.js\"]\n",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("install").arg(tmp.path()).assert().success();
+
+    let (code, output) = run("check", tmp.path(), &["--offline"]);
+    assert_eq!(Some(1), code);
+    assert!(
+        output.starts_with("intro.md:3: warning: The skin"),
+        "{}",
+        output
+    );
+    assert_eq!(1, output.lines().count());
+}
+
+#[test]
+fn reports_unknown_keys_and_mismatched_data() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "# Intro\n\n```wavedrom\n{signal: [\n  {name: 'a', wave: '01', nodes: '.a'},\n  \
+             {name: 'd', wave: 'x==x', data: ['one', 'two', 'three']}\n]}\n```\n",
+        )],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert_eq!(
+        "intro.md:3: warning: lane 'a' has the key 'nodes', which WaveDrom ignores, \
+         did you mean 'node'? [unknown-key]\n\
+         intro.md:3: warning: lane 'd' has 3 data labels for the 2 data bricks of its wave \
+         'x==x' [data-mismatch]\n",
+        output
+    );
+}
+
+#[test]
+fn reports_taken_ids() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "```wavedrom caption=\"SPI write\"\n{signal: []}\n```\n\n\
+             ```wavedrom id=spi-write\n{signal: []}\n```\n\n\
+             ```wavedrom id=spi-write\n{signal: []}\n```\n",
+        )],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(2), code);
+    assert_eq!(
+        "intro.md:9: error: Another diagram of the chapter has the id `spi-write` already\n",
+        output
+    );
+}
+
+#[test]
+fn fails_on_warnings_when_strict() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            "```wavedrom\n{signal: [{name: 'a', wave: '01'}], edge: ['a->b']}\n```\n",
+        )],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert!(output.starts_with("intro.md:1: warning:"), "{}", output);
+    let (code, output) = run("check", tmp.path(), &["--strict"]);
+    assert_eq!(Some(2), code);
+    assert!(output.starts_with("intro.md:1: error:"), "{}", output);
+}
+
+#[test]
+fn skips_allowed_warnings() {
+    let tmp = tempfile::tempdir().unwrap();
+    let diagram =
+        "```wavedrom\n{signal: [{name: 'a', wave: '01', node: '.a'}], edge: ['a->b']}\n```\n";
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\n\
+         allow = [{ warning = \"dangling-edge\", path = \"appendix\" }]\n",
+        "- [Intro](intro.md)\n- [Registers](appendix/regs.md)\n",
+        &[("intro.md", diagram), ("appendix/regs.md", diagram)],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert_eq!(1, output.lines().count(), "{}", output);
+    assert!(output.starts_with("intro.md:1: warning:"), "{}", output);
+    assert!(output.ends_with("[dangling-edge]\n"), "{}", output);
+}
+
+#[test]
+fn checks_views_against_their_master() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n",
+        SUMMARY,
+        &[
+            (
+                "intro.md",
+                "```wavedrom src=waves/master.json5 signals=\"clk,req\" range=0..4\n```\n\n\
+                 ```wavedrom src=waves/master.json5 signals=\"req,ack\" range=4..12\n```\n",
+            ),
+            (
+                "waves/master.json5",
+                "{signal: [{name: 'clk', wave: 'p.......'}, {name: 'req', wave: '01..0...'}]}\n",
+            ),
+        ],
+    );
+
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert_eq!(
+        "intro.md:4: warning: No signal named 'ack' to show\n\
+         intro.md:4: warning: `range=4..12` ends after the 8 cycles of the diagram\n",
+        output
+    );
+}
+
+#[test]
+fn reports_chapters_over_the_diagram_budget() {
+    let tmp = tempfile::tempdir().unwrap();
+    let diagram = "```wavedrom\n{signal: []}\n```\n\n";
+    write_book(
+        tmp.path(),
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\ndiagram-budget = 2\n",
+        SUMMARY,
+        &[(
+            "intro.md",
+            &format!(
+                "{}{}```wavedrom render=false\n{{signal: []}}\n```\n",
+                diagram, diagram
+            ),
+        )],
+    );
+    assert_eq!((Some(0), String::new()), run("check", tmp.path(), &[]));
+
+    fs::write(tmp.path().join("src/intro.md"), diagram.repeat(3)).unwrap();
+    let (code, output) = run("check", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert!(
+        output.starts_with(
+            "intro.md: warning: the chapter has 3 diagrams, more than the budget of 2"
+        ),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("[diagram-budget]\n"), "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn compares_renders_with_golden_images() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    // "Renders" a diagram by copying its source to the image.
+    let renderer = tmp.path().join("render.sh");
+    fs::write(
+        &renderer,
+        "#!/bin/sh\n[ \"$1\" = -i ] && [ -n \"$4\" ] && cp \"$2\" \"$4\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&renderer, fs::Permissions::from_mode(0o755)).unwrap();
+    write_book(
+        tmp.path(),
+        &format!(
+            "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\nrender-command = \"{}\"\n",
+            renderer.display()
+        ),
+        SUMMARY,
+        &[(
+            "intro.md",
+            "```wavedrom\n{signal: [{wave: '01'}]}\n```\n\n```wavedrom\n{signal: [{wave: '10'}]}\n```\n\n\
+             ```wavedrom\n{signal: [{wave: 'p.'}]}\n```\n",
+        )],
+    );
+    fs::create_dir_all(tmp.path().join("golden")).unwrap();
+    fs::write(
+        tmp.path().join("golden/intro-1.svg"),
+        "{signal: [{wave: '01'}]}\r\n",
+    )
+    .unwrap();
+    fs::write(
+        tmp.path().join("golden/intro-2.svg"),
+        "{signal: [{wave: '1'}]}\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        (Some(0), String::new()),
+        run("check", tmp.path(), &["--render"])
+    );
+    let (code, output) = run("check", tmp.path(), &["--render", "--golden-dir", "golden"]);
+    assert_eq!(Some(2), code);
+    assert_eq!(
+        "intro.md:5: error: The diagram renders differently than its golden image golden/intro-2.svg\n\
+         intro.md:9: error: There's no golden image golden/intro-3.svg, write it with `export-images`\n",
+        output
+    );
+}
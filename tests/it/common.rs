@@ -0,0 +1,36 @@
+//! Fixtures shared by the tests of the subcommands.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+/// Write a book into `dir` with `book_toml`, its `summary` and the `files` of its `src`
+/// directory, given by their path and content.
+pub fn write_book(dir: &Path, book_toml: &str, summary: &str, files: &[(&str, &str)]) {
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("book.toml"), book_toml).unwrap();
+    fs::write(dir.join("src/SUMMARY.md"), summary).unwrap();
+    for (path, content) in files {
+        let file = dir.join("src").join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, content).unwrap();
+    }
+}
+
+/// Run `mdbook-wavedrom <subcommand>` with `args` in `dir`, for its exit code and what it
+/// printed.
+pub fn run(subcommand: &str, dir: &Path, args: &[&str]) -> (Option<i32>, String) {
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd
+        .arg(subcommand)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
@@ -0,0 +1,23 @@
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+fn completions(shell: &str) -> String {
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd.args(["completions", shell]).output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn completes_subcommands_and_flags() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let script = completions(shell);
+        for word in ["install", "export-images", "check", "a11y", "config-only"] {
+            assert!(script.contains(word), "{} completions lack {}", shell, word);
+        }
+    }
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["completions", "tcsh"]).assert().failure();
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+#[test]
+fn converts_files_and_stdin() {
+    let tmp = tempfile::tempdir().unwrap();
+    let file = tmp.path().join("spi.json5");
+    fs::write(&file, "{signal: [{name: 'clk', wave: 'p.',},],}\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd.arg("convert").arg(&file).output().unwrap();
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.starts_with("{\n  \"signal\": ["), "{}", json);
+
+    let mut cmd = assert_cmd::Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["convert", "--from", "json", "--to", "wavejson", "-"])
+        .write_stdin(json)
+        .assert()
+        .success()
+        .stdout("{signal: [\n  {name: 'clk', wave: 'p.'}\n]}\n");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["convert", "--from", "json"])
+        .arg(&file)
+        .assert()
+        .code(2);
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?}", args);
+}
+
+fn chapter(dir: &Path, diagrams: &[&str]) {
+    let content: Vec<String> = diagrams
+        .iter()
+        .map(|diagram| format!("```wavedrom\n{}\n```\n", diagram))
+        .collect();
+    fs::write(dir.join("book/src/intro.md"), content.join("\n")).unwrap();
+}
+
+#[test]
+fn lists_changed_diagrams() {
+    let tmp = tempfile::tempdir().unwrap();
+    let book = tmp.path().join("book");
+    fs::create_dir_all(book.join("src")).unwrap();
+    fs::write(book.join("book.toml"), "[book]\ntitle = \"Test\"\n").unwrap();
+    fs::write(book.join("src/SUMMARY.md"), "- [Intro](intro.md)\n").unwrap();
+    chapter(
+        tmp.path(),
+        &[
+            "{signal: [{name: 'clk', wave: 'p...'}]}",
+            "{signal: [{name: 'req', wave: '01..'}, {name: 'ack', wave: '0.1.'}]}",
+            "{signal: [{name: 'irq', wave: '010.'}]}",
+        ],
+    );
+    git(tmp.path(), &["init", "-q"]);
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "-q", "-m", "First"]);
+    chapter(
+        tmp.path(),
+        &[
+            "{signal: [ {name: 'clk', wave: 'p...'} ]}",
+            "{signal: [{name: 'req', wave: '0.1.'}, {name: 'ack', wave: '0.1.'}]}",
+        ],
+    );
+
+    let page = tmp.path().join("diff.html");
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd
+        .args(["diff", "HEAD", "--dir", "book", "--html"])
+        .arg(&page)
+        .current_dir(tmp.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        "intro.md:5: changed: Intro, diagram 2\n    lane 'req': wave '01..' -> '0.1.'\n\
+         intro.md:9: removed: Intro, diagram 3\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+    let page = fs::read_to_string(page).unwrap();
+    assert!(
+        page.contains("<script type=\"WaveDrom\">{signal: [{name: 'irq', wave: '010.'}]}</script>")
+    );
+
+    // The working tree and the index are left alone.
+    git(tmp.path(), &["diff", "--quiet", "--cached"]);
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "-q", "-m", "Second"]);
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["diff", "HEAD~", "HEAD", "--dir", "book"])
+        .current_dir(tmp.path())
+        .assert()
+        .code(1);
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["diff", "HEAD", "--dir", "book"])
+        .current_dir(tmp.path())
+        .assert()
+        .code(0);
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["diff", "no-such-revision", "--dir", "book"])
+        .current_dir(tmp.path())
+        .assert()
+        .code(3);
+    // A revision looking like an option is no option to git.
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["diff", "--dir", "book", "--", "--index-output=stolen"])
+        .current_dir(tmp.path())
+        .assert()
+        .code(3);
+    assert!(!tmp.path().join("stolen").exists());
+}
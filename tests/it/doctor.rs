@@ -0,0 +1,92 @@
+use std::fs;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+use crate::common::run;
+
+#[cfg(unix)]
+#[test]
+fn renders_with_the_configured_command() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let script = tmp.path().join("render.sh");
+    fs::write(&script, "#!/bin/sh\ncp \"$2\" \"$4\"\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        tmp.path().join("book.toml"),
+        format!(
+            "[preprocessor.wavedrom]\nmode = \"data-uri\"\nrender-command = \"{}\"\n",
+            script.display()
+        ),
+    )
+    .unwrap();
+
+    let (code, output) = run("doctor", tmp.path(), &[]);
+    assert_eq!(Some(0), code, "{}", output);
+    assert_eq!(
+        format!(
+            "ok: Found the render command at {}\n\
+             ok: The render command renders diagrams\n\
+             ok: The WaveDrom runtime doesn't have to be installed, the pages bring what they need\n",
+            script.display()
+        ),
+        output
+    );
+}
+
+#[test]
+fn explains_what_is_missing() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::write(
+        tmp.path().join("book.toml"),
+        "[preprocessor.wavedrom]\nrender-command = \"no-such-renderer --flag\"\n",
+    )
+    .unwrap();
+
+    let (code, output) = run("doctor", tmp.path(), &[]);
+    assert_eq!(Some(2), code);
+    assert_eq!(
+        "warning: The render command `no-such-renderer` isn't on the PATH, \
+         `export-images` won't work\n\
+         error: `wavedrom.min.js` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`, \
+         the diagrams would show as WaveJSON text\n\
+         error: `wavedrome-default.js` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`, \
+         the diagrams would show as WaveJSON text\n\
+         error: `wavedrom-runtime.js` isn't in `output.html.additional-js`, run `mdbook-wavedrom install`, \
+         the diagrams would show as WaveJSON text\n",
+        output
+    );
+}
+
+#[test]
+fn uses_the_selected_profile() {
+    let tmp = tempfile::tempdir().unwrap();
+    fs::write(
+        tmp.path().join("book.toml"),
+        "[preprocessor.wavedrom]\nmode = \"data-uri\"\nrender-command = \"no-such-renderer\"\n\n\
+         [preprocessor.wavedrom.profile.draft]\nmode = \"script\"\ninline-runtime = true\n",
+    )
+    .unwrap();
+
+    let (code, _) = run("doctor", tmp.path(), &[]);
+    assert_eq!(Some(2), code);
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd
+        .args(["doctor", "--profile", "draft"])
+        .arg(tmp.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    let output = cmd
+        .arg("doctor")
+        .arg(tmp.path())
+        .env("MDBOOK_WAVEDROM_PROFILE", "release")
+        .output()
+        .unwrap();
+    assert_eq!(Some(3), output.status.code());
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+/// A render command that "renders" a diagram by copying its source to the output file.
+const FAKE_RENDERER: &str = "#!/bin/sh\n[ \"$1\" = -i ] && [ -n \"$4\" ] && cp \"$2\" \"$4\"\n";
+
+fn book(dir: &Path, render_command: &str) {
+    fs::create_dir_all(dir.join("src/a")).unwrap();
+    fs::write(
+        dir.join("book.toml"),
+        format!(
+            "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\nrender-command = \"{}\"\n",
+            render_command
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/SUMMARY.md"),
+        "- [Intro](intro.md)\n  - [Deep](a/deep.md)\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/intro.md"),
+        "```wavedrom\n{signal: [{wave: '01'}]}\n```\n\n```wavedrom\n{signal: [{wave: '10'}]}\n```\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/a/deep.md"),
+        "```wavedrom\n{signal: [{wave: 'p.'}]}\n```\n",
+    )
+    .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn exports_images_mirroring_chapters() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let renderer = tmp.path().join("render.sh");
+    fs::write(&renderer, FAKE_RENDERER).unwrap();
+    fs::set_permissions(&renderer, fs::Permissions::from_mode(0o755)).unwrap();
+    let root = tmp.path().join("book");
+    book(&root, &renderer.display().to_string());
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("export-images")
+        .arg(&root)
+        .arg("--out")
+        .arg(tmp.path().join("images"))
+        .arg("--jobs")
+        .arg("2")
+        .assert()
+        .success();
+
+    let read = |file: &str| fs::read_to_string(tmp.path().join("images").join(file)).unwrap();
+    assert_eq!("{signal: [{wave: '01'}]}\n", read("intro-1.svg"));
+    assert_eq!("{signal: [{wave: '10'}]}\n", read("intro-2.svg"));
+    assert_eq!("{signal: [{wave: 'p.'}]}\n", read("a/deep-1.svg"));
+}
+
+#[test]
+fn failing_renderer() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path(), "mdbook-wavedrom-no-such-renderer");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("export-images")
+        .arg(tmp.path())
+        .arg("--format")
+        .arg("png")
+        .current_dir(tmp.path())
+        .assert()
+        .code(2);
+    assert!(!tmp.path().join("images/intro-1.png").exists());
+}
+
+#[test]
+fn missing_book() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("export-images").arg(tmp.path()).assert().code(3);
+}
+
+#[cfg(unix)]
+#[test]
+fn renders_a_single_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let renderer = tmp.path().join("render.sh");
+    fs::write(&renderer, FAKE_RENDERER).unwrap();
+    fs::set_permissions(&renderer, fs::Permissions::from_mode(0o755)).unwrap();
+    let root = tmp.path().join("book");
+    book(&root, &renderer.display().to_string());
+    fs::create_dir_all(root.join("waves")).unwrap();
+    fs::write(root.join("waves/clk.json5"), "{name: 'clk', wave: 'p.'},\n").unwrap();
+    fs::write(
+        root.join("waves/bus.json5"),
+        "{signal: [\n{{#include clk.json5}}\n]}\n",
+    )
+    .unwrap();
+
+    // The render command is the one of the book the file is in.
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["render", "waves/bus.json5", "-o"])
+        .arg(tmp.path().join("bus.svg"))
+        .current_dir(&root)
+        .assert()
+        .success();
+    assert_eq!(
+        "{signal: [\n{name: 'clk', wave: 'p.'},\n]}\n",
+        fs::read_to_string(tmp.path().join("bus.svg")).unwrap()
+    );
+
+    fs::write(root.join("waves/broken.json5"), "{signal: [\n").unwrap();
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["render", "waves/broken.json5"])
+        .current_dir(&root)
+        .assert()
+        .code(2)
+        .stdout("");
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use crate::common::{run, write_book};
+
+fn book(dir: &Path) {
+    write_book(
+        dir,
+        "[book]\ntitle = \"Test\"\n",
+        "- [Intro](intro.md)\n  - [DMA](a/dma.md)\n",
+        &[
+            (
+                "intro.md",
+                "# Intro\n\n```wavedrom\n{signal: [{name: 'clk', wave: 'p.'}]}\n```\n",
+            ),
+            (
+                "a/dma.md",
+                "# DMA\n\n```wavedrom\n{signal: [{name: 'clk'}]}\n```\n\nText\n\n\
+                 ```wavedrom\n{signal: [['irq', {name: 'dma_irq'}, {name: 'dma_irq_n'}]]}\n```\n",
+            ),
+        ],
+    );
+}
+
+#[test]
+fn lists_matching_diagrams() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path());
+
+    assert_eq!(
+        (
+            Some(0),
+            "intro.md:3: Intro, diagram 1: clk\na/dma.md:3: DMA, diagram 1: clk\n".to_string()
+        ),
+        run("grep", tmp.path(), &["clk"])
+    );
+    assert_eq!(
+        (
+            Some(0),
+            "a/dma.md:9: DMA, diagram 2: dma_irq, dma_irq_n\n".to_string()
+        ),
+        run("grep", tmp.path(), &["--regex", "^dma_", "."])
+    );
+}
+
+#[test]
+fn nothing_found() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path());
+
+    assert_eq!((Some(1), String::new()), run("grep", tmp.path(), &["dma"]));
+    assert_eq!(Some(3), run("grep", tmp.path(), &["-e", "dma("]).0);
+}
@@ -2,6 +2,23 @@ use std::fs;
 use std::process::Command;
 
 use assert_cmd::prelude::*;
+use mdbook_wavedrom::assets::integrity;
+
+/// The `toml` without the `asset-hashes` table `install` pins the files in, which changes with
+/// every change of the files.
+fn without_pins(toml: &str) -> String {
+    let mut output = String::new();
+    let mut pins = false;
+    for line in toml.split_inclusive('\n') {
+        if line.starts_with('[') {
+            pins = line.trim() == "[preprocessor.wavedrom.asset-hashes]";
+        }
+        if !pins {
+            output.push_str(line);
+        }
+    }
+    output
+}
 
 macro_rules! test_install {
     ($inputfile:expr, $msg:expr) => {
@@ -17,7 +34,7 @@ macro_rules! test_install {
         cmd.arg("install").current_dir(tmp.path());
         cmd.assert().success();
 
-        let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+        let output = without_pins(&fs::read_to_string(&book_toml).expect("can't read book.toml"));
         pretty_assertions::assert_eq!(
             expected,
             output,
@@ -34,6 +51,18 @@ macro_rules! test_install {
             tmp.path().join("wavedrome-default.js").exists(),
             "Failed to copy default.min.js"
         );
+        assert!(
+            tmp.path().join("wavedrom-events.js").exists(),
+            "Failed to copy wavedrom-events.js"
+        );
+        assert!(
+            tmp.path().join("wavedrom-highlight.js").exists(),
+            "Failed to copy wavedrom-highlight.js"
+        );
+        assert!(
+            tmp.path().join("wavedrom-theme.css").exists(),
+            "Failed to copy wavedrom-theme.css"
+        );
     };
 }
 
@@ -56,3 +85,449 @@ fn some() {
 fn missing_js() {
     test_install!("missing-js.toml", "should add missing javascript file");
 }
+
+#[test]
+fn discovers_book_root() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+    let chapter_dir = tmp.path().join("src").join("chapter");
+    fs::create_dir_all(&chapter_dir).expect("can't create chapter directory");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("install").current_dir(&chapter_dir);
+    cmd.assert().success();
+
+    let output = without_pins(&fs::read_to_string(&book_toml).expect("can't read book.toml"));
+    pretty_assertions::assert_eq!(include_str!("empty.toml.output"), output);
+    assert!(tmp.path().join("wavedrom.min.js").exists());
+    assert!(!chapter_dir.join("wavedrom.min.js").exists());
+}
+
+#[test]
+fn no_discover() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    fs::write(tmp.path().join("book.toml"), include_str!("empty.toml"))
+        .expect("can't write book.toml");
+    let src_dir = tmp.path().join("src");
+    fs::create_dir_all(&src_dir).expect("can't create src directory");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--no-discover"]).current_dir(&src_dir);
+    cmd.assert().failure();
+}
+
+#[test]
+fn prefixed() {
+    test_install!(
+        "prefixed.toml",
+        "should not mistake files in other places for the wavedrom files"
+    );
+}
+
+#[test]
+fn duplicates() {
+    test_install!(
+        "duplicates.toml",
+        "should remove duplicate entries and keep the order"
+    );
+}
+
+#[test]
+fn book_per_language() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    for language in ["de", "en"] {
+        let book = tmp.path().join(language);
+        fs::create_dir(&book).expect("can't create language directory");
+        fs::write(book.join("book.toml"), include_str!("empty.toml"))
+            .expect("can't write book.toml");
+    }
+    fs::create_dir(tmp.path().join("po")).expect("can't create po directory");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("install").current_dir(tmp.path());
+    cmd.assert().success();
+
+    for language in ["de", "en"] {
+        let book = tmp.path().join(language);
+        let output = without_pins(
+            &fs::read_to_string(book.join("book.toml")).expect("can't read book.toml"),
+        );
+        pretty_assertions::assert_eq!(include_str!("empty.toml.output"), output);
+        assert!(book.join("wavedrom.min.js").exists());
+    }
+    assert!(!tmp.path().join("wavedrom.min.js").exists());
+    assert!(!tmp.path().join("po/wavedrom.min.js").exists());
+}
+
+#[test]
+fn config_only() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--config-only"])
+        .current_dir(tmp.path());
+    cmd.assert().success();
+
+    let output = without_pins(&fs::read_to_string(&book_toml).expect("can't read book.toml"));
+    pretty_assertions::assert_eq!(include_str!("empty.toml.output"), output);
+    assert!(!tmp.path().join("wavedrom.min.js").exists());
+}
+
+#[test]
+fn assets_only() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--assets-only"])
+        .current_dir(tmp.path());
+    cmd.assert().success();
+
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    pretty_assertions::assert_eq!(include_str!("empty.toml"), output);
+    assert!(tmp.path().join("wavedrom.min.js").exists());
+    assert!(tmp.path().join("wavedrom-theme.css").exists());
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--assets-only", "--config-only"])
+        .current_dir(tmp.path());
+    cmd.assert().failure();
+}
+
+#[test]
+fn watches_wave_sources() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(
+        &book_toml,
+        "[book]\ntitle = \"Test\"\n\n[build]\nextra-watch-dirs = [\"sim\"]\n",
+    )
+    .expect("can't write book.toml");
+    fs::create_dir(tmp.path().join("src")).expect("can't create src directory");
+    fs::write(tmp.path().join("src/SUMMARY.md"), "[Intro](intro.md)\n")
+        .expect("can't write SUMMARY.md");
+    fs::write(
+        tmp.path().join("src/intro.md"),
+        "```wavedrom src=../waves/spi.json5\n```\n\n\
+         ```wavedrom results=../sim/run.json\n{signal: []}\n```\n",
+    )
+    .expect("can't write chapter");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--config-only"])
+        .current_dir(tmp.path());
+    cmd.assert().success();
+
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(
+        output.contains("extra-watch-dirs = [\"sim\", \"waves\"]"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn adds_example_chapter() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    fs::write(tmp.path().join("book.toml"), include_str!("empty.toml"))
+        .expect("can't write book.toml");
+    let src_dir = tmp.path().join("src");
+    fs::create_dir_all(&src_dir).expect("can't create src directory");
+    fs::write(
+        src_dir.join("SUMMARY.md"),
+        "# Summary\n\n- [Intro](intro.md)",
+    )
+    .expect("can't write SUMMARY.md");
+    fs::write(src_dir.join("intro.md"), "# Intro\n").expect("can't write intro.md");
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(["install", "--example"]).current_dir(tmp.path());
+        cmd.assert().success();
+    }
+
+    let summary = fs::read_to_string(src_dir.join("SUMMARY.md")).expect("can't read SUMMARY.md");
+    pretty_assertions::assert_eq!(
+        "# Summary\n\n- [Intro](intro.md)\n- [Timing diagrams](wavedrom-example.md)\n",
+        summary
+    );
+    assert!(src_dir.join("wavedrom-example.md").exists());
+
+    // The examples are free of warnings.
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("check").current_dir(tmp.path());
+    cmd.assert().success().stdout("");
+}
+
+#[test]
+fn loads_runtime_from_cdn() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(["install", "--cdn", "--yes"])
+            .current_dir(tmp.path());
+        cmd.assert().success();
+    }
+
+    let output = without_pins(&fs::read_to_string(&book_toml).expect("can't read book.toml"));
+    let expected = include_str!("empty.toml.output")
+        .replace(
+            "command = \"mdbook-wavedrom\"\n",
+            "command = \"mdbook-wavedrom\"\nsource = \"cdn\"\n",
+        )
+        .replace("additional-js = [\"wavedrom.min.js\", \"wavedrome-default.js\", \"wavedrom-runtime.js\", \"wavedrom-events.js\", \"wavedrom-highlight.js\"]\n", "");
+    pretty_assertions::assert_eq!(expected, output);
+    // No scripts at all, the chapters load the runtime and inline the rest.
+    assert!(!tmp.path().join("wavedrom.min.js").exists());
+    assert!(!tmp.path().join("wavedrome-default.js").exists());
+    assert!(tmp.path().join("wavedrom-theme.css").exists());
+}
+
+#[test]
+fn pins_asset_hashes() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+    let pin = |name: &str| {
+        let content = fs::read(tmp.path().join(name)).expect("can't read file");
+        format!("\"{}\" = \"{}\"", name, integrity(&content))
+    };
+    let install = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.arg("install").args(args).current_dir(tmp.path());
+        cmd.assert().success()
+    };
+
+    install(&["--yes"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(
+        output.contains("[preprocessor.wavedrom.asset-hashes]"),
+        "{}",
+        output
+    );
+    for name in [
+        "wavedrom.min.js",
+        "wavedrome-default.js",
+        "wavedrom-theme.css",
+    ] {
+        assert!(output.contains(&pin(name)), "{}", output);
+    }
+
+    // A replaced file keeps its pin, until it's pinned again on purpose.
+    let original = pin("wavedrom-events.js");
+    fs::write(tmp.path().join("wavedrom-events.js"), "alert(1)").expect("can't write file");
+    let assert = install(&["--yes"]);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(
+        stderr.contains("'wavedrom-events.js' changed since it was pinned, keeping its pin"),
+        "{}",
+        stderr
+    );
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(output.contains(&original), "{}", output);
+
+    install(&["--yes", "--repin"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(output.contains(&pin("wavedrom-events.js")), "{}", output);
+    assert!(!output.contains(&original), "{}", output);
+}
+
+#[test]
+fn copies_into_assets_dir() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(["install", "--assets-dir", "./theme/wavedrom/"])
+            .current_dir(tmp.path());
+        cmd.assert().success();
+    }
+
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    let expected =
+        include_str!("empty.toml.output").replace("\"wavedrom", "\"theme/wavedrom/wavedrom");
+    pretty_assertions::assert_eq!(expected, without_pins(&output));
+    let content = fs::read(tmp.path().join("theme/wavedrom/wavedrom.min.js"))
+        .expect("can't read wavedrom.min.js");
+    assert!(output.contains(&format!(
+        "\"theme/wavedrom/wavedrom.min.js\" = \"{}\"",
+        integrity(&content)
+    )));
+    assert!(tmp
+        .path()
+        .join("theme/wavedrom/wavedrom-theme.css")
+        .exists());
+    assert!(!tmp.path().join("wavedrom.min.js").exists());
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["install", "--assets-dir", "../outside"])
+        .current_dir(tmp.path());
+    cmd.assert().failure();
+}
+
+#[test]
+fn uninstalls() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(
+        &book_toml,
+        "[book]\ntitle = \"Test\"\n\n[output.html]\nadditional-css = [\"custom.css\"]\n",
+    )
+    .expect("can't write book.toml");
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(args).current_dir(tmp.path());
+        cmd.assert().success();
+    };
+
+    run(&["install", "--assets-dir", "theme"]);
+    fs::write(tmp.path().join("theme/wavedrome-default.js"), "// mine\n")
+        .expect("can't write file");
+    run(&["uninstall"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    pretty_assertions::assert_eq!(
+        "[book]\ntitle = \"Test\"\n\n[output.html]\nadditional-css = [\"custom.css\"]\n",
+        output
+    );
+    // Only the file changed by hand is left.
+    assert!(!tmp.path().join("theme/wavedrom.min.js").exists());
+    assert!(!tmp.path().join("theme/wavedrom-theme.css").exists());
+    assert!(tmp.path().join("theme/wavedrome-default.js").exists());
+
+    run(&["install"]);
+    run(&["uninstall", "--keep-files"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(!output.contains("wavedrom"), "{}", output);
+    assert!(tmp.path().join("wavedrom.min.js").exists());
+}
+
+#[test]
+fn copies_configured_skins() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(
+        &book_toml,
+        "[book]\ntitle = \"Test\"\n\n[preprocessor.wavedrom]\nskin = \"narrow\"\n",
+    )
+    .expect("can't write book.toml");
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(args).current_dir(tmp.path());
+        cmd.assert().success();
+    };
+
+    run(&["install"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(output.contains("\"wavedrom-skin-narrow.js\""), "{}", output);
+    assert!(!output.contains("lowkey"), "{}", output);
+    assert!(tmp.path().join("wavedrom-skin-narrow.js").exists());
+    assert!(!tmp.path().join("wavedrom-skin-lowkey.js").exists());
+
+    run(&["install", "--skin", "lowkey", "--skin", "dark"]);
+    let output = fs::read_to_string(&book_toml).expect("can't read book.toml");
+    assert!(output.contains("\"wavedrom-skin-lowkey.js\""), "{}", output);
+    // Once in `additional-js` and once in the pins.
+    assert_eq!(
+        2,
+        output.matches("\"wavedrom-skin-narrow.js\"").count(),
+        "{}",
+        output
+    );
+
+    run(&["uninstall"]);
+    assert!(!tmp.path().join("wavedrom-skin-narrow.js").exists());
+    assert!(!tmp.path().join("wavedrom-skin-lowkey.js").exists());
+}
+
+#[test]
+fn upgrades_outdated_files() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    fs::write(&book_toml, include_str!("empty.toml")).expect("can't write book.toml");
+    let run = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(args).current_dir(tmp.path());
+        let assert = cmd.assert().success();
+        String::from_utf8_lossy(&assert.get_output().stderr).into_owned()
+    };
+    run(&["install", "--assets-dir", "theme"]);
+    let current = fs::read(tmp.path().join("theme/wavedrome-default.js")).unwrap();
+
+    // As an older version would have installed and pinned it.
+    let old = b"WaveDrom.ProcessAll();\n";
+    fs::write(tmp.path().join("theme/wavedrome-default.js"), old).unwrap();
+    let toml = fs::read_to_string(&book_toml)
+        .unwrap()
+        .replace(&integrity(&current), &integrity(old));
+    fs::write(&book_toml, &toml).unwrap();
+    // And one changed by hand since.
+    fs::write(tmp.path().join("theme/wavedrom-events.js"), "// mine\n").unwrap();
+
+    let stderr = run(&["install", "--upgrade"]);
+    assert!(stderr.contains("Upgrading"), "{}", stderr);
+    assert!(
+        stderr.contains("Keeping 'theme/wavedrom-events.js', which was changed by hand"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        current,
+        fs::read(tmp.path().join("theme/wavedrome-default.js")).unwrap()
+    );
+    assert_eq!(
+        b"// mine\n".to_vec(),
+        fs::read(tmp.path().join("theme/wavedrom-events.js")).unwrap()
+    );
+    let output = fs::read_to_string(&book_toml).unwrap();
+    assert!(output.contains(&format!(
+        "\"theme/wavedrome-default.js\" = \"{}\"",
+        integrity(&current)
+    )));
+    assert_eq!(toml.replace(&integrity(old), &integrity(&current)), output);
+
+    let stderr = run(&["install", "--upgrade"]);
+    assert!(!stderr.contains("Upgrading"), "{}", stderr);
+}
+
+#[test]
+fn reports_malformed_books() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = tmp.path().join("book.toml");
+    let install = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+        cmd.args(args).current_dir(tmp.path());
+        let assert = cmd.assert().code(1);
+        String::from_utf8_lossy(&assert.get_output().stderr).into_owned()
+    };
+
+    fs::write(&book_toml, "[book\ntitle = 'Broken'\n").unwrap();
+    let stderr = install(&["install", "--config-only"]);
+    assert!(stderr.contains("Unable to parse"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    fs::write(&book_toml, "[output.html]\nadditional-js = 'custom.js'\n").unwrap();
+    let stderr = install(&["install", "--config-only"]);
+    assert!(
+        stderr.contains("`output.html.additional-js` isn't a list"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    fs::write(&book_toml, "preprocessor = 'wavedrom'\n").unwrap();
+    let stderr = install(&["install", "--config-only"]);
+    assert!(
+        stderr.contains("`preprocessor` isn't a table"),
+        "{}",
+        stderr
+    );
+}
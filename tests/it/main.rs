@@ -1 +1,12 @@
+mod build;
+mod check;
+mod common;
+mod completions;
+mod convert;
+mod diff;
+mod doctor;
+mod export;
+mod grep;
 mod install;
+mod supports;
+mod sync;
@@ -0,0 +1,76 @@
+use std::fs;
+use std::process::Command;
+
+use assert_cmd::prelude::*;
+
+fn supports(book_toml: Option<&str>, renderer: &str) -> bool {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    if let Some(book_toml) = book_toml {
+        fs::write(tmp.path().join("book.toml"), book_toml).expect("can't write book.toml");
+    }
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.arg("supports").arg(renderer).current_dir(tmp.path());
+    cmd.status().expect("can't run mdbook-wavedrom").success()
+}
+
+#[test]
+fn html_by_default() {
+    assert!(supports(None, "html"));
+    assert!(supports(None, "linkcheck"));
+    assert!(supports(None, "epub"));
+    assert!(!supports(None, "markdown"));
+}
+
+#[test]
+fn answers_from_the_built_in_renderers() {
+    // mdbook checks the book's `renderers` itself, before asking.
+    let book_toml = r#"
+[preprocessor.wavedrom]
+command = "mdbook-wavedrom"
+renderers = ["markdown"]
+"#;
+    assert!(supports(Some(book_toml), "html"));
+    assert!(!supports(Some(book_toml), "markdown"));
+}
+
+#[test]
+fn lists_renderers_as_json() {
+    let tmp = tempfile::tempdir().expect("can't create tempdir");
+    let book_toml = r#"
+[preprocessor.wavedrom]
+command = "mdbook-wavedrom"
+renderers = ["html", "epub"]
+
+[output.html]
+[output.markdown]
+"#;
+    fs::write(tmp.path().join("book.toml"), book_toml).expect("can't write book.toml");
+
+    let mut cmd = Command::cargo_bin("mdbook-wavedrom").unwrap();
+    cmd.args(["supports", "--json", "--dir"]).arg(tmp.path());
+    let output = cmd.output().expect("can't run mdbook-wavedrom");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let renderers: Vec<_> = json["renderers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| {
+            (
+                r["renderer"].as_str().unwrap(),
+                r["behavior"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![("html", "embed"), ("markdown", "skip"), ("epub", "images")],
+        renderers
+    );
+    assert_eq!("script", json["renderers"][0]["embedding"]["mode"]);
+    assert_eq!(
+        "additional-js",
+        json["renderers"][0]["embedding"]["runtime"]
+    );
+    assert_eq!("png", json["renderers"][2]["image-format"]);
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+use crate::common::{run, write_book};
+
+const CHAPTER: &str =
+    "# Intro\n\n- ```wavedrom src=../waves/a.json5 keep\n  {signal: [{wave: '01'}]}\n  ```\n";
+
+fn book(dir: &Path) {
+    write_book(
+        dir,
+        "[book]\ntitle = \"Test\"\n",
+        "- [Intro](ch/intro.md)\n",
+        &[
+            ("ch/intro.md", CHAPTER),
+            ("waves/a.json5", "{signal: [\n  {wave: '0110'}\n]}\n"),
+        ],
+    );
+}
+
+#[test]
+fn checks_and_pulls() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path());
+
+    let (code, stdout) = run("sync", tmp.path(), &[]);
+    assert_eq!(Some(1), code);
+    assert!(
+        stdout.starts_with("ch/intro.md:3: differs from "),
+        "{}",
+        stdout
+    );
+    assert_eq!(
+        CHAPTER,
+        fs::read_to_string(tmp.path().join("src/ch/intro.md")).unwrap()
+    );
+
+    assert_eq!(Some(0), run("sync", tmp.path(), &["--pull"]).0);
+    assert_eq!(
+        "# Intro\n\n- ```wavedrom src=../waves/a.json5 keep\n  {signal: [\n    {wave: '0110'}\n  ]}\n  ```\n",
+        fs::read_to_string(tmp.path().join("src/ch/intro.md")).unwrap()
+    );
+    assert_eq!((Some(0), String::new()), run("sync", tmp.path(), &[]));
+}
+
+#[test]
+fn pushes() {
+    let tmp = tempfile::tempdir().unwrap();
+    book(tmp.path());
+
+    assert_eq!(Some(0), run("sync", tmp.path(), &["--push"]).0);
+    assert_eq!(
+        "{signal: [{wave: '01'}]}\n",
+        fs::read_to_string(tmp.path().join("src/waves/a.json5")).unwrap()
+    );
+    assert_eq!(
+        CHAPTER,
+        fs::read_to_string(tmp.path().join("src/ch/intro.md")).unwrap()
+    );
+    assert_eq!(Some(0), run("sync", tmp.path(), &[]).0);
+}